@@ -0,0 +1,132 @@
+//! API keys that clients use to call the API from their own systems.
+
+use axum::http::Method;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, EnumString};
+
+use crate::error::{GlobeliseError, GlobeliseResult};
+
+/// Prefix of every API key, which tells them apart from JWTs.
+pub const API_KEY_PREFIX: &str = "glb_";
+
+/// How long an API key can be valid for, in days.
+pub const MAX_API_KEY_LIFETIME_DAYS: i64 = 365;
+
+/// How often the last use of an API key is recorded, in seconds.
+///
+/// Keeps requests made with the same key in quick succession from all writing to the database.
+pub const API_KEY_LAST_USED_PRECISION_SECONDS: i64 = 60;
+
+/// What an API key is allowed to do.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, EnumString, Display, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ApiKeyScope {
+    ReadContractors,
+    WriteContractors,
+    ReadContracts,
+    WriteContracts,
+    ReadPayslips,
+    ReadTaxReports,
+    ReadInvoices,
+}
+
+/// Routes that can be called with an API key, along with the scopes needed to read and write.
+///
+/// Routes that are not listed here cannot be called with an API key.
+const API_KEY_ROUTES: &[(&str, Option<ApiKeyScope>, Option<ApiKeyScope>)] = &[
+    // User management microservice
+    (
+        "/client/contractors",
+        Some(ApiKeyScope::ReadContractors),
+        None,
+    ),
+    (
+        "/client/branch/individual-contractors",
+        Some(ApiKeyScope::ReadContractors),
+        None,
+    ),
+    (
+        "/client-view/contractor-account-settings",
+        Some(ApiKeyScope::ReadContractors),
+        Some(ApiKeyScope::WriteContractors),
+    ),
+    (
+        "/admin-pic/account-settings/contractors",
+        Some(ApiKeyScope::ReadContractors),
+        Some(ApiKeyScope::WriteContractors),
+    ),
+    // Contractor management microservice
+    ("/contractors", Some(ApiKeyScope::ReadContractors), None),
+    (
+        "/contracts/client",
+        Some(ApiKeyScope::ReadContracts),
+        Some(ApiKeyScope::WriteContracts),
+    ),
+    ("/payslips/client", Some(ApiKeyScope::ReadPayslips), None),
+    (
+        "/tax-reports/client",
+        Some(ApiKeyScope::ReadTaxReports),
+        None,
+    ),
+    (
+        "/invoices/individual/client",
+        Some(ApiKeyScope::ReadInvoices),
+        None,
+    ),
+    (
+        "/invoices/group/client",
+        Some(ApiKeyScope::ReadInvoices),
+        None,
+    ),
+];
+
+impl ApiKeyScope {
+    /// Gets the scope needed to call a route with an API key.
+    ///
+    /// Returns `None` if the route cannot be called with an API key.
+    pub fn required_for(method: &Method, path: &str) -> Option<Self> {
+        let (_, read, write) = API_KEY_ROUTES.iter().find(|(prefix, _, _)| {
+            path.strip_prefix(prefix)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+        })?;
+        if *method == Method::GET {
+            *read
+        } else {
+            *write
+        }
+    }
+}
+
+/// Generates a new API key.
+///
+/// Only its hash is stored, so the key has to be shown to the client right away.
+pub fn generate_api_key() -> GlobeliseResult<String> {
+    let mut secret = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| GlobeliseError::internal("Could not generate API key"))?;
+    Ok(format!(
+        "{}{}",
+        API_KEY_PREFIX,
+        base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// Hashes an API key for storage and lookup.
+///
+/// API keys are random, so a fast hash is enough unlike for passwords.
+pub fn hash_api_key(api_key: &str) -> String {
+    base64::encode(digest(&SHA256, api_key.as_bytes()))
+}
+
+/// The start of an API key, which is stored so that clients can tell their keys apart.
+pub fn api_key_hint(api_key: &str) -> String {
+    api_key.chars().take(API_KEY_PREFIX.len() + 6).collect()
+}
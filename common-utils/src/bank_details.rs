@@ -0,0 +1,549 @@
+//! Validation of bank account details.
+//!
+//! Checks IBANs (including the mod-97 check digits), BIC structure, and the account, bank
+//! code and branch code formats of the countries we pay into. Countries without specific
+//! rules only get the generic checks.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    custom_serde::Country,
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+/// A problem with one field of a set of bank details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankDetailsError {
+    /// Name of the field, as it appears in requests.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for BankDetailsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Bank details to validate, borrowed from wherever they are stored.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BankDetails<'a> {
+    /// Country of the account. Only the generic checks apply when it is unknown.
+    pub country: Option<Country>,
+    pub bank_account_number: &'a str,
+    pub bank_code: Option<&'a str>,
+    pub branch_code: Option<&'a str>,
+    pub swift_code: Option<&'a str>,
+    pub iban: Option<&'a str>,
+}
+
+impl BankDetails<'_> {
+    /// Returns every problem with the details.
+    pub fn errors(&self) -> Vec<BankDetailsError> {
+        let mut errors = Errors::default();
+
+        let account_number = compact(self.bank_account_number);
+        if account_number.is_empty() {
+            errors.push("bank-account-number", "is required");
+        } else if account_number.len() > 34 {
+            errors.push("bank-account-number", "must be at most 34 characters");
+        } else if !account_number.chars().all(|c| c.is_ascii_alphanumeric()) {
+            errors.push(
+                "bank-account-number",
+                "may only contain letters, digits, spaces and dashes",
+            );
+        }
+
+        let bank_code = non_empty(self.bank_code).map(compact);
+        let branch_code = non_empty(self.branch_code).map(compact);
+        let iban = non_empty(self.iban);
+
+        if let Some(swift_code) = non_empty(self.swift_code) {
+            if let Err(message) = validate_bic(swift_code) {
+                errors.push("swift-code", message);
+            }
+        }
+        if let Some(iban) = iban {
+            if let Err(message) = validate_iban(iban) {
+                errors.push("iban", message);
+            }
+        }
+
+        match self.country {
+            Some(Country::SG) => {
+                errors.digits("bank-code", bank_code.as_deref(), 4, 4);
+                errors.digits("branch-code", branch_code.as_deref(), 3, 3);
+                errors.digits("bank-account-number", Some(&account_number), 6, 17);
+            }
+            Some(Country::MY) => {
+                errors.digits("bank-account-number", Some(&account_number), 6, 17);
+            }
+            Some(Country::PH) => {
+                errors.digits("bank-account-number", Some(&account_number), 6, 17);
+                // Branch codes are the 9 digit BRSTN, when given.
+                if branch_code.is_some() {
+                    errors.digits("branch-code", branch_code.as_deref(), 9, 9);
+                }
+            }
+            Some(Country::IN) => {
+                match bank_code.as_deref() {
+                    None => errors.push("bank-code", "is required"),
+                    Some(ifsc) if !is_ifsc(ifsc) => errors.push(
+                        "bank-code",
+                        "must be an 11 character IFSC, e.g. SBIN0001234",
+                    ),
+                    Some(_) => {}
+                }
+                errors.digits("bank-account-number", Some(&account_number), 9, 18);
+            }
+            Some(Country::GB) if iban.is_none() => {
+                // Sort code and account number, unless the account is given as an IBAN.
+                errors.digits("bank-code", bank_code.as_deref(), 6, 6);
+                errors.digits("bank-account-number", Some(&account_number), 8, 8);
+            }
+            Some(country) if is_sepa_country(country) => {
+                // Accounts in the EU are identified by their IBAN alone.
+                let (field, value) = match iban {
+                    Some(iban) => ("iban", iban),
+                    None => ("bank-account-number", self.bank_account_number),
+                };
+                match validate_iban(value) {
+                    Ok(()) if !compact(value).starts_with(country.as_str()) => {
+                        errors.push(field, format!("must be an IBAN from {}", country.as_str()))
+                    }
+                    Ok(()) => {}
+                    // Already reported above.
+                    Err(_) if field == "iban" => {}
+                    Err(message) => errors.push(field, message),
+                }
+            }
+            _ => {}
+        }
+
+        errors.0
+    }
+
+    /// Fails with every problem with the details, if there are any.
+    pub fn validate(&self) -> GlobeliseResult<()> {
+        let errors = self.errors();
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        Err(GlobeliseError::bad_request(
+            errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        ))
+    }
+}
+
+/// Checks the structure, length and check digits of an IBAN.
+///
+/// Spaces and dashes are ignored.
+pub fn validate_iban(iban: &str) -> Result<(), String> {
+    let iban = compact(iban);
+    if iban.len() < 5 || !iban.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("is not a valid IBAN".to_string());
+    }
+
+    let (country, check_digits) = (&iban[..2], &iban[2..4]);
+    if !check_digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err("is not a valid IBAN".to_string());
+    }
+    match iban_length(country) {
+        None => return Err(format!("{} does not use IBANs", country)),
+        Some(length) if iban.len() != length => {
+            return Err(format!(
+                "must be {} characters for an IBAN from {}",
+                length, country
+            ))
+        }
+        Some(_) => {}
+    }
+
+    // Move the first 4 characters to the end, turn letters into 10..=35, and take the
+    // result modulo 97 one digit at a time.
+    let remainder = iban[4..].chars().chain(iban[..4].chars()).fold(0, |r, c| {
+        let value = c.to_digit(36).unwrap_or_default();
+        if value < 10 {
+            (r * 10 + value) % 97
+        } else {
+            (r * 100 + value) % 97
+        }
+    });
+    if remainder != 1 {
+        return Err("has invalid check digits".to_string());
+    }
+
+    Ok(())
+}
+
+/// Checks the structure of a BIC (SWIFT code).
+pub fn validate_bic(bic: &str) -> Result<(), String> {
+    let bic = compact(bic);
+    if !bic.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("may only contain letters and digits".to_string());
+    }
+    if bic.len() != 8 && bic.len() != 11 {
+        return Err("must be 8 or 11 characters".to_string());
+    }
+    if !bic[..4].chars().all(|c| c.is_ascii_uppercase()) {
+        return Err("must start with a 4 letter bank code".to_string());
+    }
+    if Country::from_str(&bic[4..6]).is_err() {
+        return Err(format!("has an unknown country code '{}'", &bic[4..6]));
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Errors(Vec<BankDetailsError>);
+
+impl Errors {
+    fn push<S: ToString>(&mut self, field: &'static str, message: S) {
+        self.0.push(BankDetailsError {
+            field,
+            message: message.to_string(),
+        });
+    }
+
+    /// Requires a value of `min` to `max` digits.
+    fn digits(&mut self, field: &'static str, value: Option<&str>, min: usize, max: usize) {
+        let length = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        match value {
+            None => self.push(field, "is required"),
+            Some(value)
+                if value.len() < min
+                    || value.len() > max
+                    || !value.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                self.push(field, format!("must be {} digits", length))
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Uppercases the value and drops the spaces and dashes people use to group digits.
+pub fn compact(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase()
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.filter(|value| !value.trim().is_empty())
+}
+
+fn is_ifsc(value: &str) -> bool {
+    value.is_ascii()
+        && value.len() == 11
+        && value[..4].chars().all(|c| c.is_ascii_uppercase())
+        && &value[4..5] == "0"
+        && value[5..].chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn is_sepa_country(country: Country) -> bool {
+    matches!(
+        country,
+        Country::AT
+            | Country::BE
+            | Country::BG
+            | Country::HR
+            | Country::CY
+            | Country::CZ
+            | Country::DK
+            | Country::EE
+            | Country::FI
+            | Country::FR
+            | Country::DE
+            | Country::GR
+            | Country::HU
+            | Country::IE
+            | Country::IT
+            | Country::LV
+            | Country::LT
+            | Country::LU
+            | Country::MT
+            | Country::NL
+            | Country::PL
+            | Country::PT
+            | Country::RO
+            | Country::SK
+            | Country::SI
+            | Country::ES
+            | Country::SE
+    )
+}
+
+/// Length of the IBANs of a country, from the SWIFT IBAN registry.
+fn iban_length(country: &str) -> Option<usize> {
+    Some(match country {
+        "NO" => 15,
+        "BE" => 16,
+        "DK" | "FI" | "FO" | "GL" | "NL" => 18,
+        "MK" | "SI" => 19,
+        "AT" | "BA" | "EE" | "KZ" | "LT" | "LU" | "XK" => 20,
+        "CH" | "HR" | "LI" | "LV" => 21,
+        "BG" | "BH" | "CR" | "DE" | "GB" | "GE" | "IE" | "ME" | "RS" | "VA" => 22,
+        "AE" | "GI" | "IL" | "IQ" | "TL" => 23,
+        "AD" | "CZ" | "ES" | "MD" | "PK" | "RO" | "SA" | "SE" | "SK" | "TN" | "VG" => 24,
+        "PT" | "ST" => 25,
+        "IS" | "TR" => 26,
+        "FR" | "GR" | "IT" | "MC" | "MR" | "SM" => 27,
+        "AL" | "AZ" | "BY" | "CY" | "DO" | "GT" | "HU" | "LB" | "PL" | "SV" => 28,
+        "BR" | "EG" | "PS" | "QA" | "UA" => 29,
+        "JO" | "KW" | "MU" => 30,
+        "MT" | "SC" => 31,
+        "LC" => 32,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(details: BankDetails) -> Vec<(&'static str, String)> {
+        details
+            .errors()
+            .into_iter()
+            .map(|e| (e.field, e.message))
+            .collect()
+    }
+
+    fn error(field: &'static str, message: &str) -> (&'static str, String) {
+        (field, message.to_string())
+    }
+
+    #[test]
+    fn valid_ibans_pass_the_mod_97_check() {
+        for iban in [
+            "GB82 WEST 1234 5698 7654 32",
+            "DE89370400440532013000",
+            "nl91abna0417164300",
+            "FR14 2004 1010 0505 0001 3M02 606",
+            "BE68-5390-0754-7034",
+        ] {
+            assert_eq!(validate_iban(iban), Ok(()), "{}", iban);
+        }
+    }
+
+    #[test]
+    fn invalid_ibans_are_rejected() {
+        assert_eq!(
+            validate_iban("GB82WEST12345698765433"),
+            Err("has invalid check digits".to_string())
+        );
+        assert_eq!(
+            validate_iban("DE00370400440532013000"),
+            Err("has invalid check digits".to_string())
+        );
+        assert_eq!(
+            validate_iban("GB82WEST123456987654"),
+            Err("must be 22 characters for an IBAN from GB".to_string())
+        );
+        assert_eq!(
+            validate_iban("US12345678901"),
+            Err("US does not use IBANs".to_string())
+        );
+        for iban in ["GB82", "GBXXWEST12345698765432", "GB82WEST1234569876543!"] {
+            assert_eq!(
+                validate_iban(iban),
+                Err("is not a valid IBAN".to_string()),
+                "{}",
+                iban
+            );
+        }
+    }
+
+    #[test]
+    fn bics_are_checked_for_structure() {
+        assert_eq!(validate_bic("DEUTDEFF"), Ok(()));
+        assert_eq!(validate_bic("deutdeff500"), Ok(()));
+        assert_eq!(
+            validate_bic("DEUTDEF"),
+            Err("must be 8 or 11 characters".to_string())
+        );
+        assert_eq!(
+            validate_bic("DEUT.DEFF"),
+            Err("may only contain letters and digits".to_string())
+        );
+        assert_eq!(
+            validate_bic("DEU1DEFF"),
+            Err("must start with a 4 letter bank code".to_string())
+        );
+        assert_eq!(
+            validate_bic("DEUTXXFF"),
+            Err("has an unknown country code 'XX'".to_string())
+        );
+    }
+
+    #[test]
+    fn singapore_accounts_need_bank_and_branch_codes() {
+        let details = BankDetails {
+            country: Some(Country::SG),
+            bank_account_number: "0123-456-789",
+            bank_code: Some("7171"),
+            branch_code: Some("001"),
+            ..Default::default()
+        };
+        assert!(errors(details).is_empty());
+
+        assert_eq!(
+            errors(BankDetails {
+                bank_code: Some("71"),
+                branch_code: None,
+                ..details
+            }),
+            [
+                error("bank-code", "must be 4 digits"),
+                error("branch-code", "is required"),
+            ]
+        );
+    }
+
+    #[test]
+    fn indian_accounts_need_an_ifsc() {
+        let details = BankDetails {
+            country: Some(Country::IN),
+            bank_account_number: "123456789",
+            bank_code: Some("sbin0001234"),
+            ..Default::default()
+        };
+        assert!(errors(details).is_empty());
+
+        assert_eq!(
+            errors(BankDetails {
+                bank_code: Some("SBIN1001234"),
+                ..details
+            }),
+            [error(
+                "bank-code",
+                "must be an 11 character IFSC, e.g. SBIN0001234"
+            )]
+        );
+        assert_eq!(
+            errors(BankDetails {
+                bank_code: None,
+                ..details
+            }),
+            [error("bank-code", "is required")]
+        );
+    }
+
+    #[test]
+    fn british_accounts_need_a_sort_code_unless_given_as_an_iban() {
+        let details = BankDetails {
+            country: Some(Country::GB),
+            bank_account_number: "12345678",
+            bank_code: Some("40-47-84"),
+            ..Default::default()
+        };
+        assert!(errors(details).is_empty());
+
+        assert_eq!(
+            errors(BankDetails {
+                bank_account_number: "1234567",
+                ..details
+            }),
+            [error("bank-account-number", "must be 8 digits")]
+        );
+        assert!(errors(BankDetails {
+            bank_account_number: "GB82WEST12345698765432",
+            bank_code: None,
+            iban: Some("GB82WEST12345698765432"),
+            ..details
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn sepa_accounts_need_an_iban_from_their_country() {
+        let details = BankDetails {
+            country: Some(Country::DE),
+            bank_account_number: "DE89 3704 0044 0532 0130 00",
+            ..Default::default()
+        };
+        assert!(errors(details).is_empty());
+
+        assert_eq!(
+            errors(BankDetails {
+                iban: Some("GB82WEST12345698765432"),
+                ..details
+            }),
+            [error("iban", "must be an IBAN from DE")]
+        );
+        // An invalid IBAN is only reported once.
+        assert_eq!(
+            errors(BankDetails {
+                iban: Some("DE00370400440532013000"),
+                ..details
+            }),
+            [error("iban", "has invalid check digits")]
+        );
+    }
+
+    #[test]
+    fn other_countries_only_get_the_generic_checks() {
+        assert_eq!(
+            errors(BankDetails {
+                bank_account_number: "",
+                ..Default::default()
+            }),
+            [error("bank-account-number", "is required")]
+        );
+        assert_eq!(
+            errors(BankDetails {
+                bank_account_number: &"1".repeat(35),
+                ..Default::default()
+            }),
+            [error(
+                "bank-account-number",
+                "must be at most 34 characters"
+            )]
+        );
+        assert_eq!(
+            errors(BankDetails {
+                country: Some(Country::JP),
+                bank_account_number: "12#34",
+                swift_code: Some("DEUT"),
+                ..Default::default()
+            }),
+            [
+                error(
+                    "bank-account-number",
+                    "may only contain letters, digits, spaces and dashes"
+                ),
+                error("swift-code", "must be 8 or 11 characters"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validating_joins_every_problem() {
+        let details = BankDetails {
+            country: Some(Country::SG),
+            bank_account_number: "0123456789",
+            bank_code: Some("71"),
+            branch_code: Some("1"),
+            ..Default::default()
+        };
+
+        let error = details.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "bank-code: must be 4 digits; branch-code: must be 3 digits"
+        );
+    }
+}
@@ -47,6 +47,45 @@ impl From<Option<sqlx::types::time::OffsetDateTime>> for OptionOffsetDateWrapper
     }
 }
 
+/// A calendar date written as `YYYY-MM-DD`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DateWrapper(pub String);
+
+impl TryFrom<DateWrapper> for sqlx::types::time::Date {
+    type Error = GlobeliseError;
+
+    fn try_from(date: DateWrapper) -> Result<Self, Self::Error> {
+        sqlx::types::time::Date::parse(date.0, "%Y-%m-%d").map_err(GlobeliseError::bad_request)
+    }
+}
+
+impl From<sqlx::types::time::Date> for DateWrapper {
+    fn from(date: sqlx::types::time::Date) -> Self {
+        Self(date.format("%Y-%m-%d"))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OptionDateWrapper(pub Option<String>);
+
+impl TryFrom<OptionDateWrapper> for Option<sqlx::types::time::Date> {
+    type Error = GlobeliseError;
+
+    fn try_from(date: OptionDateWrapper) -> Result<Self, Self::Error> {
+        date.0
+            .map(|v| {
+                sqlx::types::time::Date::parse(v, "%Y-%m-%d").map_err(GlobeliseError::bad_request)
+            })
+            .transpose()
+    }
+}
+
+impl From<Option<sqlx::types::time::Date>> for OptionDateWrapper {
+    fn from(date: Option<sqlx::types::time::Date>) -> Self {
+        Self(date.map(|d| d.format("%Y-%m-%d")))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EmailWrapper(pub EmailAddress);
 
@@ -58,7 +97,7 @@ impl AsRef<EmailWrapper> for EmailWrapper {
 
 impl Clone for EmailWrapper {
     fn clone(&self) -> EmailWrapper {
-        self.to_owned()
+        EmailWrapper(self.0.clone())
     }
 }
 
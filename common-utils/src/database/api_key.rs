@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    api_key::{ApiKeyScope, API_KEY_LAST_USED_PRECISION_SECONDS},
+    calc_limit_and_offset,
+    custom_serde::{EmailWrapper, OffsetDateWrapper, OptionOffsetDateWrapper, UserType},
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+use super::Database;
+
+#[serde_as]
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApiKey {
+    pub ulid: Uuid,
+    pub user_ulid: Uuid,
+    pub email: EmailWrapper,
+    pub user_type: UserType,
+    pub name: String,
+    /// The start of the key, so that clients can tell their keys apart.
+    pub hint: String,
+    pub scopes: Vec<String>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub expires_at: sqlx::types::time::OffsetDateTime,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub last_used_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub revoked_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| ApiKeyScope::from_str(s).map_or(false, |s| s == scope))
+    }
+}
+
+impl Database {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_one_api_key(
+        &self,
+        user_ulid: Uuid,
+        user_type: UserType,
+        name: &str,
+        hint: &str,
+        key_hash: &str,
+        scopes: &[ApiKeyScope],
+        expires_at: sqlx::types::time::OffsetDateTime,
+    ) -> GlobeliseResult<Uuid> {
+        let ulid = Uuid::new_v4();
+
+        sqlx::query(
+            "
+        INSERT INTO api_keys (
+            ulid, user_ulid, user_type, name, hint, key_hash, scopes, expires_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8
+        )",
+        )
+        .bind(ulid)
+        .bind(user_ulid)
+        .bind(user_type)
+        .bind(name)
+        .bind(hint)
+        .bind(key_hash)
+        .bind(scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+        .bind(expires_at)
+        .execute(&self.0)
+        .await?;
+
+        Ok(ulid)
+    }
+
+    /// Gets the API key with the given hash, if it has not expired or been revoked.
+    pub async fn select_one_active_api_key_by_hash(
+        &self,
+        key_hash: &str,
+    ) -> GlobeliseResult<Option<ApiKey>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            api_keys.ulid, api_keys.user_ulid, users.email, api_keys.user_type,
+            api_keys.name, api_keys.hint, api_keys.scopes, api_keys.expires_at,
+            api_keys.last_used_at, api_keys.revoked_at, api_keys.created_at
+        FROM
+            api_keys
+        JOIN
+            users ON users.ulid = api_keys.user_ulid
+        WHERE
+            api_keys.key_hash = $1 AND
+            api_keys.revoked_at IS NULL AND
+            api_keys.expires_at > CURRENT_TIMESTAMP",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_many_api_keys(
+        &self,
+        user_ulid: Option<Uuid>,
+        per_page: Option<u32>,
+        page: Option<u32>,
+    ) -> GlobeliseResult<Vec<ApiKey>> {
+        let (limit, offset) = calc_limit_and_offset(per_page, page);
+
+        let result = sqlx::query_as(
+            "
+        SELECT
+            api_keys.ulid, api_keys.user_ulid, users.email, api_keys.user_type,
+            api_keys.name, api_keys.hint, api_keys.scopes, api_keys.expires_at,
+            api_keys.last_used_at, api_keys.revoked_at, api_keys.created_at
+        FROM
+            api_keys
+        JOIN
+            users ON users.ulid = api_keys.user_ulid
+        WHERE
+            ($1 IS NULL OR api_keys.user_ulid = $1)
+        ORDER BY
+            api_keys.created_at DESC
+        LIMIT
+            $2
+        OFFSET
+            $3",
+        )
+        .bind(user_ulid)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Records that an API key was just used, unless that was already recorded less than
+    /// [`API_KEY_LAST_USED_PRECISION_SECONDS`] ago.
+    pub async fn update_one_api_key_last_used(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        UPDATE
+            api_keys
+        SET
+            last_used_at = CURRENT_TIMESTAMP
+        WHERE
+            ulid = $1 AND
+            (
+                last_used_at IS NULL OR
+                last_used_at < CURRENT_TIMESTAMP - $2 * INTERVAL '1 second'
+            )",
+        )
+        .bind(ulid)
+        .bind(API_KEY_LAST_USED_PRECISION_SECONDS as f64)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Revokes an API key.
+    ///
+    /// When `user_ulid` is given, only keys belonging to that user can be revoked.
+    pub async fn update_one_api_key_revoked(
+        &self,
+        ulid: Uuid,
+        user_ulid: Option<Uuid>,
+    ) -> GlobeliseResult<()> {
+        let result = sqlx::query(
+            "
+        UPDATE
+            api_keys
+        SET
+            revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP)
+        WHERE
+            ulid = $1 AND
+            ($2 IS NULL OR user_ulid = $2)",
+        )
+        .bind(ulid)
+        .bind(user_ulid)
+        .execute(&self.0)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(GlobeliseError::not_found(
+                "Cannot find API key with that ulid",
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -20,7 +20,7 @@ pub struct ContractsIndex {
     contract_name: String,
     contract_type: String,
     contract_status: String,
-    contract_amount: f64,
+    contract_amount: sqlx::types::Decimal,
     currency: String,
     job_title: String,
     seniority: String,
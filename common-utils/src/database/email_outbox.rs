@@ -0,0 +1,144 @@
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{custom_serde::EmailWrapper, error::GlobeliseResult, mail::RenderedEmail};
+
+use super::Database;
+
+/// An email taken off the outbox to be sent.
+#[derive(Debug, FromRow)]
+pub struct ClaimedOutboxEmail {
+    pub ulid: Uuid,
+    pub to_address: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    /// Starts at 1.
+    pub attempts: i32,
+}
+
+impl Database {
+    /// Queues a rendered email to be sent by the outbox sender.
+    pub async fn insert_one_outbox_email(
+        &self,
+        to: &EmailWrapper,
+        email: &RenderedEmail,
+    ) -> GlobeliseResult<Uuid> {
+        let ulid = Uuid::new_v4();
+
+        sqlx::query(
+            "
+            INSERT INTO email_outbox (
+                ulid, template, locale, to_address, subject, html_body, text_body
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7
+            )",
+        )
+        .bind(ulid)
+        .bind(email.template.as_str())
+        .bind(email.locale)
+        .bind(to.0.to_string())
+        .bind(&email.subject)
+        .bind(&email.html_body)
+        .bind(&email.text_body)
+        .execute(&self.0)
+        .await?;
+
+        Ok(ulid)
+    }
+
+    /// Takes the next email that is due, or one whose sender stopped while sending it.
+    pub async fn claim_next_outbox_email(
+        &self,
+        stale_after_seconds: i64,
+    ) -> GlobeliseResult<Option<ClaimedOutboxEmail>> {
+        let result = sqlx::query_as(
+            "
+            UPDATE
+                email_outbox
+            SET
+                status = 'sending',
+                attempts = attempts + 1,
+                claimed_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = (
+                    SELECT
+                        ulid
+                    FROM
+                        email_outbox
+                    WHERE
+                        (status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP) OR
+                        (
+                            status = 'sending' AND
+                            claimed_at < CURRENT_TIMESTAMP - $1 * INTERVAL '1 second'
+                        )
+                    ORDER BY
+                        next_attempt_at
+                    LIMIT
+                        1
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING
+                ulid, to_address, subject, html_body, text_body, attempts",
+        )
+        .bind(stale_after_seconds as f64)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Marks an email as sent, dropping its bodies, which can hold links and passwords.
+    pub async fn update_outbox_email_as_sent(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                email_outbox
+            SET
+                status = 'sent',
+                last_error = NULL,
+                sent_at = CURRENT_TIMESTAMP,
+                html_body = NULL,
+                text_body = NULL
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records why an email could not be sent, and when to try again if it will be.
+    ///
+    /// The bodies of an email that will not be tried again are dropped, like the ones of a
+    /// sent email.
+    pub async fn update_outbox_email_as_failed(
+        &self,
+        ulid: Uuid,
+        error: &str,
+        retry_in_seconds: Option<i64>,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                email_outbox
+            SET
+                status = CASE WHEN $3 IS NULL THEN 'failed' ELSE 'pending' END,
+                last_error = $2,
+                next_attempt_at = CURRENT_TIMESTAMP + COALESCE($3, 0) * INTERVAL '1 second',
+                html_body = CASE WHEN $3 IS NULL THEN NULL ELSE html_body END,
+                text_body = CASE WHEN $3 IS NULL THEN NULL ELSE text_body END
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .bind(error)
+        .bind(retry_in_seconds.map(|seconds| seconds as f64))
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{
+    types::{time::Date, Decimal},
+    FromRow,
+};
+use uuid::Uuid;
+
+use crate::{
+    calc_limit_and_offset,
+    custom_serde::{Currency, DateWrapper, OffsetDateWrapper},
+    error::{GlobeliseError, GlobeliseResult},
+    fx::FxRates,
+};
+
+use super::Database;
+
+#[serde_as]
+#[derive(Debug, FromRow, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FxRate {
+    pub ulid: Uuid,
+    pub base_currency: Currency,
+    pub quote_currency: Currency,
+    /// Units of the quote currency one unit of the base currency buys.
+    pub rate: Decimal,
+    #[serde_as(as = "TryFromInto<DateWrapper>")]
+    pub effective_date: Date,
+    /// manual or import
+    pub source: String,
+    pub created_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+impl Database {
+    /// Sets the rate of a pair from a date on, replacing the rate of that date if any.
+    pub async fn upsert_one_fx_rate(
+        &self,
+        base_currency: Currency,
+        quote_currency: Currency,
+        rate: Decimal,
+        effective_date: Date,
+        source: &str,
+        created_by: Uuid,
+    ) -> GlobeliseResult<Uuid> {
+        if base_currency.as_str() == quote_currency.as_str() {
+            return Err(GlobeliseError::bad_request(
+                "The base and quote currencies must differ",
+            ));
+        }
+        if rate <= Decimal::from(0) {
+            return Err(GlobeliseError::bad_request("FX rates must be positive"));
+        }
+
+        let ulid = sqlx::query_scalar(
+            "
+        INSERT INTO fx_rates (
+            ulid, base_currency, quote_currency, rate, effective_date, source, created_by
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7
+        ) ON CONFLICT (base_currency, quote_currency, effective_date) DO UPDATE SET
+            rate = $4,
+            source = $6,
+            created_by = $7,
+            created_at = now()
+        RETURNING
+            ulid",
+        )
+        .bind(Uuid::new_v4())
+        .bind(base_currency)
+        .bind(quote_currency)
+        .bind(rate)
+        .bind(effective_date)
+        .bind(source)
+        .bind(created_by)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(ulid)
+    }
+
+    pub async fn select_many_fx_rates(
+        &self,
+        base_currency: Option<Currency>,
+        quote_currency: Option<Currency>,
+        page: Option<u32>,
+        per_page: Option<u32>,
+    ) -> GlobeliseResult<Vec<FxRate>> {
+        let (limit, offset) = calc_limit_and_offset(per_page, page);
+
+        let result = sqlx::query_as(
+            "
+        SELECT
+            *
+        FROM
+            fx_rates
+        WHERE
+            ($1 IS NULL OR base_currency = $1) AND
+            ($2 IS NULL OR quote_currency = $2)
+        ORDER BY
+            effective_date DESC, base_currency, quote_currency
+        LIMIT $3 OFFSET $4",
+        )
+        .bind(base_currency)
+        .bind(quote_currency)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn delete_one_fx_rate(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        let deleted = sqlx::query("DELETE FROM fx_rates WHERE ulid = $1")
+            .bind(ulid)
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(GlobeliseError::not_found("Cannot find the FX rate"));
+        }
+
+        Ok(())
+    }
+
+    /// The rates in force on a date: the latest of every pair on or before it.
+    pub async fn select_fx_rates_as_of(&self, as_of: Date) -> GlobeliseResult<FxRates> {
+        let rates = sqlx::query_as(
+            "
+        SELECT DISTINCT ON (base_currency, quote_currency)
+            *
+        FROM
+            fx_rates
+        WHERE
+            effective_date <= $1
+        ORDER BY
+            base_currency, quote_currency, effective_date DESC",
+        )
+        .bind(as_of)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(FxRates::new(as_of, rates))
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    calc_limit_and_offset, custom_serde::OffsetDateWrapper, error::GlobeliseResult,
+    token::Impersonation,
+};
+
+use super::Database;
+
+#[serde_as]
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImpersonationLog {
+    pub ulid: Uuid,
+    pub admin_ulid: Uuid,
+    pub user_ulid: Uuid,
+    pub read_only: bool,
+    pub method: String,
+    pub path: String,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+impl Database {
+    /// Records a request made by an admin while acting as a user.
+    pub async fn insert_one_impersonation_log(
+        &self,
+        impersonation: &Impersonation,
+        method: &str,
+        path: &str,
+    ) -> GlobeliseResult<Uuid> {
+        let ulid = Uuid::new_v4();
+
+        sqlx::query(
+            "
+        INSERT INTO impersonation_logs (
+            ulid, admin_ulid, user_ulid, read_only, method, path
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6
+        )",
+        )
+        .bind(ulid)
+        .bind(impersonation.admin_ulid)
+        .bind(impersonation.user_ulid)
+        .bind(impersonation.read_only)
+        .bind(method)
+        .bind(path)
+        .execute(&self.0)
+        .await?;
+
+        Ok(ulid)
+    }
+
+    pub async fn select_many_impersonation_logs(
+        &self,
+        admin_ulid: Option<Uuid>,
+        user_ulid: Option<Uuid>,
+        per_page: Option<u32>,
+        page: Option<u32>,
+    ) -> GlobeliseResult<Vec<ImpersonationLog>> {
+        let (limit, offset) = calc_limit_and_offset(per_page, page);
+
+        let result = sqlx::query_as(
+            "
+        SELECT
+            *
+        FROM
+            impersonation_logs
+        WHERE
+            ($1 IS NULL OR admin_ulid = $1) AND
+            ($2 IS NULL OR user_ulid = $2)
+        ORDER BY
+            created_at DESC
+        LIMIT
+            $3
+        OFFSET
+            $4",
+        )
+        .bind(admin_ulid)
+        .bind(user_ulid)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
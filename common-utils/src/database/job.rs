@@ -0,0 +1,467 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{
+    postgres::{PgTypeInfo, PgValueRef},
+    FromRow,
+};
+use strum::{Display, EnumString};
+use uuid::Uuid;
+
+use crate::{
+    calc_limit_and_offset,
+    custom_serde::{OffsetDateWrapper, OptionOffsetDateWrapper},
+    error::GlobeliseResult,
+};
+
+use super::Database;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for JobStatus {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("text")
+    }
+}
+
+impl<'r> sqlx::Decode<'r, sqlx::Postgres> for JobStatus {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        let value: &'r str = sqlx::decode::Decode::decode(value)?;
+        Ok(JobStatus::from_str(value)?)
+    }
+}
+
+impl sqlx::encode::Encode<'_, sqlx::Postgres> for JobStatus {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::encode(val, buf)
+    }
+    fn size_hint(&self) -> std::primitive::usize {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::size_hint(&val)
+    }
+}
+
+/// A job as shown to whoever polls it, without its payload and result.
+#[serde_as]
+#[derive(Debug, FromRow, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Job {
+    pub ulid: Uuid,
+    pub kind: String,
+    pub status: JobStatus,
+    pub progress_done: i32,
+    pub progress_total: Option<i32>,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub cancel_requested: bool,
+    /// Set once the job has a result to download.
+    pub result_content_type: Option<String>,
+    pub created_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub started_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub finished_at: Option<sqlx::types::time::OffsetDateTime>,
+}
+
+/// A job taken off the queue by a worker.
+#[derive(Debug, FromRow)]
+pub struct ClaimedJob {
+    pub ulid: Uuid,
+    pub kind: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+impl Database {
+    /// Queues a job.
+    ///
+    /// If a job with the same unique key is still queued or running, that job is returned
+    /// instead of queueing another one.
+    pub async fn insert_one_job(
+        &self,
+        kind: &str,
+        unique_key: Option<&str>,
+        payload: &str,
+        max_attempts: i32,
+        created_by: Option<Uuid>,
+    ) -> GlobeliseResult<Uuid> {
+        let inserted: Option<(Uuid,)> = sqlx::query_as(
+            "
+            INSERT INTO jobs (
+                ulid, kind, unique_key, payload, max_attempts, created_by
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6
+            ) ON CONFLICT (unique_key) WHERE status IN ('queued', 'running') DO NOTHING
+            RETURNING
+                ulid",
+        )
+        .bind(Uuid::new_v4())
+        .bind(kind)
+        .bind(unique_key)
+        .bind(payload)
+        .bind(max_attempts)
+        .bind(created_by)
+        .fetch_optional(&self.0)
+        .await?;
+        if let Some((ulid,)) = inserted {
+            return Ok(ulid);
+        }
+
+        // The conflict can only have happened on the unique key.
+        let (ulid,) = sqlx::query_as(
+            "
+            SELECT
+                ulid
+            FROM
+                jobs
+            WHERE
+                unique_key = $1 AND
+                status IN ('queued', 'running')",
+        )
+        .bind(unique_key)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(ulid)
+    }
+
+    pub async fn select_one_job(&self, ulid: Uuid) -> GlobeliseResult<Option<Job>> {
+        let result = sqlx::query_as(
+            "
+            SELECT
+                ulid, kind, status, progress_done, progress_total, attempts, max_attempts,
+                last_error, cancel_requested, result_content_type, created_by, created_at,
+                started_at, finished_at
+            FROM
+                jobs
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_many_jobs(
+        &self,
+        page: Option<u32>,
+        per_page: Option<u32>,
+        kind: Option<&str>,
+        status: Option<JobStatus>,
+    ) -> GlobeliseResult<Vec<Job>> {
+        let (limit, offset) = calc_limit_and_offset(per_page, page);
+
+        let result = sqlx::query_as(
+            "
+            SELECT
+                ulid, kind, status, progress_done, progress_total, attempts, max_attempts,
+                last_error, cancel_requested, result_content_type, created_by, created_at,
+                started_at, finished_at
+            FROM
+                jobs
+            WHERE
+                ($1 IS NULL OR kind = $1) AND
+                ($2 IS NULL OR status = $2)
+            ORDER BY
+                created_at DESC
+            LIMIT
+                $3
+            OFFSET
+                $4",
+        )
+        .bind(kind)
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// The content type and bytes of the result of a job, if it has one.
+    pub async fn select_one_job_result(
+        &self,
+        ulid: Uuid,
+    ) -> GlobeliseResult<Option<(String, Vec<u8>)>> {
+        let result = sqlx::query_as(
+            "
+            SELECT
+                result_content_type, result
+            FROM
+                jobs
+            WHERE
+                ulid = $1 AND
+                result IS NOT NULL",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Takes the oldest job of one of the kinds that is due.
+    ///
+    /// Running jobs whose worker stopped sending heartbeats, because the service was
+    /// restarted in the middle of them for example, are cancelled if their cancellation was
+    /// requested, taken again if they have attempts left, and failed otherwise.
+    pub async fn claim_next_job(
+        &self,
+        kinds: &[&str],
+        stale_after_seconds: i64,
+    ) -> GlobeliseResult<Option<ClaimedJob>> {
+        sqlx::query(
+            "
+            UPDATE
+                jobs
+            SET
+                status = CASE WHEN cancel_requested THEN 'cancelled' ELSE 'failed' END,
+                last_error = CASE
+                    WHEN cancel_requested THEN last_error
+                    ELSE COALESCE(last_error, 'The worker running the job stopped')
+                END,
+                payload = NULL,
+                finished_at = CURRENT_TIMESTAMP
+            WHERE
+                kind = ANY($1) AND
+                status = 'running' AND
+                (cancel_requested OR attempts >= max_attempts) AND
+                heartbeat_at < CURRENT_TIMESTAMP - $2 * INTERVAL '1 second'",
+        )
+        .bind(kinds.to_vec())
+        .bind(stale_after_seconds as f64)
+        .execute(&self.0)
+        .await?;
+
+        let result = sqlx::query_as(
+            "
+            UPDATE
+                jobs
+            SET
+                status = 'running',
+                attempts = attempts + 1,
+                started_at = CURRENT_TIMESTAMP,
+                heartbeat_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = (
+                    SELECT
+                        ulid
+                    FROM
+                        jobs
+                    WHERE
+                        kind = ANY($1) AND
+                        NOT cancel_requested AND
+                        (
+                            (status = 'queued' AND run_after <= CURRENT_TIMESTAMP) OR
+                            (
+                                status = 'running' AND
+                                attempts < max_attempts AND
+                                heartbeat_at < CURRENT_TIMESTAMP - $2 * INTERVAL '1 second'
+                            )
+                        )
+                    ORDER BY
+                        run_after
+                    LIMIT
+                        1
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING
+                ulid, kind, payload, attempts, max_attempts",
+        )
+        .bind(kinds.to_vec())
+        .bind(stale_after_seconds as f64)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Keeps a running job from being taken by another worker.
+    ///
+    /// Returns whether its cancellation was requested.
+    pub async fn update_job_heartbeat(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let result: Option<(bool,)> = sqlx::query_as(
+            "
+            UPDATE
+                jobs
+            SET
+                heartbeat_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = $1 AND
+                status = 'running'
+            RETURNING
+                cancel_requested",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        // A job that is not running anymore has nothing left to do.
+        Ok(result.map_or(true, |(cancel_requested,)| cancel_requested))
+    }
+
+    /// Records how far a running job is, which also counts as a heartbeat.
+    ///
+    /// Returns whether its cancellation was requested.
+    pub async fn update_job_progress(
+        &self,
+        ulid: Uuid,
+        done: i32,
+        total: Option<i32>,
+    ) -> GlobeliseResult<bool> {
+        let result: Option<(bool,)> = sqlx::query_as(
+            "
+            UPDATE
+                jobs
+            SET
+                progress_done = $2,
+                progress_total = COALESCE($3, progress_total),
+                heartbeat_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = $1 AND
+                status = 'running'
+            RETURNING
+                cancel_requested",
+        )
+        .bind(ulid)
+        .bind(done)
+        .bind(total)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result.map_or(true, |(cancel_requested,)| cancel_requested))
+    }
+
+    pub async fn update_job_as_succeeded(
+        &self,
+        ulid: Uuid,
+        result: Option<(&str, &[u8])>,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                jobs
+            SET
+                status = 'succeeded',
+                payload = NULL,
+                last_error = NULL,
+                result_content_type = $2,
+                result = $3,
+                finished_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .bind(result.map(|(content_type, _)| content_type))
+        .bind(result.map(|(_, data)| data))
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt of a job, queueing it again after `retry_in_seconds` or
+    /// failing it for good if that is None.
+    pub async fn update_job_as_failed(
+        &self,
+        ulid: Uuid,
+        error: &str,
+        retry_in_seconds: Option<i64>,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                jobs
+            SET
+                status = CASE WHEN $3 IS NULL THEN 'failed' ELSE 'queued' END,
+                payload = CASE WHEN $3 IS NULL THEN NULL ELSE payload END,
+                last_error = $2,
+                run_after = CURRENT_TIMESTAMP + COALESCE($3, 0) * INTERVAL '1 second',
+                finished_at = CASE WHEN $3 IS NULL THEN CURRENT_TIMESTAMP END
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .bind(error)
+        .bind(retry_in_seconds.map(|seconds| seconds as f64))
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_job_as_cancelled(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                jobs
+            SET
+                status = 'cancelled',
+                payload = NULL,
+                finished_at = CURRENT_TIMESTAMP
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Requests the cancellation of a job.
+    ///
+    /// A queued job is cancelled right away, while a running one stops the next time it
+    /// checks. Returns false if the job has already finished.
+    pub async fn update_job_cancel_requested(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let updated = sqlx::query(
+            "
+            UPDATE
+                jobs
+            SET
+                cancel_requested = true,
+                status = CASE WHEN status = 'queued' THEN 'cancelled' ELSE status END,
+                payload = CASE WHEN status = 'queued' THEN NULL ELSE payload END,
+                finished_at = CASE WHEN status = 'queued' THEN CURRENT_TIMESTAMP END
+            WHERE
+                ulid = $1 AND
+                status IN ('queued', 'running')",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+
+        Ok(updated > 0)
+    }
+}
@@ -3,10 +3,16 @@ use std::{sync::Arc, time::Duration};
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 use tokio::sync::Mutex;
 
+pub mod api_key;
 pub mod client_contractor_pair;
 pub mod contract;
+pub mod email_outbox;
+pub mod fx_rate;
+pub mod impersonation;
+pub mod job;
 pub mod notification;
 pub mod onboard;
+pub mod signing_key;
 pub mod user;
 
 /// Convenience wrapper around PostgreSQL.
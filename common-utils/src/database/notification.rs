@@ -99,6 +99,27 @@ impl Database {
         Ok(())
     }
 
+    pub async fn create_one_admin_see_notification_for_all_admins(
+        &self,
+        notification_ulid: Uuid,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        INSERT INTO admin_see_notification (
+            admin_ulid, notification_ulid
+        ) SELECT
+            ulid AS admin_ulid, $1 AS notification_ulid
+        FROM
+            admin_users
+        ON CONFLICT (admin_ulid, notification_ulid) DO NOTHING",
+        )
+        .bind(notification_ulid)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn update_one_notification_as_read(
         &self,
         user_ulid: Uuid,
@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
 use uuid::Uuid;
 
-use crate::{custom_serde::UserType, database::Database, error::GlobeliseResult};
+use crate::{
+    bank_details::BankDetails,
+    custom_serde::{Country, UserType},
+    database::Database,
+    error::GlobeliseResult,
+};
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -14,6 +19,18 @@ pub struct ContractorUserDetails {
     pub branch_code: String,
 }
 
+impl ContractorUserDetails {
+    pub fn bank_details(&self, country: Option<Country>) -> BankDetails<'_> {
+        BankDetails {
+            country,
+            bank_account_number: &self.bank_account_number,
+            bank_code: Some(&self.bank_code),
+            branch_code: Some(&self.branch_code),
+            ..Default::default()
+        }
+    }
+}
+
 impl Database {
     pub async fn insert_one_onboard_user_bank_details(
         &self,
@@ -21,33 +38,7 @@ impl Database {
         user_type: UserType,
         details: &ContractorUserDetails,
     ) -> GlobeliseResult<()> {
-        let table = match user_type {
-            UserType::Individual => "individual_contractor_bank_details",
-            UserType::Entity => "entity_contractor_bank_details",
-        };
-
-        sqlx::query(&format!(
-            "
-        INSERT INTO {table} (
-            ulid, bank_name, bank_account_name, bank_account_number, bank_code,
-            branch_code
-        ) VALUES (
-            $1, $2, $3, $4, $5,
-            $6
-        ) ON CONFLICT(ulid) DO UPDATE SET 
-            bank_name = $2, bank_account_name = $3, bank_account_number = $4, bank_code = $5,
-            branch_code = $6",
-        ))
-        .bind(ulid)
-        .bind(&details.bank_name)
-        .bind(&details.bank_account_name)
-        .bind(&details.bank_account_number)
-        .bind(&details.bank_code)
-        .bind(&details.branch_code)
-        .execute(&self.0)
-        .await?;
-
-        Ok(())
+        insert_one_onboard_user_bank_details(&self.0, ulid, user_type, details).await
     }
 
     pub async fn select_one_onboard_user_bank_detail(
@@ -75,4 +66,61 @@ impl Database {
 
         Ok(result)
     }
+
+    /// Country of an onboarded contractor, whose bank rules apply to their bank details.
+    pub async fn select_one_onboard_contractor_country(
+        &self,
+        ulid: Uuid,
+        user_type: UserType,
+    ) -> GlobeliseResult<Option<Country>> {
+        let country = match user_type {
+            UserType::Individual => self
+                .select_one_onboard_individual_contractor_account_details(ulid)
+                .await?
+                .map(|details| details.country),
+            UserType::Entity => self
+                .get_onboard_entity_contractor_account_details(ulid)
+                .await?
+                .map(|details| details.country),
+        };
+
+        Ok(country)
+    }
+}
+
+/// [`Database::insert_one_onboard_user_bank_details`] with any executor, such as a
+/// transaction.
+pub async fn insert_one_onboard_user_bank_details<'c>(
+    executor: impl PgExecutor<'c>,
+    ulid: Uuid,
+    user_type: UserType,
+    details: &ContractorUserDetails,
+) -> GlobeliseResult<()> {
+    let table = match user_type {
+        UserType::Individual => "individual_contractor_bank_details",
+        UserType::Entity => "entity_contractor_bank_details",
+    };
+
+    sqlx::query(&format!(
+        "
+    INSERT INTO {table} (
+        ulid, bank_name, bank_account_name, bank_account_number, bank_code,
+        branch_code
+    ) VALUES (
+        $1, $2, $3, $4, $5,
+        $6
+    ) ON CONFLICT(ulid) DO UPDATE SET 
+        bank_name = $2, bank_account_name = $3, bank_account_number = $4, bank_code = $5,
+        branch_code = $6",
+    ))
+    .bind(ulid)
+    .bind(&details.bank_name)
+    .bind(&details.bank_account_name)
+    .bind(&details.bank_account_number)
+    .bind(&details.bank_code)
+    .bind(&details.branch_code)
+    .execute(executor)
+    .await?;
+
+    Ok(())
 }
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
 use uuid::Uuid;
 
 use crate::{
@@ -117,36 +117,7 @@ impl Database {
         ulid: Uuid,
         details: &EntityContractorAccountDetails,
     ) -> GlobeliseResult<()> {
-        let query = "
-            INSERT INTO entity_contractor_account_details (
-                ulid, company_name, country, entity_type, registration_number, 
-                tax_id, company_address, city, postal_code, time_zone, 
-                logo, company_profile
-            ) VALUES (
-                $1, $2, $3, $4, $5, 
-                $6, $7, $8, $9, $10, 
-                $11, $12
-            ) ON CONFLICT(ulid) DO UPDATE SET 
-                company_name = $2, country = $3, entity_type = $4, registration_number = $5,
-                tax_id = $6, company_address = $7, city = $8, postal_code = $9, time_zone = $10,
-                logo = $11, company_profile = $12";
-        sqlx::query(query)
-            .bind(&ulid)
-            .bind(&details.company_name)
-            .bind(&details.country)
-            .bind(&details.entity_type)
-            .bind(&details.registration_number)
-            .bind(&details.tax_id)
-            .bind(&details.company_address)
-            .bind(&details.city)
-            .bind(&details.postal_code)
-            .bind(&details.time_zone)
-            .bind(&details.logo.as_ref().map(|b| b.to_owned()))
-            .bind(&details.company_profile.as_ref())
-            .execute(&self.0)
-            .await?;
-
-        Ok(())
+        insert_one_onboard_entity_contractor_account_details(&self.0, ulid, details).await
     }
 
     pub async fn get_onboard_entity_contractor_account_details(
@@ -168,3 +139,42 @@ impl Database {
         Ok(result)
     }
 }
+
+/// [`Database::insert_one_onboard_entity_contractor_account_details`] with any executor, such
+/// as a transaction.
+pub async fn insert_one_onboard_entity_contractor_account_details<'c>(
+    executor: impl PgExecutor<'c>,
+    ulid: Uuid,
+    details: &EntityContractorAccountDetails,
+) -> GlobeliseResult<()> {
+    let query = "
+        INSERT INTO entity_contractor_account_details (
+            ulid, company_name, country, entity_type, registration_number, 
+            tax_id, company_address, city, postal_code, time_zone, 
+            logo, company_profile
+        ) VALUES (
+            $1, $2, $3, $4, $5, 
+            $6, $7, $8, $9, $10, 
+            $11, $12
+        ) ON CONFLICT(ulid) DO UPDATE SET 
+            company_name = $2, country = $3, entity_type = $4, registration_number = $5,
+            tax_id = $6, company_address = $7, city = $8, postal_code = $9, time_zone = $10,
+            logo = $11, company_profile = $12";
+    sqlx::query(query)
+        .bind(&ulid)
+        .bind(&details.company_name)
+        .bind(&details.country)
+        .bind(&details.entity_type)
+        .bind(&details.registration_number)
+        .bind(&details.tax_id)
+        .bind(&details.company_address)
+        .bind(&details.city)
+        .bind(&details.postal_code)
+        .bind(&details.time_zone)
+        .bind(&details.logo.as_ref().map(|b| b.to_owned()))
+        .bind(&details.company_profile.as_ref())
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as, TryFromInto};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
 use uuid::Uuid;
 
 use crate::{
@@ -132,55 +132,7 @@ impl Database {
         ulid: Uuid,
         details: &IndividualContractorAccountDetails,
     ) -> GlobeliseResult<()> {
-        let query = "
-            INSERT INTO individual_contractor_account_details (
-                ulid, first_name, last_name, dob, dial_code, 
-                phone_number, country, city, address, postal_code, 
-                tax_id, time_zone, profile_picture, cv, gender,
-                marital_status, nationality, email_address, national_id, passport_number,
-                passport_expiry_date, work_permit, added_related_pay_item_id, total_dependants
-            ) VALUES (
-                $1, $2, $3, $4, $5, 
-                $6, $7, $8, $9, $10, 
-                $11, $12, $13, $14, $15,
-                $16, $17, $18, $19, $20,
-                $21, $22, $23, $24       
-            ) ON CONFLICT(ulid) DO UPDATE SET 
-                first_name = $2, last_name = $3, dob = $4, dial_code = $5, 
-                phone_number = $6, country = $7, city = $8, address = $9, postal_code = $10, 
-                tax_id = $11, time_zone = $12, profile_picture = $13, cv = $14, gender = $15,
-                marital_status = $16, nationality = $17, email_address = $18, national_id = $19, passport_number = $20,
-                passport_expiry_date = $21, work_permit = $22, added_related_pay_item_id = $23, total_dependants = $24";
-
-        sqlx::query(query)
-            .bind(ulid)
-            .bind(&details.first_name)
-            .bind(&details.last_name)
-            .bind(details.dob)
-            .bind(&details.dial_code)
-            .bind(&details.phone_number)
-            .bind(&details.country)
-            .bind(&details.city)
-            .bind(&details.address)
-            .bind(&details.postal_code)
-            .bind(&details.tax_id)
-            .bind(&details.time_zone)
-            .bind(details.profile_picture.as_ref().map(|b| b.to_owned()))
-            .bind(&details.cv)
-            .bind(&details.gender)
-            .bind(&details.marital_status)
-            .bind(&details.nationality)
-            .bind(&details.email_address)
-            .bind(&details.national_id)
-            .bind(&details.passport_number)
-            .bind(&details.passport_expiry_date)
-            .bind(&details.work_permit)
-            .bind(details.added_related_pay_item_id)
-            .bind(details.total_dependants)
-            .execute(&self.0)
-            .await?;
-
-        Ok(())
+        insert_one_onboard_individual_contractor_account_details(&self.0, ulid, details).await
     }
 
     pub async fn select_one_onboard_individual_contractor_account_details(
@@ -203,3 +155,61 @@ impl Database {
         Ok(result)
     }
 }
+
+/// [`Database::insert_one_onboard_individual_contractor_account_details`] with any executor,
+/// such as a transaction.
+pub async fn insert_one_onboard_individual_contractor_account_details<'c>(
+    executor: impl PgExecutor<'c>,
+    ulid: Uuid,
+    details: &IndividualContractorAccountDetails,
+) -> GlobeliseResult<()> {
+    let query = "
+        INSERT INTO individual_contractor_account_details (
+            ulid, first_name, last_name, dob, dial_code, 
+            phone_number, country, city, address, postal_code, 
+            tax_id, time_zone, profile_picture, cv, gender,
+            marital_status, nationality, email_address, national_id, passport_number,
+            passport_expiry_date, work_permit, added_related_pay_item_id, total_dependants
+        ) VALUES (
+            $1, $2, $3, $4, $5, 
+            $6, $7, $8, $9, $10, 
+            $11, $12, $13, $14, $15,
+            $16, $17, $18, $19, $20,
+            $21, $22, $23, $24       
+        ) ON CONFLICT(ulid) DO UPDATE SET 
+            first_name = $2, last_name = $3, dob = $4, dial_code = $5, 
+            phone_number = $6, country = $7, city = $8, address = $9, postal_code = $10, 
+            tax_id = $11, time_zone = $12, profile_picture = $13, cv = $14, gender = $15,
+            marital_status = $16, nationality = $17, email_address = $18, national_id = $19, passport_number = $20,
+            passport_expiry_date = $21, work_permit = $22, added_related_pay_item_id = $23, total_dependants = $24";
+
+    sqlx::query(query)
+        .bind(ulid)
+        .bind(&details.first_name)
+        .bind(&details.last_name)
+        .bind(details.dob)
+        .bind(&details.dial_code)
+        .bind(&details.phone_number)
+        .bind(&details.country)
+        .bind(&details.city)
+        .bind(&details.address)
+        .bind(&details.postal_code)
+        .bind(&details.tax_id)
+        .bind(&details.time_zone)
+        .bind(details.profile_picture.as_ref().map(|b| b.to_owned()))
+        .bind(&details.cv)
+        .bind(&details.gender)
+        .bind(&details.marital_status)
+        .bind(&details.nationality)
+        .bind(&details.email_address)
+        .bind(&details.national_id)
+        .bind(&details.passport_number)
+        .bind(&details.passport_expiry_date)
+        .bind(&details.work_permit)
+        .bind(details.added_related_pay_item_id)
+        .bind(details.total_dependants)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
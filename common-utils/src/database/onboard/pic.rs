@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as, TryFromInto};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
 use uuid::Uuid;
 
 use crate::{
@@ -37,36 +37,18 @@ impl Database {
         phone_number: &String,
         profile_picture: Option<&ImageData>,
     ) -> GlobeliseResult<()> {
-        let table = match role {
-            UserRole::Client => "entity_client_pic_details",
-            UserRole::Contractor => "entity_contractor_pic_details",
-        };
-
-        let query = format!(
-            "
-            INSERT INTO {table} (
-                ulid, first_name, last_name, dob, dial_code, 
-                phone_number, profile_picture
-            ) VALUES (
-                $1, $2, $3, $4, $5, 
-                $6, $7
-            ) ON CONFLICT(ulid) DO UPDATE SET 
-                first_name = $2, last_name = $3, dob = $4, dial_code = $5, 
-                phone_number = $6, profile_picture = $7",
-        );
-
-        sqlx::query(&query)
-            .bind(ulid)
-            .bind(first_name)
-            .bind(last_name)
-            .bind(dob)
-            .bind(dial_code)
-            .bind(phone_number)
-            .bind(profile_picture)
-            .execute(&self.0)
-            .await?;
-
-        Ok(())
+        insert_one_onboard_entity_pic_details(
+            &self.0,
+            ulid,
+            role,
+            first_name,
+            last_name,
+            dob,
+            dial_code,
+            phone_number,
+            profile_picture,
+        )
+        .await
     }
 
     pub async fn select_one_onboard_entity_pic_details(
@@ -97,3 +79,49 @@ impl Database {
         Ok(result)
     }
 }
+
+/// [`Database::insert_one_onboard_entity_pic_details`] with any executor, such as a
+/// transaction.
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_one_onboard_entity_pic_details<'c>(
+    executor: impl PgExecutor<'c>,
+    ulid: &Uuid,
+    role: &UserRole,
+    first_name: &String,
+    last_name: &String,
+    dob: &sqlx::types::time::OffsetDateTime,
+    dial_code: &String,
+    phone_number: &String,
+    profile_picture: Option<&ImageData>,
+) -> GlobeliseResult<()> {
+    let table = match role {
+        UserRole::Client => "entity_client_pic_details",
+        UserRole::Contractor => "entity_contractor_pic_details",
+    };
+
+    let query = format!(
+        "
+        INSERT INTO {table} (
+            ulid, first_name, last_name, dob, dial_code, 
+            phone_number, profile_picture
+        ) VALUES (
+            $1, $2, $3, $4, $5, 
+            $6, $7
+        ) ON CONFLICT(ulid) DO UPDATE SET 
+            first_name = $2, last_name = $3, dob = $4, dial_code = $5, 
+            phone_number = $6, profile_picture = $7",
+    );
+
+    sqlx::query(&query)
+        .bind(ulid)
+        .bind(first_name)
+        .bind(last_name)
+        .bind(dob)
+        .bind(dial_code)
+        .bind(phone_number)
+        .bind(profile_picture)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{error::GlobeliseResult, token::SigningKey, DaprAppId};
+
+use super::Database;
+
+impl Database {
+    /// Gets the signing keys of a service that have not expired yet.
+    pub async fn select_many_signing_keys(
+        &self,
+        dapr_app_id: DaprAppId,
+    ) -> GlobeliseResult<Vec<SigningKey>> {
+        let rows: Vec<(Vec<u8>, Option<OffsetDateTime>)> = sqlx::query_as(
+            "
+            SELECT
+                encrypted_private_key, expires_at
+            FROM
+                signing_keys
+            WHERE
+                dapr_app_id = $1 AND
+                (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            ORDER BY
+                created_at DESC",
+        )
+        .bind(dapr_app_id.as_str())
+        .fetch_all(&self.0)
+        .await?;
+
+        rows.into_iter()
+            .map(|(encrypted_private_key, expires_at)| {
+                SigningKey::open(dapr_app_id, &encrypted_private_key, expires_at)
+            })
+            .collect()
+    }
+
+    /// Replaces the signing key of a service.
+    ///
+    /// The previous key keeps being accepted until the grace period is over. The private keys
+    /// are encrypted before they are stored.
+    pub async fn rotate_signing_key(
+        &self,
+        dapr_app_id: DaprAppId,
+        previous: &SigningKey,
+        next: &SigningKey,
+        grace_period: Duration,
+    ) -> GlobeliseResult<()> {
+        let expires_at = OffsetDateTime::now_utc() + grace_period;
+
+        let mut transaction = self.0.begin().await?;
+
+        sqlx::query(
+            "
+            UPDATE
+                signing_keys
+            SET
+                expires_at = $2
+            WHERE
+                dapr_app_id = $1 AND
+                expires_at IS NULL",
+        )
+        .bind(dapr_app_id.as_str())
+        .bind(expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        // The previous key may have been loaded from disk and never stored.
+        sqlx::query(
+            "
+            INSERT INTO signing_keys (
+                kid, dapr_app_id, encrypted_private_key, expires_at
+            ) VALUES (
+                $1, $2, $3, $4
+            ) ON CONFLICT (kid) DO NOTHING",
+        )
+        .bind(&previous.kid)
+        .bind(dapr_app_id.as_str())
+        .bind(previous.seal(dapr_app_id)?)
+        .bind(expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query(
+            "
+            INSERT INTO signing_keys (
+                kid, dapr_app_id, encrypted_private_key, expires_at
+            ) VALUES (
+                $1, $2, $3, NULL
+            )",
+        )
+        .bind(&next.kid)
+        .bind(dapr_app_id.as_str())
+        .bind(next.seal(dapr_app_id)?)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Whether an admin can do what only the super admins can, like rotating signing keys.
+    pub async fn is_super_admin(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let result: Option<bool> = sqlx::query_scalar(
+            "
+            SELECT
+                is_super_admin
+            FROM
+                admin_users
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result.unwrap_or(false))
+    }
+}
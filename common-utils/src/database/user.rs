@@ -1,11 +1,11 @@
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, TryFromInto};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
 use uuid::Uuid;
 
 use crate::{
     calc_limit_and_offset,
-    custom_serde::{EmailWrapper, OffsetDateWrapper, UserRole, UserType},
+    custom_serde::{EmailWrapper, OffsetDateWrapper, OptionOffsetDateWrapper, UserRole, UserType},
     error::{GlobeliseError, GlobeliseResult},
 };
 
@@ -27,6 +27,7 @@ pub struct OnboardedUserIndex {
     pub created_at: sqlx::types::time::OffsetDateTime,
 }
 
+#[serde_as]
 #[derive(Debug, FromRow, Deserialize, Serialize)]
 pub struct User {
     pub ulid: Uuid,
@@ -38,6 +39,10 @@ pub struct User {
     pub is_individual: bool,
     pub is_client: bool,
     pub is_contractor: bool,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub email_verified_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub password_changed_at: sqlx::types::time::OffsetDateTime,
 }
 
 impl User {
@@ -113,20 +118,7 @@ impl Database {
         client_ulid: Uuid,
         contractor_ulid: Uuid,
     ) -> GlobeliseResult<()> {
-        sqlx::query(&format!(
-            "
-        INSERT INTO 
-            client_contractor_pairs (client_ulid, contractor_ulid)
-        VALUES($1, $2)
-            ON CONFLICT(client_ulid, contractor_ulid) DO NOTHING
-        "
-        ))
-        .bind(client_ulid)
-        .bind(contractor_ulid)
-        .execute(&self.0)
-        .await?;
-
-        Ok(())
+        create_client_contractor_pair(&self.0, client_ulid, contractor_ulid).await
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -141,30 +133,18 @@ impl Database {
         is_client: bool,
         is_contractor: bool,
     ) -> GlobeliseResult<Uuid> {
-        let ulid = Uuid::new_v4();
-
-        sqlx::query(
-            "INSERT INTO users (
-                ulid, email, password, is_google, is_outlook,
-                is_entity, is_individual, is_client, is_contractor
-            ) VALUES (
-                $1, $2, $3, $4, $5,
-                $6, $7, $8, $9
-            )",
+        insert_one_user(
+            &self.0,
+            email,
+            password,
+            is_google,
+            is_outlook,
+            is_entity,
+            is_individual,
+            is_client,
+            is_contractor,
         )
-        .bind(ulid)
-        .bind(email)
-        .bind(password)
-        .bind(is_google)
-        .bind(is_outlook)
-        .bind(is_entity)
-        .bind(is_individual)
-        .bind(is_client)
-        .bind(is_contractor)
-        .execute(&self.0)
-        .await?;
-
-        Ok(ulid)
+        .await
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -197,6 +177,9 @@ impl Database {
         Ok(ulid)
     }
 
+    /// Replaces the password hash of a user.
+    ///
+    /// The previous hash is kept in the password history.
     pub async fn update_user_password_hash(
         &self,
         ulid: Uuid,
@@ -204,27 +187,151 @@ impl Database {
         // TODO: Create a newtype to ensure only hashed password are inserted
         new_password_hash: Option<String>,
     ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
+        insert_password_history(&mut transaction, PasswordOwner::User, ulid).await?;
+
         sqlx::query(
             "
             UPDATE 
                 users 
             SET 
-                password = $1 
+                password = $1,
+                password_changed_at = CURRENT_TIMESTAMP
             WHERE 
                 ulid = $2 AND
-                is_entity = $2 AND
-                is_individual = $3",
+                is_entity = $3 AND
+                is_individual = $4",
         )
         .bind(new_password_hash)
         .bind(ulid)
         .bind(user_type == UserType::Entity)
         .bind(user_type == UserType::Individual)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Gets the current and previous password hashes of a user, most recent first.
+    pub async fn select_many_user_password_hashes(
+        &self,
+        ulid: Uuid,
+        limit: usize,
+    ) -> GlobeliseResult<Vec<String>> {
+        let result = sqlx::query_scalar(
+            "
+            SELECT
+                password
+            FROM (
+                SELECT
+                    password, password_changed_at AS created_at
+                FROM
+                    users
+                WHERE
+                    ulid = $1 AND
+                    password IS NOT NULL
+                UNION ALL
+                SELECT
+                    password, created_at
+                FROM
+                    user_password_history
+                WHERE
+                    user_ulid = $1
+            ) AS hashes
+            ORDER BY
+                created_at DESC
+            LIMIT
+                $2",
+        )
+        .bind(ulid)
+        .bind(limit as i64)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Gets the current and previous password hashes of an admin, most recent first.
+    pub async fn select_many_admin_password_hashes(
+        &self,
+        ulid: Uuid,
+        limit: usize,
+    ) -> GlobeliseResult<Vec<String>> {
+        let result = sqlx::query_scalar(
+            "
+            SELECT
+                password
+            FROM (
+                SELECT
+                    password, password_changed_at AS created_at
+                FROM
+                    admin_users
+                WHERE
+                    ulid = $1 AND
+                    password IS NOT NULL
+                UNION ALL
+                SELECT
+                    password, created_at
+                FROM
+                    admin_password_history
+                WHERE
+                    admin_ulid = $1
+            ) AS hashes
+            ORDER BY
+                created_at DESC
+            LIMIT
+                $2",
+        )
+        .bind(ulid)
+        .bind(limit as i64)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Marks the user's email address as verified.
+    ///
+    /// Verifying an already verified address keeps the original timestamp.
+    pub async fn update_one_user_email_verified(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE
+                users
+            SET
+                email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+            WHERE
+                ulid = $1",
+        )
+        .bind(ulid)
         .execute(&self.0)
         .await?;
 
         Ok(())
     }
 
+    pub async fn is_user_email_verified(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
+            "
+            SELECT
+                1
+            FROM
+                users
+            WHERE
+                ulid = $1 AND
+                email_verified_at IS NOT NULL",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .is_some();
+
+        Ok(result)
+    }
+
     pub async fn find_one_user(
         &self,
         ulid: Option<Uuid>,
@@ -235,7 +342,8 @@ impl Database {
             "
             SELECT 
                 ulid, email, password, is_google, is_outlook, 
-                is_entity, is_individual, is_client, is_contractor
+                is_entity, is_individual, is_client, is_contractor,
+                email_verified_at, password_changed_at
             FROM 
                 users
             WHERE 
@@ -459,3 +567,104 @@ impl Database {
         Ok(result)
     }
 }
+
+/// Whose password history a password is kept in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordOwner {
+    User,
+    Admin,
+}
+
+/// Keeps the current password hash of a user or an admin in their password history.
+///
+/// Should run in the same transaction as the update that replaces the password.
+pub async fn insert_password_history<'c>(
+    executor: impl PgExecutor<'c>,
+    owner: PasswordOwner,
+    ulid: Uuid,
+) -> GlobeliseResult<()> {
+    let (history_table, owner_column, accounts_table) = match owner {
+        PasswordOwner::User => ("user_password_history", "user_ulid", "users"),
+        PasswordOwner::Admin => ("admin_password_history", "admin_ulid", "admin_users"),
+    };
+
+    sqlx::query(&format!(
+        "
+    INSERT INTO {} (
+        {}, password, created_at
+    ) SELECT
+        ulid, password, password_changed_at
+    FROM
+        {}
+    WHERE
+        ulid = $1 AND
+        password IS NOT NULL",
+        history_table, owner_column, accounts_table
+    ))
+    .bind(ulid)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// [`Database::create_client_contractor_pair`] with any executor, such as a transaction.
+pub async fn create_client_contractor_pair<'c>(
+    executor: impl PgExecutor<'c>,
+    client_ulid: Uuid,
+    contractor_ulid: Uuid,
+) -> GlobeliseResult<()> {
+    sqlx::query(&format!(
+        "
+    INSERT INTO 
+        client_contractor_pairs (client_ulid, contractor_ulid)
+    VALUES($1, $2)
+        ON CONFLICT(client_ulid, contractor_ulid) DO NOTHING
+    "
+    ))
+    .bind(client_ulid)
+    .bind(contractor_ulid)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// [`Database::insert_one_user`] with any executor, such as a transaction.
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_one_user<'c>(
+    executor: impl PgExecutor<'c>,
+    email: &EmailWrapper,
+    password: Option<&String>,
+    is_google: bool,
+    is_outlook: bool,
+    is_entity: bool,
+    is_individual: bool,
+    is_client: bool,
+    is_contractor: bool,
+) -> GlobeliseResult<Uuid> {
+    let ulid = Uuid::new_v4();
+
+    sqlx::query(
+        "INSERT INTO users (
+            ulid, email, password, is_google, is_outlook,
+            is_entity, is_individual, is_client, is_contractor
+        ) VALUES (
+            $1, $2, $3, $4, $5,
+            $6, $7, $8, $9
+        )",
+    )
+    .bind(ulid)
+    .bind(email)
+    .bind(password)
+    .bind(is_google)
+    .bind(is_outlook)
+    .bind(is_entity)
+    .bind(is_individual)
+    .bind(is_client)
+    .bind(is_contractor)
+    .execute(executor)
+    .await?;
+
+    Ok(ulid)
+}
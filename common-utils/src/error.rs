@@ -10,6 +10,7 @@ pub type GlobeliseResult<T> = std::result::Result<T, GlobeliseError>;
 pub enum GlobeliseError {
     UnavailableEmail,
     WrongUserType,
+    EmailNotVerified,
     UnsupportedImageFormat,
     BadRequest(String),
     Unauthorized(String),
@@ -65,6 +66,9 @@ impl IntoResponse for GlobeliseError {
             GlobeliseError::WrongUserType => {
                 (StatusCode::UNAUTHORIZED, "Wrong user type").into_response()
             }
+            GlobeliseError::EmailNotVerified => {
+                (StatusCode::FORBIDDEN, "Email address has not been verified").into_response()
+            }
             GlobeliseError::UnsupportedImageFormat => (
                 StatusCode::UNSUPPORTED_MEDIA_TYPE,
                 "Image must be PNG or JPEG",
@@ -91,6 +95,7 @@ impl IntoResponse for GlobeliseError {
 impl std::fmt::Display for GlobeliseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            GlobeliseError::EmailNotVerified => write!(f, "Email address has not been verified"),
             GlobeliseError::UnsupportedImageFormat => write!(f, "Image must be PNG or JPEG"),
             GlobeliseError::BadRequest(message) => write!(f, "{message}"),
             GlobeliseError::PayloadTooLarge(message) => write!(f, "{message}"),
@@ -0,0 +1,296 @@
+//! Currency conversion with the FX rates admins maintain.
+//!
+//! A rate says how many units of the quote currency one unit of the base currency buys,
+//! from its effective date until the next rate of the pair. A conversion uses the latest
+//! rate of the pair on or before the reporting date, the inverse of the opposite pair when
+//! only that one is known, or a cross rate through USD.
+
+use serde::Serialize;
+use serde_with::{serde_as, TryFromInto};
+use sqlx::types::{time::Date, Decimal};
+
+use crate::{
+    custom_serde::{Currency, DateWrapper, OptionDateWrapper},
+    database::fx_rate::FxRate,
+    error::{GlobeliseError, GlobeliseResult},
+    money::Money,
+};
+
+/// The rates in force on a date.
+#[derive(Debug)]
+pub struct FxRates {
+    as_of: Date,
+    rates: Vec<FxRate>,
+}
+
+/// How an amount was converted.
+#[serde_as]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Conversion {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: Decimal,
+    /// Effective date of the rate, or of the older rate of a cross rate. None when no
+    /// conversion was needed.
+    #[serde_as(as = "TryFromInto<OptionDateWrapper>")]
+    pub rate_date: Option<Date>,
+}
+
+impl Conversion {
+    /// Converts an amount, rounded to the minor units of the target currency.
+    pub fn convert(&self, amount: Decimal) -> Decimal {
+        Money::round(amount * self.rate, self.to).amount()
+    }
+}
+
+/// Amounts in several currencies and their total in one.
+#[serde_as]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertedTotal {
+    pub currency: Currency,
+    pub total: Decimal,
+    /// The date whose rates were used.
+    #[serde_as(as = "TryFromInto<DateWrapper>")]
+    pub as_of: Date,
+    pub parts: Vec<ConvertedAmount>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertedAmount {
+    pub amount: Decimal,
+    pub converted_amount: Decimal,
+    pub conversion: Conversion,
+}
+
+impl FxRates {
+    /// Wraps the latest rate of every pair on or before `as_of`.
+    pub fn new(as_of: Date, rates: Vec<FxRate>) -> Self {
+        Self { as_of, rates }
+    }
+
+    pub fn as_of(&self) -> Date {
+        self.as_of
+    }
+
+    pub fn conversion(&self, from: Currency, to: Currency) -> GlobeliseResult<Conversion> {
+        let conversion = |(rate, rate_date)| Conversion {
+            from,
+            to,
+            rate,
+            rate_date,
+        };
+
+        if let Some(direct) = self.direct(from, to) {
+            return Ok(conversion(direct));
+        }
+        if let (Some((from_rate, from_date)), Some((to_rate, to_date))) = (
+            self.direct(from, Currency::USD),
+            self.direct(Currency::USD, to),
+        ) {
+            let rate_date = match (from_date, to_date) {
+                (Some(from_date), Some(to_date)) => Some(from_date.min(to_date)),
+                (from_date, to_date) => from_date.or(to_date),
+            };
+            return Ok(conversion((from_rate * to_rate, rate_date)));
+        }
+
+        Err(GlobeliseError::bad_request(format!(
+            "There is no FX rate from {} to {} on or before {}",
+            from.as_str(),
+            to.as_str(),
+            self.as_of.format("%Y-%m-%d")
+        )))
+    }
+
+    /// Converts amounts in several currencies and adds them up.
+    ///
+    /// Fails naming every pair without a rate, so that they can all be added at once.
+    pub fn convert_totals(
+        &self,
+        amounts: impl IntoIterator<Item = (Currency, Decimal)>,
+        to: Currency,
+    ) -> GlobeliseResult<ConvertedTotal> {
+        let mut parts = Vec::new();
+        let mut missing = Vec::new();
+        for (currency, amount) in amounts {
+            match self.conversion(currency, to) {
+                Ok(conversion) => parts.push(ConvertedAmount {
+                    amount,
+                    converted_amount: conversion.convert(amount),
+                    conversion,
+                }),
+                Err(_) => missing.push(format!("{} to {}", currency.as_str(), to.as_str())),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(GlobeliseError::bad_request(format!(
+                "There are no FX rates on or before {} for {}",
+                self.as_of.format("%Y-%m-%d"),
+                missing.join(", ")
+            )));
+        }
+
+        Ok(ConvertedTotal {
+            currency: to,
+            total: parts.iter().map(|part| part.converted_amount).sum(),
+            as_of: self.as_of,
+            parts,
+        })
+    }
+
+    /// The rate of a pair or the inverse of the opposite pair.
+    fn direct(&self, from: Currency, to: Currency) -> Option<(Decimal, Option<Date>)> {
+        if from.as_str() == to.as_str() {
+            return Some((Decimal::from(1), None));
+        }
+
+        let pair = |rate: &&FxRate, base: Currency, quote: Currency| {
+            rate.base_currency.as_str() == base.as_str()
+                && rate.quote_currency.as_str() == quote.as_str()
+        };
+        if let Some(rate) = self.rates.iter().find(|rate| pair(rate, from, to)) {
+            return Some((rate.rate, Some(rate.effective_date)));
+        }
+        let inverse = self.rates.iter().find(|rate| pair(rate, to, from))?;
+        let rate = Decimal::from(1).checked_div(inverse.rate)?;
+        Some((rate, Some(inverse.effective_date)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn day(day: u8) -> Date {
+        Date::try_from_ymd(2022, 9, day).unwrap()
+    }
+
+    fn rate(base: Currency, quote: Currency, rate: &str, effective_day: u8) -> FxRate {
+        FxRate {
+            ulid: Uuid::new_v4(),
+            base_currency: base,
+            quote_currency: quote,
+            rate: dec(rate),
+            effective_date: day(effective_day),
+            source: "manual".to_string(),
+            created_by: None,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn rates() -> FxRates {
+        FxRates::new(
+            day(30),
+            vec![
+                rate(Currency::SGD, Currency::USD, "0.72", 5),
+                rate(Currency::USD, Currency::EUR, "0.98", 12),
+                rate(Currency::USD, Currency::JPY, "143.5", 20),
+                rate(Currency::MYR, Currency::SGD, "0.31", 1),
+            ],
+        )
+    }
+
+    #[test]
+    fn a_currency_converts_to_itself_without_a_rate() {
+        let conversion = rates().conversion(Currency::SGD, Currency::SGD).unwrap();
+
+        assert_eq!(conversion.rate, dec("1"));
+        assert_eq!(conversion.rate_date, None);
+    }
+
+    #[test]
+    fn pairs_use_their_rate_or_the_inverse_of_the_opposite_pair() {
+        let conversion = rates().conversion(Currency::MYR, Currency::SGD).unwrap();
+        assert_eq!(conversion.rate, dec("0.31"));
+        assert_eq!(conversion.rate_date, Some(day(1)));
+
+        let rates = FxRates::new(day(30), vec![rate(Currency::USD, Currency::SGD, "1.25", 7)]);
+        let conversion = rates.conversion(Currency::SGD, Currency::USD).unwrap();
+        assert_eq!(conversion.rate, dec("0.8"));
+        assert_eq!(conversion.rate_date, Some(day(7)));
+    }
+
+    #[test]
+    fn other_pairs_cross_through_usd_with_the_older_date() {
+        let conversion = rates().conversion(Currency::SGD, Currency::EUR).unwrap();
+        assert_eq!(conversion.rate, dec("0.7056"));
+        assert_eq!(conversion.rate_date, Some(day(5)));
+
+        // Through the inverse of SGD/USD on one side.
+        let conversion = rates().conversion(Currency::EUR, Currency::SGD).unwrap();
+        assert_eq!(conversion.rate_date, Some(day(5)));
+        assert_eq!(
+            conversion.convert(dec("0.7056")),
+            dec("1.00"),
+            "the inverse cross rate round trips"
+        );
+    }
+
+    #[test]
+    fn pairs_without_a_rate_fail() {
+        let error = rates()
+            .conversion(Currency::MYR, Currency::EUR)
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "There is no FX rate from MYR to EUR on or before 2022-09-30"
+        );
+    }
+
+    #[test]
+    fn converted_amounts_are_rounded_to_the_target_currency() {
+        let conversion = rates().conversion(Currency::SGD, Currency::JPY).unwrap();
+
+        assert_eq!(conversion.rate, dec("103.320"));
+        assert_eq!(conversion.convert(dec("10.01")), dec("1034"));
+    }
+
+    #[test]
+    fn totals_add_up_the_converted_amounts() {
+        let total = rates()
+            .convert_totals(
+                [
+                    (Currency::SGD, dec("1000")),
+                    (Currency::USD, dec("100.50")),
+                    (Currency::EUR, dec("0")),
+                ],
+                Currency::USD,
+            )
+            .unwrap();
+
+        assert_eq!(total.as_of, day(30));
+        assert_eq!(total.parts.len(), 3);
+        assert_eq!(total.parts[0].converted_amount, dec("720.00"));
+        assert_eq!(total.total, dec("820.50"));
+    }
+
+    #[test]
+    fn totals_name_every_pair_without_a_rate() {
+        let error = rates()
+            .convert_totals(
+                [
+                    (Currency::MYR, dec("1")),
+                    (Currency::SGD, dec("1")),
+                    (Currency::GBP, dec("1")),
+                ],
+                Currency::EUR,
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "There are no FX rates on or before 2022-09-30 for MYR to EUR, GBP to EUR"
+        );
+    }
+}
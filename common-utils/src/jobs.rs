@@ -0,0 +1,475 @@
+//! Background jobs for work that takes longer than a request may, like imports of large
+//! files or calls to slow partner APIs.
+//!
+//! A request handler queues a job with [`enqueue`] and returns it, so that the client can poll
+//! its status and download its result. The workers started by [`JobRunner::spawn`] take the
+//! jobs off the queue in the `jobs` table, retry the ones that fail with an internal error,
+//! and stop the ones whose cancellation is requested the next time they report progress.
+//! The payload of a job is cleared once it has finished.
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        job::{ClaimedJob, Job},
+        CommonDatabase,
+    },
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+/// How long a worker waits before looking for due jobs again when there were none.
+const DEFAULT_JOB_POLL_INTERVAL_SECONDS: u64 = 2;
+
+/// How often a running job tells the queue that its worker is still alive.
+const HEARTBEAT_INTERVAL_SECONDS: u64 = 30;
+
+/// How long a running job can go without a heartbeat before another worker takes it.
+const STALE_AFTER_SECONDS: i64 = 5 * 60;
+
+/// The delay before the first retry, doubled on every attempt after that.
+const RETRY_BASE_SECONDS: i64 = 30;
+
+const RETRY_MAX_SECONDS: i64 = 60 * 60;
+
+/// Queues a job and returns it as it should be shown to the client.
+pub async fn enqueue<T>(
+    database: &CommonDatabase,
+    kind: &str,
+    unique_key: Option<&str>,
+    payload: &T,
+    max_attempts: i32,
+    created_by: Option<Uuid>,
+) -> GlobeliseResult<Job>
+where
+    T: Serialize,
+{
+    let payload = serde_json::to_string(payload)?;
+
+    let database = database.lock().await;
+    let ulid = database
+        .insert_one_job(kind, unique_key, &payload, max_attempts, created_by)
+        .await?;
+
+    database
+        .select_one_job(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::internal("Cannot find the job that was just queued"))
+}
+
+/// What a job produces, stored to be downloaded once it has succeeded.
+#[derive(Debug)]
+pub struct JobOutput {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl JobOutput {
+    pub fn json<T>(value: &T) -> GlobeliseResult<Self>
+    where
+        T: Serialize,
+    {
+        Ok(JobOutput {
+            content_type: "application/json".to_string(),
+            data: serde_json::to_vec(value)?,
+        })
+    }
+}
+
+/// What a job handler is given to run a job.
+pub struct JobContext {
+    pub ulid: Uuid,
+    /// Starts at 1.
+    pub attempt: i32,
+    payload: String,
+    database: CommonDatabase,
+}
+
+impl JobContext {
+    pub fn payload<T>(&self) -> GlobeliseResult<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_str(&self.payload)
+            .map_err(|e| GlobeliseError::internal(format!("Cannot read the job payload: {}", e)))
+    }
+
+    /// Records how many of the `total` steps of the job are done.
+    ///
+    /// Fails if the cancellation of the job was requested, so that handlers stop with `?`.
+    pub async fn progress(&self, done: usize, total: usize) -> GlobeliseResult<()> {
+        let cancel_requested = self
+            .database
+            .lock()
+            .await
+            .update_job_progress(self.ulid, done as i32, Some(total as i32))
+            .await?;
+        if cancel_requested {
+            return Err(GlobeliseError::bad_request("The job was cancelled"));
+        }
+
+        Ok(())
+    }
+}
+
+type JobFuture = Pin<Box<dyn Future<Output = GlobeliseResult<Option<JobOutput>>> + Send>>;
+
+type JobHandler = Arc<dyn Fn(JobContext) -> JobFuture + Send + Sync>;
+
+/// The workers of a service, with the handler of every kind of job it runs.
+pub struct JobRunner {
+    database: CommonDatabase,
+    handlers: HashMap<&'static str, JobHandler>,
+}
+
+impl JobRunner {
+    pub fn new(database: CommonDatabase) -> Self {
+        JobRunner {
+            database,
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn register<F, Fut>(mut self, kind: &'static str, handler: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = GlobeliseResult<Option<JobOutput>>> + Send + 'static,
+    {
+        self.handlers.insert(
+            kind,
+            Arc::new(move |context| -> JobFuture { Box::pin(handler(context)) }),
+        );
+        self
+    }
+
+    /// Starts `workers` workers, each running one job at a time.
+    pub fn spawn(self, workers: usize) {
+        let interval = std::env::var("JOB_POLL_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_JOB_POLL_INTERVAL_SECONDS);
+
+        let runner = Arc::new(self);
+        for _ in 0..workers {
+            let runner = runner.clone();
+            tokio::spawn(async move {
+                loop {
+                    match runner.run_next().await {
+                        Ok(true) => (),
+                        Ok(false) => tokio::time::sleep(Duration::from_secs(interval)).await,
+                        Err(e) => {
+                            println!("Cannot run the next job: {:?}", e);
+                            tokio::time::sleep(Duration::from_secs(interval)).await;
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    /// Runs the next due job, returning false if there was none.
+    async fn run_next(&self) -> GlobeliseResult<bool> {
+        let kinds = self.handlers.keys().copied().collect::<Vec<_>>();
+        let job = match self
+            .database
+            .lock()
+            .await
+            .claim_next_job(&kinds, STALE_AFTER_SECONDS)
+            .await?
+        {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+
+        let handler = self
+            .handlers
+            .get(job.kind.as_str())
+            .cloned()
+            .ok_or_else(|| GlobeliseError::internal("Claimed a job of an unknown kind"))?;
+
+        let heartbeat = tokio::spawn(heartbeat(self.database.clone(), job.ulid));
+        // Spawned so that a panicking handler fails its job instead of stopping the worker.
+        let result = tokio::spawn(handler(JobContext {
+            ulid: job.ulid,
+            attempt: job.attempts,
+            payload: job.payload.clone(),
+            database: self.database.clone(),
+        }))
+        .await
+        .unwrap_or_else(|e| Err(GlobeliseError::internal(format!("The job panicked: {}", e))));
+        heartbeat.abort();
+
+        self.finish(&job, result).await?;
+
+        Ok(true)
+    }
+
+    async fn finish(
+        &self,
+        job: &ClaimedJob,
+        result: GlobeliseResult<Option<JobOutput>>,
+    ) -> GlobeliseResult<()> {
+        let database = self.database.lock().await;
+
+        let error = match result {
+            Ok(output) => {
+                return database
+                    .update_job_as_succeeded(
+                        job.ulid,
+                        output
+                            .as_ref()
+                            .map(|output| (output.content_type.as_str(), output.data.as_slice())),
+                    )
+                    .await;
+            }
+            Err(error) => error,
+        };
+
+        let cancel_requested = database
+            .select_one_job(job.ulid)
+            .await?
+            .map_or(false, |job| job.cancel_requested);
+        if cancel_requested {
+            return database.update_job_as_cancelled(job.ulid).await;
+        }
+
+        database
+            .update_job_as_failed(
+                job.ulid,
+                &error_message(&error),
+                retry_in_seconds(&error, job.attempts, job.max_attempts),
+            )
+            .await
+    }
+}
+
+/// When a job that failed its `attempts`th attempt with `error` is tried again, if ever.
+fn retry_in_seconds(error: &GlobeliseError, attempts: i32, max_attempts: i32) -> Option<i64> {
+    // Only internal errors, like a database or network failure, can go away by themselves.
+    match error {
+        GlobeliseError::Internal(_) if attempts < max_attempts => {
+            Some((RETRY_BASE_SECONDS << (attempts - 1).clamp(0, 16)).min(RETRY_MAX_SECONDS))
+        }
+        _ => None,
+    }
+}
+
+async fn heartbeat(database: CommonDatabase, ulid: Uuid) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECONDS)).await;
+        if let Err(e) = database.lock().await.update_job_heartbeat(ulid).await {
+            println!("Cannot record the heartbeat of job {}: {:?}", ulid, e);
+        }
+    }
+}
+
+/// The message stored with a failed job, which admins see when polling it.
+fn error_message(error: &GlobeliseError) -> String {
+    match error {
+        GlobeliseError::BadRequest(message)
+        | GlobeliseError::NotFound(message)
+        | GlobeliseError::Unauthorized(message)
+        | GlobeliseError::PayloadTooLarge(message)
+        | GlobeliseError::Internal(message) => message.clone(),
+        error => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::Mutex;
+
+    use crate::database::Database;
+
+    use super::*;
+
+    #[test]
+    fn internal_errors_are_retried_with_a_growing_delay() {
+        let error = GlobeliseError::internal("Cannot reach the bank");
+
+        assert_eq!(retry_in_seconds(&error, 1, 5), Some(30));
+        assert_eq!(retry_in_seconds(&error, 2, 5), Some(60));
+        assert_eq!(retry_in_seconds(&error, 4, 5), Some(240));
+        assert_eq!(retry_in_seconds(&error, 9, 20), Some(RETRY_MAX_SECONDS));
+        assert_eq!(retry_in_seconds(&error, 5, 5), None);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let error = GlobeliseError::bad_request("The file has no rows");
+
+        assert_eq!(retry_in_seconds(&error, 1, 5), None);
+    }
+
+    async fn connect() -> CommonDatabase {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+
+        Arc::new(Mutex::new(Database(pool)))
+    }
+
+    /// A kind of job no other test or worker takes.
+    fn test_kind() -> &'static str {
+        Box::leak(format!("test-{}", Uuid::new_v4()).into_boxed_str())
+    }
+
+    async fn state(database: &CommonDatabase, ulid: Uuid) -> (String, i32, Option<String>) {
+        sqlx::query_as("SELECT status, attempts, payload FROM jobs WHERE ulid = $1")
+            .bind(ulid)
+            .fetch_one(&database.lock().await.0)
+            .await
+            .unwrap()
+    }
+
+    async fn make_stale(database: &CommonDatabase, ulid: Uuid) {
+        sqlx::query(
+            "UPDATE jobs SET heartbeat_at = CURRENT_TIMESTAMP - INTERVAL '1 hour' WHERE ulid = $1",
+        )
+        .bind(ulid)
+        .execute(&database.lock().await.0)
+        .await
+        .unwrap();
+    }
+
+    async fn claim(database: &CommonDatabase, kind: &str) -> Option<ClaimedJob> {
+        database
+            .lock()
+            .await
+            .claim_next_job(&[kind], STALE_AFTER_SECONDS)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn jobs_are_run_once_and_their_payload_is_cleared() {
+        let database = connect().await;
+        let kind = test_kind();
+        let job = enqueue(&database, kind, None, &"payload", 3, None)
+            .await
+            .unwrap();
+
+        let runner = JobRunner::new(database.clone()).register(kind, |context| async move {
+            assert_eq!(context.payload::<String>()?, "payload");
+            Ok(Some(JobOutput::json(&"done")?))
+        });
+        assert!(runner.run_next().await.unwrap());
+        assert!(!runner.run_next().await.unwrap());
+
+        let (status, attempts, payload) = state(&database, job.ulid).await;
+        assert_eq!(status, "succeeded");
+        assert_eq!(attempts, 1);
+        assert_eq!(payload, None);
+        let (content_type, data) = database
+            .lock()
+            .await
+            .select_one_job_result(job.ulid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(content_type, "application/json");
+        assert_eq!(data, b"\"done\"");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn failed_jobs_are_queued_again_until_out_of_attempts() {
+        let database = connect().await;
+        let kind = test_kind();
+        let job = enqueue(&database, kind, None, &"payload", 2, None)
+            .await
+            .unwrap();
+        let runner = JobRunner::new(database.clone()).register(kind, |_| async move {
+            Err(GlobeliseError::internal("Cannot reach the bank"))
+        });
+
+        assert!(runner.run_next().await.unwrap());
+        let (status, attempts, payload) = state(&database, job.ulid).await;
+        assert_eq!(status, "queued");
+        assert_eq!(attempts, 1);
+        assert!(payload.is_some());
+        // Not due before its backoff has passed.
+        assert!(!runner.run_next().await.unwrap());
+
+        sqlx::query("UPDATE jobs SET run_after = CURRENT_TIMESTAMP WHERE ulid = $1")
+            .bind(job.ulid)
+            .execute(&database.lock().await.0)
+            .await
+            .unwrap();
+        assert!(runner.run_next().await.unwrap());
+        let (status, attempts, payload) = state(&database, job.ulid).await;
+        assert_eq!(status, "failed");
+        assert_eq!(attempts, 2);
+        assert_eq!(payload, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn stale_jobs_are_taken_again_until_out_of_attempts() {
+        let database = connect().await;
+        let kind = test_kind();
+        let job = enqueue(&database, kind, None, &"payload", 2, None)
+            .await
+            .unwrap();
+
+        assert_eq!(claim(&database, kind).await.unwrap().ulid, job.ulid);
+        // Still running with a fresh heartbeat.
+        assert!(claim(&database, kind).await.is_none());
+
+        make_stale(&database, job.ulid).await;
+        let claimed = claim(&database, kind).await.unwrap();
+        assert_eq!(claimed.ulid, job.ulid);
+        assert_eq!(claimed.attempts, 2);
+
+        make_stale(&database, job.ulid).await;
+        assert!(claim(&database, kind).await.is_none());
+        let (status, _, payload) = state(&database, job.ulid).await;
+        assert_eq!(status, "failed");
+        assert_eq!(payload, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn cancelled_jobs_are_not_run_again() {
+        let database = connect().await;
+        let kind = test_kind();
+        let queued = enqueue(&database, kind, None, &"payload", 3, None)
+            .await
+            .unwrap();
+        assert!(database
+            .lock()
+            .await
+            .update_job_cancel_requested(queued.ulid)
+            .await
+            .unwrap());
+        let (status, _, payload) = state(&database, queued.ulid).await;
+        assert_eq!(status, "cancelled");
+        assert_eq!(payload, None);
+
+        let running = enqueue(&database, kind, None, &"payload", 3, None)
+            .await
+            .unwrap();
+        assert_eq!(claim(&database, kind).await.unwrap().ulid, running.ulid);
+        assert!(database
+            .lock()
+            .await
+            .update_job_cancel_requested(running.ulid)
+            .await
+            .unwrap());
+        // The worker stopped before it noticed the cancellation.
+        make_stale(&database, running.ulid).await;
+        assert!(claim(&database, kind).await.is_none());
+        let (status, attempts, payload) = state(&database, running.ulid).await;
+        assert_eq!(status, "cancelled");
+        assert_eq!(attempts, 1);
+        assert_eq!(payload, None);
+    }
+}
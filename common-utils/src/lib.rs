@@ -4,9 +4,16 @@ use error::GlobeliseResult;
 use serde::{Deserialize, Serialize};
 use strum::Display;
 
+pub mod api_key;
+pub mod bank_details;
 pub mod custom_serde;
 pub mod database;
 pub mod error;
+pub mod fx;
+pub mod jobs;
+pub mod mail;
+pub mod money;
+pub mod password;
 pub mod pubsub;
 pub mod token;
 
@@ -0,0 +1,374 @@
+//! Transactional emails, rendered from the templates in `common-utils/templates/email` and sent
+//! by the outbox sender instead of inside the request that sends them.
+//!
+//! Every template has a subject, an HTML body and a text alternative for each locale, in
+//! `<locale>/<template>.subject.txt`, `<locale>/<template>.html` and `<locale>/<template>.txt`.
+//! `{{ name }}` is replaced with the value of `name`, escaped in the HTML body, and
+//! `{{{ name }}}` with the value as is.
+//!
+//! Emails are rendered in the locale of their recipient where it is known: the
+//! `Accept-Language` of a request for the emails sent to whoever made it, see
+//! [`request_locale`], and the country of a contractor for their invitation, see
+//! [`country_locale`].
+//!
+//! A request queues an email with [`Database::insert_one_outbox_email`], and
+//! [`outbox::spawn_outbox_sender`] sends it with the [`transport::MailTransport`] of the
+//! service.
+//!
+//! [`Database::insert_one_outbox_email`]: crate::database::Database::insert_one_outbox_email
+
+use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap};
+
+use crate::{
+    custom_serde::Country,
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+pub mod outbox;
+pub mod transport;
+
+/// The locale of the emails sent to users whose locale has no templates.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The locales that have templates.
+const LOCALES: [&str; 2] = ["en", "id"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    /// Variables: `link`.
+    EmailVerification,
+    /// Variables: `link`.
+    PasswordReset,
+    /// Variables: `link`, `contractor_type`.
+    ContractorInvitation,
+    /// A contractor invitation with the password of the account an admin created.
+    ///
+    /// Variables: `link`, `contractor_type`, `password`.
+    ContractorAccountCreated,
+    /// Variables: `body`, written by the client and included as is.
+    ContractSigningRequest,
+}
+
+impl EmailTemplate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTemplate::EmailVerification => "email-verification",
+            EmailTemplate::PasswordReset => "password-reset",
+            EmailTemplate::ContractorInvitation => "contractor-invitation",
+            EmailTemplate::ContractorAccountCreated => "contractor-account-created",
+            EmailTemplate::ContractSigningRequest => "contract-signing-request",
+        }
+    }
+
+    /// Renders the template in the locale closest to `locale`, like `en` for `en-GB`.
+    pub fn render(
+        &self,
+        locale: Option<&str>,
+        variables: &[(&str, &str)],
+    ) -> GlobeliseResult<RenderedEmail> {
+        let locale = resolve_locale(locale);
+        let source = template_source(*self, locale).ok_or_else(|| {
+            GlobeliseError::internal(format!(
+                "The {} email template has no {} version",
+                self.as_str(),
+                locale
+            ))
+        })?;
+
+        Ok(RenderedEmail {
+            template: *self,
+            locale,
+            subject: render(source.subject, variables, false)?.trim().to_string(),
+            html_body: render(source.html, variables, true)?,
+            text_body: render(source.text, variables, false)?,
+        })
+    }
+}
+
+/// An email ready to be queued.
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub template: EmailTemplate,
+    pub locale: &'static str,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+struct TemplateSource {
+    subject: &'static str,
+    html: &'static str,
+    text: &'static str,
+}
+
+macro_rules! template_source {
+    ($locale:literal, $name:literal) => {
+        TemplateSource {
+            subject: include_str!(concat!(
+                "../../templates/email/",
+                $locale,
+                "/",
+                $name,
+                ".subject.txt"
+            )),
+            html: include_str!(concat!(
+                "../../templates/email/",
+                $locale,
+                "/",
+                $name,
+                ".html"
+            )),
+            text: include_str!(concat!(
+                "../../templates/email/",
+                $locale,
+                "/",
+                $name,
+                ".txt"
+            )),
+        }
+    };
+}
+
+/// The templates are compiled in, so that every service has them without deploying them.
+fn template_source(template: EmailTemplate, locale: &str) -> Option<TemplateSource> {
+    match (locale, template) {
+        ("en", EmailTemplate::EmailVerification) => {
+            Some(template_source!("en", "email-verification"))
+        }
+        ("en", EmailTemplate::PasswordReset) => Some(template_source!("en", "password-reset")),
+        ("en", EmailTemplate::ContractorInvitation) => {
+            Some(template_source!("en", "contractor-invitation"))
+        }
+        ("en", EmailTemplate::ContractorAccountCreated) => {
+            Some(template_source!("en", "contractor-account-created"))
+        }
+        ("en", EmailTemplate::ContractSigningRequest) => {
+            Some(template_source!("en", "contract-signing-request"))
+        }
+        ("id", EmailTemplate::EmailVerification) => {
+            Some(template_source!("id", "email-verification"))
+        }
+        ("id", EmailTemplate::PasswordReset) => Some(template_source!("id", "password-reset")),
+        ("id", EmailTemplate::ContractorInvitation) => {
+            Some(template_source!("id", "contractor-invitation"))
+        }
+        ("id", EmailTemplate::ContractorAccountCreated) => {
+            Some(template_source!("id", "contractor-account-created"))
+        }
+        ("id", EmailTemplate::ContractSigningRequest) => {
+            Some(template_source!("id", "contract-signing-request"))
+        }
+        _ => None,
+    }
+}
+
+/// The locale preferred by whoever made a request, for the emails sent to them.
+///
+/// Only the first language of the `Accept-Language` header counts, as browsers put the one of
+/// the user first.
+pub fn request_locale(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(ACCEPT_LANGUAGE)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .split(';')
+        .next()
+        .map(str::trim)
+        .filter(|locale| !locale.is_empty() && *locale != "*")
+}
+
+/// The locale with templates for the people of a country, when it is not the default one.
+pub fn country_locale(country: Country) -> Option<&'static str> {
+    match country {
+        Country::ID => Some("id"),
+        _ => None,
+    }
+}
+
+/// The value of an environment variable the mail setup needs.
+fn env_var(name: &str) -> GlobeliseResult<String> {
+    std::env::var(name).map_err(|_| GlobeliseError::internal(format!("{} is not set", name)))
+}
+
+/// The locale with templates that is closest to `locale`.
+fn resolve_locale(locale: Option<&str>) -> &'static str {
+    let locale = match locale {
+        Some(locale) => locale.trim().replace('_', "-").to_lowercase(),
+        None => return DEFAULT_LOCALE,
+    };
+    let language = locale.split('-').next().unwrap_or_default();
+
+    LOCALES
+        .iter()
+        .find(|supported| **supported == locale)
+        .or_else(|| LOCALES.iter().find(|supported| **supported == language))
+        .copied()
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Replaces the placeholders of a template with their values.
+fn render(template: &str, variables: &[(&str, &str)], escape: bool) -> GlobeliseResult<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let (is_raw, open, close) = if rest.starts_with("{{{") {
+            (true, 3, "}}}")
+        } else {
+            (false, 2, "}}")
+        };
+        let end = rest[open..]
+            .find(close)
+            .ok_or_else(|| GlobeliseError::internal("An email template has an unclosed {{"))?;
+        let name = rest[open..open + end].trim();
+        let value = variables
+            .iter()
+            .find(|(variable, _)| *variable == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                GlobeliseError::internal(format!("No value for {} in an email template", name))
+            })?;
+
+        if escape && !is_raw {
+            output.push_str(&escape_html(value));
+        } else {
+            output.push_str(value);
+        }
+        rest = &rest[open + end + close.len()..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const TEMPLATES: [EmailTemplate; 5] = [
+        EmailTemplate::EmailVerification,
+        EmailTemplate::PasswordReset,
+        EmailTemplate::ContractorInvitation,
+        EmailTemplate::ContractorAccountCreated,
+        EmailTemplate::ContractSigningRequest,
+    ];
+
+    const VARIABLES: [(&str, &str); 4] = [
+        (
+            "link",
+            "https://app.globelise.com/signup?as=contractor&type=entity",
+        ),
+        ("contractor_type", "entity"),
+        ("password", "secret"),
+        ("body", "<p>Please sign</p>"),
+    ];
+
+    #[test]
+    fn every_template_renders_in_every_locale() {
+        for locale in LOCALES {
+            for template in TEMPLATES {
+                let email = template.render(Some(locale), &VARIABLES).unwrap();
+                assert_eq!(email.locale, locale);
+                assert!(!email.subject.is_empty());
+                assert!(!email.subject.contains('\n'));
+                assert!(!email.html_body.contains("{{"));
+                assert!(!email.text_body.contains("{{"));
+            }
+        }
+    }
+
+    #[test]
+    fn locales_fall_back_to_their_language_then_the_default() {
+        assert_eq!(resolve_locale(None), "en");
+        assert_eq!(resolve_locale(Some("id")), "id");
+        assert_eq!(resolve_locale(Some("id_ID")), "id");
+        assert_eq!(resolve_locale(Some(" ID-id ")), "id");
+        assert_eq!(resolve_locale(Some("en-GB")), "en");
+        assert_eq!(resolve_locale(Some("fr-FR")), "en");
+        assert_eq!(resolve_locale(Some("")), "en");
+    }
+
+    #[test]
+    fn html_values_are_escaped_unless_raw() {
+        let variables = [("name", "<b>Tom & \"Jerry's\"</b>")];
+
+        assert_eq!(
+            render("Hi {{ name }}", &variables, true).unwrap(),
+            "Hi &lt;b&gt;Tom &amp; &quot;Jerry&#39;s&quot;&lt;/b&gt;"
+        );
+        assert_eq!(
+            render("Hi {{{ name }}}", &variables, true).unwrap(),
+            "Hi <b>Tom & \"Jerry's\"</b>"
+        );
+        assert_eq!(
+            render("Hi {{name}}", &variables, false).unwrap(),
+            "Hi <b>Tom & \"Jerry's\"</b>"
+        );
+    }
+
+    #[test]
+    fn links_are_escaped_in_the_html_body_only() {
+        let email = EmailTemplate::ContractorInvitation
+            .render(None, &VARIABLES)
+            .unwrap();
+
+        assert!(email
+            .html_body
+            .contains("href=\"https://app.globelise.com/signup?as=contractor&amp;type=entity\""));
+        assert!(email
+            .text_body
+            .contains("https://app.globelise.com/signup?as=contractor&type=entity"));
+    }
+
+    #[test]
+    fn templates_with_missing_or_unclosed_placeholders_fail() {
+        assert!(render("Hi {{ name }}", &[], false).is_err());
+        assert!(render("Hi {{ name", &[("name", "Tom")], false).is_err());
+        assert!(EmailTemplate::PasswordReset.render(None, &[]).is_err());
+    }
+
+    #[test]
+    fn the_request_locale_is_the_first_accepted_language() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_locale(&headers), None);
+
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::from_static("id-ID,id;q=0.9,en;q=0.8"),
+        );
+        assert_eq!(request_locale(&headers), Some("id-ID"));
+
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en;q=0.8, id"));
+        assert_eq!(request_locale(&headers), Some("en"));
+
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("*"));
+        assert_eq!(request_locale(&headers), None);
+    }
+
+    #[test]
+    fn countries_without_a_locale_of_their_own_get_the_default() {
+        assert_eq!(resolve_locale(country_locale(Country::ID)), "id");
+        assert_eq!(resolve_locale(country_locale(Country::SG)), "en");
+    }
+}
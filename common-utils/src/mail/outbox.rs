@@ -0,0 +1,268 @@
+//! Sends the emails queued in the `email_outbox` table, retrying the ones that fail.
+
+use std::{sync::Arc, time::Duration};
+
+use lettre::{
+    message::{Mailbox, MultiPart},
+    Message,
+};
+
+use crate::{
+    database::{email_outbox::ClaimedOutboxEmail, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+use super::{env_var, transport::MailTransport};
+
+/// How long the sender waits before looking for due emails again when there were none.
+const DEFAULT_EMAIL_OUTBOX_INTERVAL_SECONDS: u64 = 5;
+
+/// How long an email can stay claimed before another sender takes it.
+const STALE_AFTER_SECONDS: i64 = 5 * 60;
+
+const MAX_ATTEMPTS: i32 = 6;
+
+/// The delay before the first retry, doubled on every attempt after that.
+const RETRY_BASE_SECONDS: i64 = 60;
+
+const RETRY_MAX_SECONDS: i64 = 60 * 60;
+
+/// Starts sending the emails in the outbox, from `GLOBELISE_SENDER_EMAIL`.
+///
+/// Fails when the sender is not configured, so that a service does not start without being
+/// able to send emails. The interval between looks at an empty outbox is configured with
+/// `EMAIL_OUTBOX_INTERVAL_SECONDS`.
+pub fn spawn_outbox_sender(
+    database: CommonDatabase,
+    transport: Arc<dyn MailTransport>,
+) -> GlobeliseResult<()> {
+    let sender = sender_from_env()?;
+    let interval = std::env::var("EMAIL_OUTBOX_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_EMAIL_OUTBOX_INTERVAL_SECONDS);
+
+    tokio::spawn(async move {
+        loop {
+            match send_next(&database, &transport, &sender).await {
+                Ok(true) => (),
+                Ok(false) => tokio::time::sleep(Duration::from_secs(interval)).await,
+                Err(e) => {
+                    println!("Cannot send the next email of the outbox: {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(interval)).await;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn sender_from_env() -> GlobeliseResult<Mailbox> {
+    env_var("GLOBELISE_SENDER_EMAIL")?
+        .parse::<Mailbox>()
+        .map_err(|e| {
+            GlobeliseError::internal(format!("GLOBELISE_SENDER_EMAIL is not a mailbox: {}", e))
+        })
+}
+
+/// Sends the next due email, returning false if there was none.
+async fn send_next(
+    database: &CommonDatabase,
+    transport: &Arc<dyn MailTransport>,
+    sender: &Mailbox,
+) -> GlobeliseResult<bool> {
+    let email = match database
+        .lock()
+        .await
+        .claim_next_outbox_email(STALE_AFTER_SECONDS)
+        .await?
+    {
+        Some(email) => email,
+        None => return Ok(false),
+    };
+
+    let result = match message(&email, sender) {
+        Ok(message) => {
+            let transport = transport.clone();
+            tokio::task::spawn_blocking(move || transport.send(&message))
+                .await
+                .unwrap_or_else(|e| {
+                    Err(GlobeliseError::internal(format!(
+                        "The mail transport panicked: {}",
+                        e
+                    )))
+                })
+                .map_err(|e| (e, true))
+        }
+        // An email that cannot be built never will be.
+        Err(e) => Err((e, false)),
+    };
+
+    let database = database.lock().await;
+    match result {
+        Ok(()) => database.update_outbox_email_as_sent(email.ulid).await?,
+        Err((error, can_retry)) => {
+            let retry_in_seconds = if can_retry {
+                retry_in_seconds(email.attempts)
+            } else {
+                None
+            };
+            // Only the message is kept, which the display of an internal error hides.
+            let error = match error {
+                GlobeliseError::Internal(message) => message,
+                error => error.to_string(),
+            };
+            database
+                .update_outbox_email_as_failed(email.ulid, &error, retry_in_seconds)
+                .await?
+        }
+    }
+
+    Ok(true)
+}
+
+/// How long to wait before the next attempt at an email that failed, if there is one.
+fn retry_in_seconds(attempts: i32) -> Option<i64> {
+    (attempts < MAX_ATTEMPTS)
+        .then(|| (RETRY_BASE_SECONDS << (attempts - 1).clamp(0, 16)).min(RETRY_MAX_SECONDS))
+}
+
+fn message(email: &ClaimedOutboxEmail, sender: &Mailbox) -> GlobeliseResult<Message> {
+    let message = Message::builder()
+        .from(sender.clone())
+        .reply_to(sender.clone())
+        .to(email.to_address.parse()?)
+        .subject(&email.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.clone(),
+            email.html_body.clone(),
+        ))?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    use crate::{
+        custom_serde::EmailWrapper,
+        database::Database,
+        mail::{transport::MemoryMailTransport, EmailTemplate},
+    };
+
+    use super::*;
+
+    struct FailingMailTransport;
+
+    impl MailTransport for FailingMailTransport {
+        fn send(&self, _: &Message) -> GlobeliseResult<()> {
+            Err(GlobeliseError::internal("The SMTP server is down"))
+        }
+    }
+
+    fn sender() -> Mailbox {
+        "Globelise <no-reply@globelise.com>".parse().unwrap()
+    }
+
+    fn claimed_email(to_address: &str) -> ClaimedOutboxEmail {
+        ClaimedOutboxEmail {
+            ulid: Uuid::new_v4(),
+            to_address: to_address.to_string(),
+            subject: "Verify Your Email Address".to_string(),
+            html_body: "<p>Hello</p>".to_string(),
+            text_body: "Hello".to_string(),
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn retries_back_off_until_the_last_attempt() {
+        assert_eq!(retry_in_seconds(1), Some(60));
+        assert_eq!(retry_in_seconds(2), Some(120));
+        assert_eq!(retry_in_seconds(5), Some(960));
+        assert_eq!(retry_in_seconds(MAX_ATTEMPTS), None);
+        assert_eq!(retry_in_seconds(MAX_ATTEMPTS + 1), None);
+    }
+
+    #[test]
+    fn messages_are_sent_from_the_sender() {
+        let message = message(&claimed_email("jane@example.com"), &sender()).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+
+        assert!(formatted.contains("From: Globelise <no-reply@globelise.com>"));
+        assert!(formatted.contains("Reply-To: Globelise <no-reply@globelise.com>"));
+        assert!(formatted.contains("To: jane@example.com"));
+        assert!(formatted.contains("Subject: Verify Your Email Address"));
+    }
+
+    #[test]
+    fn emails_to_invalid_addresses_cannot_be_built() {
+        assert!(message(&claimed_email("not an address"), &sender()).is_err());
+    }
+
+    async fn connect() -> CommonDatabase {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+
+        Arc::new(Mutex::new(Database(pool)))
+    }
+
+    async fn status(database: &CommonDatabase, ulid: Uuid) -> (String, i32, Option<String>) {
+        sqlx::query_as("SELECT status, attempts, html_body FROM email_outbox WHERE ulid = $1")
+            .bind(ulid)
+            .fetch_one(&database.lock().await.0)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn failed_emails_are_retried_then_sent_without_keeping_their_bodies() {
+        let database = connect().await;
+        let to = EmailWrapper("outbox-test@example.com".parse().unwrap());
+        let email = EmailTemplate::PasswordReset
+            .render(None, &[("link", "https://app.globelise.com/reset")])
+            .unwrap();
+        let ulid = database
+            .lock()
+            .await
+            .insert_one_outbox_email(&to, &email)
+            .await
+            .unwrap();
+
+        let failing: Arc<dyn MailTransport> = Arc::new(FailingMailTransport);
+        assert!(send_next(&database, &failing, &sender()).await.unwrap());
+        let (status_after_failure, attempts, html_body) = status(&database, ulid).await;
+        assert_eq!(status_after_failure, "pending");
+        assert_eq!(attempts, 1);
+        assert!(html_body.is_some());
+
+        sqlx::query("UPDATE email_outbox SET next_attempt_at = CURRENT_TIMESTAMP WHERE ulid = $1")
+            .bind(ulid)
+            .execute(&database.lock().await.0)
+            .await
+            .unwrap();
+        let memory = Arc::new(MemoryMailTransport::new());
+        let transport: Arc<dyn MailTransport> = memory.clone();
+        assert!(send_next(&database, &transport, &sender()).await.unwrap());
+
+        let (status_after_sending, attempts, html_body) = status(&database, ulid).await;
+        assert_eq!(status_after_sending, "sent");
+        assert_eq!(attempts, 2);
+        assert_eq!(html_body, None);
+
+        let messages = memory.messages();
+        assert_eq!(messages.len(), 1);
+        let formatted = String::from_utf8(messages[0].formatted()).unwrap();
+        assert!(formatted.contains("To: outbox-test@example.com"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Where the outbox sender sends emails: an SMTP server in production, files or memory when
+//! developing and testing.
+
+use std::{path::PathBuf, sync::Arc};
+
+use lettre::{transport::smtp::authentication::Credentials, Message, SmtpTransport, Transport};
+use uuid::Uuid;
+
+use crate::error::{GlobeliseError, GlobeliseResult};
+
+use super::env_var;
+
+pub trait MailTransport: Send + Sync {
+    /// Sends one email. It is called from a blocking thread, so it can block.
+    fn send(&self, message: &Message) -> GlobeliseResult<()>;
+}
+
+pub struct SmtpMailTransport(SmtpTransport);
+
+impl SmtpMailTransport {
+    /// Connects to an SMTP relay over TLS.
+    pub fn relay(url: &str, username: String, password: String) -> GlobeliseResult<Self> {
+        let transport = SmtpTransport::relay(url)?
+            .credentials(Credentials::new(username, password))
+            .build();
+
+        Ok(SmtpMailTransport(transport))
+    }
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send(&self, message: &Message) -> GlobeliseResult<()> {
+        self.0.send(message)?;
+
+        Ok(())
+    }
+}
+
+/// Writes every email to an `.eml` file in a directory instead of sending it.
+pub struct FileMailTransport {
+    directory: PathBuf,
+}
+
+impl FileMailTransport {
+    pub fn new(directory: PathBuf) -> GlobeliseResult<Self> {
+        std::fs::create_dir_all(&directory)?;
+
+        Ok(FileMailTransport { directory })
+    }
+}
+
+impl MailTransport for FileMailTransport {
+    fn send(&self, message: &Message) -> GlobeliseResult<()> {
+        let path = self
+            .directory
+            .join(format!("{}.eml", Uuid::new_v4().to_simple()));
+        std::fs::write(path, message.formatted())?;
+
+        Ok(())
+    }
+}
+
+/// Keeps every email in memory, for tests to check what was sent.
+#[derive(Default)]
+pub struct MemoryMailTransport {
+    messages: std::sync::Mutex<Vec<Message>>,
+}
+
+impl MemoryMailTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The emails sent so far, oldest first.
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages
+            .lock()
+            .map(|messages| messages.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl MailTransport for MemoryMailTransport {
+    fn send(&self, message: &Message) -> GlobeliseResult<()> {
+        self.messages
+            .lock()
+            .map_err(|_| GlobeliseError::internal("The in-memory mail transport is poisoned"))?
+            .push(message.clone());
+
+        Ok(())
+    }
+}
+
+/// The transport configured with `MAIL_TRANSPORT`.
+///
+/// - `smtp`, the default, uses `GLOBELISE_SMTP_URL`, `GLOBELISE_SMTP_USERNAME` and
+///   `GLOBELISE_SMTP_PASSWORD`.
+/// - `file` writes to `MAIL_DROP_DIRECTORY`, or to `globelise-mail` in the temporary directory.
+/// - `memory` keeps the emails until the service stops.
+pub fn transport_from_env() -> GlobeliseResult<Arc<dyn MailTransport>> {
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "smtp".to_string());
+
+    Ok(match transport.as_str() {
+        "smtp" => Arc::new(SmtpMailTransport::relay(
+            &env_var("GLOBELISE_SMTP_URL")?,
+            env_var("GLOBELISE_SMTP_USERNAME")?,
+            env_var("GLOBELISE_SMTP_PASSWORD")?,
+        )?),
+        "file" => Arc::new(FileMailTransport::new(
+            std::env::var("MAIL_DROP_DIRECTORY")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| std::env::temp_dir().join("globelise-mail")),
+        )?),
+        "memory" => Arc::new(MemoryMailTransport::new()),
+        transport => {
+            return Err(GlobeliseError::internal(format!(
+                "Unknown MAIL_TRANSPORT {}",
+                transport
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(to: &str) -> Message {
+        Message::builder()
+            .from("no-reply@globelise.com".parse().unwrap())
+            .to(to.parse().unwrap())
+            .subject("Invitation to Globelise")
+            .body("Hello".to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn the_memory_transport_keeps_the_emails_in_order() {
+        let transport = MemoryMailTransport::new();
+        assert!(transport.messages().is_empty());
+
+        transport.send(&message("jane@example.com")).unwrap();
+        transport.send(&message("john@example.com")).unwrap();
+
+        let recipients = transport
+            .messages()
+            .iter()
+            .map(|message| String::from_utf8(message.formatted()).unwrap())
+            .map(|formatted| formatted.contains("To: jane@example.com"))
+            .collect::<Vec<_>>();
+        assert_eq!(recipients, [true, false]);
+    }
+
+    #[test]
+    fn the_file_transport_writes_one_file_per_email() {
+        let directory = std::env::temp_dir().join(format!("globelise-mail-{}", Uuid::new_v4()));
+        let transport = FileMailTransport::new(directory.clone()).unwrap();
+
+        transport.send(&message("jane@example.com")).unwrap();
+
+        let files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: Invitation to Globelise"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
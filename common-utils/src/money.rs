@@ -0,0 +1,250 @@
+//! Amounts of money as exact decimals.
+//!
+//! Money must never go through floating point numbers: `0.1 + 0.2` is not `0.3` in `f64`,
+//! which is enough to reject a balanced journal or to pay out a cent less. A [`Money`] is a
+//! decimal amount in a currency, with no more decimal places than the currency has minor
+//! units.
+
+use std::{fmt, ops::Neg};
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::Decimal;
+
+use crate::{
+    custom_serde::Currency,
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+/// The number of decimal places of a currency, by ISO 4217 code.
+pub fn minor_units(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+impl Currency {
+    pub fn minor_units(&self) -> u32 {
+        minor_units(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", try_from = "MoneyData")]
+pub struct Money {
+    amount: Decimal,
+    currency: Currency,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct MoneyData {
+    amount: Decimal,
+    currency: Currency,
+}
+
+impl TryFrom<MoneyData> for Money {
+    type Error = GlobeliseError;
+
+    fn try_from(data: MoneyData) -> Result<Self, Self::Error> {
+        Money::new(data.amount, data.currency)
+    }
+}
+
+impl Money {
+    /// Creates an amount, rejecting more decimal places than the currency has.
+    pub fn new(amount: Decimal, currency: Currency) -> GlobeliseResult<Self> {
+        let minor_units = currency.minor_units();
+        if amount.normalize().scale() > minor_units {
+            return Err(GlobeliseError::bad_request(format!(
+                "{} amounts can have at most {} decimal places, got {}",
+                currency.as_str(),
+                minor_units,
+                amount
+            )));
+        }
+
+        Ok(Self::round(amount, currency))
+    }
+
+    /// Creates an amount, rounding it half to even to the minor units of the currency.
+    pub fn round(amount: Decimal, currency: Currency) -> Self {
+        let mut amount = amount.round_dp(currency.minor_units());
+        amount.rescale(currency.minor_units());
+        Self { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::round(Decimal::ZERO, currency)
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.amount > Decimal::ZERO
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount < Decimal::ZERO
+    }
+
+    /// Adds two amounts of the same currency.
+    pub fn checked_add(&self, other: &Money) -> GlobeliseResult<Money> {
+        if self.currency.as_str() != other.currency.as_str() {
+            return Err(GlobeliseError::bad_request(format!(
+                "Cannot add {} to {}",
+                other, self
+            )));
+        }
+        let amount = self
+            .amount
+            .checked_add(other.amount)
+            .ok_or_else(|| GlobeliseError::bad_request("The amount is too large"))?;
+
+        Ok(Self::round(amount, self.currency))
+    }
+
+    /// Adds up amounts that must all be in `currency`.
+    pub fn sum<'a>(
+        currency: Currency,
+        amounts: impl IntoIterator<Item = &'a Money>,
+    ) -> GlobeliseResult<Money> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), |total, amount| {
+                total.checked_add(amount)
+            })
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money {
+            amount: -self.amount,
+            currency: self.currency,
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn sgd(amount: &str) -> Money {
+        Money::new(dec(amount), Currency::SGD).unwrap()
+    }
+
+    #[test]
+    fn amounts_have_the_decimal_places_of_their_currency() {
+        assert_eq!(sgd("12.5").amount().to_string(), "12.50");
+        assert_eq!(sgd("12.500").amount().to_string(), "12.50");
+        assert_eq!(
+            Money::new(dec("1500"), Currency::JPY)
+                .unwrap()
+                .amount()
+                .to_string(),
+            "1500"
+        );
+        assert_eq!(
+            Money::new(dec("1.005"), Currency::KWD)
+                .unwrap()
+                .amount()
+                .to_string(),
+            "1.005"
+        );
+    }
+
+    #[test]
+    fn amounts_with_too_many_decimal_places_are_rejected() {
+        assert!(Money::new(dec("12.505"), Currency::SGD).is_err());
+        assert!(Money::new(dec("1500.5"), Currency::JPY).is_err());
+        assert!(Money::new(dec("1.0005"), Currency::KWD).is_err());
+    }
+
+    #[test]
+    fn rounding_is_half_to_even() {
+        assert_eq!(
+            Money::round(dec("2.345"), Currency::SGD).amount(),
+            dec("2.34")
+        );
+        assert_eq!(
+            Money::round(dec("2.355"), Currency::SGD).amount(),
+            dec("2.36")
+        );
+        assert_eq!(
+            Money::round(dec("-2.345"), Currency::SGD).amount(),
+            dec("-2.34")
+        );
+        assert_eq!(Money::round(dec("2.5"), Currency::JPY).amount(), dec("2"));
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let total = sgd("0.1").checked_add(&sgd("0.2")).unwrap();
+        assert_eq!(total.amount().to_string(), "0.30");
+
+        let total =
+            Money::sum(Currency::SGD, &[sgd("1000.10"), sgd("-0.10"), sgd("0.01")]).unwrap();
+        assert_eq!(total.amount().to_string(), "1000.01");
+        assert_eq!(
+            Money::sum(Currency::SGD, std::iter::empty())
+                .unwrap()
+                .amount()
+                .to_string(),
+            "0.00"
+        );
+    }
+
+    #[test]
+    fn amounts_in_different_currencies_cannot_be_added() {
+        let usd = Money::new(dec("1"), Currency::USD).unwrap();
+
+        assert!(sgd("1").checked_add(&usd).is_err());
+        assert!(Money::sum(Currency::SGD, &[sgd("1"), usd]).is_err());
+    }
+
+    #[test]
+    fn signs_and_display() {
+        let amount = sgd("12.5");
+
+        assert!(amount.is_positive());
+        assert!((-amount).is_negative());
+        assert!(Money::zero(Currency::SGD).is_zero());
+        assert_eq!((-amount).to_string(), "-12.50 SGD");
+    }
+
+    #[test]
+    fn deserializing_checks_the_decimal_places() {
+        let money: Money =
+            serde_json::from_str(r#"{"amount": "12.5", "currency": "SGD"}"#).unwrap();
+        assert_eq!(money.amount().to_string(), "12.50");
+
+        assert!(
+            serde_json::from_str::<Money>(r#"{"amount": "12.505", "currency": "SGD"}"#).is_err()
+        );
+    }
+}
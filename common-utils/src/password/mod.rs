@@ -0,0 +1,244 @@
+//! Password policy shared by every flow that sets a password.
+
+use std::collections::HashSet;
+
+use argon2::verify_encoded;
+use once_cell::sync::Lazy;
+use time::{Duration, OffsetDateTime};
+
+use crate::error::{GlobeliseError, GlobeliseResult};
+
+/// Offline list of passwords known to appear in public breaches.
+///
+/// One password per line, compared case-insensitively.
+static BREACHED_PASSWORDS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    include_str!("breached_passwords.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+});
+
+/// The password policy configured for this deployment.
+pub static PASSWORD_POLICY: Lazy<PasswordPolicy> = Lazy::new(PasswordPolicy::from_env);
+
+/// Rules that a new password must satisfy.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// How long a password stays valid before it has to be reset.
+    pub max_age: Option<Duration>,
+    /// Number of most recent passwords, including the current one, that cannot be reused.
+    pub history_size: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            max_age: None,
+            history_size: 5,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Creates the policy from the `PASSWORD_*` environment variables.
+    ///
+    /// Unset variables fall back to the default policy.
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.parse().ok())
+        }
+
+        let default = Self::default();
+        Self {
+            min_length: var("PASSWORD_MIN_LENGTH").unwrap_or(default.min_length),
+            require_lowercase: var("PASSWORD_REQUIRE_LOWERCASE")
+                .unwrap_or(default.require_lowercase),
+            require_uppercase: var("PASSWORD_REQUIRE_UPPERCASE")
+                .unwrap_or(default.require_uppercase),
+            require_digit: var("PASSWORD_REQUIRE_DIGIT").unwrap_or(default.require_digit),
+            require_symbol: var("PASSWORD_REQUIRE_SYMBOL").unwrap_or(default.require_symbol),
+            max_age: var::<i64>("PASSWORD_MAX_AGE_DAYS")
+                .filter(|days| *days > 0)
+                .map(Duration::days)
+                .or(default.max_age),
+            history_size: var("PASSWORD_HISTORY_SIZE").unwrap_or(default.history_size),
+        }
+    }
+
+    /// Checks the password against the length, character class and breached password rules.
+    ///
+    /// The password should already be NFC normalized.
+    pub fn validate(&self, password: &str) -> GlobeliseResult<()> {
+        let mut problems = vec![];
+
+        if password.chars().count() < self.min_length {
+            problems.push(format!("be at least {} characters long", self.min_length));
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            problems.push("contain a lowercase letter".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            problems.push("contain an uppercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            problems.push("contain a digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            problems.push("contain a symbol".to_string());
+        }
+
+        if !problems.is_empty() {
+            return Err(GlobeliseError::bad_request(format!(
+                "Password must {}",
+                problems.join(", ")
+            )));
+        }
+
+        if BREACHED_PASSWORDS.contains(password.to_lowercase().as_str()) {
+            return Err(GlobeliseError::bad_request(
+                "Password has appeared in a data breach. Please choose a different password",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the password does not match any of the given previous password hashes.
+    ///
+    /// Only the first `history_size` hashes are considered, so callers should pass them
+    /// from the most recent to the oldest.
+    pub fn check_history<'a, I>(&self, password: &str, previous_hashes: I) -> GlobeliseResult<()>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for hash in previous_hashes.into_iter().take(self.history_size) {
+            if let Ok(true) = verify_encoded(hash, password.as_bytes()) {
+                return Err(GlobeliseError::bad_request(format!(
+                    "Password cannot be the same as any of the last {} passwords",
+                    self.history_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a password set at the given time must be changed before it can be used.
+    pub fn is_expired(&self, password_changed_at: OffsetDateTime) -> bool {
+        match self.max_age {
+            Some(max_age) => password_changed_at + max_age < OffsetDateTime::now_utc(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{hash_encoded, Config};
+
+    use super::*;
+
+    fn hash(password: &str) -> String {
+        hash_encoded(
+            password.as_bytes(),
+            b"password-test-salt",
+            &Config::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn passwords_meeting_the_policy_are_accepted() {
+        let policy = PasswordPolicy::default();
+
+        assert!(policy.validate("Correct7Horse").is_ok());
+    }
+
+    #[test]
+    fn every_broken_rule_is_reported_at_once() {
+        let policy = PasswordPolicy {
+            require_symbol: true,
+            ..PasswordPolicy::default()
+        };
+
+        let error = policy.validate("short").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Password must be at least 8 characters long, contain an uppercase letter, \
+             contain a digit, contain a symbol"
+        );
+        assert!(policy.validate("Correct7Horse!").is_ok());
+    }
+
+    #[test]
+    fn length_counts_characters_rather_than_bytes() {
+        let policy = PasswordPolicy {
+            min_length: 4,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            ..PasswordPolicy::default()
+        };
+
+        assert!(policy.validate("ééé").is_err());
+        assert!(policy.validate("éééé").is_ok());
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_whatever_their_case() {
+        let policy = PasswordPolicy {
+            min_length: 1,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            ..PasswordPolicy::default()
+        };
+        let breached = *BREACHED_PASSWORDS.iter().next().unwrap();
+
+        assert!(policy.validate(breached).is_err());
+        assert!(policy.validate(&breached.to_uppercase()).is_err());
+    }
+
+    #[test]
+    fn only_the_most_recent_passwords_cannot_be_reused() {
+        let policy = PasswordPolicy {
+            history_size: 2,
+            ..PasswordPolicy::default()
+        };
+        let hashes = [
+            hash("Newest7Horse"),
+            hash("Older7Horse"),
+            hash("Oldest7Horse"),
+        ];
+        let hashes = || hashes.iter().map(String::as_str);
+
+        assert!(policy.check_history("Newest7Horse", hashes()).is_err());
+        assert!(policy.check_history("Older7Horse", hashes()).is_err());
+        assert!(policy.check_history("Oldest7Horse", hashes()).is_ok());
+        assert!(policy.check_history("Unused7Horse", hashes()).is_ok());
+    }
+
+    #[test]
+    fn passwords_expire_after_the_max_age_if_there_is_one() {
+        let policy = PasswordPolicy {
+            max_age: Some(Duration::days(90)),
+            ..PasswordPolicy::default()
+        };
+        let now = OffsetDateTime::now_utc();
+
+        assert!(!policy.is_expired(now - Duration::days(89)));
+        assert!(policy.is_expired(now - Duration::days(91)));
+        assert!(!PasswordPolicy::default().is_expired(now - Duration::days(3650)));
+    }
+}
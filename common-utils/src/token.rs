@@ -1,27 +1,39 @@
 //! Functions and types for handling authorization tokens.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use axum::{
     async_trait,
     extract::{Extension, FromRequest, Query, RequestParts, TypedHeader},
     headers::{authorization::Bearer, Authorization},
+    http::Method,
 };
-use http_cache_reqwest::{Cache, CacheMode, HttpCache, MokaManager};
 use jsonwebtoken::{
-    decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData,
+    Validation,
 };
 use once_cell::sync::Lazy;
 use reqwest::{
     header::{HeaderMap, HeaderValue, AUTHORIZATION},
     Client as ReqwestClient, StatusCode,
 };
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+    signature::{Ed25519KeyPair, KeyPair},
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 use tokio::sync::Mutex;
+use uuid::Uuid;
 
 use crate::{
+    api_key::{hash_api_key, ApiKeyScope, API_KEY_LAST_USED_PRECISION_SECONDS, API_KEY_PREFIX},
+    database::{api_key::ApiKey, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
     DaprAppId,
 };
@@ -29,33 +41,292 @@ use crate::{
 /// The issuer of tokens, used in the `iss` field of JWTs.
 pub const ISSUER: &str = "https://globelise.com";
 
-/// Stores the keys used for encoding and decoding tokens.
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, followed by the raw 32 byte key.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// An Ed25519 key pair used for signing tokens.
 #[derive(Clone)]
-pub struct Keys {
+pub struct SigningKey {
+    /// Identifies the key in the `kid` header of JWTs.
+    pub kid: String,
     pub encoding: EncodingKey,
     pub decoding: DecodingKey,
+    /// The private key in PKCS#8 DER form.
+    pub private_key: Vec<u8>,
+    /// The raw public key.
+    pub public_key: Vec<u8>,
+    /// When the key stops being accepted, or `None` if this is the signing key.
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+impl SigningKey {
+    /// Creates a key pair from a PKCS#8 DER private key.
+    pub fn from_pkcs8(
+        private_key: Vec<u8>,
+        expires_at: Option<OffsetDateTime>,
+    ) -> GlobeliseResult<Self> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&private_key)
+            .map_err(|e| GlobeliseError::internal(format!("Invalid signing key: {}", e)))?;
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        // The key ID is derived from the public key so that every replica agrees on it.
+        let kid = base64::encode_config(
+            digest(&SHA256, &public_key).as_ref(),
+            base64::URL_SAFE_NO_PAD,
+        )[..16]
+            .to_string();
+
+        Ok(Self {
+            kid,
+            encoding: EncodingKey::from_ed_der(&private_key),
+            decoding: DecodingKey::from_ed_der(&public_key),
+            private_key,
+            public_key,
+            expires_at,
+        })
+    }
+
+    /// Creates a key pair from a PKCS#8 PEM private key.
+    pub fn from_pem(private_key: &str) -> GlobeliseResult<Self> {
+        let body = private_key
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect::<String>();
+        let der = base64::decode(body.trim())?;
+        Self::from_pkcs8(der, None)
+    }
+
+    /// Generates a new random key pair.
+    pub fn generate() -> GlobeliseResult<Self> {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| GlobeliseError::internal("Could not generate signing key"))?;
+        Self::from_pkcs8(document.as_ref().to_vec(), None)
+    }
+
+    /// Encrypts the private key to store it, bound to the service it belongs to.
+    ///
+    /// The result is the nonce followed by the ciphertext and its tag.
+    pub fn seal(&self, dapr_app_id: DaprAppId) -> GlobeliseResult<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| GlobeliseError::internal("Could not generate a nonce"))?;
+
+        let mut sealed = self.private_key.clone();
+        signing_key_encryption_key()?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(dapr_app_id.as_str().as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| GlobeliseError::internal("Could not encrypt the signing key"))?;
+
+        Ok([nonce.as_slice(), sealed.as_slice()].concat())
+    }
+
+    /// Decrypts a private key encrypted by [`SigningKey::seal`].
+    pub fn open(
+        dapr_app_id: DaprAppId,
+        sealed: &[u8],
+        expires_at: Option<OffsetDateTime>,
+    ) -> GlobeliseResult<Self> {
+        if sealed.len() < NONCE_LEN {
+            return Err(GlobeliseError::internal(
+                "Encrypted signing key is too short",
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| GlobeliseError::internal("Invalid signing key nonce"))?;
+
+        let mut private_key = ciphertext.to_vec();
+        let length = signing_key_encryption_key()?
+            .open_in_place(
+                nonce,
+                Aad::from(dapr_app_id.as_str().as_bytes()),
+                &mut private_key,
+            )
+            .map_err(|_| GlobeliseError::internal("Could not decrypt the signing key"))?
+            .len();
+        private_key.truncate(length);
+
+        Self::from_pkcs8(private_key, expires_at)
+    }
+
+    /// The public key in PEM form.
+    pub fn public_key_pem(&self) -> String {
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend_from_slice(&self.public_key);
+        format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            base64::encode(der)
+        )
+    }
+
+    /// The public key as a JWK.
+    pub fn jwk(&self) -> Jwk {
+        Jwk {
+            kty: "OKP".into(),
+            crv: "Ed25519".into(),
+            alg: "EdDSA".into(),
+            r#use: "sig".into(),
+            kid: self.kid.clone(),
+            x: base64::encode_config(&self.public_key, base64::URL_SAFE_NO_PAD),
+        }
+    }
+
+    /// A JWT header that names this key.
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+        header
+    }
+
+    fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= OffsetDateTime::now_utc())
+    }
+}
+
+/// The key that signing keys are encrypted with in the database, from the base64 encoded
+/// 32 bytes in `SIGNING_KEY_ENCRYPTION_KEY`.
+///
+/// It is kept outside of the database so that reading the database is not enough to sign
+/// tokens.
+fn signing_key_encryption_key() -> GlobeliseResult<LessSafeKey> {
+    let key = std::env::var("SIGNING_KEY_ENCRYPTION_KEY")
+        .map_err(|_| GlobeliseError::internal("SIGNING_KEY_ENCRYPTION_KEY is not set"))?;
+    let key = base64::decode(key.trim())?;
+    let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| {
+        GlobeliseError::internal("SIGNING_KEY_ENCRYPTION_KEY must be 32 bytes, base64 encoded")
+    })?;
+
+    Ok(LessSafeKey::new(key))
 }
 
-impl Keys {
-    /// Creates a new encoding/decoding key pair from an RSA key pair.
+/// The signing key of a service, along with older keys that are still accepted.
+pub struct KeyRing(RwLock<Vec<Arc<SigningKey>>>);
+
+impl KeyRing {
+    /// Creates a key ring that only contains the given signing key.
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self(RwLock::new(vec![Arc::new(signing_key)]))
+    }
+
+    /// Replaces the keys in the ring.
     ///
-    /// The private key must be in PEM form, and the public key in JWK form.
-    pub fn new(private_key: &[u8], public_key: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_ed_pem(private_key).expect("Could not create encoding key"),
-            decoding: DecodingKey::from_ed_pem(public_key).expect("Could not create decoding key"),
+    /// The key without an expiry is used for signing. Empty lists are ignored.
+    pub fn replace(&self, keys: Vec<SigningKey>) {
+        if keys.is_empty() {
+            return;
         }
+        let mut keys = keys.into_iter().map(Arc::new).collect::<Vec<_>>();
+        keys.sort_by_key(|key| key.expires_at.is_some());
+        *self.0.write().expect("Key ring lock is poisoned") = keys;
     }
+
+    /// Gets the key used for signing new tokens.
+    pub fn current(&self) -> Arc<SigningKey> {
+        self.0.read().expect("Key ring lock is poisoned")[0].clone()
+    }
+
+    /// Gets the key that signed a token.
+    ///
+    /// Tokens without a `kid` were issued before key rotation and use the signing key.
+    pub fn get(&self, kid: Option<&str>) -> GlobeliseResult<Arc<SigningKey>> {
+        match kid {
+            Some(kid) => self
+                .0
+                .read()
+                .expect("Key ring lock is poisoned")
+                .iter()
+                .find(|key| key.kid == kid && !key.is_expired())
+                .cloned()
+                .ok_or_else(|| GlobeliseError::unauthorized("Token signed by an unknown key")),
+            None => Ok(self.current()),
+        }
+    }
+
+    /// Loads the signing keys of a service stored in the database.
+    ///
+    /// Keeps the current keys if none are stored yet.
+    pub async fn reload(
+        &self,
+        database: &CommonDatabase,
+        dapr_app_id: DaprAppId,
+    ) -> GlobeliseResult<()> {
+        let keys = database
+            .lock()
+            .await
+            .select_many_signing_keys(dapr_app_id)
+            .await?;
+        self.replace(keys);
+        Ok(())
+    }
+
+    /// Reloads the signing keys every minute, so that rotations done by other replicas are
+    /// picked up.
+    pub async fn reload_periodically(&self, database: CommonDatabase, dapr_app_id: DaprAppId) {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            if let Err(e) = self.reload(&database, dapr_app_id).await {
+                println!("Could not reload signing keys: {:?}", e);
+            }
+        }
+    }
+
+    /// Gets the public keys that are still accepted, signing key first.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .0
+                .read()
+                .expect("Key ring lock is poisoned")
+                .iter()
+                .filter(|key| !key.is_expired())
+                .map(|key| key.jwk())
+                .collect(),
+        }
+    }
+}
+
+/// A public key in JWK form.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub alg: String,
+    pub r#use: String,
+    pub kid: String,
+    /// The base64url encoded raw public key.
+    pub x: String,
+}
+
+/// A set of public keys in JWKS form.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
 }
 
 /// Creates an access token.
-pub fn create_token<P>(payload: P, encoding: &EncodingKey) -> Result<(String, i64), GlobeliseError>
+pub fn create_token<P>(payload: P, key: &SigningKey) -> Result<(String, i64), GlobeliseError>
+where
+    P: std::fmt::Debug + Serialize + DeserializeOwned + TokenLike,
+{
+    create_token_with_lifetime(payload, key, P::exp())
+}
+
+/// Creates an access token that expires after the given lifetime instead of the default one.
+pub fn create_token_with_lifetime<P>(
+    payload: P,
+    key: &SigningKey,
+    lifetime: Duration,
+) -> Result<(String, i64), GlobeliseError>
 where
     P: std::fmt::Debug + Serialize + DeserializeOwned + TokenLike,
 {
-    let claims = Token::new(payload)?;
-    let token = encode(&Header::new(Algorithm::EdDSA), &claims, encoding)
-        .map_err(GlobeliseError::internal)?;
+    let claims = Token::with_lifetime(payload, lifetime)?;
+    let token = encode(&key.header(), &claims, &key.encoding).map_err(GlobeliseError::internal)?;
     Ok((token, claims.exp))
 }
 
@@ -63,6 +334,31 @@ pub trait TokenLike {
     fn aud() -> &'static str;
     fn exp() -> Duration;
     fn dapr_app_id() -> DaprAppId;
+
+    /// Set when an admin obtained this token to act as a user.
+    fn impersonation(&self) -> Option<&Impersonation> {
+        None
+    }
+
+    /// Creates the payload for a request made with an API key.
+    ///
+    /// Returns `None` if this kind of token cannot be replaced by an API key.
+    fn from_api_key(_api_key: &ApiKey) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+/// Marks a token that an admin obtained to act as a user.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Impersonation {
+    pub admin_ulid: Uuid,
+    pub user_ulid: Uuid,
+    /// Whether the admin is limited to requests that do not change anything.
+    pub read_only: bool,
 }
 
 /// Claims for access tokens.
@@ -76,6 +372,8 @@ where
     aud: String,
     iss: String,
     exp: i64,
+    #[serde(skip)]
+    kid: Option<String>,
 }
 
 impl<P> Token<P>
@@ -83,9 +381,13 @@ where
     P: TokenLike,
 {
     pub fn new(payload: P) -> GlobeliseResult<Self> {
+        Self::with_lifetime(payload, P::exp())
+    }
+
+    pub fn with_lifetime(payload: P, lifetime: Duration) -> GlobeliseResult<Self> {
         let exp = OffsetDateTime::now_utc()
             .unix_timestamp()
-            .checked_add(P::exp().whole_seconds())
+            .checked_add(lifetime.whole_seconds())
             .ok_or_else(|| {
                 GlobeliseError::Internal(
                     "Could not calculate access token expiration timestamp".into(),
@@ -97,6 +399,62 @@ where
             aud: P::aud().to_string(),
             iss: ISSUER.to_string(),
             exp,
+            kid: None,
+        })
+    }
+
+    /// The ID of the key that signed this token.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// Encodes the token exactly as it was issued, using the key that signed it.
+    pub fn reencode(&self, keys: &KeyRing) -> GlobeliseResult<String>
+    where
+        P: Serialize,
+    {
+        let key = keys.get(self.kid())?;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = self.kid.clone();
+        Ok(encode(&header, self, &key.encoding)?)
+    }
+
+    /// Authenticates a request made with an API key instead of a token.
+    async fn from_api_key<B>(req: &mut RequestParts<B>, api_key: &str) -> GlobeliseResult<Self>
+    where
+        B: Send,
+    {
+        let scope = ApiKeyScope::required_for(req.method(), req.uri().path()).ok_or_else(|| {
+            GlobeliseError::unauthorized("API keys cannot be used for this request")
+        })?;
+
+        let Extension(database) = Extension::<CommonDatabase>::from_request(req).await?;
+        let database = database.lock().await;
+        let api_key = database
+            .select_one_active_api_key_by_hash(&hash_api_key(api_key))
+            .await?
+            .ok_or_else(|| GlobeliseError::unauthorized("Invalid API key"))?;
+        if !api_key.has_scope(scope) {
+            return Err(GlobeliseError::Forbidden);
+        }
+        let payload = P::from_api_key(&api_key).ok_or_else(|| {
+            GlobeliseError::unauthorized("API keys cannot be used for this request")
+        })?;
+        let last_used_recently = matches!(
+            api_key.last_used_at,
+            Some(last_used_at) if OffsetDateTime::now_utc() - last_used_at
+                < Duration::seconds(API_KEY_LAST_USED_PRECISION_SECONDS)
+        );
+        if !last_used_recently {
+            database.update_one_api_key_last_used(api_key.ulid).await?;
+        }
+
+        Ok(Token {
+            payload,
+            aud: P::aud().to_string(),
+            iss: ISSUER.to_string(),
+            exp: api_key.expires_at.unix_timestamp(),
+            kid: None,
         })
     }
 
@@ -112,8 +470,9 @@ where
             validation
         };
 
-        let TokenData { claims, .. } = decode::<Token<P>>(input, decoding, &validation)
+        let TokenData { header, mut claims } = decode::<Token<P>>(input, decoding, &validation)
             .map_err(|e| GlobeliseError::unauthorized(e.to_string()))?;
+        claims.kid = header.kid;
 
         Ok(claims)
     }
@@ -129,52 +488,137 @@ where
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
         let Extension(public_keys) = Extension::<SharedPublicKeys>::from_request(req).await?;
-        let mut public_keys = public_keys.lock().await;
-        let decoding_key = public_keys.get(P::dapr_app_id()).await?;
-        if let Ok(TypedHeader(Authorization(bearer))) =
+        let token = if let Ok(TypedHeader(Authorization(bearer))) =
             TypedHeader::<Authorization<Bearer>>::from_request(req).await
         {
-            Ok(Token::decode(bearer.token(), decoding_key).await?)
-        } else if let Ok(Query(param)) = Query::<HashMap<String, String>>::from_request(req).await {
-            let token = param.get("token").ok_or_else(|| {
+            // API keys are only accepted in the header, so they do not end up in URL logs.
+            if bearer.token().starts_with(API_KEY_PREFIX) {
+                return Token::from_api_key(req, bearer.token()).await;
+            }
+            bearer.token().to_string()
+        } else if let Ok(Query(mut param)) =
+            Query::<HashMap<String, String>>::from_request(req).await
+        {
+            param.remove("token").ok_or_else(|| {
                 GlobeliseError::unauthorized(
                     "Please provide access token in the query param or as auth bearer",
                 )
-            })?;
-            Ok(Token::decode(token.as_str(), decoding_key).await?)
+            })?
         } else {
-            Err(GlobeliseError::unauthorized(
+            return Err(GlobeliseError::unauthorized(
                 "Please provide access token in the query param or as auth bearer",
-            ))
+            ));
+        };
+
+        let kid = decode_header(&token)
+            .map_err(|e| GlobeliseError::unauthorized(e.to_string()))?
+            .kid;
+        let mut public_keys = public_keys.lock().await;
+        let decoding_key = public_keys.get(P::dapr_app_id(), kid.as_deref()).await?;
+        let token = Token::<P>::decode(&token, decoding_key).await?;
+        drop(public_keys);
+
+        if let Some(impersonation) = token.payload.impersonation() {
+            let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+            if impersonation.read_only && !is_read {
+                return Err(GlobeliseError::Forbidden);
+            }
+
+            // Every request made while impersonating is kept in the audit trail.
+            let Extension(database) = Extension::<CommonDatabase>::from_request(req).await?;
+            database
+                .lock()
+                .await
+                .insert_one_impersonation_log(
+                    impersonation,
+                    req.method().as_str(),
+                    req.uri().path(),
+                )
+                .await?;
         }
+
+        Ok(token)
+    }
+}
+
+/// A token that was not obtained by an admin to act as a user.
+///
+/// Impersonating admins are otherwise only limited by the HTTP method. This is used instead of
+/// [`Token`] for the requests they cannot make even though they do not change anything, like
+/// downloading files or listing API keys.
+#[derive(Debug)]
+pub struct NotImpersonated<P>(pub Token<P>)
+where
+    P: TokenLike;
+
+#[async_trait]
+impl<P, B> FromRequest<B> for NotImpersonated<P>
+where
+    B: Send,
+    P: std::fmt::Debug + Serialize + DeserializeOwned + TokenLike,
+{
+    type Rejection = GlobeliseError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let token = Token::<P>::from_request(req).await?;
+        if token.payload.impersonation().is_some() {
+            return Err(GlobeliseError::Forbidden);
+        }
+
+        Ok(NotImpersonated(token))
     }
 }
 
 /// HTTP client for public keys
-static HTTP_CLIENT: Lazy<ClientWithMiddleware> = Lazy::new(|| {
-    ClientBuilder::new(ReqwestClient::new())
-        .with(Cache(HttpCache {
-            mode: CacheMode::Default,
-            manager: Arc::new(MokaManager::default()),
-            options: None,
-        }))
-        .build()
-});
+static HTTP_CLIENT: Lazy<ReqwestClient> = Lazy::new(ReqwestClient::new);
+
+/// Minimum number of seconds between two fetches of the same service's public keys.
+///
+/// Prevents tokens with made-up `kid`s from causing a fetch on every request.
+const PUBLIC_KEYS_REFRESH_INTERVAL: i64 = 30;
+
+/// Number of seconds a service's public keys are used before they are fetched again.
+///
+/// Keys that a service retired stop being accepted, and tokens without a `kid` are checked
+/// with the key the service currently signs with.
+const PUBLIC_KEYS_TTL: i64 = 600;
 
+/// The public keys published by a service.
+struct ServicePublicKeys {
+    keys: HashMap<String, DecodingKey>,
+    /// The key that the service currently signs with.
+    current: Option<String>,
+    fetched_at: OffsetDateTime,
+}
+
+/// Cache of the public keys of other services, refreshed when a token has an unknown `kid`
+/// and when the keys are older than [`PUBLIC_KEYS_TTL`].
 #[derive(Default)]
-pub struct PublicKeys(std::collections::HashMap<DaprAppId, DecodingKey>);
+pub struct PublicKeys(HashMap<DaprAppId, ServicePublicKeys>);
 
 pub type SharedPublicKeys = Arc<Mutex<PublicKeys>>;
 
 impl PublicKeys {
-    pub async fn get(&mut self, key: DaprAppId) -> GlobeliseResult<&DecodingKey> {
-        let m_value = self.0.get(&key);
-        if m_value.is_none() {
-            let public_key_str = HTTP_CLIENT
-                .get(&format!(
-                    "{}/auth/public-key",
-                    key.microservice_domain_url()?
-                ))
+    pub async fn get(
+        &mut self,
+        key: DaprAppId,
+        kid: Option<&str>,
+    ) -> GlobeliseResult<&DecodingKey> {
+        let is_known = match (self.0.get(&key), kid) {
+            (Some(service), Some(kid)) => service.keys.contains_key(kid),
+            (Some(service), None) => service.current.is_some(),
+            (None, _) => false,
+        };
+        let now = OffsetDateTime::now_utc();
+        let is_stale = self.0.get(&key).map_or(true, |service| {
+            service.fetched_at + Duration::seconds(PUBLIC_KEYS_TTL) < now
+        });
+        let can_refresh = self.0.get(&key).map_or(true, |service| {
+            service.fetched_at + Duration::seconds(PUBLIC_KEYS_REFRESH_INTERVAL) < now
+        });
+        if (!is_known && can_refresh) || is_stale {
+            let jwks = HTTP_CLIENT
+                .get(&format!("{}/auth/jwks", key.microservice_domain_url()?))
                 .headers({
                     let mut headers = HeaderMap::default();
                     headers.insert("dapr-app-id", HeaderValue::from_static(key.as_str()));
@@ -182,12 +626,31 @@ impl PublicKeys {
                 })
                 .send()
                 .await?
-                .text()
+                .json::<JwkSet>()
                 .await?;
-            let decoding_key = DecodingKey::from_ed_pem(public_key_str.as_bytes())?;
-            self.0.insert(key, decoding_key);
+            let current = jwks.keys.first().map(|jwk| jwk.kid.clone());
+            let mut keys = HashMap::new();
+            for jwk in jwks.keys {
+                let public_key = base64::decode_config(&jwk.x, base64::URL_SAFE_NO_PAD)?;
+                keys.insert(jwk.kid, DecodingKey::from_ed_der(&public_key));
+            }
+            self.0.insert(
+                key,
+                ServicePublicKeys {
+                    keys,
+                    current,
+                    fetched_at: OffsetDateTime::now_utc(),
+                },
+            );
         }
-        Ok(self.0.get(&key).unwrap())
+
+        let service = self
+            .0
+            .get(&key)
+            .ok_or_else(|| GlobeliseError::unauthorized("Cannot get public keys"))?;
+        kid.or(service.current.as_deref())
+            .and_then(|kid| service.keys.get(kid))
+            .ok_or_else(|| GlobeliseError::unauthorized("Token signed by an unknown key"))
     }
 }
 
@@ -226,3 +689,29 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_signing_keys_only_open_for_their_service() {
+        std::env::set_var("SIGNING_KEY_ENCRYPTION_KEY", base64::encode([7u8; 32]));
+
+        let key = SigningKey::generate().unwrap();
+        let sealed = key.seal(DaprAppId::UserManagementMicroservice).unwrap();
+        assert_ne!(&sealed[NONCE_LEN..], key.private_key.as_slice());
+
+        let opened =
+            SigningKey::open(DaprAppId::UserManagementMicroservice, &sealed, None).unwrap();
+        assert_eq!(opened.kid, key.kid);
+        assert_eq!(opened.private_key, key.private_key);
+
+        assert!(SigningKey::open(DaprAppId::EorAdminMicroservice, &sealed, None).is_err());
+
+        let mut tampered = sealed;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(SigningKey::open(DaprAppId::UserManagementMicroservice, &tampered, None).is_err());
+    }
+}
@@ -7,6 +7,8 @@ use axum::{
 use common_utils::{
     database::{user::OnboardedUserIndex, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
+    mail::EmailTemplate,
+    money::Money,
     token::Token,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
@@ -14,7 +16,7 @@ use eor_admin_microservice_sdk::token::AdminAccessToken;
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::{serde_as, TryFromInto};
-use sqlx::FromRow;
+use sqlx::{types::Decimal, FromRow};
 use tokio::sync::Mutex;
 use user_management_microservice_sdk::token::UserAccessToken;
 use uuid::Uuid;
@@ -23,15 +25,12 @@ mod database;
 
 use common_utils::custom_serde::EmailWrapper;
 use common_utils::custom_serde::OptionOffsetDateWrapper;
-use lettre::{Message, SmtpTransport, Transport};
 
 use crate::{
     common::PaginatedQuery,
     database::{Database, SharedDatabase},
 };
 
-use crate::env::{GLOBELISE_SENDER_EMAIL, GLOBELISE_SMTP_URL, SMTP_CREDENTIAL};
-
 #[serde_as]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -98,6 +97,8 @@ pub struct SignContractInviteRequest {
     pub contract_ulid: Uuid,
     pub email: EmailWrapper,
     pub body: String,
+    /// Locale of the contractor, for the email. The default one when not given.
+    pub locale: Option<String>,
 }
 
 #[serde_as]
@@ -126,7 +127,7 @@ pub struct ContractsIndexResponse {
     pub contractor_date_signed: Option<sqlx::types::time::OffsetDateTime>,
     pub team_ulid: Option<Uuid>,
     pub job_scope: Option<String>,
-    pub contract_amount: f64,
+    pub contract_amount: Decimal,
     pub country_of_contractors_tax_residence: Option<String>,
     pub notice_period: Option<i32>,
     pub offer_stock_option: bool,
@@ -173,7 +174,7 @@ pub struct SingleContractsIndexResponse {
     pub contractor_date_signed: Option<sqlx::types::time::OffsetDateTime>,
     pub team_ulid: Option<Uuid>,
     pub job_scope: Option<String>,
-    pub contract_amount: f64,
+    pub contract_amount: Decimal,
     pub country_of_contractors_tax_residence: Option<String>,
     pub notice_period: Option<i32>,
     pub offer_stock_option: bool,
@@ -225,7 +226,7 @@ pub struct ContractsResponse {
     pub contractor_date_signed: Option<sqlx::types::time::OffsetDateTime>,
     pub team_ulid: Option<Uuid>,
     pub job_scope: Option<String>,
-    pub contract_amount: f64,
+    pub contract_amount: Decimal,
     pub country_of_contractors_tax_residence: String,
     pub notice_period: i32,
     pub offer_stock_option: bool,
@@ -269,7 +270,7 @@ pub struct ContractsRequest {
     pub contractor_date_signed: Option<sqlx::types::time::OffsetDateTime>,
     pub team_ulid: Option<Uuid>,
     pub job_scope: Option<String>,
-    pub contract_amount: f64,
+    pub contract_amount: Decimal,
     pub country_of_contractors_tax_residence: Option<String>,
     pub notice_period: Option<i32>,
     pub offer_stock_option: bool,
@@ -486,6 +487,11 @@ pub async fn client_post_update_contract(
 
     request.client_ulid = Some(claims.payload.ulid);
 
+    if let Some(currency) = &request.currency {
+        let currency = currency.parse().map_err(GlobeliseError::bad_request)?;
+        request.contract_amount = Money::new(request.contract_amount, currency)?.amount();
+    }
+
     if request.ulid.is_none() {
         //becomes a new contract
         request.ulid = Some(Uuid::new_v4());
@@ -606,6 +612,7 @@ pub async fn contractor_revoke_sign_contract(
 pub async fn client_invite_contractor(
     claims: Token<UserAccessToken>,
     Json(request): Json<SignContractInviteRequest>,
+    Extension(common_database): Extension<CommonDatabase>,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
@@ -633,42 +640,15 @@ pub async fn client_invite_contractor(
             request.contract_ulid,
         )
         .await?;
-    let receiver_email = contractor_email
-        .0
-        .to_display("")
-        .parse()
-        .map_err(GlobeliseError::bad_request)?;
-
-    let email = Message::builder()
-        .from(GLOBELISE_SENDER_EMAIL.clone())
-        .reply_to(GLOBELISE_SENDER_EMAIL.clone())
-        .to(receiver_email)
-        .subject("Contract Signing Request")
-        .header(lettre::message::header::ContentType::TEXT_HTML)
-        // TODO: Once designer have a template for this. Use a templating library to populate data.
-        .body(format!(
-            r##"
-            <!DOCTYPE html>
-            <html>
-            <head>
-                <title></title>
-            </head>
-            <body>
-                {}
-            </body>
-            </html>
-            "##,
-            request.body,
-        ))
-        .map_err(GlobeliseError::internal)?;
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(&GLOBELISE_SMTP_URL)
-        .map_err(GlobeliseError::internal)?
-        .credentials(SMTP_CREDENTIAL.clone())
-        .build();
-
-    mailer.send(&email).map_err(GlobeliseError::internal)?;
+    drop(database);
+
+    let email = EmailTemplate::ContractSigningRequest
+        .render(request.locale.as_deref(), &[("body", &request.body)])?;
+    common_database
+        .lock()
+        .await
+        .insert_one_outbox_email(&contractor_email, &email)
+        .await?;
 
     Ok(())
 }
@@ -1,9 +1,10 @@
-use common_utils::{calc_limit_and_offset, error::GlobeliseResult};
+use common_utils::{calc_limit_and_offset, custom_serde::Currency, error::GlobeliseResult};
 
 use crate::database::Database;
 
 use super::{
     InvoiceGroupIndex, InvoiceGroupIndexQuery, InvoiceIndividualIndex, InvoiceIndividualIndexQuery,
+    InvoiceReportQuery,
 };
 
 impl Database {
@@ -73,4 +74,57 @@ impl Database {
 
         Ok(index)
     }
+
+    /// Invoiced amounts with tax, per currency billed in.
+    pub async fn invoice_amounts_by_currency(
+        &self,
+        query: &InvoiceReportQuery,
+    ) -> GlobeliseResult<Vec<(Currency, sqlx::types::Decimal)>> {
+        let result = sqlx::query_as(
+            "
+                SELECT
+                    i.currency, SUM(idx.invoice_amount + i.invoice_tax_amount)
+                FROM
+                    invoice_individual_index idx
+                JOIN
+                    invoice_individual i ON i.ulid = idx.ulid
+                WHERE
+                    i.currency IS NOT NULL AND
+                    ($1 IS NULL OR (idx.client_ulid = $1)) AND
+                    ($2 IS NULL OR (idx.invoice_status = $2))
+                GROUP BY
+                    i.currency",
+        )
+        .bind(query.client_ulid)
+        .bind(&query.invoice_status)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn count_invoices_without_currency(
+        &self,
+        query: &InvoiceReportQuery,
+    ) -> GlobeliseResult<i64> {
+        let result = sqlx::query_scalar(
+            "
+                SELECT
+                    COUNT(*)
+                FROM
+                    invoice_individual_index idx
+                JOIN
+                    invoice_individual i ON i.ulid = idx.ulid
+                WHERE
+                    i.currency IS NULL AND
+                    ($1 IS NULL OR (idx.client_ulid = $1)) AND
+                    ($2 IS NULL OR (idx.invoice_status = $2))",
+        )
+        .bind(query.client_ulid)
+        .bind(&query.invoice_status)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(result)
+    }
 }
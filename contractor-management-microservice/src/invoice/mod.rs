@@ -3,14 +3,16 @@ use axum::{
     Json,
 };
 use common_utils::{
-    custom_serde::{OffsetDateWrapper, UserRole},
+    custom_serde::{Currency, OffsetDateWrapper, OptionDateWrapper, UserRole},
+    database::CommonDatabase,
     error::GlobeliseResult,
+    fx::ConvertedTotal,
     token::Token,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use itertools::izip;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, FromInto};
+use serde_with::{serde_as, FromInto, TryFromInto};
 use sqlx::{postgres::PgRow, FromRow, Row};
 use user_management_microservice_sdk::token::UserAccessToken;
 use uuid::Uuid;
@@ -73,6 +75,58 @@ pub async fn eor_admin_invoice_group_index(
     Ok(Json(database.invoice_group_index(query).await?))
 }
 
+/// The invoiced amounts, with tax, converted into one currency.
+pub async fn eor_admin_invoice_report(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<InvoiceReportQuery>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<InvoiceReport>> {
+    let (amounts, invoices_without_currency) = {
+        let database = database.lock().await;
+        (
+            database.invoice_amounts_by_currency(&query).await?,
+            database.count_invoices_without_currency(&query).await?,
+        )
+    };
+
+    let as_of = query
+        .date
+        .unwrap_or_else(|| sqlx::types::time::OffsetDateTime::now_utc().date());
+    let rates = common_database
+        .lock()
+        .await
+        .select_fx_rates_as_of(as_of)
+        .await?;
+
+    Ok(Json(InvoiceReport {
+        total: rates.convert_totals(amounts, query.currency)?,
+        invoices_without_currency,
+    }))
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InvoiceReportQuery {
+    pub client_ulid: Option<Uuid>,
+    pub invoice_status: Option<String>,
+    /// The currency to report in.
+    pub currency: Currency,
+    /// The date whose FX rates are used, today if not given.
+    #[serde_as(as = "TryFromInto<OptionDateWrapper>")]
+    #[serde(default)]
+    pub date: Option<sqlx::types::time::Date>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InvoiceReport {
+    pub total: ConvertedTotal,
+    /// Invoices left out because the currency they were billed in is unknown.
+    pub invoices_without_currency: i64,
+}
+
 #[serde_as]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
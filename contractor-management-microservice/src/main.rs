@@ -9,6 +9,7 @@ use axum::{
 };
 use common_utils::{
     error::GlobeliseResult,
+    mail::{outbox::spawn_outbox_sender, transport::transport_from_env},
     pubsub::{PubSub, TopicSubscription},
     token::PublicKeys,
 };
@@ -31,7 +32,7 @@ use env::{DAPR_ADDRESS, DATABASE_URL, FRONTEND_URL, LISTENING_ADDRESS};
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> GlobeliseResult<()> {
     dotenv::dotenv().ok();
 
     let reqwest_client = Client::builder()
@@ -44,6 +45,8 @@ async fn main() {
         common_utils::database::Database::new(&*DATABASE_URL).await,
     ));
 
+    spawn_outbox_sender(common_database.clone(), transport_from_env()?)?;
+
     let public_keys = Arc::new(Mutex::new(PublicKeys::default()));
 
     let shared_pubsub = Arc::new(Mutex::new(PubSub::new(
@@ -172,6 +175,10 @@ async fn main() {
             "/eor-admin/invoices/group",
             get(invoice::eor_admin_invoice_group_index),
         )
+        .route(
+            "/eor-admin/invoices/report",
+            get(invoice::eor_admin_invoice_report),
+        )
         // ========== PUBSUB PAGES ==========
         .route("/dapr/subscribe", get(dapr_subscription_list))
         // ========== DEBUG PAGES ==========
@@ -216,6 +223,8 @@ async fn main() {
     .serve(app.into_make_service())
     .await
     .unwrap();
+
+    Ok(())
 }
 
 async fn handle_healthz() -> String {
@@ -10,7 +10,7 @@ use axum::{
 use common_utils::{
     custom_serde::{OffsetDateWrapper, UserRole, FORM_DATA_LENGTH_LIMIT},
     error::{GlobeliseError, GlobeliseResult},
-    token::Token,
+    token::{NotImpersonated, Token},
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use serde::{Deserialize, Serialize};
@@ -84,7 +84,7 @@ pub async fn user_get_one_payslip_index(
 }
 
 pub async fn user_download_one_payslip_index(
-    claims: Token<UserAccessToken>,
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
     Path((user_role, payslip_ulid)): Path<(UserRole, Uuid)>,
     Extension(shared_database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<impl IntoResponse> {
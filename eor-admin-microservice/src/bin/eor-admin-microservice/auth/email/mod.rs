@@ -0,0 +1,100 @@
+//! Endpoints for verifying the email address of an admin.
+
+use axum::{extract::Extension, http::HeaderMap, response::Redirect};
+use common_utils::{
+    custom_serde::EmailWrapper,
+    database::CommonDatabase,
+    error::{GlobeliseError, GlobeliseResult},
+    mail::{self, EmailTemplate},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        token::one_time::{OneTimeToken, OneTimeTokenParam},
+        SharedDatabase, SharedState, State,
+    },
+    env::{EOR_ADMIN_MICROSERVICE_DOMAIN_URL, FRONTEND_URL},
+};
+
+mod token;
+
+pub use token::EmailVerificationToken;
+
+/// Resend the verification email to the logged in admin.
+pub async fn resend(
+    claims: Token<AdminAccessToken>,
+    headers: HeaderMap,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+    let admin = database
+        .find_one_admin(Some(claims.payload.ulid), None)
+        .await?
+        .ok_or_else(|| GlobeliseError::unauthorized("Cannot find admin with that ulid"))?;
+    if admin.email_verified_at.is_some() {
+        return Err(GlobeliseError::bad_request(
+            "Email address has already been verified",
+        ));
+    }
+
+    let mut shared_state = shared_state.lock().await;
+    send_verification_email(
+        &common_database,
+        &mut shared_state,
+        admin.ulid,
+        &admin.email,
+        mail::request_locale(&headers),
+    )
+    .await
+}
+
+/// Respond to admin clicking the verification link in their email.
+pub async fn verify(
+    OneTimeTokenParam(claims): OneTimeTokenParam<OneTimeToken<EmailVerificationToken>>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<Redirect> {
+    let database = database.lock().await;
+    database.update_one_admin_email_verified(claims.sub).await?;
+
+    // The links of the other verification emails must not work either.
+    let mut shared_state = shared_state.lock().await;
+    shared_state
+        .revoke_one_time_sessions::<EmailVerificationToken>(claims.sub)
+        .await?;
+
+    let redirect_url = format!("{}/eor/email-verified", *FRONTEND_URL);
+    Ok(Redirect::to(&redirect_url))
+}
+
+/// Opens an email verification session for the admin and emails them the link, in `locale`.
+pub async fn send_verification_email(
+    common_database: &CommonDatabase,
+    shared_state: &mut State,
+    ulid: Uuid,
+    email: &EmailWrapper,
+    locale: Option<&str>,
+) -> GlobeliseResult<()> {
+    let one_time_token = shared_state
+        .open_one_time_session::<EmailVerificationToken>(ulid)
+        .await?;
+
+    let link = format!(
+        "{}/auth/email/verify?token={}",
+        (*EOR_ADMIN_MICROSERVICE_DOMAIN_URL),
+        one_time_token
+    );
+    let rendered = EmailTemplate::EmailVerification.render(locale, &[("link", &link)])?;
+    common_database
+        .lock()
+        .await
+        .insert_one_outbox_email(email, &rendered)
+        .await?;
+
+    Ok(())
+}
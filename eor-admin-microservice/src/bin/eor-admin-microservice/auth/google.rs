@@ -27,12 +27,16 @@ pub async fn login(
     let database = database.lock().await;
     let mut shared_state = shared_state.lock().await;
     if let Some(admin) = database.find_one_admin(None, Some(&claims.email)).await? {
+        // Google has already verified that the admin owns this email address.
+        database.update_one_admin_email_verified(admin.ulid).await?;
+
         let refresh_token = shared_state.open_session(admin.ulid).await?;
         Ok(refresh_token)
     } else {
         let ulid = database
             .insert_one_admin(claims.email, None, true, false)
             .await?;
+        database.update_one_admin_email_verified(ulid).await?;
 
         let refresh_token = shared_state.open_session(ulid).await?;
         Ok(refresh_token)
@@ -1,20 +1,29 @@
 //! Endpoints for admin authentication and authorization.
 
 use argon2::{self, hash_encoded, verify_encoded, Config};
-use axum::extract::{ContentLengthLimit, Extension, Json};
+use axum::{
+    extract::{ContentLengthLimit, Extension, Json},
+    http::HeaderMap,
+};
 use common_utils::{
     custom_serde::{EmailWrapper, FORM_DATA_LENGTH_LIMIT},
+    database::CommonDatabase,
     error::{GlobeliseError, GlobeliseResult},
-    token::{create_token, Token},
+    mail,
+    password::PASSWORD_POLICY,
+    token::{create_token, JwkSet, SigningKey, Token},
+    DaprAppId,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use once_cell::sync::Lazy;
 use rand::Rng;
 use serde::Deserialize;
+use time::Duration;
 use unicode_normalization::UnicodeNormalization;
 
 use crate::database::{auth::Admin, SharedDatabase};
 
+pub mod email;
 pub mod google;
 pub mod password;
 mod state;
@@ -30,17 +39,15 @@ pub async fn signup(
         Json<CreateAccountRequest>,
         FORM_DATA_LENGTH_LIMIT,
     >,
+    headers: HeaderMap,
     Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<String> {
     let password: String = body.password.nfc().collect();
     let confirm_password: String = body.confirm_password.nfc().collect();
 
-    if password.len() < 8 {
-        return Err(GlobeliseError::bad_request(
-            "Password must be at least 8 characters long",
-        ));
-    }
+    PASSWORD_POLICY.validate(&password)?;
     if password != confirm_password {
         return Err(GlobeliseError::bad_request("Passwords do not match"));
     }
@@ -61,10 +68,19 @@ pub async fn signup(
     }
 
     let ulid = database
-        .insert_one_admin(body.email, Some(hash), false, false)
+        .insert_one_admin(body.email.clone(), Some(hash), false, false)
         .await?;
 
     let mut shared_state = shared_state.lock().await;
+    email::send_verification_email(
+        &common_database,
+        &mut shared_state,
+        ulid,
+        &body.email,
+        mail::request_locale(&headers),
+    )
+    .await?;
+
     let refresh_token = shared_state.open_session(ulid).await?;
     Ok(refresh_token)
 }
@@ -85,11 +101,18 @@ pub async fn login(
     if let Some(Admin {
         password: Some(hash),
         ulid,
+        password_changed_at,
         ..
     }) = database.find_one_admin(None, Some(&body.email)).await?
     {
         {
             if let Ok(true) = verify_encoded(&hash, password.as_bytes()) {
+                if PASSWORD_POLICY.is_expired(password_changed_at) {
+                    return Err(GlobeliseError::unauthorized(
+                        "Password has expired. Please reset your password",
+                    ));
+                }
+
                 let mut shared_state = shared_state.lock().await;
                 let refresh_token = shared_state.open_session(ulid).await?;
                 Ok(refresh_token)
@@ -118,11 +141,7 @@ pub async fn access_token(
         .clear_expired_sessions(claims.payload.ulid)
         .await;
     if let Some(sessions) = shared_state.sessions(claims.payload.ulid).await? {
-        let encoded_claims = jsonwebtoken::encode(
-            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::EdDSA),
-            &claims,
-            &KEYS.encoding,
-        )?;
+        let encoded_claims = claims.reencode(&KEYS)?;
         for (hash, _) in sessions.iter() {
             if let Ok(true) = verify_encoded(hash, encoded_claims.as_bytes()) {
                 is_session_valid = true;
@@ -143,7 +162,7 @@ pub async fn access_token(
             ulid: claims.payload.ulid,
             email: admin.email,
         };
-        let (access_token, _) = create_token(access, &KEYS.encoding)?;
+        let (access_token, _) = create_token(access, &KEYS.current())?;
         Ok(access_token)
     } else {
         Err(GlobeliseError::unauthorized("Invalid refresh token"))
@@ -151,10 +170,67 @@ pub async fn access_token(
 }
 
 /// Gets the public key for decoding tokens.
+///
+/// Only the current signing key is returned. Use the JWKS endpoint to get every accepted key.
 pub async fn public_key() -> String {
-    (*token::PUBLIC_KEY).clone()
+    KEYS.current().public_key_pem()
 }
 
+/// Gets the public keys for decoding tokens, current signing key first.
+pub async fn jwks() -> Json<JwkSet> {
+    Json(KEYS.jwks())
+}
+
+/// Replaces the signing key.
+///
+/// Tokens signed by the previous key are accepted until the grace period is over. Only super
+/// admins can rotate keys.
+pub async fn rotate_keys(
+    claims: Token<AdminAccessToken>,
+    Json(body): Json<RotateKeysRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<JwkSet>> {
+    if !database
+        .lock()
+        .await
+        .is_super_admin(claims.payload.ulid)
+        .await?
+    {
+        return Err(GlobeliseError::Forbidden);
+    }
+
+    let grace_period = Duration::hours(
+        body.grace_period_hours
+            .unwrap_or(DEFAULT_KEY_GRACE_PERIOD_HOURS),
+    );
+    if grace_period.is_negative() {
+        return Err(GlobeliseError::bad_request(
+            "Grace period cannot be negative",
+        ));
+    }
+
+    let next = SigningKey::generate()?;
+    database
+        .lock()
+        .await
+        .rotate_signing_key(
+            DaprAppId::EorAdminMicroservice,
+            &KEYS.current(),
+            &next,
+            grace_period,
+        )
+        .await?;
+    KEYS.reload(&database, DaprAppId::EorAdminMicroservice)
+        .await?;
+
+    Ok(Json(KEYS.jwks()))
+}
+
+/// How long tokens signed by a rotated key stay valid by default.
+///
+/// Covers the lifetime of the longest-lived tokens.
+const DEFAULT_KEY_GRACE_PERIOD_HOURS: i64 = 48;
+
 /// Request for creating a admin.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -172,6 +248,13 @@ pub struct LoginRequest {
     password: String,
 }
 
+/// Request for rotating the signing key.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RotateKeysRequest {
+    grace_period_hours: Option<i64>,
+}
+
 /// The parameters used for hashing.
 // TODO: Calibrate hash parameters for production server.
 pub static HASH_CONFIG: Lazy<Config> = Lazy::new(|| Config {
@@ -1,23 +1,24 @@
 use argon2::{self, hash_encoded};
 use axum::{
     extract::{ContentLengthLimit, Extension, Json},
+    http::HeaderMap,
     response::Redirect,
 };
 use common_utils::{
     custom_serde::{EmailWrapper, FORM_DATA_LENGTH_LIMIT},
+    database::CommonDatabase,
     error::{GlobeliseError, GlobeliseResult},
+    mail::{self, EmailTemplate},
+    password::PASSWORD_POLICY,
 };
-use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
 use rand::Rng;
 use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
 use crate::{
     auth::token::one_time::create_one_time_token,
-    env::{
-        EOR_ADMIN_MICROSERVICE_DOMAIN_URL, FRONTEND_URL, GLOBELISE_SENDER_EMAIL,
-        GLOBELISE_SMTP_URL, SMTP_CREDENTIAL,
-    },
+    env::{EOR_ADMIN_MICROSERVICE_DOMAIN_URL, FRONTEND_URL},
 };
 
 use crate::auth::{
@@ -35,7 +36,9 @@ pub async fn send_email(
         Json<LostPasswordRequest>,
         FORM_DATA_LENGTH_LIMIT,
     >,
+    headers: HeaderMap,
     Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
@@ -57,50 +60,21 @@ pub async fn send_email(
         }
     };
 
-    let receiver_email = body
-        .email
-        .0
-        // TODO: Get the name of the person associated to this email address
-        .to_display("")
-        .parse::<Mailbox>()
-        .map_err(GlobeliseError::bad_request)?;
-    let email = Message::builder()
-        .from(GLOBELISE_SENDER_EMAIL.clone())
-        .reply_to(GLOBELISE_SENDER_EMAIL.clone())
-        .to(receiver_email)
-        .subject("Confirm Request to Reset Password")
-        .header(lettre::message::header::ContentType::TEXT_HTML)
-        // TODO: Once designer have a template for this. Use a templating library to populate data.
-        .body(format!(
-            r##"
-            <!DOCTYPE html>
-            <html>
-            <head>
-                <title>Change Password Request</title>
-            </head>
-            <body>
-                <p>
-                If you requested to change your password, please follow this
-                <a href="{}/auth/password/reset/initiate?token={}">link</a> to reset it.
-                </p>
-                <p>Otherwise, please report this occurence.</p>
-            </body>
-            </html>
-            "##,
-            (*EOR_ADMIN_MICROSERVICE_DOMAIN_URL),
-            one_time_token
-        ))
-        .map_err(GlobeliseError::internal)?;
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(&GLOBELISE_SMTP_URL)
-        .map_err(GlobeliseError::internal)?
-        .credentials(SMTP_CREDENTIAL.clone())
-        .build();
+    // The email is only sent to admins who asked for it, but built either way.
+    let link = format!(
+        "{}/auth/password/reset/initiate?token={}",
+        (*EOR_ADMIN_MICROSERVICE_DOMAIN_URL),
+        one_time_token
+    );
+    let rendered =
+        EmailTemplate::PasswordReset.render(mail::request_locale(&headers), &[("link", &link)])?;
 
-    // Send the email
     if is_valid_attempt && created_valid_token {
-        mailer.send(&email).map_err(GlobeliseError::internal)?;
+        common_database
+            .lock()
+            .await
+            .insert_one_outbox_email(&body.email, &rendered)
+            .await?;
     }
 
     Ok(())
@@ -127,20 +101,32 @@ pub async fn initiate(
 pub async fn execute(
     Json(request): Json<ChangePasswordRequest>,
     OneTimeTokenBearer(claims): OneTimeTokenBearer<OneTimeToken<ChangePasswordToken>>,
+    Extension(common_database): Extension<CommonDatabase>,
     Extension(database): Extension<SharedDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<()> {
-    if request.new_password != request.confirm_new_password {
+    let new_password: String = request.new_password.nfc().collect();
+    let confirm_new_password: String = request.confirm_new_password.nfc().collect();
+
+    if new_password != confirm_new_password {
         return Err(GlobeliseError::bad_request("Passwords do not match"));
     }
 
+    PASSWORD_POLICY.validate(&new_password)?;
+
+    let common_database = common_database.lock().await;
     let database = database.lock().await;
     let mut shared_state = shared_state.lock().await;
 
+    let previous_hashes = common_database
+        .select_many_admin_password_hashes(claims.sub, PASSWORD_POLICY.history_size)
+        .await?;
+    PASSWORD_POLICY.check_history(&new_password, previous_hashes.iter().map(String::as_str))?;
+
     // NOTE: This is not atomic, so this check is quite pointless.
     // Either rely completely on SQL or use some kind of transaction commit.
     let salt: [u8; 16] = rand::thread_rng().gen();
-    let hash = hash_encoded(request.new_password.as_bytes(), &salt, &HASH_CONFIG)
+    let hash = hash_encoded(new_password.as_bytes(), &salt, &HASH_CONFIG)
         .map_err(GlobeliseError::internal)?;
 
     database
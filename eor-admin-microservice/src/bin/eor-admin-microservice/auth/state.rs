@@ -145,6 +145,17 @@ impl State {
         Ok(false)
     }
 
+    /// Revokes every one-time session of a kind for a user, so that none of the tokens sent
+    /// to them can be used any more.
+    pub async fn revoke_one_time_sessions<T>(&mut self, ulid: Uuid) -> GlobeliseResult<()>
+    where
+        T: OneTimeTokenAudience,
+    {
+        let category = Self::one_time_session_category::<T>();
+        self.serialize(&*category, &ulid.to_string(), OneTimeSessions::default())
+            .await
+    }
+
     /// Serializes and stores data in the state store.
     async fn serialize<T>(&mut self, category: &str, key: &str, value: T) -> GlobeliseResult<()>
     where
@@ -199,7 +210,7 @@ impl Sessions {
     ///
     /// Returns the refresh token for the session.
     fn open(&mut self, payload: RefreshToken) -> GlobeliseResult<String> {
-        let (refresh_token, expiration) = create_token(payload, &KEYS.encoding)?;
+        let (refresh_token, expiration) = create_token(payload, &KEYS.current())?;
         let salt: [u8; 16] = rand::thread_rng().gen();
         let hash = hash_encoded(refresh_token.as_bytes(), &salt, &HASH_CONFIG)
             .map_err(GlobeliseError::internal)?;
@@ -3,7 +3,7 @@
 use std::{fs::File, io::Read};
 
 use common_utils::{
-    token::{Keys, TokenLike},
+    token::{KeyRing, SigningKey, TokenLike},
     DaprAppId,
 };
 use once_cell::sync::Lazy;
@@ -36,22 +36,14 @@ impl TokenLike for RefreshToken {
     }
 }
 
-/// The public key used for decoding tokens.
-pub static PUBLIC_KEY: Lazy<String> = Lazy::new(|| {
-    let mut public_key = String::new();
-    File::open("public.pem")
-        .expect("Could not open public key")
-        .read_to_string(&mut public_key)
-        .expect("Could not read public key");
-    public_key
-});
-
-/// The encoding/decoding key pair.
-pub static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let mut private_key: Vec<u8> = Vec::new();
+/// The signing keys of this service.
+///
+/// Starts with the key pair on disk until the keys stored in the database are loaded.
+pub static KEYS: Lazy<KeyRing> = Lazy::new(|| {
+    let mut private_key = String::new();
     File::open("private.pem")
         .expect("Could not open private key")
-        .read_to_end(&mut private_key)
+        .read_to_string(&mut private_key)
         .expect("Could not read private key");
-    Keys::new(&private_key, PUBLIC_KEY.as_bytes())
+    KeyRing::new(SigningKey::from_pem(&private_key).expect("Could not create signing key"))
 });
@@ -9,7 +9,7 @@ use common_utils::{
     error::{GlobeliseError, GlobeliseResult},
     token::ISSUER,
 };
-use jsonwebtoken::{decode, encode, Algorithm, Header, TokenData, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
@@ -40,9 +40,9 @@ where
         one_time_audience: PhantomData,
     };
 
+    let key = KEYS.current();
     Ok((
-        encode(&Header::new(Algorithm::RS256), &claims, &KEYS.encoding)
-            .map_err(GlobeliseError::internal)?,
+        encode(&key.header(), &claims, &key.encoding).map_err(GlobeliseError::internal)?,
         expiration,
     ))
 }
@@ -66,15 +66,18 @@ where
     T: OneTimeTokenAudience,
 {
     fn decode(input: &str) -> GlobeliseResult<Self> {
-        let mut validation = Validation::new(Algorithm::RS256);
+        let mut validation = Validation::new(Algorithm::EdDSA);
         validation.set_audience(&[T::name()]);
         validation.set_issuer(&[ISSUER]);
         validation.set_required_spec_claims(&["sub", "aud", "iss", "exp"]);
         let validation = validation;
 
-        let TokenData { claims, .. } =
-            decode::<OneTimeToken<T>>(input, &KEYS.decoding, &validation)
-                .map_err(GlobeliseError::unauthorized)?;
+        let kid = decode_header(input)
+            .map_err(GlobeliseError::unauthorized)?
+            .kid;
+        let key = KEYS.get(kid.as_deref())?;
+        let TokenData { claims, .. } = decode::<OneTimeToken<T>>(input, &key.decoding, &validation)
+            .map_err(GlobeliseError::unauthorized)?;
         Ok(claims)
     }
 }
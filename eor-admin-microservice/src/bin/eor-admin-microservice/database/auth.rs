@@ -1,10 +1,16 @@
-use common_utils::{custom_serde::EmailWrapper, error::GlobeliseResult};
+use common_utils::{
+    custom_serde::{EmailWrapper, OffsetDateWrapper, OptionOffsetDateWrapper},
+    database::user::{insert_password_history, PasswordOwner},
+    error::GlobeliseResult,
+};
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
 use sqlx::FromRow;
 use uuid::Uuid;
 
 use super::Database;
 
+#[serde_as]
 #[derive(Debug, FromRow, Deserialize, Serialize)]
 pub struct Admin {
     pub ulid: Uuid,
@@ -12,6 +18,10 @@ pub struct Admin {
     pub password: Option<String>,
     pub is_google: bool,
     pub is_outlook: bool,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub email_verified_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub password_changed_at: sqlx::types::time::OffsetDateTime,
 }
 
 impl Database {
@@ -45,23 +55,50 @@ impl Database {
     }
 
     /// Updates a admin's password.
+    ///
+    /// The previous hash is kept in the password history.
     pub async fn update_one_admin_password(
         &self,
         ulid: Uuid,
         // TODO: Create a newtype to ensure only hashed password are inserted
         new_password_hash: Option<String>,
     ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
+        insert_password_history(&mut transaction, PasswordOwner::Admin, ulid).await?;
+
         sqlx::query(
             "
             UPDATE 
                 admin_users 
             SET 
-                password = $1 
+                password = $1,
+                password_changed_at = CURRENT_TIMESTAMP
             WHERE 
                 ulid = $2",
         )
         .bind(new_password_hash)
         .bind(ulid)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Marks a admin's email address as verified.
+    pub async fn update_one_admin_email_verified(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+            UPDATE 
+                admin_users 
+            SET 
+                email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+            WHERE 
+                ulid = $1",
+        )
+        .bind(ulid)
         .execute(&self.0)
         .await?;
 
@@ -8,7 +8,12 @@ use axum::{
     routing::{get, post},
     BoxError, Router,
 };
-use common_utils::token::PublicKeys;
+use common_utils::{
+    error::GlobeliseResult,
+    mail::{outbox::spawn_outbox_sender, transport::transport_from_env},
+    token::PublicKeys,
+    DaprAppId,
+};
 use database::Database;
 use tokio::sync::Mutex;
 use tower::ServiceBuilder;
@@ -19,16 +24,28 @@ mod database;
 mod env;
 mod onboard;
 
-use env::{FRONTEND_URL, LISTENING_ADDRESS};
+use env::{DATABASE_URL, FRONTEND_URL, LISTENING_ADDRESS};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> GlobeliseResult<()> {
     dotenv::dotenv().ok();
 
     let shared_state = auth::State::new().await.expect("Could not connect to Dapr");
     let shared_state = Arc::new(Mutex::new(shared_state));
 
     let database = Arc::new(Mutex::new(Database::new().await));
+    let common_database = Arc::new(Mutex::new(
+        common_utils::database::Database::new(&*DATABASE_URL).await,
+    ));
+
+    KEYS.reload(&common_database, DaprAppId::EorAdminMicroservice)
+        .await
+        .expect("Could not load signing keys");
+    tokio::spawn(
+        KEYS.reload_periodically(common_database.clone(), DaprAppId::EorAdminMicroservice),
+    );
+
+    spawn_outbox_sender(common_database.clone(), transport_from_env()?)?;
 
     let public_keys = Arc::new(Mutex::new(PublicKeys::default()));
 
@@ -54,8 +71,12 @@ async fn main() {
             get(onboard::individual::get_account_details)
                 .post(onboard::individual::account_details),
         )
+        .route("/auth/email/verify", get(auth::email::verify))
+        .route("/auth/email/verify/resend", post(auth::email::resend))
         .route("/auth/access-token", post(auth::access_token))
         .route("/auth/public-key", get(auth::public_key))
+        .route("/auth/jwks", get(auth::jwks))
+        .route("/auth/keys/rotate", post(auth::rotate_keys))
         .route("/healthz", get(handle_healthz))
         .layer(
             ServiceBuilder::new()
@@ -81,8 +102,8 @@ async fn main() {
                         .allow_headers(Any),
                 )
                 .layer(Extension(database))
+                .layer(Extension(common_database))
                 .layer(Extension(shared_state))
-                .layer(Extension(KEYS.decoding.clone()))
                 .layer(Extension(public_keys)),
         );
 
@@ -95,6 +116,8 @@ async fn main() {
     .serve(app.into_make_service())
     .await
     .unwrap();
+
+    Ok(())
 }
 
 async fn handle_healthz() -> String {
@@ -1,7 +1,7 @@
 use axum::extract::{ContentLengthLimit, Extension, Json};
 use common_utils::{
     custom_serde::{Country, ImageData, OffsetDateWrapper, FORM_DATA_LENGTH_LIMIT},
-    error::GlobeliseResult,
+    error::{GlobeliseError, GlobeliseResult},
     token::Token,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
@@ -20,6 +20,15 @@ pub async fn account_details(
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
+    if database
+        .find_one_admin(Some(claims.payload.ulid), None)
+        .await?
+        .and_then(|admin| admin.email_verified_at)
+        .is_none()
+    {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
+
     database
         .onboard_admin_details(claims.payload.ulid, request)
         .await
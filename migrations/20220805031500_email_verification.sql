@@ -0,0 +1,13 @@
+-- Track when a user or admin has verified their email address.
+
+ALTER TABLE public.users
+    ADD COLUMN email_verified_at timestamp with time zone;
+
+ALTER TABLE public.admin_users
+    ADD COLUMN email_verified_at timestamp with time zone;
+
+-- Accounts created before verification was introduced are treated as verified.
+
+UPDATE public.users SET email_verified_at = created_at;
+
+UPDATE public.admin_users SET email_verified_at = created_at;
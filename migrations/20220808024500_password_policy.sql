@@ -0,0 +1,31 @@
+-- Track password age and previous password hashes for the password policy.
+
+ALTER TABLE public.users
+    ADD COLUMN password_changed_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL;
+
+ALTER TABLE public.admin_users
+    ADD COLUMN password_changed_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL;
+
+CREATE TABLE public.user_password_history (
+    ulid uuid NOT NULL PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_ulid uuid NOT NULL REFERENCES public.users(ulid) ON DELETE CASCADE,
+    password text NOT NULL,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX user_password_history_user_ulid_idx
+    ON public.user_password_history (user_ulid, created_at DESC);
+
+ALTER TABLE public.user_password_history OWNER TO postgres;
+
+CREATE TABLE public.admin_password_history (
+    ulid uuid NOT NULL PRIMARY KEY DEFAULT uuid_generate_v4(),
+    admin_ulid uuid NOT NULL REFERENCES public.admin_users(ulid) ON DELETE CASCADE,
+    password text NOT NULL,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX admin_password_history_admin_ulid_idx
+    ON public.admin_password_history (admin_ulid, created_at DESC);
+
+ALTER TABLE public.admin_password_history OWNER TO postgres;
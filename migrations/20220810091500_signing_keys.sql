@@ -0,0 +1,21 @@
+-- Token signing keys of each service, so that keys can be rotated without a restart.
+--
+-- The private keys are stored encrypted with SIGNING_KEY_ENCRYPTION_KEY, which is kept
+-- outside the database.
+
+CREATE TABLE public.signing_keys (
+    kid text NOT NULL PRIMARY KEY,
+    dapr_app_id text NOT NULL,
+    encrypted_private_key bytea NOT NULL,
+    expires_at timestamp with time zone,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX signing_keys_dapr_app_id_idx
+    ON public.signing_keys (dapr_app_id, created_at DESC);
+
+ALTER TABLE public.signing_keys OWNER TO postgres;
+
+-- Only these admins can rotate the signing keys.
+ALTER TABLE public.admin_users
+    ADD COLUMN is_super_admin boolean DEFAULT false NOT NULL;
@@ -0,0 +1,19 @@
+-- Audit trail of admins acting as users.
+
+CREATE TABLE public.impersonation_logs (
+    ulid uuid NOT NULL PRIMARY KEY DEFAULT uuid_generate_v4(),
+    admin_ulid uuid NOT NULL,
+    user_ulid uuid NOT NULL,
+    read_only boolean NOT NULL,
+    method text NOT NULL,
+    path text NOT NULL,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX impersonation_logs_admin_ulid_idx
+    ON public.impersonation_logs (admin_ulid, created_at DESC);
+
+CREATE INDEX impersonation_logs_user_ulid_idx
+    ON public.impersonation_logs (user_ulid, created_at DESC);
+
+ALTER TABLE public.impersonation_logs OWNER TO postgres;
@@ -0,0 +1,20 @@
+-- API keys that clients use to call the API from their own systems.
+
+CREATE TABLE public.api_keys (
+    ulid uuid NOT NULL PRIMARY KEY,
+    user_ulid uuid NOT NULL REFERENCES public.users(ulid) ON DELETE CASCADE,
+    user_type text NOT NULL,
+    name text NOT NULL,
+    hint text NOT NULL,
+    key_hash text NOT NULL UNIQUE,
+    scopes text[] NOT NULL,
+    expires_at timestamp with time zone,
+    last_used_at timestamp with time zone,
+    revoked_at timestamp with time zone,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX api_keys_user_ulid_idx
+    ON public.api_keys (user_ulid);
+
+ALTER TABLE public.api_keys OWNER TO postgres;
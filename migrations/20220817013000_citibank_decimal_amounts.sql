@@ -0,0 +1,4 @@
+-- Store transfer amounts as exact decimals instead of floating point numbers.
+
+ALTER TABLE public.uploaded_citibank_transfer_initiation_files_records
+    ALTER COLUMN amount TYPE numeric USING amount::numeric;
@@ -0,0 +1,22 @@
+-- Status files downloaded from the Citibank SFTP server.
+--
+-- Each file is claimed here before it is processed so that it is only ever applied once,
+-- even when several replicas poll the server at the same time.
+
+CREATE TABLE public.citibank_status_files (
+    file_name text NOT NULL PRIMARY KEY,
+    file_kind text,
+    content_hash text,
+    -- processing, retrying, processed, duplicate, ignored or failed
+    status text NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    error text,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX citibank_status_files_processed_content_hash_idx
+    ON public.citibank_status_files (content_hash)
+    WHERE status = 'processed';
+
+ALTER TABLE public.citibank_status_files OWNER TO postgres;
@@ -0,0 +1,37 @@
+-- Transfer batches need a second admin to approve them before they are sent to the bank.
+-- Statuses: draft -> submitted -> approved -> sent.
+--
+-- Every change to the records of a transfer batch bumps its version, so that an approval
+-- only applies to the exact records the approver reviewed.
+
+ALTER TABLE public.uploaded_citibank_transfer_initiation_files
+    ADD COLUMN submitted_by uuid REFERENCES public.admin_users(ulid),
+    ADD COLUMN submitted_at timestamp with time zone,
+    ADD COLUMN approved_by uuid REFERENCES public.admin_users(ulid),
+    ADD COLUMN approved_at timestamp with time zone,
+    ADD COLUMN version integer NOT NULL DEFAULT 1,
+    ADD CONSTRAINT uploaded_citibank_transfer_initiation_files_four_eyes_check
+        CHECK (approved_by IS NULL OR approved_by <> submitted_by);
+
+UPDATE public.uploaded_citibank_transfer_initiation_files
+SET status = 'draft'
+WHERE status = 'pending';
+
+CREATE OR REPLACE VIEW public.uploaded_citibank_transfer_initiation_files_index
+ AS
+ SELECT uploaded_citibank_transfer_initiation_files.ulid,
+    uploaded_citibank_transfer_initiation_files.title_identifier,
+    uploaded_citibank_transfer_initiation_files.status,
+    uploaded_citibank_transfer_initiation_files.created_at,
+    uploaded_citibank_transfer_initiation_files.client_ulid,
+    ( SELECT count(*) AS count
+           FROM uploaded_citibank_transfer_initiation_files_records
+          WHERE uploaded_citibank_transfer_initiation_files_records.file_ulid = uploaded_citibank_transfer_initiation_files.ulid) AS entries,
+    uploaded_citibank_transfer_initiation_files.submitted_by,
+    uploaded_citibank_transfer_initiation_files.submitted_at,
+    uploaded_citibank_transfer_initiation_files.approved_by,
+    uploaded_citibank_transfer_initiation_files.approved_at
+   FROM uploaded_citibank_transfer_initiation_files;
+
+ALTER TABLE public.uploaded_citibank_transfer_initiation_files_index
+    OWNER TO postgres;
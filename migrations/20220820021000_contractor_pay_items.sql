@@ -0,0 +1,14 @@
+-- The pay items of a branch that are paid to a contractor, with the amount when it is the same
+-- every pay period. Transfer batches deduct and add them to get the net pay.
+
+CREATE TABLE public.contractor_pay_items (
+    contractor_ulid uuid NOT NULL REFERENCES public.users(ulid) ON DELETE CASCADE,
+    pay_item_ulid uuid NOT NULL
+        REFERENCES public.entity_client_branch_pay_items(ulid) ON DELETE CASCADE,
+    amount numeric,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (contractor_ulid, pay_item_ulid)
+);
+
+ALTER TABLE public.contractor_pay_items OWNER TO postgres;
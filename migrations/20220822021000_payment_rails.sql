@@ -0,0 +1,28 @@
+-- Payment rails: how the transfers of a client branch reach the bank.
+--
+-- CITIBANK sends pain.001 files to Citibank over SFTP, SEPA produces pain.001 files for
+-- any bank in the SEPA zone and CSV produces a plain bulk payment file. Branches without
+-- a row here use CITIBANK.
+
+CREATE TABLE public.entity_client_branch_payment_rails (
+    branch_ulid uuid NOT NULL PRIMARY KEY REFERENCES public.entity_client_branches(ulid) ON DELETE CASCADE,
+    payment_rail text NOT NULL CHECK (payment_rail IN ('CITIBANK', 'SEPA', 'CSV')),
+    -- The account the branch pays from, used by SEPA.
+    debtor_name text,
+    debtor_iban text,
+    debtor_bic text,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CONSTRAINT entity_client_branch_payment_rails_sepa_debtor_check
+        CHECK (payment_rail <> 'SEPA' OR (debtor_name IS NOT NULL AND debtor_iban IS NOT NULL))
+);
+
+ALTER TABLE public.entity_client_branch_payment_rails OWNER TO postgres;
+
+-- Transfer files remember the branch they pay for, so they are sent through its rail.
+ALTER TABLE public.uploaded_citibank_transfer_initiation_files
+    ADD COLUMN branch_ulid uuid REFERENCES public.entity_client_branches(ulid);
+
+-- Status reports of every rail are tracked in the same table.
+ALTER TABLE public.citibank_status_files
+    ADD COLUMN payment_rail text NOT NULL DEFAULT 'CITIBANK';
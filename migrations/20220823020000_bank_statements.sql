@@ -0,0 +1,56 @@
+-- Bank statements (camt.053 or MT940) and their lines, reconciled against the transfers
+-- we sent and the invoices our clients pay.
+
+CREATE TABLE public.bank_statements (
+    ulid uuid NOT NULL PRIMARY KEY,
+    -- camt.053 or mt940
+    format text NOT NULL,
+    statement_id text NOT NULL,
+    account text NOT NULL,
+    currency text,
+    opening_balance numeric,
+    closing_balance numeric,
+    uploaded_by uuid NOT NULL REFERENCES public.admin_users(ulid),
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE (account, statement_id)
+);
+
+ALTER TABLE public.bank_statements OWNER TO postgres;
+
+CREATE TABLE public.bank_statement_lines (
+    ulid uuid NOT NULL PRIMARY KEY,
+    statement_ulid uuid NOT NULL REFERENCES public.bank_statements(ulid) ON DELETE CASCADE,
+    line_number integer NOT NULL,
+    -- YYYY-MM-DD
+    booking_date text,
+    value_date text,
+    credit_debit text NOT NULL CHECK (credit_debit IN ('CRDT', 'DBIT')),
+    amount numeric NOT NULL,
+    currency text NOT NULL,
+    end_to_end_id text,
+    reference text,
+    remittance_information text,
+    counterparty_name text,
+    -- unmatched, matched or ignored
+    match_status text NOT NULL DEFAULT 'unmatched',
+    matched_record_ulid uuid REFERENCES public.uploaded_citibank_transfer_initiation_files_records(ulid) ON DELETE SET NULL,
+    matched_invoice_ulid uuid REFERENCES public.invoice_individual(ulid),
+    -- Why the line was matched, ignored or could not be matched automatically.
+    match_note text,
+    -- NULL when matched automatically.
+    matched_by uuid REFERENCES public.admin_users(ulid),
+    matched_at timestamp with time zone,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE (statement_ulid, line_number)
+);
+
+-- A transfer leaves the account only once.
+CREATE UNIQUE INDEX bank_statement_lines_matched_record_ulid_idx
+    ON public.bank_statement_lines (matched_record_ulid)
+    WHERE matched_record_ulid IS NOT NULL;
+
+CREATE INDEX bank_statement_lines_unmatched_idx
+    ON public.bank_statement_lines (created_at)
+    WHERE match_status = 'unmatched';
+
+ALTER TABLE public.bank_statement_lines OWNER TO postgres;
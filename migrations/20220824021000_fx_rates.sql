@@ -0,0 +1,36 @@
+-- Daily FX rates: one unit of the base currency buys `rate` units of the quote currency,
+-- from the effective date until the next rate of the pair.
+
+CREATE TABLE public.fx_rates (
+    ulid uuid NOT NULL PRIMARY KEY,
+    base_currency text NOT NULL REFERENCES public.currency_codes(code),
+    quote_currency text NOT NULL REFERENCES public.currency_codes(code),
+    rate numeric NOT NULL CHECK (rate > 0),
+    effective_date date NOT NULL,
+    -- manual or import
+    source text NOT NULL,
+    created_by uuid REFERENCES public.admin_users(ulid),
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CHECK (base_currency <> quote_currency),
+    UNIQUE (base_currency, quote_currency, effective_date)
+);
+
+ALTER TABLE public.fx_rates OWNER TO postgres;
+
+-- Invoices are billed in the currency of the contract between the client and the contractor.
+ALTER TABLE public.invoice_individual
+    ADD COLUMN currency text REFERENCES public.currency_codes(code);
+
+UPDATE public.invoice_individual i SET
+    currency = (
+        SELECT
+            c.currency
+        FROM
+            public.contracts c
+        WHERE
+            c.client_ulid = i.client_ulid AND
+            c.contractor_ulid = i.contractor_ulid
+        ORDER BY
+            c.created_at DESC
+        LIMIT 1
+    );
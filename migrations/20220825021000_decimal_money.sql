@@ -0,0 +1,114 @@
+-- Store contract amounts and payroll information as exact decimals instead of floating
+-- point numbers. The views reading these columns are dropped and recreated around the
+-- type change.
+
+DROP VIEW public.contractor_payroll_information;
+
+ALTER TABLE public.individual_contractor_payroll_information
+    ALTER COLUMN monthly_basic_salary_amount TYPE numeric USING monthly_basic_salary_amount::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_addition_section TYPE numeric USING monthly_added_pay_items_for_addition_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_deduction_section TYPE numeric USING monthly_added_pay_items_for_deduction_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_statement_only_section TYPE numeric USING monthly_added_pay_items_for_statement_only_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_employers_contribution_section TYPE numeric USING monthly_added_pay_items_for_employers_contribution_section::numeric;
+
+ALTER TABLE public.entity_contractor_payroll_information
+    ALTER COLUMN monthly_basic_salary_amount TYPE numeric USING monthly_basic_salary_amount::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_addition_section TYPE numeric USING monthly_added_pay_items_for_addition_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_deduction_section TYPE numeric USING monthly_added_pay_items_for_deduction_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_statement_only_section TYPE numeric USING monthly_added_pay_items_for_statement_only_section::numeric,
+    ALTER COLUMN monthly_added_pay_items_for_employers_contribution_section TYPE numeric USING monthly_added_pay_items_for_employers_contribution_section::numeric;
+
+---- contractor_payroll_information
+CREATE OR REPLACE VIEW public.contractor_payroll_information
+ AS
+ SELECT individual_contractor_payroll_information.contractor_ulid,
+    individual_contractor_payroll_information.client_ulid,
+    individual_contractor_payroll_information.monthly_basic_salary_amount,
+    individual_contractor_payroll_information.monthly_added_pay_items_for_addition_section,
+    individual_contractor_payroll_information.monthly_added_pay_items_for_deduction_section,
+    individual_contractor_payroll_information.monthly_added_pay_items_for_statement_only_section,
+    individual_contractor_payroll_information.monthly_added_pay_items_for_employers_contribution_section
+   FROM individual_contractor_payroll_information
+UNION
+ SELECT entity_contractor_payroll_information.contractor_ulid,
+    entity_contractor_payroll_information.client_ulid,
+    entity_contractor_payroll_information.monthly_basic_salary_amount,
+    entity_contractor_payroll_information.monthly_added_pay_items_for_addition_section,
+    entity_contractor_payroll_information.monthly_added_pay_items_for_deduction_section,
+    entity_contractor_payroll_information.monthly_added_pay_items_for_statement_only_section,
+    entity_contractor_payroll_information.monthly_added_pay_items_for_employers_contribution_section
+   FROM entity_contractor_payroll_information;
+
+ALTER TABLE public.contractor_payroll_information
+    OWNER TO postgres;
+
+---- contracts_index
+DROP VIEW public.contracts_index;
+
+ALTER TABLE public.contracts
+    ALTER COLUMN contract_amount DROP DEFAULT,
+    ALTER COLUMN contract_amount TYPE numeric USING contract_amount::numeric,
+    ALTER COLUMN contract_amount SET DEFAULT 0;
+
+CREATE OR REPLACE VIEW public.contracts_index
+ AS
+ WITH contractors AS (
+         SELECT entity_contractor_account_details.ulid AS contractor_ulid,
+            entity_contractor_account_details.company_name AS contractor_name
+           FROM entity_contractor_account_details
+        UNION
+         SELECT individual_contractor_account_details.ulid AS contractor_ulid,
+            concat(individual_contractor_account_details.first_name, ' ', individual_contractor_account_details.last_name) AS contractor_name
+           FROM individual_contractor_account_details
+        ), clients AS (
+         SELECT entity_client_account_details.ulid AS client_ulid,
+            entity_client_account_details.company_name AS client_name
+           FROM entity_client_account_details
+        UNION
+         SELECT individual_client_account_details.ulid AS client_ulid,
+            concat(individual_client_account_details.first_name, ' ', individual_client_account_details.last_name) AS client_name
+           FROM individual_client_account_details
+        )
+ SELECT contracts.ulid,
+    contracts.client_ulid,
+    contracts.contractor_ulid,
+    contracts.contract_name,
+    contracts.contract_type,
+    contracts.contract_status,
+    contracts.currency,
+    contracts.job_title,
+    contracts.seniority,
+    contracts.begin_at,
+    contracts.end_at,
+    contracts.branch_ulid,
+    contracts.created_at,
+    contracts.client_signature,
+    contracts.contractor_signature,
+    contracts.client_date_signed,
+    contracts.contractor_date_signed,
+    contracts.team_ulid,
+    contracts.job_scope,
+    contracts.contract_amount,
+    contracts.country_of_contractors_tax_residence,
+    contracts.notice_period,
+    contracts.offer_stock_option,
+    contracts.special_clause,
+    contracts.cut_off,
+    contracts.pay_day,
+    contracts.due_date,
+    contractors.contractor_name,
+    clients.client_name,
+    contracts.tax_settings,
+    contracts.statutory_fund_settings,
+    contracts.payment_calculation_settings,
+    contracts.client_rejected_reason,
+    contracts.contractor_rejected_reason,
+    contracts.cancelled_reason,
+    contracts.activate_to_draft_reason
+   FROM contracts
+     JOIN clients ON contracts.client_ulid = clients.client_ulid
+     LEFT JOIN contractors ON contracts.contractor_ulid = contractors.contractor_ulid;
+
+ALTER TABLE public.contracts_index
+    OWNER TO postgres;
+
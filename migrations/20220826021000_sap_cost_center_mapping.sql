@@ -0,0 +1,8 @@
+-- Map the cost centers of clients to SAP cost centers, so that payroll journals can be
+-- generated from the contracts in each cost center.
+
+ALTER TABLE public.cost_center
+    ADD COLUMN sap_cost_center_code text
+        REFERENCES public.sap_mulesoft_payroll_journal_cost_centers(code)
+        ON UPDATE CASCADE
+        ON DELETE SET NULL;
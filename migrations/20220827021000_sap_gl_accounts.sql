@@ -0,0 +1,44 @@
+-- GL accounts journal lines are posted to, per company code. A line is posted either for
+-- a purpose of its own (salaries, the outgoing bank payment) or for a pay item, by pay
+-- item type and optionally pay item method.
+
+CREATE TABLE public.sap_mulesoft_payroll_journal_gl_accounts (
+    ulid uuid NOT NULL PRIMARY KEY,
+    company_code text NOT NULL
+        REFERENCES public.sap_mulesoft_payroll_journal_company_codes(code)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+    purpose text NOT NULL,
+    pay_item_type text,
+    pay_item_method text,
+    gl_account text NOT NULL,
+    description text,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CONSTRAINT sap_gl_accounts_purpose_check CHECK (
+        purpose IN ('salary', 'bank_outgoing', 'pay_item')
+    ),
+    CONSTRAINT sap_gl_accounts_pay_item_check CHECK (
+        (purpose = 'pay_item') = (pay_item_type IS NOT NULL) AND
+        (pay_item_method IS NULL OR pay_item_type IS NOT NULL)
+    )
+);
+
+ALTER TABLE public.sap_mulesoft_payroll_journal_gl_accounts OWNER TO postgres;
+
+CREATE UNIQUE INDEX sap_mulesoft_payroll_journal_gl_accounts_key
+    ON public.sap_mulesoft_payroll_journal_gl_accounts (
+        company_code, purpose, COALESCE(pay_item_type, ''), COALESCE(pay_item_method, '')
+    );
+
+-- The accounts that used to be hard-coded.
+INSERT INTO public.sap_mulesoft_payroll_journal_gl_accounts (
+    ulid, company_code, purpose, gl_account, description
+)
+SELECT
+    uuid_generate_v4(), code, purpose, gl_account, description
+FROM
+    public.sap_mulesoft_payroll_journal_company_codes,
+    (VALUES
+        ('salary', '430101001', 'Salaries'),
+        ('bank_outgoing', '120202003', 'Bank outgoing')
+    ) AS accounts (purpose, gl_account, description);
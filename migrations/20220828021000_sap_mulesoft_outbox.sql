@@ -0,0 +1,75 @@
+-- Post payroll journals to Mulesoft from an outbox: every journal is stored with the exact
+-- payload to send, posted by a background worker with retries, and every attempt is kept.
+
+ALTER TABLE public.sap_mulesoft_payroll_journals_entries
+    ADD COLUMN status text NOT NULL DEFAULT 'pending',
+    ADD COLUMN payload text,
+    ADD COLUMN attempt_count integer NOT NULL DEFAULT 0,
+    ADD COLUMN next_attempt_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN posting_started_at timestamp with time zone,
+    ADD COLUMN posted_at timestamp with time zone,
+    ADD COLUMN last_error text,
+    ADD COLUMN reverses_entry_ulid uuid
+        REFERENCES public.sap_mulesoft_payroll_journals_entries(ulid),
+    ADD CONSTRAINT sap_mulesoft_payroll_journals_entries_status_check CHECK (
+        status IN ('pending', 'posting', 'posted', 'failed', 'cancelled')
+    );
+
+-- A journal is reversed at most once.
+CREATE UNIQUE INDEX sap_mulesoft_payroll_journals_entries_reverses_key
+    ON public.sap_mulesoft_payroll_journals_entries (reverses_entry_ulid)
+    WHERE status <> 'cancelled';
+
+-- Journals from before the outbox were posted once. Those that did not go through have no
+-- stored payload, so they are marked as failed and have to be uploaded again.
+UPDATE public.sap_mulesoft_payroll_journals_entries e
+SET
+    status = CASE
+        WHEN EXISTS (
+            SELECT 1 FROM public.sap_mulesoft_payroll_journals_rows r
+            WHERE r.entry_ulid = e.ulid AND r.uploaded
+        ) THEN 'posted'
+        ELSE 'failed'
+    END,
+    next_attempt_at = NULL,
+    last_error = 'Posted before the outbox was introduced';
+
+CREATE TABLE public.sap_mulesoft_payroll_journal_attempts (
+    ulid uuid NOT NULL PRIMARY KEY,
+    entry_ulid uuid NOT NULL
+        REFERENCES public.sap_mulesoft_payroll_journals_entries(ulid)
+        ON DELETE CASCADE,
+    attempted_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    status_code integer,
+    response text,
+    error text
+);
+
+ALTER TABLE public.sap_mulesoft_payroll_journal_attempts OWNER TO postgres;
+
+CREATE INDEX sap_mulesoft_payroll_journal_attempts_entry_ulid_idx
+    ON public.sap_mulesoft_payroll_journal_attempts (entry_ulid, attempted_at);
+
+DROP VIEW public.sap_mulesoft_payroll_journals_entry_index;
+
+CREATE OR REPLACE VIEW public.sap_mulesoft_payroll_journals_entry_index AS
+    SELECT
+        ulid,
+        country_code,
+        created_at,
+        client_ulid,
+        uploaded_file,
+        file_name,
+        (SELECT COUNT(*) FROM public.sap_mulesoft_payroll_journals_rows b WHERE b.entry_ulid = a.ulid) AS row_count,
+        status,
+        attempt_count,
+        next_attempt_at,
+        posted_at,
+        last_error,
+        reverses_entry_ulid,
+        (
+            SELECT c.ulid FROM public.sap_mulesoft_payroll_journals_entries c
+            WHERE c.reverses_entry_ulid = a.ulid AND c.status <> 'cancelled'
+        ) AS reversed_by_entry_ulid
+    FROM
+        sap_mulesoft_payroll_journals_entries a;
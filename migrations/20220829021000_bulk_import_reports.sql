@@ -0,0 +1,38 @@
+-- The outcome of every bulk import, row by row, with the uploaded file annotated with it.
+
+CREATE TABLE public.bulk_import_reports (
+    ulid uuid NOT NULL PRIMARY KEY,
+    client_ulid uuid NOT NULL,
+    file_name text NOT NULL,
+    dry_run boolean NOT NULL,
+    created_count integer NOT NULL,
+    linked_count integer NOT NULL,
+    skipped_count integer NOT NULL,
+    failed_count integer NOT NULL,
+    created_by uuid NOT NULL,
+    annotated_file bytea NOT NULL,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+ALTER TABLE public.bulk_import_reports OWNER TO postgres;
+
+CREATE INDEX bulk_import_reports_client_ulid_idx
+    ON public.bulk_import_reports (client_ulid, created_at);
+
+CREATE TABLE public.bulk_import_report_rows (
+    report_ulid uuid NOT NULL
+        REFERENCES public.bulk_import_reports(ulid)
+        ON DELETE CASCADE,
+    row_number integer NOT NULL,
+    email text,
+    status text NOT NULL,
+    field text,
+    reason text,
+    user_ulid uuid,
+    PRIMARY KEY (report_ulid, row_number),
+    CONSTRAINT bulk_import_report_rows_status_check CHECK (
+        status IN ('created', 'linked', 'skipped', 'failed')
+    )
+);
+
+ALTER TABLE public.bulk_import_report_rows OWNER TO postgres;
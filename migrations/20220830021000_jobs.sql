@@ -0,0 +1,39 @@
+-- Work that takes too long to run inside a request, picked up by the workers of the service
+-- that enqueued it.
+
+CREATE TABLE public.jobs (
+    ulid uuid NOT NULL PRIMARY KEY,
+    kind text NOT NULL,
+    -- Only one queued or running job can have a given key.
+    unique_key text,
+    status text DEFAULT 'queued' NOT NULL,
+    -- Cleared once the job has finished, it can hold the personal data of an import.
+    payload text,
+    progress_done integer DEFAULT 0 NOT NULL,
+    progress_total integer,
+    attempts integer DEFAULT 0 NOT NULL,
+    max_attempts integer NOT NULL,
+    run_after timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    last_error text,
+    cancel_requested boolean DEFAULT false NOT NULL,
+    result bytea,
+    result_content_type text,
+    created_by uuid,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    started_at timestamp with time zone,
+    heartbeat_at timestamp with time zone,
+    finished_at timestamp with time zone,
+    CONSTRAINT jobs_status_check CHECK (
+        status IN ('queued', 'running', 'succeeded', 'failed', 'cancelled')
+    )
+);
+
+ALTER TABLE public.jobs OWNER TO postgres;
+
+CREATE INDEX jobs_queued_idx
+    ON public.jobs (kind, run_after)
+    WHERE status = 'queued';
+
+CREATE UNIQUE INDEX jobs_unique_key_idx
+    ON public.jobs (unique_key)
+    WHERE status IN ('queued', 'running');
@@ -0,0 +1,28 @@
+-- Bulk imports that update the contractors who already exist, with the values they changed.
+
+ALTER TABLE public.bulk_import_reports
+    ADD COLUMN mode text DEFAULT 'create-only' NOT NULL,
+    ADD COLUMN updated_count integer DEFAULT 0 NOT NULL,
+    ADD CONSTRAINT bulk_import_reports_mode_check CHECK (
+        mode IN ('create-only', 'update-existing', 'upsert')
+    );
+
+ALTER TABLE public.bulk_import_report_rows
+    DROP CONSTRAINT bulk_import_report_rows_status_check,
+    ADD CONSTRAINT bulk_import_report_rows_status_check CHECK (
+        status IN ('created', 'linked', 'updated', 'skipped', 'failed')
+    );
+
+CREATE TABLE public.bulk_import_report_changes (
+    report_ulid uuid NOT NULL,
+    row_number integer NOT NULL,
+    field text NOT NULL,
+    old_value text,
+    new_value text,
+    PRIMARY KEY (report_ulid, row_number, field),
+    FOREIGN KEY (report_ulid, row_number)
+        REFERENCES public.bulk_import_report_rows(report_ulid, row_number)
+        ON DELETE CASCADE
+);
+
+ALTER TABLE public.bulk_import_report_changes OWNER TO postgres;
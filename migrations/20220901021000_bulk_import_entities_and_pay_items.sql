@@ -0,0 +1,7 @@
+-- Bulk imports of entity contractors and of the pay items assigned to contractors.
+
+ALTER TABLE public.bulk_import_reports
+    ADD COLUMN kind text DEFAULT 'individual-contractors' NOT NULL,
+    ADD CONSTRAINT bulk_import_reports_kind_check CHECK (
+        kind IN ('individual-contractors', 'entity-contractors', 'pay-items')
+    );
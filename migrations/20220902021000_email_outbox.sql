@@ -0,0 +1,31 @@
+-- Emails rendered by a request and sent afterwards, so that a slow or unavailable SMTP server
+-- does not fail the request.
+--
+-- The bodies of an email are only kept until it is sent or given up on, as they can hold
+-- sign up links and the passwords of accounts created by an admin.
+
+CREATE TABLE public.email_outbox (
+    ulid uuid NOT NULL PRIMARY KEY,
+    template text NOT NULL,
+    locale text NOT NULL,
+    to_address text NOT NULL,
+    subject text NOT NULL,
+    html_body text,
+    text_body text,
+    status text DEFAULT 'pending' NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    next_attempt_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    last_error text,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    claimed_at timestamp with time zone,
+    sent_at timestamp with time zone,
+    CONSTRAINT email_outbox_status_check CHECK (
+        status IN ('pending', 'sending', 'sent', 'failed')
+    )
+);
+
+ALTER TABLE public.email_outbox OWNER TO postgres;
+
+CREATE INDEX email_outbox_pending_idx
+    ON public.email_outbox (next_attempt_at)
+    WHERE status = 'pending';
@@ -0,0 +1,11 @@
+-- Every API key expires. The keys created without an expiry expire a year after they were
+-- created, or in a month if that is sooner than a month from now.
+
+UPDATE public.api_keys
+SET
+    expires_at = GREATEST(created_at + INTERVAL '1 year', CURRENT_TIMESTAMP + INTERVAL '1 month')
+WHERE
+    expires_at IS NULL;
+
+ALTER TABLE public.api_keys
+    ALTER COLUMN expires_at SET NOT NULL;
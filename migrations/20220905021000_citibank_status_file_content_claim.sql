@@ -0,0 +1,8 @@
+-- A status file claims its content hash before the report is applied, so that the same
+-- report under two file names cannot be applied by two replicas at once.
+
+DROP INDEX public.citibank_status_files_processed_content_hash_idx;
+
+CREATE UNIQUE INDEX citibank_status_files_claimed_content_hash_idx
+    ON public.citibank_status_files (content_hash)
+    WHERE status IN ('processing', 'processed');
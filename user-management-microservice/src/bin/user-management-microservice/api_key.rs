@@ -0,0 +1,154 @@
+use axum::{
+    extract::{ContentLengthLimit, Path, Query},
+    Extension, Json,
+};
+use common_utils::{
+    api_key::{
+        api_key_hint, generate_api_key, hash_api_key, ApiKeyScope, MAX_API_KEY_LIFETIME_DAYS,
+    },
+    custom_serde::{OffsetDateWrapper, UserRole, FORM_DATA_LENGTH_LIMIT},
+    database::{api_key::ApiKey, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    token::{NotImpersonated, Token},
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use time::{Duration, OffsetDateTime};
+use user_management_microservice_sdk::token::UserAccessToken;
+use uuid::Uuid;
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GetApiKeyRequest {
+    user_ulid: Option<Uuid>,
+    per_page: Option<u32>,
+    page: Option<u32>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostApiKeyRequest {
+    name: String,
+    scopes: Vec<ApiKeyScope>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    expires_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostApiKeyResponse {
+    ulid: Uuid,
+    /// Only returned once, since just its hash is stored.
+    api_key: String,
+}
+
+pub async fn user_get_many(
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
+    Query(query): Query<GetApiKeyRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Vec<ApiKey>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_api_keys(Some(claims.payload.ulid), query.per_page, query.page)
+        .await?;
+
+    Ok(Json(result))
+}
+
+pub async fn user_post_one(
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<
+        Json<PostApiKeyRequest>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<PostApiKeyResponse>> {
+    // API keys can only be created by the client themselves.
+    if !claims.payload.user_roles.contains(&UserRole::Client)
+        || claims.payload.api_key_ulid.is_some()
+    {
+        return Err(GlobeliseError::Forbidden);
+    }
+    if body.name.trim().is_empty() {
+        return Err(GlobeliseError::bad_request("API key name cannot be empty"));
+    }
+    if body.scopes.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "API key must have at least one scope",
+        ));
+    }
+    if body.expires_at <= OffsetDateTime::now_utc() {
+        return Err(GlobeliseError::bad_request(
+            "API key expiry must be in the future",
+        ));
+    }
+    if body.expires_at > OffsetDateTime::now_utc() + Duration::days(MAX_API_KEY_LIFETIME_DAYS) {
+        return Err(GlobeliseError::bad_request(format!(
+            "API key expiry cannot be more than {} days away",
+            MAX_API_KEY_LIFETIME_DAYS
+        )));
+    }
+
+    let database = database.lock().await;
+
+    let api_key = generate_api_key()?;
+    let ulid = database
+        .insert_one_api_key(
+            claims.payload.ulid,
+            claims.payload.user_type,
+            body.name.trim(),
+            &api_key_hint(&api_key),
+            &hash_api_key(&api_key),
+            &body.scopes,
+            body.expires_at,
+        )
+        .await?;
+
+    Ok(Json(PostApiKeyResponse { ulid, api_key }))
+}
+
+pub async fn user_delete_one(
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
+    Path(api_key_ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+
+    database
+        .update_one_api_key_revoked(api_key_ulid, Some(claims.payload.ulid))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn admin_get_many(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<GetApiKeyRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Vec<ApiKey>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_api_keys(query.user_ulid, query.per_page, query.page)
+        .await?;
+
+    Ok(Json(result))
+}
+
+pub async fn admin_delete_one(
+    _: Token<AdminAccessToken>,
+    Path(api_key_ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+
+    database
+        .update_one_api_key_revoked(api_key_ulid, None)
+        .await?;
+
+    Ok(())
+}
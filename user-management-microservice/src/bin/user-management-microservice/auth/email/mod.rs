@@ -0,0 +1,156 @@
+//! Endpoints for verifying the email address of a user.
+
+use axum::{
+    extract::{Extension, Path},
+    http::HeaderMap,
+    response::Redirect,
+};
+use common_utils::{
+    custom_serde::{EmailWrapper, UserType},
+    database::{CommonDatabase, Database},
+    error::{GlobeliseError, GlobeliseResult},
+    mail::{self, EmailTemplate},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use user_management_microservice_sdk::token::UserAccessToken;
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        state::State,
+        token::one_time::{OneTimeToken, OneTimeTokenParam},
+        SharedState,
+    },
+    env::{FRONTEND_URL, USER_MANAGEMENT_MICROSERVICE_DOMAIN_URL},
+};
+
+mod token;
+
+pub use token::EmailVerificationToken;
+
+/// Resend the verification email to the logged in user.
+pub async fn user_resend(
+    claims: Token<UserAccessToken>,
+    headers: HeaderMap,
+    Extension(database): Extension<CommonDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+    if database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::bad_request(
+            "Email address has already been verified",
+        ));
+    }
+
+    let mut shared_state = shared_state.lock().await;
+    send_verification_email(
+        &database,
+        &mut shared_state,
+        claims.payload.ulid,
+        claims.payload.user_type,
+        &claims.payload.email,
+        mail::request_locale(&headers),
+    )
+    .await
+}
+
+/// Resend the verification email to a user on their behalf.
+pub async fn admin_resend(
+    _: Token<AdminAccessToken>,
+    Path(user_ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+    let user = database
+        .find_one_user(Some(user_ulid), None, None)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find user with that ulid"))?;
+    if user.email_verified_at.is_some() {
+        return Err(GlobeliseError::bad_request(
+            "Email address has already been verified",
+        ));
+    }
+
+    let mut shared_state = shared_state.lock().await;
+    send_verification_email(
+        &database,
+        &mut shared_state,
+        user.ulid,
+        user.user_type()?,
+        &user.email,
+        // The language of the admin says nothing about the one of the user.
+        None,
+    )
+    .await
+}
+
+/// Respond to user clicking the verification link in their email.
+pub async fn verify(
+    OneTimeTokenParam(claims): OneTimeTokenParam<OneTimeToken<EmailVerificationToken>>,
+    Extension(database): Extension<CommonDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<Redirect> {
+    let database = database.lock().await;
+    database.update_one_user_email_verified(claims.sub).await?;
+
+    // The links of the other verification emails must not work either.
+    let mut shared_state = shared_state.lock().await;
+    shared_state
+        .revoke_one_time_sessions::<EmailVerificationToken>(claims.sub)
+        .await?;
+
+    let redirect_url = format!("{}/email-verified", (*FRONTEND_URL));
+    Ok(Redirect::to(&redirect_url))
+}
+
+/// Mark the email address of a user as verified without them following the link.
+pub async fn admin_verify(
+    _: Token<AdminAccessToken>,
+    Path(user_ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+    Extension(shared_state): Extension<SharedState>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+    if database
+        .find_one_user(Some(user_ulid), None, None)
+        .await?
+        .is_none()
+    {
+        return Err(GlobeliseError::not_found("Cannot find user with that ulid"));
+    }
+
+    database.update_one_user_email_verified(user_ulid).await?;
+
+    let mut shared_state = shared_state.lock().await;
+    shared_state
+        .revoke_one_time_sessions::<EmailVerificationToken>(user_ulid)
+        .await?;
+
+    Ok(())
+}
+
+/// Opens an email verification session for the user and emails them the link, in `locale`.
+pub async fn send_verification_email(
+    database: &Database,
+    shared_state: &mut State,
+    ulid: Uuid,
+    user_type: UserType,
+    email: &EmailWrapper,
+    locale: Option<&str>,
+) -> GlobeliseResult<()> {
+    let one_time_token = shared_state
+        .open_one_time_session::<EmailVerificationToken>(ulid, user_type)
+        .await?;
+
+    let link = format!(
+        "{}/auth/email/verify?token={}",
+        (*USER_MANAGEMENT_MICROSERVICE_DOMAIN_URL),
+        one_time_token
+    );
+    let rendered = EmailTemplate::EmailVerification.render(locale, &[("link", &link)])?;
+    database.insert_one_outbox_email(email, &rendered).await?;
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use time::Duration;
+
+use crate::auth::token::one_time::OneTimeTokenAudience;
+
+#[derive(Debug)]
+pub struct EmailVerificationToken;
+
+impl OneTimeTokenAudience for EmailVerificationToken {
+    fn name() -> &'static str {
+        "email_verification"
+    }
+
+    fn lifetime() -> Duration {
+        Duration::hours(24)
+    }
+}
@@ -34,6 +34,9 @@ pub async fn login(
         .find_one_user(None, Some(&claims.email), None)
         .await?
     {
+        // Google has already verified that the user owns this email address.
+        database.update_one_user_email_verified(user.ulid).await?;
+
         let user_type = user.user_type()?;
         let refresh_token = shared_state.open_session(user.ulid, user_type).await?;
 
@@ -63,6 +66,8 @@ pub async fn signup(
         .find_one_user(None, Some(&claims.email), None)
         .await?
     {
+        database.update_one_user_email_verified(user.ulid).await?;
+
         let user_type = user.user_type()?;
         let refresh_token = shared_state.open_session(user.ulid, user_type).await?;
 
@@ -95,6 +100,7 @@ pub async fn signup(
                 false,
             )
             .await?;
+        database.update_one_user_email_verified(ulid).await?;
 
         let refresh_token = shared_state.open_session(ulid, user_type).await?;
         Ok(refresh_token)
@@ -0,0 +1,123 @@
+//! Endpoints for EOR admins acting as users.
+
+use axum::{
+    extract::{ContentLengthLimit, Extension, Json, Path, Query},
+    http::{Method, Uri},
+};
+use common_utils::{
+    custom_serde::FORM_DATA_LENGTH_LIMIT,
+    database::{impersonation::ImpersonationLog, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    token::{create_token_with_lifetime, Impersonation, Token},
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use time::Duration;
+use user_management_microservice_sdk::token::UserAccessToken;
+use uuid::Uuid;
+
+use super::{token::KEYS, user_roles};
+
+/// How many minutes an impersonation token is valid for.
+const IMPERSONATION_TOKEN_LIFETIME_MINUTES: i64 = 15;
+
+/// Gets an access token for acting as a user.
+///
+/// The user is notified, and every request made with the token is logged.
+pub async fn admin_impersonate(
+    claims: Token<AdminAccessToken>,
+    method: Method,
+    uri: Uri,
+    Path(user_ulid): Path<Uuid>,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<
+        Json<ImpersonateRequest>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<String> {
+    let database = database.lock().await;
+
+    let user = database
+        .find_one_user(Some(user_ulid), None, None)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find user with that ulid"))?;
+    let user_type = user.user_type()?;
+
+    let impersonation = Impersonation {
+        admin_ulid: claims.payload.ulid,
+        user_ulid,
+        read_only: body.read_only.unwrap_or(true),
+    };
+    database
+        .insert_one_impersonation_log(&impersonation, method.as_str(), uri.path())
+        .await?;
+
+    let message = if impersonation.read_only {
+        format!(
+            "Globelise support ({}) is viewing your account",
+            claims.payload.email.0
+        )
+    } else {
+        format!(
+            "Globelise support ({}) is making changes to your account on your behalf",
+            claims.payload.email.0
+        )
+    };
+    let notification_ulid = database.create_one_user_notification(message).await?;
+    database
+        .create_one_user_see_notification_for_specific_users(&[user_ulid], notification_ulid)
+        .await?;
+
+    let access_token = UserAccessToken {
+        ulid: user_ulid,
+        email: user.email,
+        user_type,
+        user_roles: user_roles(&database, user_ulid, user_type).await?,
+        impersonation: Some(impersonation),
+        api_key_ulid: None,
+    };
+    let (access_token, _) = create_token_with_lifetime(
+        access_token,
+        &KEYS.current(),
+        Duration::minutes(IMPERSONATION_TOKEN_LIFETIME_MINUTES),
+    )?;
+
+    Ok(access_token)
+}
+
+/// Gets the requests made by admins while acting as users.
+pub async fn admin_get_many_logs(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<GetImpersonationLogsRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Vec<ImpersonationLog>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_impersonation_logs(
+            query.admin_ulid,
+            query.user_ulid,
+            query.per_page,
+            query.page,
+        )
+        .await?;
+
+    Ok(Json(result))
+}
+
+/// Request for acting as a user.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImpersonateRequest {
+    /// Defaults to read-only.
+    pub read_only: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GetImpersonationLogsRequest {
+    pub admin_ulid: Option<Uuid>,
+    pub user_ulid: Option<Uuid>,
+    pub per_page: Option<u32>,
+    pub page: Option<u32>,
+}
@@ -3,22 +3,31 @@
 use argon2::{self, hash_encoded, verify_encoded, Config};
 use axum::{
     extract::{ContentLengthLimit, Extension, Path},
+    http::HeaderMap,
     Json,
 };
 use common_utils::{
     custom_serde::{EmailWrapper, UserRole, UserType, FORM_DATA_LENGTH_LIMIT},
-    database::{user::User, CommonDatabase},
+    database::{user::User, CommonDatabase, Database},
     error::{GlobeliseError, GlobeliseResult},
-    token::{create_token, Token},
+    mail,
+    password::PASSWORD_POLICY,
+    token::{create_token, JwkSet, SigningKey, Token},
+    DaprAppId,
 };
+use eor_admin_microservice_sdk::token::AdminAccessToken;
 use once_cell::sync::Lazy;
 use rand::Rng;
 use serde::Deserialize;
+use time::Duration;
 use token::RefreshToken;
 use unicode_normalization::UnicodeNormalization;
 use user_management_microservice_sdk::token::UserAccessToken;
+use uuid::Uuid;
 
+pub mod email;
 pub mod google;
+pub mod impersonation;
 pub mod password;
 pub mod state;
 pub mod token;
@@ -34,17 +43,14 @@ pub async fn signup(
         FORM_DATA_LENGTH_LIMIT,
     >,
     Path(user_type): Path<UserType>,
+    headers: HeaderMap,
     Extension(database): Extension<CommonDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<String> {
     let password: String = body.password.nfc().collect();
     let confirm_password: String = body.confirm_password.nfc().collect();
 
-    if password.len() < 8 {
-        return Err(GlobeliseError::bad_request(
-            "Password must be at least 8 characters long",
-        ));
-    }
+    PASSWORD_POLICY.validate(&password)?;
 
     if password != confirm_password {
         return Err(GlobeliseError::bad_request("Passwords do not match"));
@@ -56,35 +62,30 @@ pub async fn signup(
 
     let database = database.lock().await;
 
-    let ulid = if let Some(user) = database
+    // An existing account, even one without a password such as a Google sign-in,
+    // must not get a password from whoever signs up with its email address.
+    if database
         .find_one_user(None, Some(&body.email), None)
         .await?
+        .is_some()
     {
-        if user.password == None {
-            database
-                .update_one_user(user.ulid, Some(&password), user.is_google, user.is_outlook)
-                .await?;
-            Ok(user.ulid)
-        } else {
-            Err(GlobeliseError::bad_request(
-                "User with that email already exists. Did you forget the password?",
-            ))
-        }
-    } else {
-        let ulid = database
-            .insert_one_user(
-                &body.email,
-                Some(&hash),
-                false,
-                false,
-                user_type == UserType::Entity,
-                user_type == UserType::Individual,
-                false,
-                false,
-            )
-            .await?;
-        Ok(ulid)
-    }?;
+        return Err(GlobeliseError::bad_request(
+            "User with that email already exists. Did you forget the password?",
+        ));
+    }
+
+    let ulid = database
+        .insert_one_user(
+            &body.email,
+            Some(&hash),
+            false,
+            false,
+            user_type == UserType::Entity,
+            user_type == UserType::Individual,
+            false,
+            false,
+        )
+        .await?;
 
     //register user for benefits marketplace
     let email = &(body.email.0.clone()).to_string();
@@ -103,6 +104,16 @@ pub async fn signup(
     }
 
     let mut shared_state = shared_state.lock().await;
+    email::send_verification_email(
+        &database,
+        &mut shared_state,
+        ulid,
+        user_type,
+        &body.email,
+        mail::request_locale(&headers),
+    )
+    .await?;
+
     let refresh_token = shared_state.open_session(ulid, user_type).await?;
     Ok(refresh_token)
 }
@@ -125,6 +136,12 @@ pub async fn login(
     {
         if let Some(hash) = &user.password {
             if let Ok(true) = verify_encoded(hash, password.as_bytes()) {
+                if PASSWORD_POLICY.is_expired(user.password_changed_at) {
+                    return Err(GlobeliseError::unauthorized(
+                        "Password has expired. Please reset your password",
+                    ));
+                }
+
                 let mut shared_state = shared_state.lock().await;
 
                 let user_type = user.user_type()?;
@@ -161,11 +178,7 @@ pub async fn access_token(
     let mut is_session_valid = false;
     let _ = shared_state.clear_expired_sessions(ulid).await;
     if let Some(sessions) = shared_state.sessions(ulid).await? {
-        let encoded_claims = jsonwebtoken::encode(
-            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::EdDSA),
-            &claims,
-            &KEYS.encoding,
-        )?;
+        let encoded_claims = claims.reencode(&KEYS)?;
         for (hash, _) in sessions.iter() {
             if let Ok(true) = verify_encoded(hash, encoded_claims.as_bytes()) {
                 is_session_valid = true;
@@ -182,26 +195,15 @@ pub async fn access_token(
         .find_one_user(Some(ulid), None, Some(user_type))
         .await?
     {
-        let mut user_roles = vec![];
-        if database
-            .get_is_user_fully_onboarded(ulid, user_type, UserRole::Client)
-            .await?
-        {
-            user_roles.push(UserRole::Client);
-        };
-        if database
-            .get_is_user_fully_onboarded(ulid, user_type, UserRole::Contractor)
-            .await?
-        {
-            user_roles.push(UserRole::Contractor);
-        };
         let access_token = UserAccessToken {
             ulid,
             email,
             user_type,
-            user_roles,
+            user_roles: user_roles(&database, ulid, user_type).await?,
+            impersonation: None,
+            api_key_ulid: None,
         };
-        let (access_token, _) = create_token(access_token, &KEYS.encoding)?;
+        let (access_token, _) = create_token(access_token, &KEYS.current())?;
         Ok(access_token)
     } else {
         Err(GlobeliseError::unauthorized(
@@ -210,11 +212,90 @@ pub async fn access_token(
     }
 }
 
+/// Gets the roles that a user has fully onboarded for.
+pub async fn user_roles(
+    database: &Database,
+    ulid: Uuid,
+    user_type: UserType,
+) -> GlobeliseResult<Vec<UserRole>> {
+    let mut user_roles = vec![];
+    if database
+        .get_is_user_fully_onboarded(ulid, user_type, UserRole::Client)
+        .await?
+    {
+        user_roles.push(UserRole::Client);
+    };
+    if database
+        .get_is_user_fully_onboarded(ulid, user_type, UserRole::Contractor)
+        .await?
+    {
+        user_roles.push(UserRole::Contractor);
+    };
+    Ok(user_roles)
+}
+
 /// Gets the public key for decoding tokens.
+///
+/// Only the current signing key is returned. Use the JWKS endpoint to get every accepted key.
 pub async fn public_key() -> String {
-    (*token::PUBLIC_KEY).clone()
+    KEYS.current().public_key_pem()
+}
+
+/// Gets the public keys for decoding tokens, current signing key first.
+pub async fn jwks() -> Json<JwkSet> {
+    Json(KEYS.jwks())
 }
 
+/// Replaces the signing key.
+///
+/// Tokens signed by the previous key are accepted until the grace period is over. Only super
+/// admins can rotate keys.
+pub async fn rotate_keys(
+    claims: Token<AdminAccessToken>,
+    Json(body): Json<RotateKeysRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<JwkSet>> {
+    if !database
+        .lock()
+        .await
+        .is_super_admin(claims.payload.ulid)
+        .await?
+    {
+        return Err(GlobeliseError::Forbidden);
+    }
+
+    let grace_period = Duration::hours(
+        body.grace_period_hours
+            .unwrap_or(DEFAULT_KEY_GRACE_PERIOD_HOURS),
+    );
+    if grace_period.is_negative() {
+        return Err(GlobeliseError::bad_request(
+            "Grace period cannot be negative",
+        ));
+    }
+
+    let next = SigningKey::generate()?;
+    database
+        .lock()
+        .await
+        .rotate_signing_key(
+            DaprAppId::UserManagementMicroservice,
+            &KEYS.current(),
+            &next,
+            grace_period,
+        )
+        .await?;
+    KEYS.reload(&database, DaprAppId::UserManagementMicroservice)
+        .await?;
+
+    Ok(Json(KEYS.jwks()))
+}
+
+/// How long tokens signed by a rotated key stay valid by default.
+///
+/// Covers the lifetime of the longest-lived tokens.
+const DEFAULT_KEY_GRACE_PERIOD_HOURS: i64 = 48;
+
 /// Request for creating a user.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -232,6 +313,13 @@ pub struct LoginRequest {
     password: String,
 }
 
+/// Request for rotating the signing key.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RotateKeysRequest {
+    grace_period_hours: Option<i64>,
+}
+
 /// The parameters used for hashing.
 // TODO: Calibrate hash parameters for production server.
 pub static HASH_CONFIG: Lazy<Config> = Lazy::new(|| Config {
@@ -2,8 +2,11 @@
 
 use std::{fs::File, io::Read};
 
-use common_utils::{custom_serde::UserType, token::TokenLike, DaprAppId};
-use jsonwebtoken::{DecodingKey, EncodingKey};
+use common_utils::{
+    custom_serde::UserType,
+    token::{KeyRing, SigningKey, TokenLike},
+    DaprAppId,
+};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
@@ -35,41 +38,14 @@ impl TokenLike for RefreshToken {
     }
 }
 
-/// Stores the keys used for encoding and decoding tokens.
-#[derive(Clone)]
-pub struct Keys {
-    pub encoding: EncodingKey,
-    pub decoding: DecodingKey,
-}
-
-impl Keys {
-    /// Creates a new encoding/decoding key pair from an Ed25519 key pair.
-    ///
-    /// The keys must be in PEM form.
-    fn new(private_key: &[u8], public_key: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_ed_pem(private_key).expect("Could not create encoding key"),
-            decoding: DecodingKey::from_ed_pem(public_key).expect("Could not create decoding key"),
-        }
-    }
-}
-
-/// The public key used for decoding tokens.
-pub static PUBLIC_KEY: Lazy<String> = Lazy::new(|| {
-    let mut public_key = String::new();
-    File::open("public.pem")
-        .expect("Could not open public key")
-        .read_to_string(&mut public_key)
-        .expect("Could not read public key");
-    public_key
-});
-
-/// The encoding/decoding key pair.
-pub static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let mut private_key: Vec<u8> = Vec::new();
+/// The signing keys of this service.
+///
+/// Starts with the key pair on disk until the keys stored in the database are loaded.
+pub static KEYS: Lazy<KeyRing> = Lazy::new(|| {
+    let mut private_key = String::new();
     File::open("private.pem")
         .expect("Could not open private key")
-        .read_to_end(&mut private_key)
+        .read_to_string(&mut private_key)
         .expect("Could not read private key");
-    Keys::new(&private_key, PUBLIC_KEY.as_bytes())
+    KeyRing::new(SigningKey::from_pem(&private_key).expect("Could not create signing key"))
 });
@@ -11,7 +11,7 @@ use common_utils::{
     error::{GlobeliseError, GlobeliseResult},
     token::ISSUER,
 };
-use jsonwebtoken::{decode, encode, Algorithm, Header, TokenData, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
@@ -41,9 +41,9 @@ where
         one_time_audience: PhantomData,
     };
 
+    let key = KEYS.current();
     Ok((
-        encode(&Header::new(Algorithm::EdDSA), &claims, &KEYS.encoding)
-            .map_err(GlobeliseError::internal)?,
+        encode(&key.header(), &claims, &key.encoding).map_err(GlobeliseError::internal)?,
         expiration,
     ))
 }
@@ -75,9 +75,12 @@ where
         validation.set_required_spec_claims(&["sub", "aud", "iss", "exp"]);
         let validation = validation;
 
-        let TokenData { claims, .. } =
-            decode::<OneTimeToken<T>>(input, &KEYS.decoding, &validation)
-                .map_err(GlobeliseError::unauthorized)?;
+        let kid = decode_header(input)
+            .map_err(GlobeliseError::unauthorized)?
+            .kid;
+        let key = KEYS.get(kid.as_deref())?;
+        let TokenData { claims, .. } = decode::<OneTimeToken<T>>(input, &key.decoding, &validation)
+            .map_err(GlobeliseError::unauthorized)?;
         Ok(claims)
     }
 }
@@ -1,8 +1,9 @@
 use axum::extract::{ContentLengthLimit, Extension, Json, Path};
 use common_utils::{
-    custom_serde::{Currency, UserType, FORM_DATA_LENGTH_LIMIT},
+    bank_details::BankDetails,
+    custom_serde::{Country, Currency, UserType, FORM_DATA_LENGTH_LIMIT},
     error::{GlobeliseError, GlobeliseResult},
-    token::Token,
+    token::{NotImpersonated, Token},
 };
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
@@ -75,6 +76,26 @@ impl Database {
 
         Ok(result)
     }
+
+    pub async fn select_one_branch_country(
+        &self,
+        branch_ulid: Uuid,
+    ) -> GlobeliseResult<Option<Country>> {
+        let result = sqlx::query_scalar(
+            "
+            SELECT
+                country
+            FROM
+                entity_client_branch_account_details
+            WHERE
+                ulid = $1",
+        )
+        .bind(branch_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
 }
 
 pub async fn post_branch_bank_details(
@@ -92,6 +113,16 @@ pub async fn post_branch_bank_details(
 
     let database = database.lock().await;
 
+    BankDetails {
+        country: database.select_one_branch_country(branch_ulid).await?,
+        bank_account_number: &body.bank_account_number,
+        bank_code: body.bank_code.as_deref(),
+        branch_code: body.branch_code.as_deref(),
+        swift_code: body.swift_code.as_deref(),
+        iban: body.iban.as_deref(),
+    }
+    .validate()?;
+
     database
         .post_branch_bank_details(
             branch_ulid,
@@ -109,7 +140,7 @@ pub async fn post_branch_bank_details(
 }
 
 pub async fn get_branch_bank_details(
-    claims: Token<UserAccessToken>,
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
     Path(branch_ulid): Path<Uuid>,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<Json<BranchBankDetails>> {
@@ -262,7 +262,7 @@ pub struct PayItemsIndexQuery {
 }
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum PayItemType {
     Tax,
@@ -336,7 +336,7 @@ impl sqlx::encode::Encode<'_, sqlx::Postgres> for PayItemType {
 //-When statement only is selected, this pay item will be reflected on payroll table and payroll report. but not included in total earning, total deductions and net pay. Also not reflected on payslip.
 //-When employer's contribution is selected, this pay item will be reflected on payroll table, payroll report as well as payslip, but not included in total earning, total deductions and net pay
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum PayItemMethod {
     Addition,
@@ -0,0 +1,506 @@
+//! Bulk import of entity contractors, with their company, PIC and bank details.
+
+use axum::{
+    extract::{ContentLengthLimit, Extension, Json},
+    response::IntoResponse,
+};
+use common_utils::{
+    custom_serde::{
+        Country, EmailWrapper, OffsetDateWrapper, UserRole, UserType, FORM_DATA_LENGTH_LIMIT,
+    },
+    database::{
+        job::Job,
+        onboard::{
+            self, bank::ContractorUserDetails, entity::EntityContractorAccountDetails,
+            pic::EntityPicDetails,
+        },
+        user, CommonDatabase,
+    },
+    error::GlobeliseResult,
+    jobs::{JobContext, JobOutput},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+
+use crate::database::SharedDatabase;
+
+use super::{
+    bank_detail_values, changed_values, enqueue_import, import_rows, insert_contractor_user,
+    invalid_bank_details, pay_items, read_records,
+    report::{BulkImportKind, BulkImportRowStatus, NewBulkImportReport},
+    save_report, send_invitation_after_import, template_workbook, BulkImportJob,
+    PostOneAddBulkEmployee, RowOutcome,
+};
+
+pub const BULK_ADD_ENTITY_CONTRACTORS_JOB: &str = "bulk-add-entity-contractors";
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrefillEntityContractorDetailsForBulkUpload {
+    #[serde(rename = "Company Name")]
+    pub company_name: String,
+    #[serde(rename = "Country")]
+    pub country: Country,
+    #[serde(rename = "Entity Type")]
+    pub entity_type: String,
+    #[serde(rename = "Registration Number")]
+    pub registration_number: Option<String>,
+    #[serde(rename = "Tax ID")]
+    pub tax_id: Option<String>,
+    #[serde(rename = "Company Address")]
+    pub company_address: String,
+    #[serde(rename = "City")]
+    pub city: String,
+    #[serde(rename = "Postal Code")]
+    pub postal_code: String,
+    #[serde(rename = "Timezone")]
+    pub time_zone: String,
+    #[serde(rename = "PIC First Name")]
+    pub pic_first_name: String,
+    #[serde(rename = "PIC Last Name")]
+    pub pic_last_name: String,
+    #[serde(rename = "PIC Date of Birth")]
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub pic_dob: sqlx::types::time::OffsetDateTime,
+    #[serde(rename = "PIC Dial Code")]
+    pub pic_dial_code: String,
+    #[serde(rename = "PIC Phone Number")]
+    pub pic_phone_number: String,
+    #[serde(rename = "Email Address")]
+    pub email: EmailWrapper,
+    #[serde(rename = "Bank Name")]
+    pub bank_name: String,
+    #[serde(rename = "Bank Account Owner Name")]
+    pub bank_account_name: String,
+    #[serde(rename = "Bank Account Number")]
+    pub bank_account_number: String,
+    #[serde(rename = "Bank Code")]
+    pub bank_code: String,
+    #[serde(rename = "Branch Code")]
+    pub bank_branch_code: String,
+}
+
+const TEMPLATE_HEADER: [&str; 20] = [
+    "Company Name",
+    "Country",
+    "Entity Type",
+    "Registration Number",
+    "Tax ID",
+    "Company Address",
+    "City",
+    "Postal Code",
+    "Timezone",
+    "PIC First Name",
+    "PIC Last Name",
+    "PIC Date of Birth",
+    "PIC Dial Code",
+    "PIC Phone Number",
+    "Email Address",
+    "Bank Name",
+    "Bank Account Owner Name",
+    "Bank Account Number",
+    "Bank Code",
+    "Branch Code",
+];
+
+/// Queues the import of entity contractors. The report of what happened to every row is the
+/// result of the job.
+pub async fn post_one(
+    claims: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<
+        Json<PostOneAddBulkEmployee>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(shared_database): Extension<SharedDatabase>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let job = enqueue_import(
+        &database,
+        &shared_database,
+        BULK_ADD_ENTITY_CONTRACTORS_JOB,
+        claims,
+        body,
+    )
+    .await?;
+
+    Ok(Json(job))
+}
+
+/// Imports entity contractors, one per row.
+pub async fn run_import_job(
+    context: JobContext,
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let BulkImportJob {
+        created_by,
+        request,
+    } = context.payload()?;
+
+    let (header, records) = read_records(&request.file_name, &request.file_data)?;
+    let (rows, cancelled) = import_rows(
+        |done, total| context.progress(done, total),
+        &header,
+        &records,
+        &["Email Address"],
+        |value: PrefillEntityContractorDetailsForBulkUpload| {
+            process_row(value, database.clone(), shared_database.clone(), &request)
+        },
+    )
+    .await;
+
+    let report = NewBulkImportReport {
+        kind: BulkImportKind::EntityContractors,
+        client_ulid: request.client_ulid,
+        file_name: &request.file_name,
+        mode: request.mode,
+        dry_run: request.dry_run,
+        created_by,
+        header: &header,
+        records: &records,
+        rows: &rows,
+    };
+    save_report(&shared_database, &report, cancelled).await
+}
+
+/// Imports one entity contractor.
+///
+/// Works like the import of an individual contractor, with the company and PIC details in
+/// place of the personal ones.
+async fn process_row(
+    value: PrefillEntityContractorDetailsForBulkUpload,
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+    request: &PostOneAddBulkEmployee,
+) -> GlobeliseResult<RowOutcome> {
+    let mode = request.mode;
+
+    let database = database.lock().await;
+    let shared_database = shared_database.lock().await;
+
+    let existing_user_ulid = match database
+        .find_one_user(None, Some(&value.email), None)
+        .await?
+    {
+        Some(user) if user.is_entity && user.is_contractor => Some(user.ulid),
+        Some(_) => {
+            return Ok(RowOutcome::failed(
+                "Email Address",
+                "This email address does not belong to an entity contractor",
+            ))
+        }
+        None => None,
+    };
+
+    match existing_user_ulid {
+        Some(_) => (),
+        None if !mode.creates() => {
+            return Ok(RowOutcome::skipped(
+                None,
+                "No contractor has this email address",
+            ))
+        }
+        None => (),
+    }
+    let update = existing_user_ulid.is_some() && mode.updates();
+
+    let mut changes = Vec::new();
+
+    let existing_details = match existing_user_ulid {
+        Some(user_ulid) => {
+            database
+                .get_onboard_entity_contractor_account_details(user_ulid)
+                .await?
+        }
+        None => None,
+    };
+    let details = match existing_details {
+        None => Some(account_details(&value, None)),
+        Some(existing) if update => {
+            let old_values = account_detail_values(&existing);
+            let details = account_details(&value, Some(existing));
+            let details_changes = changed_values(old_values, account_detail_values(&details));
+            if details_changes.is_empty() {
+                None
+            } else {
+                changes.extend(details_changes);
+                Some(details)
+            }
+        }
+        Some(_) => None,
+    };
+
+    let existing_pic_details = match existing_user_ulid {
+        Some(user_ulid) => {
+            database
+                .select_one_onboard_entity_pic_details(user_ulid, UserRole::Contractor)
+                .await?
+        }
+        None => None,
+    };
+    let pic_details = match existing_pic_details {
+        None => Some(pic_details(&value, None)),
+        Some(existing) if update => {
+            let old_values = pic_detail_values(&existing);
+            let pic_details = pic_details(&value, Some(existing));
+            let pic_changes = changed_values(old_values, pic_detail_values(&pic_details));
+            if pic_changes.is_empty() {
+                None
+            } else {
+                changes.extend(pic_changes);
+                Some(pic_details)
+            }
+        }
+        Some(_) => None,
+    };
+
+    let existing_bank_details = match existing_user_ulid {
+        Some(user_ulid) => {
+            database
+                .select_one_onboard_user_bank_detail(user_ulid, UserType::Entity)
+                .await?
+        }
+        None => None,
+    };
+    let bank_details = match existing_bank_details {
+        None => Some(bank_details(&value)),
+        Some(existing) if update => {
+            let bank_details = bank_details(&value);
+            let bank_changes = changed_values(
+                bank_detail_values(&existing),
+                bank_detail_values(&bank_details),
+            );
+            if bank_changes.is_empty() {
+                None
+            } else {
+                changes.extend(bank_changes);
+                Some(bank_details)
+            }
+        }
+        Some(_) => None,
+    };
+    if let Some(outcome) = bank_details
+        .as_ref()
+        .and_then(|bank_details| invalid_bank_details(bank_details, value.country))
+    {
+        return Ok(outcome);
+    }
+
+    let is_paired = match existing_user_ulid {
+        Some(user_ulid) => !database
+            .select_many_client_contractor_pair_index(
+                None,
+                None,
+                Some(request.client_ulid),
+                Some(user_ulid),
+            )
+            .await?
+            .is_empty(),
+        None => false,
+    };
+    let joins_branch = match (request.branch_ulid, existing_user_ulid) {
+        (Some(branch_ulid), Some(user_ulid)) => {
+            !shared_database
+                .contractor_is_in_branch(user_ulid, branch_ulid)
+                .await?
+        }
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    let status = if existing_user_ulid.is_none() {
+        BulkImportRowStatus::Created
+    } else if !changes.is_empty() {
+        BulkImportRowStatus::Updated
+    } else if details.is_some()
+        || pic_details.is_some()
+        || bank_details.is_some()
+        || !is_paired
+        || joins_branch
+    {
+        BulkImportRowStatus::Linked
+    } else {
+        return Ok(RowOutcome {
+            status: BulkImportRowStatus::Skipped,
+            user_ulid: existing_user_ulid,
+            field: None,
+            reason: update.then(|| "Already up to date".to_string()),
+            changes,
+        });
+    };
+    let mut outcome = RowOutcome {
+        status,
+        user_ulid: existing_user_ulid,
+        field: None,
+        reason: None,
+        changes,
+    };
+
+    if request.dry_run {
+        return Ok(outcome);
+    }
+
+    // A row is imported completely or not at all.
+    let mut transaction = database.0.begin().await?;
+
+    let user_ulid = match existing_user_ulid {
+        Some(user_ulid) => user_ulid,
+        None => insert_contractor_user(&mut transaction, &value.email, UserType::Entity).await?,
+    };
+
+    if let Some(details) = details {
+        onboard::entity::insert_one_onboard_entity_contractor_account_details(
+            &mut transaction,
+            user_ulid,
+            &details,
+        )
+        .await?;
+    }
+
+    if let Some(pic_details) = pic_details {
+        onboard::pic::insert_one_onboard_entity_pic_details(
+            &mut transaction,
+            &user_ulid,
+            &UserRole::Contractor,
+            &pic_details.first_name,
+            &pic_details.last_name,
+            &pic_details.dob,
+            &pic_details.dial_code,
+            &pic_details.phone_number,
+            pic_details.profile_picture.as_ref(),
+        )
+        .await?;
+    }
+
+    if let Some(bank_details) = bank_details {
+        onboard::bank::insert_one_onboard_user_bank_details(
+            &mut transaction,
+            user_ulid,
+            UserType::Entity,
+            &bank_details,
+        )
+        .await?;
+    }
+
+    user::create_client_contractor_pair(&mut transaction, request.client_ulid, user_ulid).await?;
+
+    if let Some(branch_ulid) = request.branch_ulid {
+        pay_items::insert_contractor_into_branch(
+            &mut transaction,
+            user_ulid,
+            UserType::Entity,
+            branch_ulid,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    outcome.user_ulid = Some(user_ulid);
+
+    if let Some(true) = request.debug {
+        return Ok(outcome);
+    }
+
+    if outcome.status == BulkImportRowStatus::Created {
+        send_invitation_after_import(
+            &database,
+            &value.email,
+            UserType::Entity,
+            value.country,
+            &mut outcome,
+        )
+        .await;
+    }
+
+    Ok(outcome)
+}
+
+/// The company details of a row, on top of the ones the contractor already has.
+///
+/// Blank optional cells keep what the contractor already has, and so do the logo and the
+/// company profile, which are not in the template.
+fn account_details(
+    value: &PrefillEntityContractorDetailsForBulkUpload,
+    existing: Option<EntityContractorAccountDetails>,
+) -> EntityContractorAccountDetails {
+    let (registration_number, tax_id, logo, company_profile) = match existing {
+        Some(existing) => (
+            existing.registration_number,
+            existing.tax_id,
+            existing.logo,
+            existing.company_profile,
+        ),
+        None => (None, None, None, None),
+    };
+
+    EntityContractorAccountDetails {
+        company_name: value.company_name.clone(),
+        country: value.country,
+        entity_type: value.entity_type.clone(),
+        registration_number: value.registration_number.clone().or(registration_number),
+        tax_id: value.tax_id.clone().or(tax_id),
+        company_address: value.company_address.clone(),
+        city: value.city.clone(),
+        postal_code: value.postal_code.clone(),
+        time_zone: value.time_zone.clone(),
+        logo,
+        company_profile,
+    }
+}
+
+/// The PIC details of a row, keeping the profile picture the contractor already has.
+fn pic_details(
+    value: &PrefillEntityContractorDetailsForBulkUpload,
+    existing: Option<EntityPicDetails>,
+) -> EntityPicDetails {
+    EntityPicDetails {
+        first_name: value.pic_first_name.clone(),
+        last_name: value.pic_last_name.clone(),
+        dob: value.pic_dob,
+        dial_code: value.pic_dial_code.clone(),
+        phone_number: value.pic_phone_number.clone(),
+        profile_picture: existing.and_then(|existing| existing.profile_picture),
+    }
+}
+
+fn bank_details(value: &PrefillEntityContractorDetailsForBulkUpload) -> ContractorUserDetails {
+    ContractorUserDetails {
+        bank_name: value.bank_name.clone(),
+        bank_account_name: value.bank_account_name.clone(),
+        bank_account_number: value.bank_account_number.clone(),
+        bank_code: value.bank_code.clone(),
+        branch_code: value.bank_branch_code.clone(),
+    }
+}
+
+fn account_detail_values(
+    details: &EntityContractorAccountDetails,
+) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("Company Name", Some(details.company_name.clone())),
+        ("Country", Some(details.country.as_str().to_string())),
+        ("Entity Type", Some(details.entity_type.clone())),
+        ("Registration Number", details.registration_number.clone()),
+        ("Tax ID", details.tax_id.clone()),
+        ("Company Address", Some(details.company_address.clone())),
+        ("City", Some(details.city.clone())),
+        ("Postal Code", Some(details.postal_code.clone())),
+        ("Timezone", Some(details.time_zone.clone())),
+    ]
+}
+
+fn pic_detail_values(details: &EntityPicDetails) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("PIC First Name", Some(details.first_name.clone())),
+        ("PIC Last Name", Some(details.last_name.clone())),
+        ("PIC Date of Birth", Some(details.dob.date().to_string())),
+        ("PIC Dial Code", Some(details.dial_code.clone())),
+        ("PIC Phone Number", Some(details.phone_number.clone())),
+    ]
+}
+
+pub async fn download(_: Token<AdminAccessToken>) -> GlobeliseResult<impl IntoResponse> {
+    template_workbook(&TEMPLATE_HEADER)
+}
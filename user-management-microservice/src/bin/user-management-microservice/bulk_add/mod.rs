@@ -12,25 +12,39 @@ use common_utils::{
         FORM_DATA_LENGTH_LIMIT,
     },
     database::{
-        onboard::{bank::ContractorUserDetails, individual::IndividualContractorAccountDetails},
-        CommonDatabase, Database,
+        job::Job,
+        onboard::{
+            self, bank::ContractorUserDetails, individual::IndividualContractorAccountDetails,
+        },
+        user, CommonDatabase, Database,
     },
     error::{GlobeliseError, GlobeliseResult},
+    jobs::{self, JobContext, JobOutput},
+    mail::{self, EmailTemplate},
     token::Token,
 };
 use csv::{ReaderBuilder, StringRecord};
 use eor_admin_microservice_sdk::token::AdminAccessToken;
-use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
 use once_cell::sync::Lazy;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as, TryFromInto};
-use sqlx::FromRow;
-use std::{io::Cursor, str::FromStr, sync::Arc};
-use tokio::sync::Mutex;
+use sqlx::{FromRow, Postgres, Transaction};
+use std::{collections::HashMap, future::Future, io::Cursor, str::FromStr};
 use uuid::Uuid;
 
-use crate::env::{FRONTEND_URL, GLOBELISE_SENDER_EMAIL, GLOBELISE_SMTP_URL, SMTP_CREDENTIAL};
+use crate::{
+    database::SharedDatabase, env::FRONTEND_URL, eor_admin::bank_transfer::citi_bank::cell_text,
+};
+
+use self::report::{
+    BulkImportFieldChange, BulkImportKind, BulkImportMode, BulkImportRow, BulkImportRowStatus,
+    NewBulkImportReport,
+};
+
+pub mod entity;
+pub mod pay_items;
+pub mod report;
 
 #[serde_as]
 #[derive(Debug, FromRow, Serialize, Deserialize)]
@@ -108,7 +122,7 @@ pub struct PrefillIndividualContractorDetailsForBulkUpload {
     #[serde(rename = "Currency")]
     pub currency: Currency,
     #[serde(rename = "Basic Salary")]
-    pub basic_salary: Option<f64>,
+    pub basic_salary: Option<sqlx::types::Decimal>,
     #[serde(rename = "Additional Item 1")]
     pub additional_item_1: String,
     #[serde(rename = "Additional Item 2")]
@@ -131,7 +145,17 @@ pub struct PostOneAddBulkEmployee {
     #[serde_as(as = "Base64")]
     pub file_data: Vec<u8>,
     pub client_ulid: Uuid,
+    /// The branch of the client that the pay items of the file belong to. Contractors imported
+    /// with a branch are added to it.
+    #[serde(default)]
+    pub branch_ulid: Option<Uuid>,
     pub debug: Option<bool>,
+    #[serde(default)]
+    pub mode: BulkImportMode,
+    /// Checks every row and reports what the import would do, without changing anything or
+    /// sending any email.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[serde_as]
@@ -141,18 +165,249 @@ pub struct GetPrefillIndividualContractorDetailsForBulkUpload {
     pub email: EmailWrapper,
 }
 
+pub const BULK_ADD_EMPLOYEES_JOB: &str = "bulk-add-employees";
+
+/// What every kind of bulk import job is queued with.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BulkImportJob {
+    created_by: Uuid,
+    #[serde(flatten)]
+    request: PostOneAddBulkEmployee,
+}
+
+/// Queues the import of individual contractors. The report of what happened to every row is
+/// the result of the job.
 pub async fn post_one(
-    // Only for validation
-    _: Token<AdminAccessToken>,
+    claims: Token<AdminAccessToken>,
     ContentLengthLimit(Json(body)): ContentLengthLimit<
         Json<PostOneAddBulkEmployee>,
         FORM_DATA_LENGTH_LIMIT,
     >,
+    Extension(shared_database): Extension<SharedDatabase>,
     Extension(database): Extension<CommonDatabase>,
-) -> GlobeliseResult<()> {
-    let mut rows_to_enter = vec![];
+) -> GlobeliseResult<Json<Job>> {
+    let job = enqueue_import(
+        &database,
+        &shared_database,
+        BULK_ADD_EMPLOYEES_JOB,
+        claims,
+        body,
+    )
+    .await?;
+
+    Ok(Json(job))
+}
+
+async fn enqueue_import(
+    database: &CommonDatabase,
+    shared_database: &SharedDatabase,
+    kind: &str,
+    claims: Token<AdminAccessToken>,
+    request: PostOneAddBulkEmployee,
+) -> GlobeliseResult<Job> {
+    // A file that cannot be read is rejected right away instead of failing the job.
+    read_records(&request.file_name, &request.file_data)?;
+
+    if let Some(branch_ulid) = request.branch_ulid {
+        if !shared_database
+            .lock()
+            .await
+            .client_owns_branch(request.client_ulid, branch_ulid)
+            .await?
+        {
+            return Err(GlobeliseError::bad_request(
+                "The branch does not belong to the client",
+            ));
+        }
+    }
+
+    jobs::enqueue(
+        database,
+        kind,
+        None,
+        &BulkImportJob {
+            created_by: claims.payload.ulid,
+            request,
+        },
+        1,
+        Some(claims.payload.ulid),
+    )
+    .await
+}
+
+/// Imports individual contractors, one per row.
+pub async fn run_import_job(
+    context: JobContext,
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let BulkImportJob {
+        created_by,
+        request,
+    } = context.payload()?;
+
+    let (header, records) = read_records(&request.file_name, &request.file_data)?;
+    let (rows, cancelled) = import_rows(
+        |done, total| context.progress(done, total),
+        &header,
+        &records,
+        &["Email Address"],
+        |value: PrefillIndividualContractorDetailsForBulkUpload| {
+            process_row(value, database.clone(), shared_database.clone(), &request)
+        },
+    )
+    .await;
+
+    let report = NewBulkImportReport {
+        kind: BulkImportKind::IndividualContractors,
+        client_ulid: request.client_ulid,
+        file_name: &request.file_name,
+        mode: request.mode,
+        dry_run: request.dry_run,
+        created_by,
+        header: &header,
+        records: &records,
+        rows: &rows,
+    };
+    save_report(&shared_database, &report, cancelled).await
+}
+
+/// Reads every row of a file as a `T` and passes it to `process`, and reports what happened
+/// to every row. A row that cannot be imported does not stop the others.
+///
+/// Rows with the same values in the `key_columns` as an earlier row are skipped. Before each
+/// row, `progress` is told how many rows are done, like [`JobContext::progress`]; the import
+/// stops when it fails, with the error to fail the job with.
+async fn import_rows<T, F, Fut, P, PFut>(
+    mut progress: P,
+    header: &StringRecord,
+    records: &[StringRecord],
+    key_columns: &[&str],
+    mut process: F,
+) -> (Vec<BulkImportRow>, Option<GlobeliseError>)
+where
+    T: DeserializeOwned + Send,
+    F: FnMut(T) -> Fut + Send,
+    Fut: Future<Output = GlobeliseResult<RowOutcome>> + Send,
+    P: FnMut(usize, usize) -> PFut + Send,
+    PFut: Future<Output = GlobeliseResult<()>> + Send,
+{
+    let email_column = header.iter().position(|column| column == "Email Address");
+    let key_columns = key_columns
+        .iter()
+        .map(|key_column| header.iter().position(|column| column == *key_column))
+        .collect::<Vec<_>>();
+    let key_description = key_columns_description(header, &key_columns);
+
+    let mut rows = Vec::with_capacity(records.len());
+    let mut first_row_of_key = HashMap::new();
+    for (index, record) in records.iter().enumerate() {
+        if let Err(e) = progress(index, records.len()).await {
+            return (rows, Some(e));
+        }
+        // The header is row 1.
+        let row_number = index + 2;
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let email = email_column
+            .and_then(|column| record.get(column))
+            .map(|email| email.to_lowercase())
+            .filter(|email| !email.is_empty());
+
+        let value = match record.deserialize::<T>(Some(header)) {
+            Ok(value) => value,
+            Err(e) => {
+                let (field, reason) = deserialize_error(&e, header);
+                rows.push(BulkImportRow::failed(row_number, email, field, reason));
+                continue;
+            }
+        };
+
+        let key = key_columns
+            .iter()
+            .map(|column| {
+                column
+                    .and_then(|column| record.get(column))
+                    .map(str::to_lowercase)
+                    .filter(|value| !value.is_empty())
+            })
+            .collect::<Option<Vec<_>>>();
+        if let Some(key) = key {
+            if let Some(first_row) = first_row_of_key.get(&key) {
+                rows.push(BulkImportRow {
+                    row_number: row_number as i32,
+                    email,
+                    status: BulkImportRowStatus::Skipped,
+                    field: header
+                        .get(key_columns[0].unwrap_or_default())
+                        .map(str::to_string),
+                    reason: Some(format!("Same {} as row {}", key_description, first_row)),
+                    user_ulid: None,
+                    changes: Vec::new(),
+                });
+                continue;
+            }
+            first_row_of_key.insert(key, row_number);
+        }
+
+        let row = match process(value).await {
+            Ok(outcome) => BulkImportRow {
+                row_number: row_number as i32,
+                email,
+                status: outcome.status,
+                field: outcome.field,
+                reason: outcome.reason,
+                user_ulid: outcome.user_ulid,
+                changes: outcome.changes,
+            },
+            Err(e) => BulkImportRow::failed(row_number, email, None, e.to_string()),
+        };
+        rows.push(row);
+    }
+
+    (rows, None)
+}
+
+/// The key columns as they are named in the reason a duplicate row is skipped, like
+/// `email address and pay item`.
+fn key_columns_description(header: &StringRecord, key_columns: &[Option<usize>]) -> String {
+    key_columns
+        .iter()
+        .filter_map(|column| column.and_then(|column| header.get(column)))
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
+
+/// Stores the report of an import, which is the result of its job.
+async fn save_report(
+    shared_database: &SharedDatabase,
+    report: &NewBulkImportReport<'_>,
+    cancelled: Option<GlobeliseError>,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let shared_database = shared_database.lock().await;
+    let ulid = shared_database.insert_bulk_import_report(report).await?;
+    let result = shared_database
+        .select_one_bulk_import_report(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::internal("Cannot find the bulk import report"))?;
+
+    // The rows imported before the job was cancelled are still reported.
+    if let Some(e) = cancelled {
+        return Err(e);
+    }
 
-    let file_name = std::path::PathBuf::from_str(&body.file_name)?;
+    Ok(Some(JobOutput::json(&result)?))
+}
+
+/// Reads the header and the rows of a CSV file or of the first sheet of a workbook, as text.
+fn read_records(
+    file_name: &str,
+    file_data: &[u8],
+) -> GlobeliseResult<(StringRecord, Vec<StringRecord>)> {
+    let file_name = std::path::PathBuf::from_str(file_name)?;
 
     enum FileType {
         Csv,
@@ -167,35 +422,18 @@ pub async fn post_one(
         )),
     }?;
 
-    match file_extension {
-        FileType::Csv => {
-            let mut records = ReaderBuilder::new()
-                .has_headers(false)
-                .from_reader(body.file_data.as_slice())
-                .records()
-                .collect::<Result<Vec<StringRecord>, _>>()?;
-
-            if !records.is_empty() {
-                // Get/remove the first row because its the header.
-                let header = records.swap_remove(0);
-                for record in records {
-                    let value = record
-                        .deserialize::<PrefillIndividualContractorDetailsForBulkUpload>(Some(
-                            &header,
-                        ))
-                        .map_err(|_| {
-                            GlobeliseError::bad_request(
-                                "Please provide a CSV file that follows the template",
-                            )
-                        })?;
-                    rows_to_enter.push(value);
-                }
-            }
-        }
+    let mut records = match file_extension {
+        FileType::Csv => ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(file_data)
+            .records()
+            .collect::<Result<Vec<StringRecord>, _>>()
+            .map_err(|e| GlobeliseError::bad_request(format!("Invalid CSV file: {}", e)))?,
         FileType::Excel => {
             let excel_workbook =
-                calamine::open_workbook_auto_from_rs(Cursor::new(body.file_data.as_slice()))?
-                    .worksheets();
+                calamine::open_workbook_auto_from_rs(Cursor::new(file_data))?.worksheets();
 
             let first_worksheet = excel_workbook.first().ok_or_else(|| {
                 GlobeliseError::bad_request(
@@ -203,178 +441,564 @@ pub async fn post_one(
                 )
             })?;
 
-            let row_deserializer = first_worksheet.1.deserialize()?;
-
-            let rows = row_deserializer
-                .into_iter()
-                .collect::<Result<Vec<PrefillIndividualContractorDetailsForBulkUpload>, _>>()?;
-
-            rows_to_enter.extend(rows);
+            first_worksheet
+                .1
+                .rows()
+                .map(|row| row.iter().map(cell_text).collect::<StringRecord>())
+                .collect()
         }
     };
 
-    let mut errors = vec![];
+    if records.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "Please provide a file that follows the template",
+        ));
+    }
+    // Get/remove the first row because its the header.
+    let header = records.remove(0);
+
+    Ok((header, records))
+}
+
+/// The column and the reason a row cannot be read.
+fn deserialize_error(e: &csv::Error, header: &StringRecord) -> (Option<String>, String) {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => (
+            err.field()
+                .and_then(|field| header.get(field as usize))
+                .map(str::to_string),
+            err.kind().to_string(),
+        ),
+        _ => (None, e.to_string()),
+    }
+}
 
-    for value in rows_to_enter {
-        if let Err(err) = process_row(value, database.clone(), body.debug, body.client_ulid).await {
-            errors.push(err);
+/// What an import did, or would do in a dry run, with one row.
+struct RowOutcome {
+    status: BulkImportRowStatus,
+    /// None if a dry run would create the user, or if there is no user to update.
+    user_ulid: Option<Uuid>,
+    /// The column of the value that is wrong, when the row fails because of one value.
+    field: Option<String>,
+    reason: Option<String>,
+    changes: Vec<BulkImportFieldChange>,
+}
+
+impl RowOutcome {
+    fn failed(field: &str, reason: &str) -> Self {
+        RowOutcome {
+            status: BulkImportRowStatus::Failed,
+            user_ulid: None,
+            field: Some(field.to_string()),
+            reason: Some(reason.to_string()),
+            changes: Vec::new(),
         }
     }
 
-    Ok(())
+    fn skipped(user_ulid: Option<Uuid>, reason: &str) -> Self {
+        RowOutcome {
+            status: BulkImportRowStatus::Skipped,
+            user_ulid,
+            field: None,
+            reason: Some(reason.to_string()),
+            changes: Vec::new(),
+        }
+    }
 }
 
+/// The columns of the individual contractor template that name pay items of the branch.
+const PAY_ITEM_COLUMNS: [&str; 6] = [
+    "Additional Item 1",
+    "Additional Item 2",
+    "Deduction 1",
+    "Deduction 2",
+    "Other Pay Item 1",
+    "Other Pay Item 2",
+];
+
+/// Imports one contractor.
+///
+/// The details of a contractor who already exists are only changed in the modes that update,
+/// with the values of the row, and reported field by field. When the import has a branch, the
+/// contractor is added to it with the pay items named in the row.
+///
+/// Everything a row writes is committed together, and a new contractor is only invited once
+/// it is.
 async fn process_row(
     value: PrefillIndividualContractorDetailsForBulkUpload,
-    database: Arc<Mutex<Database>>,
-    debug: Option<bool>,
-    client_ulid: Uuid,
-) -> GlobeliseResult<()> {
-    let receiver_email = value
-        .email
-        // TODO: Get the name of the person associated to this email address
-        .0
-        .to_display("")
-        .parse::<Mailbox>()?;
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+    request: &PostOneAddBulkEmployee,
+) -> GlobeliseResult<RowOutcome> {
+    let mode = request.mode;
 
     let database = database.lock().await;
+    let shared_database = shared_database.lock().await;
 
-    let mut user_created = false;
+    // Every pay item of the row must be in the branch before anything is imported.
+    let mut pay_item_ulids = Vec::new();
+    if let Some(branch_ulid) = request.branch_ulid {
+        let pay_items = [
+            Some(&value.additional_item_1),
+            value.additional_item_2.as_ref(),
+            value.deduction_1.as_ref(),
+            value.deduction_2.as_ref(),
+            value.other_pay_item_1.as_ref(),
+            value.other_pay_item_2.as_ref(),
+        ];
+        for (column, name) in PAY_ITEM_COLUMNS.into_iter().zip(pay_items) {
+            let name = match name.map(|name| name.trim()) {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            match shared_database
+                .select_one_branch_pay_item_ulid_by_name(branch_ulid, name)
+                .await?
+            {
+                Some(pay_item_ulid) => pay_item_ulids.push(pay_item_ulid),
+                None => {
+                    return Ok(RowOutcome::failed(
+                        column,
+                        "The branch has no pay item with this name",
+                    ))
+                }
+            }
+        }
+    }
 
-    let user_ulid = if let Some(user) = database
+    let existing_user_ulid = match database
         .find_one_user(None, Some(&value.email), None)
         .await?
     {
-        user.ulid
-    } else {
-        user_created = true;
-        //TODO add default password and create also a benefits user
-        let default_password_string =
-            std::env::var("DEFAULT_USER_PASSWORD").expect("default password not set");
-        let salt: [u8; 16] = rand::thread_rng().gen();
-        let default_password_hash =
-            hash_encoded(default_password_string.as_bytes(), &salt, &HASH_CONFIG)
-                .map_err(GlobeliseError::internal)?;
-        database
-            .insert_one_user(
-                &value.email,
-                Some(&default_password_hash),
-                false,
-                false,
-                false,
-                true,
-                false,
-                true,
-            )
-            .await?
+        Some(user) if user.is_individual && user.is_contractor => Some(user.ulid),
+        Some(_) => {
+            return Ok(RowOutcome::failed(
+                "Email Address",
+                "This email address does not belong to an individual contractor",
+            ))
+        }
+        None => None,
     };
 
-    if database
-        .select_one_onboard_individual_contractor_account_details(user_ulid)
-        .await?
-        .is_none()
+    match existing_user_ulid {
+        Some(_) => (),
+        None if !mode.creates() => {
+            return Ok(RowOutcome::skipped(
+                None,
+                "No contractor has this email address",
+            ))
+        }
+        None => (),
+    }
+    let update = existing_user_ulid.is_some() && mode.updates();
+
+    let mut changes = Vec::new();
+
+    let existing_details = match existing_user_ulid {
+        Some(user_ulid) => {
+            database
+                .select_one_onboard_individual_contractor_account_details(user_ulid)
+                .await?
+        }
+        None => None,
+    };
+    let details = match existing_details {
+        None => Some(account_details(&value, None)),
+        Some(existing) if update => {
+            let old_values = account_detail_values(&existing);
+            let details = account_details(&value, Some(existing));
+            let details_changes = changed_values(old_values, account_detail_values(&details));
+            if details_changes.is_empty() {
+                None
+            } else {
+                changes.extend(details_changes);
+                Some(details)
+            }
+        }
+        Some(_) => None,
+    };
+
+    let existing_bank_details = match existing_user_ulid {
+        Some(user_ulid) => {
+            database
+                .select_one_onboard_user_bank_detail(user_ulid, UserType::Individual)
+                .await?
+        }
+        None => None,
+    };
+    let bank_details = match existing_bank_details {
+        None => Some(bank_details(&value)),
+        Some(existing) if update => {
+            let bank_details = bank_details(&value);
+            let bank_changes = changed_values(
+                bank_detail_values(&existing),
+                bank_detail_values(&bank_details),
+            );
+            if bank_changes.is_empty() {
+                None
+            } else {
+                changes.extend(bank_changes);
+                Some(bank_details)
+            }
+        }
+        Some(_) => None,
+    };
+    if let Some(outcome) = bank_details
+        .as_ref()
+        .and_then(|bank_details| invalid_bank_details(bank_details, value.country))
     {
-        database
-            .insert_one_onboard_individual_contractor_account_details(
-                user_ulid,
-                &IndividualContractorAccountDetails {
-                    first_name: value.first_name,
-                    last_name: value.last_name,
-                    dob: value.dob,
-                    dial_code: value.dial_code,
-                    phone_number: value.phone_number,
-                    country: value.country,
-                    city: value.city,
-                    address: value.address,
-                    postal_code: value.postal_code,
-                    tax_id: value.tax_id,
-                    time_zone: value.time_zone,
-                    profile_picture: None,
-                    cv: None,
-                    gender: value.gender,
-                    marital_status: value.marital_status,
-                    nationality: Some(value.nationality),
-                    email_address: Some(value.email),
-                    national_id: value.national_id,
-                    passport_number: value.passport_number,
-                    passport_expiry_date: value.passport_expiry_date,
-                    work_permit: value.work_permit,
-                    added_related_pay_item_id: None,
-                    total_dependants: value.total_dependants,
-                },
+        return Ok(outcome);
+    }
+
+    let is_paired = match existing_user_ulid {
+        Some(user_ulid) => !database
+            .select_many_client_contractor_pair_index(
+                None,
+                None,
+                Some(request.client_ulid),
+                Some(user_ulid),
             )
-            .await?;
+            .await?
+            .is_empty(),
+        None => false,
+    };
+
+    // The pay items are added to the ones the contractor has, which keep their amounts.
+    let mut new_pay_item_ulids = Vec::new();
+    for pay_item_ulid in pay_item_ulids {
+        let has_pay_item = match existing_user_ulid {
+            Some(user_ulid) => shared_database
+                .select_one_contractor_pay_item_amount(user_ulid, pay_item_ulid)
+                .await?
+                .is_some(),
+            None => false,
+        };
+        if !has_pay_item {
+            new_pay_item_ulids.push(pay_item_ulid);
+        }
     }
+    let joins_branch = match (request.branch_ulid, existing_user_ulid) {
+        (Some(branch_ulid), Some(user_ulid)) => {
+            !shared_database
+                .contractor_is_in_branch(user_ulid, branch_ulid)
+                .await?
+        }
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
 
-    if database
-        .select_one_onboard_user_bank_detail(user_ulid, UserType::Individual)
-        .await?
-        .is_none()
+    let status = if existing_user_ulid.is_none() {
+        BulkImportRowStatus::Created
+    } else if !changes.is_empty() {
+        BulkImportRowStatus::Updated
+    } else if details.is_some()
+        || bank_details.is_some()
+        || !is_paired
+        || joins_branch
+        || !new_pay_item_ulids.is_empty()
     {
-        database
-            .insert_one_onboard_user_bank_details(
-                user_ulid,
-                UserType::Individual,
-                &ContractorUserDetails {
-                    bank_name: value.bank_name,
-                    bank_account_name: value.bank_account_name,
-                    bank_account_number: value.bank_account_number,
-                    bank_code: value.bank_code,
-                    branch_code: value.bank_branch_code,
-                },
-            )
-            .await?;
+        BulkImportRowStatus::Linked
+    } else {
+        return Ok(RowOutcome {
+            status: BulkImportRowStatus::Skipped,
+            user_ulid: existing_user_ulid,
+            field: None,
+            reason: update.then(|| "Already up to date".to_string()),
+            changes,
+        });
+    };
+    let mut outcome = RowOutcome {
+        status,
+        user_ulid: existing_user_ulid,
+        field: None,
+        reason: None,
+        changes,
+    };
+
+    if request.dry_run {
+        return Ok(outcome);
+    }
+
+    // A row is imported completely or not at all.
+    let mut transaction = database.0.begin().await?;
+
+    let user_ulid = match existing_user_ulid {
+        Some(user_ulid) => user_ulid,
+        None => {
+            insert_contractor_user(&mut transaction, &value.email, UserType::Individual).await?
+        }
+    };
+
+    if let Some(details) = details {
+        onboard::individual::insert_one_onboard_individual_contractor_account_details(
+            &mut transaction,
+            user_ulid,
+            &details,
+        )
+        .await?;
+    }
+
+    if let Some(bank_details) = bank_details {
+        onboard::bank::insert_one_onboard_user_bank_details(
+            &mut transaction,
+            user_ulid,
+            UserType::Individual,
+            &bank_details,
+        )
+        .await?;
     }
     //link this contractor to this client
-    database
-        .create_client_contractor_pair(client_ulid, user_ulid)
+    user::create_client_contractor_pair(&mut transaction, request.client_ulid, user_ulid).await?;
+
+    if let Some(branch_ulid) = request.branch_ulid {
+        pay_items::insert_contractor_into_branch(
+            &mut transaction,
+            user_ulid,
+            UserType::Individual,
+            branch_ulid,
+        )
         .await?;
+        for pay_item_ulid in new_pay_item_ulids {
+            pay_items::upsert_contractor_pay_item(&mut transaction, user_ulid, pay_item_ulid, None)
+                .await?;
+        }
+    }
+
+    transaction.commit().await?;
+    outcome.user_ulid = Some(user_ulid);
+
+    if let Some(true) = request.debug {
+        return Ok(outcome);
+    }
 
-    if let Some(true) = debug {
-        return Ok(());
-    }
-
-    // Send email to the contractor
-
-    if user_created {
-        let email = Message::builder()
-            .from(GLOBELISE_SENDER_EMAIL.clone())
-            // TODO: Remove this because this is supposed to be a no-reply email?
-            .reply_to(GLOBELISE_SENDER_EMAIL.clone())
-            .to(receiver_email)
-            .subject("Invitation to Globelise")
-            .header(lettre::message::header::ContentType::TEXT_HTML)
-            // TODO: Once designer have a template for this. Use a templating library to populate data.
-            .body(format!(
-                r##"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Globelise Invitation</title>
-        </head>
-        <body>
-            <p>
-           Click the <a href="{}/signup?as=contractor&type=individual">link</a> to sign up as a Globelise individual contractor.
-            </p>
-            <p>If you did not expect to receive this email. Please ignore!</p>
-        </body>
-        </html>
-        "##,
-                (*FRONTEND_URL),
-            ))?;
-
-        // Open a remote connection to gmail
-        let mailer = SmtpTransport::relay(&GLOBELISE_SMTP_URL)?
-            .credentials(SMTP_CREDENTIAL.clone())
-            .build();
-
-        // Send the email
-        mailer.send(&email)?;
+    if outcome.status == BulkImportRowStatus::Created {
+        send_invitation_after_import(
+            &database,
+            &value.email,
+            UserType::Individual,
+            value.country,
+            &mut outcome,
+        )
+        .await;
     }
 
+    Ok(outcome)
+}
+
+/// Creates the user of a contractor with the default password.
+async fn insert_contractor_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &EmailWrapper,
+    user_type: UserType,
+) -> GlobeliseResult<Uuid> {
+    //TODO add default password and create also a benefits user
+    let default_password_string =
+        std::env::var("DEFAULT_USER_PASSWORD").expect("default password not set");
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let default_password_hash =
+        hash_encoded(default_password_string.as_bytes(), &salt, &HASH_CONFIG)
+            .map_err(GlobeliseError::internal)?;
+
+    user::insert_one_user(
+        &mut *transaction,
+        email,
+        Some(&default_password_hash),
+        false,
+        false,
+        user_type == UserType::Entity,
+        user_type == UserType::Individual,
+        false,
+        true,
+    )
+    .await
+}
+
+/// Invites a contractor created by an import to sign up, once the import is committed.
+///
+/// The contractor is imported by then, so an invitation that cannot be queued is only
+/// reported on the row.
+async fn send_invitation_after_import(
+    database: &Database,
+    email: &EmailWrapper,
+    user_type: UserType,
+    country: Country,
+    outcome: &mut RowOutcome,
+) {
+    if let Err(e) = send_invitation(database, email, user_type, country).await {
+        outcome.reason = Some(format!("The invitation could not be sent: {}", e));
+    }
+}
+
+/// Invites a contractor created by an import to sign up, in the language of their country.
+async fn send_invitation(
+    database: &Database,
+    email: &EmailWrapper,
+    user_type: UserType,
+    country: Country,
+) -> GlobeliseResult<()> {
+    let link = format!(
+        "{}/signup?as=contractor&type={}",
+        (*FRONTEND_URL),
+        user_type.as_str()
+    );
+    let rendered = EmailTemplate::ContractorInvitation.render(
+        mail::country_locale(country),
+        &[("link", &link), ("contractor_type", user_type.as_str())],
+    )?;
+    database.insert_one_outbox_email(email, &rendered).await?;
+
     Ok(())
 }
 
+/// The details of a row, on top of the ones the contractor already has.
+///
+/// Blank optional cells keep what the contractor already has, so that a sheet with only some
+/// columns filled in does not erase the others.
+fn account_details(
+    value: &PrefillIndividualContractorDetailsForBulkUpload,
+    existing: Option<IndividualContractorAccountDetails>,
+) -> IndividualContractorAccountDetails {
+    let (profile_picture, cv, added_related_pay_item_id, existing) = match existing {
+        Some(existing) => (
+            existing.profile_picture,
+            existing.cv,
+            existing.added_related_pay_item_id,
+            Some((
+                existing.national_id,
+                existing.passport_number,
+                existing.passport_expiry_date,
+                existing.work_permit,
+                existing.tax_id,
+                existing.total_dependants,
+            )),
+        ),
+        None => (None, None, None, None),
+    };
+    let (national_id, passport_number, passport_expiry_date, work_permit, tax_id, total_dependants) =
+        existing.unwrap_or_default();
+
+    IndividualContractorAccountDetails {
+        first_name: value.first_name.clone(),
+        last_name: value.last_name.clone(),
+        dob: value.dob,
+        dial_code: value.dial_code.clone(),
+        phone_number: value.phone_number.clone(),
+        country: value.country,
+        city: value.city.clone(),
+        address: value.address.clone(),
+        postal_code: value.postal_code.clone(),
+        tax_id: value.tax_id.clone().or(tax_id),
+        time_zone: value.time_zone.clone(),
+        profile_picture,
+        cv,
+        gender: value.gender.clone(),
+        marital_status: value.marital_status.clone(),
+        nationality: Some(value.nationality.clone()),
+        email_address: Some(EmailWrapper(value.email.0.clone())),
+        national_id: value.national_id.clone().or(national_id),
+        passport_number: value.passport_number.clone().or(passport_number),
+        passport_expiry_date: value.passport_expiry_date.clone().or(passport_expiry_date),
+        work_permit: value.work_permit.clone().or(work_permit),
+        added_related_pay_item_id,
+        total_dependants: value.total_dependants.or(total_dependants),
+    }
+}
+
+fn bank_details(value: &PrefillIndividualContractorDetailsForBulkUpload) -> ContractorUserDetails {
+    ContractorUserDetails {
+        bank_name: value.bank_name.clone(),
+        bank_account_name: value.bank_account_name.clone(),
+        bank_account_number: value.bank_account_number.clone(),
+        bank_code: value.bank_code.clone(),
+        branch_code: value.bank_branch_code.clone(),
+    }
+}
+
+/// Fails a row whose bank details are not valid for the country of the contractor.
+///
+/// The row is reported against the column of the first problem, with every problem as the
+/// reason.
+fn invalid_bank_details(details: &ContractorUserDetails, country: Country) -> Option<RowOutcome> {
+    let errors = details.bank_details(Some(country)).errors();
+    let column = |field| match field {
+        "bank-code" => "Bank Code",
+        "branch-code" => "Branch Code",
+        _ => "Bank Account Number",
+    };
+
+    let first = errors.first()?;
+    let reason = errors
+        .iter()
+        .map(|e| format!("{} {}", column(e.field), e.message))
+        .collect::<Vec<_>>()
+        .join("; ");
+    Some(RowOutcome::failed(column(first.field), &reason))
+}
+
+/// The details an import can change, by the column of the template they come from.
+fn account_detail_values(
+    details: &IndividualContractorAccountDetails,
+) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("First Name", Some(details.first_name.clone())),
+        ("Last Name", Some(details.last_name.clone())),
+        ("Gender", Some(details.gender.clone())),
+        ("Marital Status", Some(details.marital_status.clone())),
+        ("Nationality", details.nationality.clone()),
+        ("Date of Birth", Some(details.dob.date().to_string())),
+        ("Dial Code", Some(details.dial_code.clone())),
+        ("Phone Number", Some(details.phone_number.clone())),
+        ("Address", Some(details.address.clone())),
+        ("Country", Some(details.country.as_str().to_string())),
+        ("City", Some(details.city.clone())),
+        ("Postal Code", Some(details.postal_code.clone())),
+        ("National ID", details.national_id.clone()),
+        ("Passport Number", details.passport_number.clone()),
+        ("Passport Expiry Date", details.passport_expiry_date.clone()),
+        ("Work Permit", details.work_permit.clone()),
+        ("Tax ID", details.tax_id.clone()),
+        (
+            "Total Dependants",
+            details.total_dependants.map(|total| total.to_string()),
+        ),
+        ("Timezone", Some(details.time_zone.clone())),
+    ]
+}
+
+fn bank_detail_values(details: &ContractorUserDetails) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("Bank Name", Some(details.bank_name.clone())),
+        (
+            "Bank Account Owner Name",
+            Some(details.bank_account_name.clone()),
+        ),
+        (
+            "Bank Account Number",
+            Some(details.bank_account_number.clone()),
+        ),
+        ("Bank Code", Some(details.bank_code.clone())),
+        ("Branch Code", Some(details.branch_code.clone())),
+    ]
+}
+
+fn changed_values(
+    old_values: Vec<(&'static str, Option<String>)>,
+    new_values: Vec<(&'static str, Option<String>)>,
+) -> Vec<BulkImportFieldChange> {
+    old_values
+        .into_iter()
+        .zip(new_values)
+        .filter(|((_, old_value), (_, new_value))| old_value != new_value)
+        .map(
+            |((field, old_value), (_, new_value))| BulkImportFieldChange {
+                field: field.to_string(),
+                old_value,
+                new_value,
+            },
+        )
+        .collect()
+}
+
 pub async fn download(_: Token<AdminAccessToken>) -> impl IntoResponse {
     let bytes = include_bytes!("add_bulk_employees.xlsx").to_vec();
     (
@@ -388,9 +1012,249 @@ pub async fn download(_: Token<AdminAccessToken>) -> impl IntoResponse {
     )
 }
 
+/// A template with only the header, for the imports whose template is generated.
+fn template_workbook(header: &[&str]) -> GlobeliseResult<impl IntoResponse> {
+    let mut book = umya_spreadsheet::new_file();
+    let sheet = book
+        .get_sheet_by_name_mut("Sheet1")
+        .map_err(|_| GlobeliseError::internal("Cannot create the template spreadsheet"))?;
+    for (column, value) in header.iter().enumerate() {
+        sheet
+            .get_cell_mut(&format!("{}1", report::column_name(column)))
+            .set_value(*value);
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.xlsx", Uuid::new_v4().to_simple()));
+    umya_spreadsheet::writer::xlsx::write(&book, &path)
+        .map_err(|_| GlobeliseError::internal("Cannot write the template spreadsheet"))?;
+    let file = std::fs::read(&path);
+    std::fs::remove_file(&path)?;
+
+    Ok((
+        [(
+            CONTENT_TYPE,
+            HeaderValue::from_static(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+        )],
+        file?,
+    ))
+}
+
 /// The parameters used for hashing.
 // TODO: Calibrate hash parameters for production server.
 pub static HASH_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     variant: argon2::Variant::Argon2id,
     ..Default::default()
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct TestRow {
+        #[serde(rename = "Amount")]
+        amount: u32,
+    }
+
+    fn records(rows: &[[&str; 2]]) -> (StringRecord, Vec<StringRecord>) {
+        let header = StringRecord::from(vec!["Email Address", "Amount"]);
+        let records = rows
+            .iter()
+            .map(|row| StringRecord::from(row.to_vec()))
+            .collect();
+        (header, records)
+    }
+
+    fn created(_: TestRow) -> RowOutcome {
+        RowOutcome {
+            status: BulkImportRowStatus::Created,
+            user_ulid: None,
+            field: None,
+            reason: None,
+            changes: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rows_are_imported_with_their_row_number() {
+        let (header, records) = records(&[
+            ["jane@example.com", "10"],
+            ["", ""],
+            ["John@Example.com", "x"],
+            ["john@example.com", "20"],
+            ["joan@example.com", "0"],
+        ]);
+        let (rows, cancelled) = import_rows(
+            |_, _| async { Ok(()) },
+            &header,
+            &records,
+            &["Email Address"],
+            |value: TestRow| async move {
+                if value.amount == 0 {
+                    Err(GlobeliseError::bad_request(
+                        "The contractor could not be saved",
+                    ))
+                } else {
+                    Ok(created(value))
+                }
+            },
+        )
+        .await;
+        assert!(cancelled.is_none());
+
+        let summary = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.row_number,
+                    row.email.as_deref(),
+                    row.status,
+                    row.field.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    2,
+                    Some("jane@example.com"),
+                    BulkImportRowStatus::Created,
+                    None
+                ),
+                (
+                    4,
+                    Some("john@example.com"),
+                    BulkImportRowStatus::Failed,
+                    Some("Amount")
+                ),
+                (
+                    5,
+                    Some("john@example.com"),
+                    BulkImportRowStatus::Created,
+                    None
+                ),
+                (
+                    6,
+                    Some("joan@example.com"),
+                    BulkImportRowStatus::Failed,
+                    None
+                ),
+            ]
+        );
+        assert_eq!(
+            rows[3].reason.as_deref(),
+            Some("The contractor could not be saved")
+        );
+    }
+
+    #[tokio::test]
+    async fn rows_with_the_same_key_as_an_earlier_row_are_skipped() {
+        let (header, records) = records(&[["jane@example.com", "10"], ["JANE@example.com", "20"]]);
+        let (rows, _) = import_rows(
+            |_, _| async { Ok(()) },
+            &header,
+            &records,
+            &["Email Address"],
+            |value: TestRow| async move { Ok(created(value)) },
+        )
+        .await;
+
+        assert_eq!(rows[0].status, BulkImportRowStatus::Created);
+        assert_eq!(rows[1].status, BulkImportRowStatus::Skipped);
+        assert_eq!(rows[1].field.as_deref(), Some("Email Address"));
+        assert_eq!(
+            rows[1].reason.as_deref(),
+            Some("Same email address as row 2")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_import_stops_with_the_rows_done_so_far() {
+        let (header, records) = records(&[
+            ["jane@example.com", "10"],
+            ["john@example.com", "20"],
+            ["joan@example.com", "30"],
+        ]);
+        let mut progress_calls = Vec::new();
+        let (rows, cancelled) = import_rows(
+            |done, total| {
+                progress_calls.push((done, total));
+                async move {
+                    if done == 2 {
+                        Err(GlobeliseError::bad_request("The job was cancelled"))
+                    } else {
+                        Ok(())
+                    }
+                }
+            },
+            &header,
+            &records,
+            &["Email Address"],
+            |value: TestRow| async move { Ok(created(value)) },
+        )
+        .await;
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(cancelled.unwrap().to_string(), "The job was cancelled");
+        assert_eq!(progress_calls, [(0, 3), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn import_modes_create_and_update_contractors() {
+        assert!(BulkImportMode::CreateOnly.creates());
+        assert!(!BulkImportMode::CreateOnly.updates());
+        assert!(!BulkImportMode::UpdateExisting.creates());
+        assert!(BulkImportMode::UpdateExisting.updates());
+        assert!(BulkImportMode::Upsert.creates());
+        assert!(BulkImportMode::Upsert.updates());
+        for mode in [
+            BulkImportMode::CreateOnly,
+            BulkImportMode::UpdateExisting,
+            BulkImportMode::Upsert,
+        ] {
+            assert_eq!(BulkImportMode::from_str(mode.as_str()), Some(mode));
+        }
+        assert_eq!(BulkImportMode::default(), BulkImportMode::CreateOnly);
+    }
+
+    fn details(
+        bank_account_number: &str,
+        bank_code: &str,
+        branch_code: &str,
+    ) -> ContractorUserDetails {
+        ContractorUserDetails {
+            bank_name: "DBS".to_string(),
+            bank_account_name: "Jane Tan".to_string(),
+            bank_account_number: bank_account_number.to_string(),
+            bank_code: bank_code.to_string(),
+            branch_code: branch_code.to_string(),
+        }
+    }
+
+    #[test]
+    fn valid_bank_details_pass() {
+        assert!(invalid_bank_details(&details("0123456789", "7171", "001"), Country::SG).is_none());
+    }
+
+    #[test]
+    fn invalid_bank_details_fail_the_row_in_their_column() {
+        let outcome =
+            invalid_bank_details(&details("0123456789", "71", "001"), Country::SG).unwrap();
+        assert_eq!(outcome.status, BulkImportRowStatus::Failed);
+        assert_eq!(outcome.field.as_deref(), Some("Bank Code"));
+        assert_eq!(
+            outcome.reason.as_deref(),
+            Some("Bank Code must be 4 digits")
+        );
+
+        let outcome = invalid_bank_details(&details("", "7171", "1"), Country::SG).unwrap();
+        assert_eq!(outcome.field.as_deref(), Some("Bank Account Number"));
+        assert_eq!(
+            outcome.reason.as_deref(),
+            Some("Bank Account Number is required; Branch Code must be 3 digits; Bank Account Number must be 6 to 17 digits")
+        );
+    }
+}
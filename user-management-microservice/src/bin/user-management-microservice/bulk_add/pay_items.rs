@@ -0,0 +1,383 @@
+//! Bulk assignment of the pay items of a branch to its contractors, with the amount paid every
+//! pay period.
+
+use axum::{
+    extract::{ContentLengthLimit, Extension, Json},
+    response::IntoResponse,
+};
+use common_utils::{
+    custom_serde::{EmailWrapper, UserType, FORM_DATA_LENGTH_LIMIT},
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    jobs::{JobContext, JobOutput},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Decimal, PgExecutor, Row};
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+use super::{
+    enqueue_import, import_rows, read_records,
+    report::{BulkImportFieldChange, BulkImportKind, BulkImportRowStatus, NewBulkImportReport},
+    save_report, template_workbook, BulkImportJob, PostOneAddBulkEmployee, RowOutcome,
+};
+
+pub const BULK_ASSIGN_PAY_ITEMS_JOB: &str = "bulk-assign-pay-items";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PayItemAssignmentForBulkUpload {
+    #[serde(rename = "Email Address")]
+    pub email: EmailWrapper,
+    /// The custom name of the pay item in the branch.
+    #[serde(rename = "Pay Item")]
+    pub pay_item: String,
+    /// Blank for pay items whose amount changes every pay period.
+    #[serde(rename = "Amount")]
+    pub amount: Option<Decimal>,
+}
+
+const TEMPLATE_HEADER: [&str; 3] = ["Email Address", "Pay Item", "Amount"];
+
+/// Queues the assignment of pay items to the contractors of the branch of the request.
+pub async fn post_one(
+    claims: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<
+        Json<PostOneAddBulkEmployee>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(shared_database): Extension<SharedDatabase>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    if body.branch_ulid.is_none() {
+        return Err(GlobeliseError::bad_request(
+            "Please choose the branch of the pay items",
+        ));
+    }
+
+    let job = enqueue_import(
+        &database,
+        &shared_database,
+        BULK_ASSIGN_PAY_ITEMS_JOB,
+        claims,
+        body,
+    )
+    .await?;
+
+    Ok(Json(job))
+}
+
+/// Assigns pay items to contractors, one per row.
+pub async fn run_import_job(
+    context: JobContext,
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let BulkImportJob {
+        created_by,
+        request,
+    } = context.payload()?;
+    let branch_ulid = request
+        .branch_ulid
+        .ok_or_else(|| GlobeliseError::bad_request("Please choose the branch of the pay items"))?;
+
+    let (header, records) = read_records(&request.file_name, &request.file_data)?;
+    let (rows, cancelled) = import_rows(
+        |done, total| context.progress(done, total),
+        &header,
+        &records,
+        &["Email Address", "Pay Item"],
+        |value: PayItemAssignmentForBulkUpload| {
+            process_row(
+                value,
+                database.clone(),
+                shared_database.clone(),
+                &request,
+                branch_ulid,
+            )
+        },
+    )
+    .await;
+
+    let report = NewBulkImportReport {
+        kind: BulkImportKind::PayItems,
+        client_ulid: request.client_ulid,
+        file_name: &request.file_name,
+        mode: request.mode,
+        dry_run: request.dry_run,
+        created_by,
+        header: &header,
+        records: &records,
+        rows: &rows,
+    };
+    save_report(&shared_database, &report, cancelled).await
+}
+
+/// Assigns one pay item to one contractor of the branch.
+///
+/// Amounts of pay items the contractor already has are only changed in the modes that update.
+async fn process_row(
+    value: PayItemAssignmentForBulkUpload,
+    database: CommonDatabase,
+    shared_database: SharedDatabase,
+    request: &PostOneAddBulkEmployee,
+    branch_ulid: Uuid,
+) -> GlobeliseResult<RowOutcome> {
+    let user = match database
+        .lock()
+        .await
+        .find_one_user(None, Some(&value.email), None)
+        .await?
+    {
+        Some(user) if user.is_contractor => user,
+        Some(_) => {
+            return Ok(RowOutcome::failed(
+                "Email Address",
+                "This email address does not belong to a contractor",
+            ))
+        }
+        None => {
+            return Ok(RowOutcome::failed(
+                "Email Address",
+                "No contractor has this email address",
+            ))
+        }
+    };
+
+    let shared_database = shared_database.lock().await;
+
+    if !shared_database
+        .contractor_is_in_branch(user.ulid, branch_ulid)
+        .await?
+    {
+        return Ok(RowOutcome::failed(
+            "Email Address",
+            "The contractor is not in this branch",
+        ));
+    }
+
+    let pay_item_ulid = match shared_database
+        .select_one_branch_pay_item_ulid_by_name(branch_ulid, &value.pay_item)
+        .await?
+    {
+        Some(pay_item_ulid) => pay_item_ulid,
+        None => {
+            return Ok(RowOutcome::failed(
+                "Pay Item",
+                "The branch has no pay item with this name",
+            ))
+        }
+    };
+
+    let (status, changes) = match shared_database
+        .select_one_contractor_pay_item_amount(user.ulid, pay_item_ulid)
+        .await?
+    {
+        None if !request.mode.creates() => {
+            return Ok(RowOutcome::skipped(
+                Some(user.ulid),
+                "The contractor does not have this pay item",
+            ))
+        }
+        None => (BulkImportRowStatus::Created, Vec::new()),
+        Some(_) if !request.mode.updates() => {
+            return Ok(RowOutcome::skipped(
+                Some(user.ulid),
+                "The contractor already has this pay item",
+            ))
+        }
+        Some(amount) if amount == value.amount => {
+            return Ok(RowOutcome::skipped(Some(user.ulid), "Already up to date"))
+        }
+        Some(amount) => (
+            BulkImportRowStatus::Updated,
+            vec![BulkImportFieldChange {
+                field: "Amount".to_string(),
+                old_value: amount.map(|amount| amount.to_string()),
+                new_value: value.amount.map(|amount| amount.to_string()),
+            }],
+        ),
+    };
+
+    if !request.dry_run {
+        shared_database
+            .upsert_contractor_pay_item(user.ulid, pay_item_ulid, value.amount)
+            .await?;
+    }
+
+    Ok(RowOutcome {
+        status,
+        user_ulid: Some(user.ulid),
+        field: None,
+        reason: None,
+        changes,
+    })
+}
+
+pub async fn download(_: Token<AdminAccessToken>) -> GlobeliseResult<impl IntoResponse> {
+    template_workbook(&TEMPLATE_HEADER)
+}
+
+impl Database {
+    /// Finds a pay item of a branch by its custom name, ignoring case.
+    ///
+    /// Pay items without a custom name are found by their type, like `allowance`.
+    pub async fn select_one_branch_pay_item_ulid_by_name(
+        &self,
+        branch_ulid: Uuid,
+        name: &str,
+    ) -> GlobeliseResult<Option<Uuid>> {
+        let result = sqlx::query(
+            "
+        SELECT
+            ulid
+        FROM
+            entity_client_branch_pay_items
+        WHERE
+            branch_ulid = $1 AND
+            LOWER(COALESCE(pay_item_custom_name, pay_item_type)) = LOWER($2)
+        ORDER BY
+            created_at
+        LIMIT
+            1",
+        )
+        .bind(branch_ulid)
+        .bind(name.trim())
+        .fetch_optional(&self.0)
+        .await?
+        .map(|r| r.try_get("ulid"))
+        .transpose()?;
+
+        Ok(result)
+    }
+
+    /// The amount of a pay item of a contractor, or None if the contractor does not have it.
+    pub async fn select_one_contractor_pay_item_amount(
+        &self,
+        contractor_ulid: Uuid,
+        pay_item_ulid: Uuid,
+    ) -> GlobeliseResult<Option<Option<Decimal>>> {
+        let result = sqlx::query(
+            "
+        SELECT
+            amount
+        FROM
+            contractor_pay_items
+        WHERE
+            contractor_ulid = $1 AND
+            pay_item_ulid = $2",
+        )
+        .bind(contractor_ulid)
+        .bind(pay_item_ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .map(|r| r.try_get("amount"))
+        .transpose()?;
+
+        Ok(result)
+    }
+
+    pub async fn upsert_contractor_pay_item(
+        &self,
+        contractor_ulid: Uuid,
+        pay_item_ulid: Uuid,
+        amount: Option<Decimal>,
+    ) -> GlobeliseResult<()> {
+        upsert_contractor_pay_item(&self.0, contractor_ulid, pay_item_ulid, amount).await
+    }
+
+    /// Whether an individual or entity contractor is in a branch.
+    pub async fn contractor_is_in_branch(
+        &self,
+        contractor_ulid: Uuid,
+        branch_ulid: Uuid,
+    ) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
+            "
+        SELECT
+            EXISTS (
+                SELECT 1 FROM entity_client_branch_individual_contractor_pairs
+                WHERE contractor_ulid = $1 AND branch_ulid = $2
+            ) OR EXISTS (
+                SELECT 1 FROM entity_contractor_branch_pairs
+                WHERE contractor_ulid = $1 AND branch_ulid = $2
+            ) AS is_in_branch",
+        )
+        .bind(contractor_ulid)
+        .bind(branch_ulid)
+        .fetch_one(&self.0)
+        .await?
+        .try_get("is_in_branch")?;
+
+        Ok(result)
+    }
+
+    /// Adds a contractor to a branch, unless the contractor is already in it.
+    pub async fn insert_contractor_into_branch(
+        &self,
+        contractor_ulid: Uuid,
+        user_type: UserType,
+        branch_ulid: Uuid,
+    ) -> GlobeliseResult<()> {
+        insert_contractor_into_branch(&self.0, contractor_ulid, user_type, branch_ulid).await
+    }
+}
+
+/// [`Database::upsert_contractor_pay_item`] with any executor, such as a transaction.
+pub async fn upsert_contractor_pay_item<'c>(
+    executor: impl PgExecutor<'c>,
+    contractor_ulid: Uuid,
+    pay_item_ulid: Uuid,
+    amount: Option<Decimal>,
+) -> GlobeliseResult<()> {
+    sqlx::query(
+        "
+    INSERT INTO contractor_pay_items (
+        contractor_ulid, pay_item_ulid, amount
+    ) VALUES (
+        $1, $2, $3
+    ) ON CONFLICT (contractor_ulid, pay_item_ulid) DO UPDATE SET
+        amount = $3, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(contractor_ulid)
+    .bind(pay_item_ulid)
+    .bind(amount)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// [`Database::insert_contractor_into_branch`] with any executor, such as a transaction.
+pub async fn insert_contractor_into_branch<'c>(
+    executor: impl PgExecutor<'c>,
+    contractor_ulid: Uuid,
+    user_type: UserType,
+    branch_ulid: Uuid,
+) -> GlobeliseResult<()> {
+    let table = match user_type {
+        UserType::Individual => "entity_client_branch_individual_contractor_pairs",
+        UserType::Entity => "entity_contractor_branch_pairs",
+    };
+
+    let query = format!(
+        "
+    INSERT INTO {table} (
+        contractor_ulid, branch_ulid
+    ) SELECT
+        $1, $2
+    WHERE NOT EXISTS (
+        SELECT 1 FROM {table} WHERE contractor_ulid = $1 AND branch_ulid = $2
+    )"
+    );
+
+    sqlx::query(&query)
+        .bind(contractor_ulid)
+        .bind(branch_ulid)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
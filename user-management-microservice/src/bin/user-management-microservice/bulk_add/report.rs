@@ -0,0 +1,797 @@
+//! What a bulk import did with every row of the uploaded file.
+//!
+//! Reports are stored with a copy of the file annotated with the outcome of every row, so
+//! that the rows that failed can be fixed and uploaded again. Rows that update a contractor
+//! who already exists are reported with every value they changed. Bank account numbers and
+//! identity numbers are only kept masked, in the changes as well as in the annotated file.
+//!
+//! Every kind of bulk import, of individual contractors, of entity contractors and of pay
+//! items, is reported the same way.
+
+use axum::{
+    extract::{Extension, Json, Path, Query},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
+};
+use common_utils::{
+    calc_limit_and_offset,
+    custom_serde::OffsetDateWrapper,
+    error::{GlobeliseError, GlobeliseResult},
+    token::Token,
+};
+use csv::StringRecord;
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{FromRow, Row};
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BulkImportRowStatus {
+    /// A new user, or a new pay item of a contractor, was created.
+    Created,
+    /// The row was added to a user that already existed.
+    Linked,
+    /// The row changed the details of a contractor that already existed.
+    Updated,
+    /// Nothing had to be done.
+    Skipped,
+    Failed,
+}
+
+impl BulkImportRowStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkImportRowStatus::Created => "created",
+            BulkImportRowStatus::Linked => "linked",
+            BulkImportRowStatus::Updated => "updated",
+            BulkImportRowStatus::Skipped => "skipped",
+            BulkImportRowStatus::Failed => "failed",
+        }
+    }
+
+    pub fn from_str(string: &str) -> Option<BulkImportRowStatus> {
+        match string {
+            "created" => Some(BulkImportRowStatus::Created),
+            "linked" => Some(BulkImportRowStatus::Linked),
+            "updated" => Some(BulkImportRowStatus::Updated),
+            "skipped" => Some(BulkImportRowStatus::Skipped),
+            "failed" => Some(BulkImportRowStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for BulkImportRowStatus {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("text")
+    }
+}
+
+impl sqlx::Decode<'_, sqlx::Postgres> for BulkImportRowStatus {
+    fn decode(value: sqlx::postgres::PgValueRef<'_>) -> Result<Self, sqlx::error::BoxDynError> {
+        let status_str: &'_ str = sqlx::decode::Decode::decode(value)?;
+        let status = BulkImportRowStatus::from_str(status_str).ok_or(format!(
+            "Cannot convert {} into a BulkImportRowStatus",
+            status_str
+        ))?;
+        Ok(status)
+    }
+}
+
+impl sqlx::encode::Encode<'_, sqlx::Postgres> for BulkImportRowStatus {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::encode(val, buf)
+    }
+    fn size_hint(&self) -> std::primitive::usize {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::size_hint(&val)
+    }
+}
+
+/// What the rows of an imported file are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BulkImportKind {
+    IndividualContractors,
+    EntityContractors,
+    /// Pay items of a branch assigned to contractors.
+    PayItems,
+}
+
+impl BulkImportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkImportKind::IndividualContractors => "individual-contractors",
+            BulkImportKind::EntityContractors => "entity-contractors",
+            BulkImportKind::PayItems => "pay-items",
+        }
+    }
+
+    pub fn from_str(string: &str) -> Option<BulkImportKind> {
+        match string {
+            "individual-contractors" => Some(BulkImportKind::IndividualContractors),
+            "entity-contractors" => Some(BulkImportKind::EntityContractors),
+            "pay-items" => Some(BulkImportKind::PayItems),
+            _ => None,
+        }
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for BulkImportKind {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("text")
+    }
+}
+
+impl sqlx::Decode<'_, sqlx::Postgres> for BulkImportKind {
+    fn decode(value: sqlx::postgres::PgValueRef<'_>) -> Result<Self, sqlx::error::BoxDynError> {
+        let kind_str: &'_ str = sqlx::decode::Decode::decode(value)?;
+        let kind = BulkImportKind::from_str(kind_str)
+            .ok_or(format!("Cannot convert {} into a BulkImportKind", kind_str))?;
+        Ok(kind)
+    }
+}
+
+impl sqlx::encode::Encode<'_, sqlx::Postgres> for BulkImportKind {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::encode(val, buf)
+    }
+    fn size_hint(&self) -> std::primitive::usize {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::size_hint(&val)
+    }
+}
+
+/// What an import does with the rows of contractors who already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BulkImportMode {
+    /// Only adds the details that contractors do not have yet.
+    CreateOnly,
+    /// Only changes contractors who already exist, and skips the others.
+    UpdateExisting,
+    /// Creates the contractors who do not exist yet and changes the others.
+    Upsert,
+}
+
+impl Default for BulkImportMode {
+    fn default() -> Self {
+        BulkImportMode::CreateOnly
+    }
+}
+
+impl BulkImportMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkImportMode::CreateOnly => "create-only",
+            BulkImportMode::UpdateExisting => "update-existing",
+            BulkImportMode::Upsert => "upsert",
+        }
+    }
+
+    pub fn from_str(string: &str) -> Option<BulkImportMode> {
+        match string {
+            "create-only" => Some(BulkImportMode::CreateOnly),
+            "update-existing" => Some(BulkImportMode::UpdateExisting),
+            "upsert" => Some(BulkImportMode::Upsert),
+            _ => None,
+        }
+    }
+
+    pub fn creates(&self) -> bool {
+        matches!(self, BulkImportMode::CreateOnly | BulkImportMode::Upsert)
+    }
+
+    pub fn updates(&self) -> bool {
+        matches!(
+            self,
+            BulkImportMode::UpdateExisting | BulkImportMode::Upsert
+        )
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for BulkImportMode {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("text")
+    }
+}
+
+impl sqlx::Decode<'_, sqlx::Postgres> for BulkImportMode {
+    fn decode(value: sqlx::postgres::PgValueRef<'_>) -> Result<Self, sqlx::error::BoxDynError> {
+        let mode_str: &'_ str = sqlx::decode::Decode::decode(value)?;
+        let mode = BulkImportMode::from_str(mode_str)
+            .ok_or(format!("Cannot convert {} into a BulkImportMode", mode_str))?;
+        Ok(mode)
+    }
+}
+
+impl sqlx::encode::Encode<'_, sqlx::Postgres> for BulkImportMode {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::encode(val, buf)
+    }
+    fn size_hint(&self) -> std::primitive::usize {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::size_hint(&val)
+    }
+}
+
+/// A value that a row changed, or would change in a dry run.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BulkImportFieldChange {
+    /// The column of the value in the file.
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BulkImportRow {
+    /// The row in the file, counting the header as row 1.
+    pub row_number: i32,
+    pub email: Option<String>,
+    pub status: BulkImportRowStatus,
+    /// The column of the value that is wrong, when the problem is with one value.
+    pub field: Option<String>,
+    pub reason: Option<String>,
+    pub user_ulid: Option<Uuid>,
+    pub changes: Vec<BulkImportFieldChange>,
+}
+
+impl BulkImportRow {
+    pub fn failed(
+        row_number: usize,
+        email: Option<String>,
+        field: Option<String>,
+        reason: String,
+    ) -> Self {
+        Self {
+            row_number: row_number as i32,
+            email,
+            status: BulkImportRowStatus::Failed,
+            field,
+            reason: Some(reason),
+            user_ulid: None,
+            changes: Vec::new(),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, FromRow, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BulkImportReportIndex {
+    pub ulid: Uuid,
+    pub kind: BulkImportKind,
+    pub client_ulid: Uuid,
+    pub file_name: String,
+    pub mode: BulkImportMode,
+    /// Dry runs only report what an import would do.
+    pub dry_run: bool,
+    pub created_count: i32,
+    pub linked_count: i32,
+    pub updated_count: i32,
+    pub skipped_count: i32,
+    pub failed_count: i32,
+    pub created_by: Uuid,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BulkImportReport {
+    #[serde(flatten)]
+    pub index: BulkImportReportIndex,
+    pub rows: Vec<BulkImportRow>,
+}
+
+/// An import, before it is stored.
+pub struct NewBulkImportReport<'a> {
+    pub kind: BulkImportKind,
+    pub client_ulid: Uuid,
+    pub file_name: &'a str,
+    pub mode: BulkImportMode,
+    pub dry_run: bool,
+    pub created_by: Uuid,
+    pub header: &'a StringRecord,
+    pub records: &'a [StringRecord],
+    pub rows: &'a [BulkImportRow],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BulkImportReportQuery {
+    pub client_ulid: Option<Uuid>,
+    pub kind: Option<BulkImportKind>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+pub async fn get_many_reports(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<BulkImportReportQuery>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<Vec<BulkImportReportIndex>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_bulk_import_reports(query.client_ulid, query.kind, query.page, query.per_page)
+        .await?;
+
+    Ok(Json(result))
+}
+
+pub async fn get_one_report(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<BulkImportReport>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_one_bulk_import_report(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the bulk import report"))?;
+
+    Ok(Json(result))
+}
+
+/// Downloads the uploaded file with the outcome of every row in two extra columns.
+pub async fn download_one_report(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<(HeaderMap, Vec<u8>)> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_bulk_import_report_file(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the bulk import report"))?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+    );
+    Ok((headers, result))
+}
+
+/// Columns whose values are masked in reports.
+const SENSITIVE_FIELDS: &[&str] = &[
+    "Bank Account Number",
+    "Tax ID",
+    "National ID",
+    "Passport Number",
+];
+
+/// The value as it is kept in a report: sensitive values only show their last 4 characters,
+/// or none of them if they are that short.
+fn masked(field: &str, value: &str) -> String {
+    if !SENSITIVE_FIELDS.contains(&field.trim()) {
+        return value.to_string();
+    }
+
+    let length = value.chars().count();
+    let shown = if length > 4 { 4 } else { 0 };
+    value
+        .chars()
+        .enumerate()
+        .map(|(index, c)| if index < length - shown { '*' } else { c })
+        .collect()
+}
+
+/// The column name of a 0-based column index, like `AB` for 27.
+pub fn column_name(mut index: usize) -> String {
+    let mut name = String::new();
+    loop {
+        name.insert(0, (b'A' + (index % 26) as u8) as char);
+        if index < 26 {
+            return name;
+        }
+        index = index / 26 - 1;
+    }
+}
+
+/// Writes the uploaded rows with their outcome after the last column.
+fn annotated_workbook(report: &NewBulkImportReport) -> GlobeliseResult<Vec<u8>> {
+    let mut book = umya_spreadsheet::new_file();
+    let sheet = book
+        .get_sheet_by_name_mut("Sheet1")
+        .map_err(|_| GlobeliseError::internal("Cannot create the import report spreadsheet"))?;
+
+    let outcome_column = report.header.len();
+    let header = report
+        .header
+        .iter()
+        .chain(["Import Status", "Import Message"]);
+    for (column, value) in header.enumerate() {
+        sheet
+            .get_cell_mut(&format!("{}1", column_name(column)))
+            .set_value(value);
+    }
+    for (index, record) in report.records.iter().enumerate() {
+        // The header is row 1.
+        for (column, value) in record.iter().enumerate() {
+            let field = report.header.get(column).unwrap_or_default();
+            sheet
+                .get_cell_mut(&format!("{}{}", column_name(column), index + 2))
+                .set_value(masked(field, value));
+        }
+    }
+    for row in report.rows {
+        let message = match (&row.field, &row.reason) {
+            (Some(field), Some(reason)) => format!("{}: {}", field, reason),
+            (_, Some(reason)) => reason.clone(),
+            (_, None) if !row.changes.is_empty() => format!(
+                "Changed {}",
+                row.changes
+                    .iter()
+                    .map(|change| change.field.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            (_, None) => String::new(),
+        };
+        for (column, value) in [row.status.as_str(), message.as_str()]
+            .into_iter()
+            .enumerate()
+        {
+            sheet
+                .get_cell_mut(&format!(
+                    "{}{}",
+                    column_name(outcome_column + column),
+                    row.row_number
+                ))
+                .set_value(value);
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.xlsx", Uuid::new_v4().to_simple()));
+    umya_spreadsheet::writer::xlsx::write(&book, &path)
+        .map_err(|_| GlobeliseError::internal("Cannot write the import report spreadsheet"))?;
+    let file = std::fs::read(&path);
+    std::fs::remove_file(&path)?;
+
+    Ok(file?)
+}
+
+impl Database {
+    pub async fn insert_bulk_import_report(
+        &self,
+        report: &NewBulkImportReport<'_>,
+    ) -> GlobeliseResult<Uuid> {
+        let ulid = Uuid::new_v4();
+        let count = |status: BulkImportRowStatus| {
+            report
+                .rows
+                .iter()
+                .filter(|row| row.status == status)
+                .count() as i32
+        };
+        let annotated_file = annotated_workbook(report)?;
+
+        let mut transaction = self.0.begin().await?;
+
+        sqlx::query(
+            "
+        INSERT INTO bulk_import_reports (
+            ulid, kind, client_ulid, file_name, mode, dry_run, created_count, linked_count,
+            updated_count, skipped_count, failed_count, created_by, annotated_file
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13
+        )",
+        )
+        .bind(ulid)
+        .bind(report.kind)
+        .bind(report.client_ulid)
+        .bind(report.file_name)
+        .bind(report.mode)
+        .bind(report.dry_run)
+        .bind(count(BulkImportRowStatus::Created))
+        .bind(count(BulkImportRowStatus::Linked))
+        .bind(count(BulkImportRowStatus::Updated))
+        .bind(count(BulkImportRowStatus::Skipped))
+        .bind(count(BulkImportRowStatus::Failed))
+        .bind(report.created_by)
+        .bind(annotated_file)
+        .execute(&mut transaction)
+        .await?;
+
+        for row in report.rows {
+            sqlx::query(
+                "
+            INSERT INTO bulk_import_report_rows (
+                report_ulid, row_number, email, status, field, reason, user_ulid
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7
+            )",
+            )
+            .bind(ulid)
+            .bind(row.row_number)
+            .bind(&row.email)
+            .bind(row.status)
+            .bind(&row.field)
+            .bind(&row.reason)
+            .bind(row.user_ulid)
+            .execute(&mut transaction)
+            .await?;
+
+            for change in &row.changes {
+                sqlx::query(
+                    "
+                INSERT INTO bulk_import_report_changes (
+                    report_ulid, row_number, field, old_value, new_value
+                ) VALUES (
+                    $1, $2, $3, $4, $5
+                )",
+                )
+                .bind(ulid)
+                .bind(row.row_number)
+                .bind(&change.field)
+                .bind(
+                    change
+                        .old_value
+                        .as_deref()
+                        .map(|value| masked(&change.field, value)),
+                )
+                .bind(
+                    change
+                        .new_value
+                        .as_deref()
+                        .map(|value| masked(&change.field, value)),
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(ulid)
+    }
+
+    pub async fn select_many_bulk_import_reports(
+        &self,
+        client_ulid: Option<Uuid>,
+        kind: Option<BulkImportKind>,
+        page: Option<u32>,
+        per_page: Option<u32>,
+    ) -> GlobeliseResult<Vec<BulkImportReportIndex>> {
+        let (limit, offset) = calc_limit_and_offset(per_page, page);
+
+        let result = sqlx::query_as(
+            "
+        SELECT
+            ulid, kind, client_ulid, file_name, mode, dry_run, created_count, linked_count,
+            updated_count, skipped_count, failed_count, created_by, created_at
+        FROM
+            bulk_import_reports
+        WHERE
+            ($1 IS NULL OR client_ulid = $1) AND
+            ($2 IS NULL OR kind = $2)
+        ORDER BY
+            created_at DESC
+        LIMIT
+            $3
+        OFFSET
+            $4",
+        )
+        .bind(client_ulid)
+        .bind(kind)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_one_bulk_import_report(
+        &self,
+        ulid: Uuid,
+    ) -> GlobeliseResult<Option<BulkImportReport>> {
+        let index = sqlx::query_as(
+            "
+        SELECT
+            ulid, kind, client_ulid, file_name, mode, dry_run, created_count, linked_count,
+            updated_count, skipped_count, failed_count, created_by, created_at
+        FROM
+            bulk_import_reports
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let mut changes = sqlx::query(
+            "
+        SELECT
+            row_number, field, old_value, new_value
+        FROM
+            bulk_import_report_changes
+        WHERE
+            report_ulid = $1
+        ORDER BY
+            row_number, field",
+        )
+        .bind(ulid)
+        .fetch_all(&self.0)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok((
+                r.try_get::<i32, _>("row_number")?,
+                BulkImportFieldChange::from_row(&r)?,
+            ))
+        })
+        .collect::<GlobeliseResult<Vec<_>>>()?
+        .into_iter()
+        .peekable();
+
+        let rows = sqlx::query(
+            "
+        SELECT
+            row_number, email, status, field, reason, user_ulid
+        FROM
+            bulk_import_report_rows
+        WHERE
+            report_ulid = $1
+        ORDER BY
+            row_number",
+        )
+        .bind(ulid)
+        .fetch_all(&self.0)
+        .await?
+        .into_iter()
+        .map(|r| {
+            let row_number = r.try_get("row_number")?;
+            let mut row_changes = Vec::new();
+            while let Some((_, change)) =
+                changes.next_if(|(change_row_number, _)| *change_row_number == row_number)
+            {
+                row_changes.push(change);
+            }
+            Ok(BulkImportRow {
+                row_number,
+                email: r.try_get("email")?,
+                status: r.try_get("status")?,
+                field: r.try_get("field")?,
+                reason: r.try_get("reason")?,
+                user_ulid: r.try_get("user_ulid")?,
+                changes: row_changes,
+            })
+        })
+        .collect::<GlobeliseResult<Vec<_>>>()?;
+
+        Ok(Some(BulkImportReport { index, rows }))
+    }
+
+    async fn select_bulk_import_report_file(&self, ulid: Uuid) -> GlobeliseResult<Option<Vec<u8>>> {
+        let result = sqlx::query(
+            "
+        SELECT
+            annotated_file
+        FROM
+            bulk_import_reports
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .map(|r| r.try_get("annotated_file"))
+        .transpose()?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_values_only_show_their_last_characters() {
+        assert_eq!(masked("Bank Account Number", "0123456789"), "******6789");
+        assert_eq!(masked("Tax ID", "S1234567D"), "*****567D");
+        assert_eq!(masked("Passport Number", "E123"), "****");
+        assert_eq!(masked("Bank Name", "DBS Bank"), "DBS Bank");
+    }
+
+    #[test]
+    fn annotated_workbook_has_the_outcome_of_every_row() {
+        let header = StringRecord::from(vec!["Email Address", "Bank Account Number"]);
+        let records = [
+            StringRecord::from(vec!["jane@example.com", "0123456789"]),
+            StringRecord::from(vec!["john@example.com", "123"]),
+            StringRecord::from(vec!["joan@example.com", "9876543210"]),
+        ];
+        let row = |row_number, status| BulkImportRow {
+            row_number,
+            email: None,
+            status,
+            field: None,
+            reason: None,
+            user_ulid: None,
+            changes: Vec::new(),
+        };
+        let rows = [
+            row(2, BulkImportRowStatus::Created),
+            BulkImportRow {
+                field: Some("Bank Account Number".to_string()),
+                reason: Some("Bank Account Number must be 6 to 17 digits".to_string()),
+                ..row(3, BulkImportRowStatus::Failed)
+            },
+            BulkImportRow {
+                changes: vec![BulkImportFieldChange {
+                    field: "Bank Account Number".to_string(),
+                    old_value: Some("******1111".to_string()),
+                    new_value: Some("******3210".to_string()),
+                }],
+                ..row(4, BulkImportRowStatus::Updated)
+            },
+        ];
+        let report = NewBulkImportReport {
+            kind: BulkImportKind::IndividualContractors,
+            client_ulid: Uuid::new_v4(),
+            file_name: "contractors.csv",
+            mode: BulkImportMode::Upsert,
+            dry_run: false,
+            created_by: Uuid::new_v4(),
+            header: &header,
+            records: &records,
+            rows: &rows,
+        };
+
+        let file = annotated_workbook(&report).unwrap();
+        let (header, records) = crate::bulk_add::read_records("report.xlsx", &file).unwrap();
+        assert_eq!(
+            header.iter().collect::<Vec<_>>(),
+            [
+                "Email Address",
+                "Bank Account Number",
+                "Import Status",
+                "Import Message"
+            ]
+        );
+        let records = records
+            .iter()
+            .map(|record| record.iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                vec!["jane@example.com", "******6789", "created", ""],
+                vec![
+                    "john@example.com",
+                    "***",
+                    "failed",
+                    "Bank Account Number: Bank Account Number must be 6 to 17 digits"
+                ],
+                vec![
+                    "joan@example.com",
+                    "******3210",
+                    "updated",
+                    "Changed Bank Account Number"
+                ],
+            ]
+        );
+    }
+}
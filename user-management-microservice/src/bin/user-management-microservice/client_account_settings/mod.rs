@@ -1,10 +1,15 @@
 pub mod client;
 pub mod eor_admin;
-use argon2::{hash_encoded, Config};
+use argon2::{hash_encoded, verify_encoded, Config};
 use axum::{Extension, Json};
 use common_utils::{
+    database::{
+        user::{insert_password_history, PasswordOwner},
+        CommonDatabase,
+    },
     error::{GlobeliseError, GlobeliseResult},
-    token::Token,
+    password::PASSWORD_POLICY,
+    token::{NotImpersonated, Token},
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use serde::Deserialize;
@@ -27,17 +32,21 @@ use unicode_normalization::UnicodeNormalization;
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct ChangePasswordRequest {
+    pub current_password: String,
     pub new_password: String,
     pub confirm_new_password: String,
 }
 
 /// Replace the password for a user with the requested one.
+///
+/// Admins acting as a user cannot use it to take over the account.
 pub async fn user_change_password(
-    claims: Token<UserAccessToken>,
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
     Json(request): Json<ChangePasswordRequest>,
-    Extension(database): Extension<SharedDatabase>,
+    Extension(database): Extension<CommonDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<()> {
+    let current_password: String = request.current_password.nfc().collect();
     let new_password: String = request.new_password.nfc().collect();
     let confirm_new_password: String = request.confirm_new_password.nfc().collect();
 
@@ -45,17 +54,41 @@ pub async fn user_change_password(
         return Err(GlobeliseError::bad_request("Passwords do not match"));
     }
 
+    PASSWORD_POLICY.validate(&new_password)?;
+
     let database = database.lock().await;
     let mut shared_state = shared_state.lock().await;
 
-    // NOTE: This is not atomic, so this check is quite pointless.
-    // Either rely completely on SQL or use some kind of transaction commit.
+    let current_hash = database
+        .find_one_user(
+            Some(claims.payload.ulid),
+            None,
+            Some(claims.payload.user_type),
+        )
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find user with that ulid"))?
+        .password
+        .ok_or_else(|| {
+            GlobeliseError::bad_request("User has no password. Please reset your password")
+        })?;
+    if !matches!(
+        verify_encoded(&current_hash, current_password.as_bytes()),
+        Ok(true)
+    ) {
+        return Err(GlobeliseError::unauthorized("Entered the wrong password"));
+    }
+
+    let previous_hashes = database
+        .select_many_user_password_hashes(claims.payload.ulid, PASSWORD_POLICY.history_size)
+        .await?;
+    PASSWORD_POLICY.check_history(&new_password, previous_hashes.iter().map(String::as_str))?;
+
     let salt: [u8; 16] = rand::thread_rng().gen();
     let hash = hash_encoded(new_password.as_bytes(), &salt, &HASH_CONFIG)
         .map_err(GlobeliseError::internal)?;
 
     database
-        .user_change_password(claims.payload.ulid, hash)
+        .update_user_password_hash(claims.payload.ulid, claims.payload.user_type, Some(hash))
         .await?;
     shared_state
         .revoke_all_sessions(claims.payload.ulid)
@@ -68,9 +101,11 @@ pub async fn user_change_password(
 pub async fn admin_change_password(
     claims: Token<AdminAccessToken>,
     Json(request): Json<ChangePasswordRequest>,
+    Extension(common_database): Extension<CommonDatabase>,
     Extension(database): Extension<SharedDatabase>,
     Extension(shared_state): Extension<SharedState>,
 ) -> GlobeliseResult<()> {
+    let current_password: String = request.current_password.nfc().collect();
     let new_password: String = request.new_password.nfc().collect();
     let confirm_new_password: String = request.confirm_new_password.nfc().collect();
 
@@ -78,11 +113,30 @@ pub async fn admin_change_password(
         return Err(GlobeliseError::bad_request("Passwords do not match"));
     }
 
+    PASSWORD_POLICY.validate(&new_password)?;
+
+    let common_database = common_database.lock().await;
     let database = database.lock().await;
     let mut shared_state = shared_state.lock().await;
 
-    // NOTE: This is not atomic, so this check is quite pointless.
-    // Either rely completely on SQL or use some kind of transaction commit.
+    let current_hash = database
+        .select_one_admin_password_hash(claims.payload.ulid)
+        .await?
+        .ok_or_else(|| {
+            GlobeliseError::bad_request("Admin has no password. Please reset your password")
+        })?;
+    if !matches!(
+        verify_encoded(&current_hash, current_password.as_bytes()),
+        Ok(true)
+    ) {
+        return Err(GlobeliseError::unauthorized("Entered the wrong password"));
+    }
+
+    let previous_hashes = common_database
+        .select_many_admin_password_hashes(claims.payload.ulid, PASSWORD_POLICY.history_size)
+        .await?;
+    PASSWORD_POLICY.check_history(&new_password, previous_hashes.iter().map(String::as_str))?;
+
     let salt: [u8; 16] = rand::thread_rng().gen();
     let hash = hash_encoded(new_password.as_bytes(), &salt, &HASH_CONFIG)
         .map_err(GlobeliseError::internal)?;
@@ -105,48 +159,56 @@ pub static HASH_CONFIG: Lazy<Config> = Lazy::new(|| Config {
 });
 
 impl Database {
-    pub async fn user_change_password(
+    /// Gets a admin's current password hash.
+    pub async fn select_one_admin_password_hash(
         &self,
         ulid: Uuid,
-        new_password_hash: String,
-    ) -> GlobeliseResult<()> {
-        sqlx::query(
+    ) -> GlobeliseResult<Option<String>> {
+        let result: Option<Option<String>> = sqlx::query_scalar(
             "
-            UPDATE 
-                users 
-            SET 
-                password = $1 
+            SELECT 
+                password 
+            FROM 
+                admin_users 
             WHERE 
-                ulid = $2",
+                ulid = $1",
         )
-        .bind(new_password_hash)
         .bind(ulid)
-        .execute(&self.0)
+        .fetch_optional(&self.0)
         .await?;
 
-        Ok(())
+        Ok(result.flatten())
     }
 
     /// Updates a admin's password.
+    ///
+    /// The previous hash is kept in the password history.
     pub async fn admin_change_password(
         &self,
         ulid: Uuid,
         new_password_hash: String,
     ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
+        insert_password_history(&mut transaction, PasswordOwner::Admin, ulid).await?;
+
         sqlx::query(
             "
             UPDATE 
                 admin_users 
             SET 
-                password = $1 
+                password = $1,
+                password_changed_at = CURRENT_TIMESTAMP
             WHERE 
                 ulid = $2",
         )
         .bind(new_password_hash)
         .bind(ulid)
-        .execute(&self.0)
+        .execute(&mut transaction)
         .await?;
 
+        transaction.commit().await?;
+
         Ok(())
     }
 }
@@ -9,7 +9,7 @@ use common_utils::{
 };
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
-use sqlx::FromRow;
+use sqlx::{types::Decimal, FromRow};
 use user_management_microservice_sdk::token::UserAccessToken;
 use uuid::Uuid;
 
@@ -31,11 +31,11 @@ pub struct ListClientContractorPayrollInformationRequest {
 pub struct ListClientContractorPayrollInformationResponse {
     pub contractor_ulid: Uuid,
     pub client_ulid: Uuid,
-    pub monthly_basic_salary_amount: f64,
-    pub monthly_added_pay_items_for_addition_section: f64,
-    pub monthly_added_pay_items_for_deduction_section: f64,
-    pub monthly_added_pay_items_for_statement_only_section: f64,
-    pub monthly_added_pay_items_for_employers_contribution_section: f64,
+    pub monthly_basic_salary_amount: Decimal,
+    pub monthly_added_pay_items_for_addition_section: Decimal,
+    pub monthly_added_pay_items_for_deduction_section: Decimal,
+    pub monthly_added_pay_items_for_statement_only_section: Decimal,
+    pub monthly_added_pay_items_for_employers_contribution_section: Decimal,
 }
 
 pub async fn get_payroll_information_individual(
@@ -7,7 +7,10 @@ use common_utils::{custom_serde::OffsetDateWrapper, error::GlobeliseResult, toke
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::{serde_as, TryFromInto};
-use sqlx::{types::Uuid, FromRow};
+use sqlx::{
+    types::{Decimal, Uuid},
+    FromRow,
+};
 use user_management_microservice_sdk::token::UserAccessToken;
 
 //
@@ -88,11 +91,11 @@ pub struct EntityContractorEmployementInformation {
 pub struct EntityContractorPayrollInformation {
     pub contractor_ulid: Uuid,
     pub client_ulid: Uuid,
-    pub monthly_basic_salary_amount: f64,
-    pub monthly_added_pay_items_for_addition_section: f64,
-    pub monthly_added_pay_items_for_deduction_section: f64,
-    pub monthly_added_pay_items_for_statement_only_section: f64,
-    pub monthly_added_pay_items_for_employers_contribution_section: f64,
+    pub monthly_basic_salary_amount: Decimal,
+    pub monthly_added_pay_items_for_addition_section: Decimal,
+    pub monthly_added_pay_items_for_deduction_section: Decimal,
+    pub monthly_added_pay_items_for_statement_only_section: Decimal,
+    pub monthly_added_pay_items_for_employers_contribution_section: Decimal,
 }
 
 #[serde_as]
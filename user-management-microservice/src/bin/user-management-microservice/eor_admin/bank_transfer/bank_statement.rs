@@ -0,0 +1,1441 @@
+//! Bank statements and reconciliation.
+//!
+//! Admins upload the statements of our accounts as camt.053, or as MT940 for banks that do
+//! not offer camt.053. Every line is stored and matched automatically where possible: debits
+//! against the transfer records we sent, by end-to-end ID and amount, and credits against
+//! client invoices, by the invoice reference in the remittance information. Lines that
+//! cannot be matched wait in a queue for an admin to match or ignore them.
+
+use axum::extract::{ContentLengthLimit, Extension, Json, Path, Query};
+use common_utils::{
+    calc_limit_and_offset,
+    custom_serde::{OffsetDateWrapper, FORM_DATA_LENGTH_LIMIT},
+    error::{GlobeliseError, GlobeliseResult},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as, TryFromInto};
+use sqlx::{types::Decimal, FromRow};
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+use super::camt053_file::{Amt, Camt053File, DateAndDateTime, Ntry, Stmt, TxDtls};
+use super::mt940::parse_mt940;
+
+/// A statement read from a camt.053 or MT940 file.
+#[derive(Debug, Default)]
+pub struct ParsedStatement {
+    pub format: &'static str,
+    pub statement_id: String,
+    pub account: String,
+    pub currency: Option<String>,
+    pub opening_balance: Option<Decimal>,
+    pub closing_balance: Option<Decimal>,
+    pub lines: Vec<ParsedStatementLine>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedStatementLine {
+    pub booking_date: Option<String>,
+    pub value_date: Option<String>,
+    /// CRDT or DBIT
+    pub credit_debit: &'static str,
+    pub amount: Decimal,
+    pub currency: String,
+    pub end_to_end_id: Option<String>,
+    pub reference: Option<String>,
+    pub remittance_information: Option<String>,
+    pub counterparty_name: Option<String>,
+}
+
+/// Reads the statements of a camt.053 or MT940 file.
+pub fn parse_bank_statements(data: &str) -> GlobeliseResult<Vec<ParsedStatement>> {
+    if data.trim_start().starts_with('<') {
+        parse_camt053(data)
+    } else {
+        parse_mt940(data)
+    }
+}
+
+fn parse_camt053(data: &str) -> GlobeliseResult<Vec<ParsedStatement>> {
+    let file: Camt053File = serde_xml_rs::from_str(data)
+        .map_err(|e| GlobeliseError::bad_request(format!("Malformed camt.053 file: {}", e)))?;
+    if file.bk_to_cstmr_stmt.stmt.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "The file does not contain a camt.053 statement",
+        ));
+    }
+
+    file.bk_to_cstmr_stmt
+        .stmt
+        .into_iter()
+        .map(camt053_statement)
+        .collect()
+}
+
+fn camt053_statement(stmt: Stmt) -> GlobeliseResult<ParsedStatement> {
+    let account = stmt
+        .acct
+        .id
+        .iban
+        .or_else(|| stmt.acct.id.othr.map(|othr| othr.id))
+        .ok_or_else(|| {
+            GlobeliseError::bad_request(format!("Statement {} has no account", stmt.id))
+        })?;
+
+    let mut opening_balance = None;
+    let mut closing_balance = None;
+    for balance in &stmt.bal {
+        let amount = signed_amount(&balance.amt, &balance.cdt_dbt_ind)?;
+        match balance.tp.cd_or_prtry.cd.as_deref() {
+            // A previous closing balance is the opening balance of statements that lack one.
+            Some("OPBD") => opening_balance = Some(amount),
+            Some("PRCD") => opening_balance = opening_balance.or(Some(amount)),
+            Some("CLBD") => closing_balance = Some(amount),
+            _ => {}
+        }
+    }
+
+    let mut lines = Vec::new();
+    for entry in stmt.ntry {
+        lines.extend(camt053_entry_lines(entry)?);
+    }
+
+    Ok(ParsedStatement {
+        format: "camt.053",
+        statement_id: stmt.id,
+        account,
+        currency: stmt.acct.ccy,
+        opening_balance,
+        closing_balance,
+        lines,
+    })
+}
+
+/// Splits a batch booking into one line per transaction, so that every transfer can be
+/// matched on its own.
+fn camt053_entry_lines(entry: Ntry) -> GlobeliseResult<Vec<ParsedStatementLine>> {
+    let credit_debit = credit_debit(&entry.cdt_dbt_ind)?;
+    let booking_date = entry.bookg_dt.as_ref().and_then(date);
+    let value_date = entry.val_dt.as_ref().and_then(date);
+    let transactions = entry
+        .ntry_dtls
+        .into_iter()
+        .flat_map(|details| details.tx_dtls)
+        .collect::<Vec<_>>();
+    let single_transaction = transactions.len() == 1;
+
+    let line = |amount: &Amt,
+                reference: Option<String>,
+                transaction: Option<TxDtls>|
+     -> GlobeliseResult<ParsedStatementLine> {
+        let mut line = ParsedStatementLine {
+            booking_date: booking_date.clone(),
+            value_date: value_date.clone(),
+            credit_debit,
+            amount: parse_amount(amount)?,
+            currency: amount.ccy.clone(),
+            reference,
+            remittance_information: entry.addtl_ntry_inf.clone(),
+            ..Default::default()
+        };
+        if let Some(transaction) = transaction {
+            if let Some(refs) = transaction.refs {
+                line.end_to_end_id = refs
+                    .end_to_end_id
+                    .filter(|id| id != "NOTPROVIDED" && !id.trim().is_empty());
+                line.reference = refs.acct_svcr_ref.or(line.reference);
+            }
+            if let Some(remittance) = transaction.rmt_inf {
+                let information = remittance
+                    .ustrd
+                    .into_iter()
+                    .chain(
+                        remittance
+                            .strd
+                            .into_iter()
+                            .filter_map(|strd| strd.cdtr_ref_inf.and_then(|inf| inf.r#ref)),
+                    )
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if !information.is_empty() {
+                    line.remittance_information = Some(information);
+                }
+            }
+            line.counterparty_name = transaction.rltd_pties.and_then(|parties| {
+                // The other side of a credit is the one who paid us.
+                if credit_debit == "CRDT" {
+                    parties.dbtr.and_then(|party| party.nm)
+                } else {
+                    parties.cdtr.and_then(|party| party.nm)
+                }
+            });
+        }
+        Ok(line)
+    };
+
+    if transactions.is_empty() {
+        return Ok(vec![line(&entry.amt, entry.acct_svcr_ref.clone(), None)?]);
+    }
+
+    transactions
+        .into_iter()
+        .map(|mut transaction| {
+            // Newer versions give the amount of a transaction directly, older ones in its
+            // amount details. A single transaction is worth the whole entry.
+            let amount = transaction
+                .amt
+                .take()
+                .or_else(|| {
+                    transaction
+                        .amt_dtls
+                        .take()
+                        .and_then(|details| details.tx_amt)
+                        .map(|tx_amt| tx_amt.amt)
+                });
+            let amount = match (amount, single_transaction) {
+                (Some(amount), _) => amount,
+                (None, true) => Amt {
+                    ccy: entry.amt.ccy.clone(),
+                    value: entry.amt.value.clone(),
+                },
+                (None, false) => {
+                    return Err(GlobeliseError::bad_request(
+                        "A batch booking of the statement does not give the amounts of its transactions",
+                    ))
+                }
+            };
+            line(&amount, entry.acct_svcr_ref.clone(), Some(transaction))
+        })
+        .collect()
+}
+
+fn credit_debit(indicator: &str) -> GlobeliseResult<&'static str> {
+    match indicator {
+        "CRDT" => Ok("CRDT"),
+        "DBIT" => Ok("DBIT"),
+        _ => Err(GlobeliseError::bad_request(format!(
+            "Unknown credit/debit indicator '{}'",
+            indicator
+        ))),
+    }
+}
+
+fn parse_amount(amount: &Amt) -> GlobeliseResult<Decimal> {
+    amount
+        .value
+        .trim()
+        .parse()
+        .map_err(|_| GlobeliseError::bad_request(format!("Invalid amount '{}'", amount.value)))
+}
+
+fn signed_amount(amount: &Amt, indicator: &str) -> GlobeliseResult<Decimal> {
+    let amount = parse_amount(amount)?;
+    match credit_debit(indicator)? {
+        "DBIT" => Ok(-amount),
+        _ => Ok(amount),
+    }
+}
+
+/// Takes the date of a date or date and time.
+fn date(value: &DateAndDateTime) -> Option<String> {
+    value.dt.clone().or_else(|| {
+        value
+            .dt_tm
+            .as_ref()
+            .and_then(|dt_tm| dt_tm.get(..10))
+            .map(String::from)
+    })
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportBankStatementRequest {
+    /// The camt.053 or MT940 file.
+    #[serde_as(as = "Base64")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportBankStatementResponse {
+    pub statements: Vec<ImportedBankStatement>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportedBankStatement {
+    pub statement_id: String,
+    pub account: String,
+    /// None when the statement had already been imported.
+    pub statement_ulid: Option<Uuid>,
+    pub lines: usize,
+    pub matched: usize,
+    pub unmatched: usize,
+}
+
+/// Imports the statements of a file and matches their lines.
+///
+/// Statements that were imported before are skipped, so that overlapping files can be
+/// uploaded safely.
+pub async fn import_bank_statement(
+    claims: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(request)): ContentLengthLimit<
+        Json<ImportBankStatementRequest>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<ImportBankStatementResponse>> {
+    let raw_data = String::from_utf8(request.data).map_err(GlobeliseError::bad_request)?;
+    let statements = parse_bank_statements(&raw_data)?;
+
+    let mut response = ImportBankStatementResponse { statements: vec![] };
+    for statement in statements {
+        let mut imported = ImportedBankStatement {
+            statement_id: statement.statement_id.clone(),
+            account: statement.account.clone(),
+            statement_ulid: None,
+            lines: statement.lines.len(),
+            matched: 0,
+            unmatched: 0,
+        };
+
+        let line_ulids = {
+            let database = database.lock().await;
+            match database
+                .insert_bank_statement(&statement, claims.payload.ulid)
+                .await?
+            {
+                Some((statement_ulid, line_ulids)) => {
+                    imported.statement_ulid = Some(statement_ulid);
+                    line_ulids
+                }
+                None => {
+                    response.statements.push(imported);
+                    continue;
+                }
+            }
+        };
+
+        for (line_ulid, line) in line_ulids.into_iter().zip(&statement.lines) {
+            let database = database.lock().await;
+            if auto_match_line(&database, line_ulid, line).await? {
+                imported.matched += 1;
+            } else {
+                imported.unmatched += 1;
+            }
+        }
+        response.statements.push(imported);
+    }
+
+    Ok(Json(response))
+}
+
+/// Matches a line if exactly one transfer or invoice fits it, otherwise notes why not.
+async fn auto_match_line(
+    database: &Database,
+    line_ulid: Uuid,
+    line: &ParsedStatementLine,
+) -> GlobeliseResult<bool> {
+    let note = if line.credit_debit == "DBIT" {
+        let end_to_end_id = match transfer_end_to_end_id(line) {
+            Some(end_to_end_id) => end_to_end_id,
+            None => return Ok(false),
+        };
+        let records = database
+            .select_unmatched_transfer_records(&end_to_end_id, line.amount, &line.currency)
+            .await?;
+        match records.as_slice() {
+            [record_ulid] => {
+                return database
+                    .match_bank_statement_line(
+                        line_ulid,
+                        Some(*record_ulid),
+                        None,
+                        "Matched by end-to-end ID and amount".to_string(),
+                        None,
+                    )
+                    .await;
+            }
+            [] => "No unmatched transfer has this end-to-end ID and amount".to_string(),
+            _ => "Several transfers have this end-to-end ID and amount".to_string(),
+        }
+    } else {
+        let (invoice_ulids, invoice_ids) = invoice_references(line);
+        if invoice_ulids.is_empty() && invoice_ids.is_empty() {
+            return Ok(false);
+        }
+        let invoices = database
+            .select_invoices_by_reference(&invoice_ulids, &invoice_ids)
+            .await?;
+        let already_matched = match invoices.as_slice() {
+            [invoice] => database.is_invoice_matched(invoice.ulid).await?,
+            _ => false,
+        };
+        match invoices.as_slice() {
+            [invoice] if invoice.currency.as_deref() != Some(line.currency.as_str()) => format!(
+                "The reference names invoice {} but it is not billed in {}",
+                invoice.invoice_id, line.currency
+            ),
+            [invoice] if already_matched => format!(
+                "The reference names invoice {} but it is already matched to another line",
+                invoice.invoice_id
+            ),
+            [invoice] if invoice.total == line.amount => {
+                return database
+                    .match_bank_statement_line(
+                        line_ulid,
+                        None,
+                        Some(invoice),
+                        "Matched by invoice reference and amount".to_string(),
+                        None,
+                    )
+                    .await;
+            }
+            [invoice] => format!(
+                "The reference names invoice {} but {} is not its total of {}",
+                invoice.invoice_id, line.amount, invoice.total
+            ),
+            [] => "The reference does not name any of our invoices".to_string(),
+            _ => "The reference names several invoices".to_string(),
+        }
+    };
+
+    database
+        .update_bank_statement_line_note(line_ulid, "unmatched", note, None)
+        .await?;
+
+    Ok(false)
+}
+
+/// The end-to-end ID of a transfer we sent, in the upper case form our files use.
+///
+/// Citibank files carry the first 16 characters of the record ID and SEPA files all 32.
+fn transfer_end_to_end_id(line: &ParsedStatementLine) -> Option<String> {
+    let end_to_end_id = line.end_to_end_id.as_ref()?.trim().to_uppercase();
+    (end_to_end_id.len() >= 16
+        && end_to_end_id.len() <= 32
+        && end_to_end_id.chars().all(|c| c.is_ascii_hexdigit()))
+    .then(|| end_to_end_id)
+}
+
+/// Finds invoice IDs, like `INV1234`, and invoice ULIDs in the references of a line.
+fn invoice_references(line: &ParsedStatementLine) -> (Vec<Uuid>, Vec<i64>) {
+    let mut invoice_ulids = Vec::new();
+    let mut invoice_ids = Vec::new();
+    let text = [
+        line.end_to_end_id.as_deref(),
+        line.reference.as_deref(),
+        line.remittance_information.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    for token in text.split(|c: char| c.is_whitespace() || c == ',' || c == ';' || c == '/') {
+        let token = token.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+        if let Ok(ulid) = Uuid::parse_str(token) {
+            invoice_ulids.push(ulid);
+        } else if let Some(id) = token
+            .to_uppercase()
+            .strip_prefix("INV")
+            .map(|id| id.trim_start_matches(|c: char| c == '-' || c == '#'))
+            .and_then(|id| id.parse::<i64>().ok())
+        {
+            invoice_ids.push(id);
+        }
+    }
+    invoice_ulids.dedup();
+    invoice_ids.dedup();
+
+    (invoice_ulids, invoice_ids)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnmatchedBankStatementLinesQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    /// CRDT or DBIT
+    pub credit_debit: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "kebab-case")]
+pub struct BankStatementLine {
+    pub ulid: Uuid,
+    pub statement_ulid: Uuid,
+    pub statement_id: String,
+    pub account: String,
+    pub line_number: i32,
+    pub booking_date: Option<String>,
+    pub value_date: Option<String>,
+    pub credit_debit: String,
+    pub amount: Decimal,
+    pub currency: String,
+    pub end_to_end_id: Option<String>,
+    pub reference: Option<String>,
+    pub remittance_information: Option<String>,
+    pub counterparty_name: Option<String>,
+    pub match_status: String,
+    pub matched_record_ulid: Option<Uuid>,
+    pub matched_invoice_ulid: Option<Uuid>,
+    pub match_note: Option<String>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+/// The lines that still need an admin, oldest first.
+pub async fn list_unmatched_bank_statement_lines(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<UnmatchedBankStatementLinesQuery>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<Vec<BankStatementLine>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_unmatched_bank_statement_lines(query)
+        .await?;
+
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MatchBankStatementLineRequest {
+    /// The transfer record a debit paid out.
+    pub record_ulid: Option<Uuid>,
+    /// The invoice a credit paid.
+    pub invoice_ulid: Option<Uuid>,
+    pub note: Option<String>,
+}
+
+/// Matches an unmatched line by hand.
+pub async fn match_bank_statement_line(
+    claims: Token<AdminAccessToken>,
+    Path(line_ulid): Path<Uuid>,
+    Json(request): Json<MatchBankStatementLineRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+    let line = database.select_one_bank_statement_line(line_ulid).await?;
+    if line.match_status != "unmatched" {
+        return Err(GlobeliseError::bad_request(format!(
+            "This line is already {}",
+            line.match_status
+        )));
+    }
+    let note = request
+        .note
+        .unwrap_or_else(|| "Matched by an admin".to_string());
+
+    let matched = match (request.record_ulid, request.invoice_ulid) {
+        (Some(record_ulid), None) => {
+            if line.credit_debit != "DBIT" {
+                return Err(GlobeliseError::bad_request(
+                    "Only debits can be matched to transfers",
+                ));
+            }
+            if database
+                .select_one_transfer_record_summary(record_ulid)
+                .await?
+                .is_none()
+            {
+                return Err(GlobeliseError::not_found("Cannot find the transfer record"));
+            }
+            if database.is_transfer_record_matched(record_ulid).await? {
+                return Err(GlobeliseError::bad_request(
+                    "This transfer is already matched to another line",
+                ));
+            }
+            database
+                .match_bank_statement_line(
+                    line_ulid,
+                    Some(record_ulid),
+                    None,
+                    note,
+                    Some(claims.payload.ulid),
+                )
+                .await?
+        }
+        (None, Some(invoice_ulid)) => {
+            if line.credit_debit != "CRDT" {
+                return Err(GlobeliseError::bad_request(
+                    "Only credits can be matched to invoices",
+                ));
+            }
+            let invoice = database
+                .select_invoices_by_reference(&[invoice_ulid], &[])
+                .await?
+                .pop()
+                .ok_or_else(|| GlobeliseError::not_found("Cannot find the invoice"))?;
+            if let Some(currency) = invoice.currency.as_deref().filter(|c| *c != line.currency) {
+                return Err(GlobeliseError::bad_request(format!(
+                    "The line is in {} but the invoice is billed in {}",
+                    line.currency, currency
+                )));
+            }
+            if database.is_invoice_matched(invoice_ulid).await? {
+                return Err(GlobeliseError::bad_request(
+                    "This invoice is already matched to another line",
+                ));
+            }
+            database
+                .match_bank_statement_line(
+                    line_ulid,
+                    None,
+                    Some(&MatchedInvoice {
+                        total: line.amount,
+                        ..invoice
+                    }),
+                    note,
+                    Some(claims.payload.ulid),
+                )
+                .await?
+        }
+        _ => {
+            return Err(GlobeliseError::bad_request(
+                "Give either a record-ulid or an invoice-ulid",
+            ))
+        }
+    };
+
+    if !matched {
+        return Err(GlobeliseError::bad_request(
+            "This line was matched in the meantime",
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IgnoreBankStatementLineRequest {
+    /// Why the line needs no match, e.g. bank fees.
+    pub note: String,
+}
+
+/// Takes a line that needs no match out of the queue.
+pub async fn ignore_bank_statement_line(
+    claims: Token<AdminAccessToken>,
+    Path(line_ulid): Path<Uuid>,
+    Json(request): Json<IgnoreBankStatementLineRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    if request.note.trim().is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "Give a reason for ignoring the line",
+        ));
+    }
+
+    let database = database.lock().await;
+    let line = database.select_one_bank_statement_line(line_ulid).await?;
+    if line.match_status != "unmatched" {
+        return Err(GlobeliseError::bad_request(format!(
+            "This line is already {}",
+            line.match_status
+        )));
+    }
+
+    database
+        .update_bank_statement_line_note(
+            line_ulid,
+            "ignored",
+            request.note,
+            Some(claims.payload.ulid),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// An invoice a credit pays.
+#[derive(Debug, FromRow)]
+pub struct MatchedInvoice {
+    pub ulid: Uuid,
+    pub invoice_id: i64,
+    /// The amount with tax, or what was paid when matched by hand.
+    pub total: Decimal,
+    /// Not known for some invoices created before invoices had a currency.
+    pub currency: Option<String>,
+}
+
+impl Database {
+    /// Stores a statement and its lines, unless it was imported before.
+    ///
+    /// Returns the ULIDs of the statement and of its lines, in order.
+    pub async fn insert_bank_statement(
+        &self,
+        statement: &ParsedStatement,
+        admin_ulid: Uuid,
+    ) -> GlobeliseResult<Option<(Uuid, Vec<Uuid>)>> {
+        let mut transaction = self.0.begin().await?;
+
+        let statement_ulid = Uuid::new_v4();
+        let inserted = sqlx::query(
+            "
+        INSERT INTO bank_statements (
+            ulid, format, statement_id, account, currency, opening_balance,
+            closing_balance, uploaded_by
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8
+        ) ON CONFLICT (account, statement_id) DO NOTHING",
+        )
+        .bind(statement_ulid)
+        .bind(statement.format)
+        .bind(&statement.statement_id)
+        .bind(&statement.account)
+        .bind(&statement.currency)
+        .bind(statement.opening_balance)
+        .bind(statement.closing_balance)
+        .bind(admin_ulid)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        if inserted == 0 {
+            return Ok(None);
+        }
+
+        let mut line_ulids = Vec::with_capacity(statement.lines.len());
+        for (line_number, line) in statement.lines.iter().enumerate() {
+            let line_ulid = Uuid::new_v4();
+            sqlx::query(
+                "
+            INSERT INTO bank_statement_lines (
+                ulid, statement_ulid, line_number, booking_date, value_date, credit_debit,
+                amount, currency, end_to_end_id, reference, remittance_information,
+                counterparty_name
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12
+            )",
+            )
+            .bind(line_ulid)
+            .bind(statement_ulid)
+            .bind(line_number as i32 + 1)
+            .bind(&line.booking_date)
+            .bind(&line.value_date)
+            .bind(line.credit_debit)
+            .bind(line.amount)
+            .bind(&line.currency)
+            .bind(&line.end_to_end_id)
+            .bind(&line.reference)
+            .bind(&line.remittance_information)
+            .bind(&line.counterparty_name)
+            .execute(&mut transaction)
+            .await?;
+            line_ulids.push(line_ulid);
+        }
+
+        transaction.commit().await?;
+
+        Ok(Some((statement_ulid, line_ulids)))
+    }
+
+    /// Transfer records whose ID starts with an end-to-end ID and that no line matches yet.
+    pub async fn select_unmatched_transfer_records(
+        &self,
+        end_to_end_id: &str,
+        amount: Decimal,
+        currency: &str,
+    ) -> GlobeliseResult<Vec<Uuid>> {
+        let result = sqlx::query_scalar(
+            "
+        SELECT
+            r.ulid
+        FROM
+            uploaded_citibank_transfer_initiation_files_records r
+        WHERE
+            UPPER(REPLACE(r.ulid::text, '-', '')) LIKE $1 || '%' AND
+            r.amount = $2 AND
+            r.currency_code = $3 AND
+            NOT EXISTS (
+                SELECT 1 FROM bank_statement_lines l WHERE l.matched_record_ulid = r.ulid
+            )
+        LIMIT 2",
+        )
+        .bind(end_to_end_id)
+        .bind(amount)
+        .bind(currency)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn is_transfer_record_matched(&self, record_ulid: Uuid) -> GlobeliseResult<bool> {
+        let result = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM bank_statement_lines WHERE matched_record_ulid = $1)",
+        )
+        .bind(record_ulid)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn is_invoice_matched(&self, invoice_ulid: Uuid) -> GlobeliseResult<bool> {
+        let result = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM bank_statement_lines WHERE matched_invoice_ulid = $1)",
+        )
+        .bind(invoice_ulid)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Invoices with one of the ULIDs or invoice IDs, with their totals including tax.
+    pub async fn select_invoices_by_reference(
+        &self,
+        invoice_ulids: &[Uuid],
+        invoice_ids: &[i64],
+    ) -> GlobeliseResult<Vec<MatchedInvoice>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            i.ulid, i.invoice_id,
+            COALESCE(idx.invoice_amount, 0) + i.invoice_tax_amount AS total, i.currency
+        FROM
+            invoice_individual i
+        LEFT JOIN
+            invoice_individual_index idx
+        ON
+            idx.ulid = i.ulid
+        WHERE
+            i.ulid = ANY($1) OR i.invoice_id = ANY($2)",
+        )
+        .bind(invoice_ulids)
+        .bind(invoice_ids)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Matches an unmatched line and books a matched invoice as paid.
+    ///
+    /// Returns false if the line is no longer unmatched.
+    pub async fn match_bank_statement_line(
+        &self,
+        line_ulid: Uuid,
+        record_ulid: Option<Uuid>,
+        invoice: Option<&MatchedInvoice>,
+        note: String,
+        admin_ulid: Option<Uuid>,
+    ) -> GlobeliseResult<bool> {
+        let mut transaction = self.0.begin().await?;
+
+        let matched = sqlx::query(
+            "
+        UPDATE bank_statement_lines SET
+            match_status = 'matched',
+            matched_record_ulid = $2,
+            matched_invoice_ulid = $3,
+            match_note = $4,
+            matched_by = $5,
+            matched_at = now()
+        WHERE
+            ulid = $1 AND match_status = 'unmatched'",
+        )
+        .bind(line_ulid)
+        .bind(record_ulid)
+        .bind(invoice.map(|invoice| invoice.ulid))
+        .bind(note)
+        .bind(admin_ulid)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if let (true, Some(invoice)) = (matched, invoice) {
+            sqlx::query(
+                "
+            UPDATE invoice_individual SET
+                invoice_amount_paid = invoice_amount_paid + $2
+            WHERE
+                ulid = $1",
+            )
+            .bind(invoice.ulid)
+            .bind(invoice.total)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(matched)
+    }
+
+    pub async fn update_bank_statement_line_note(
+        &self,
+        line_ulid: Uuid,
+        match_status: &str,
+        note: String,
+        admin_ulid: Option<Uuid>,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        UPDATE bank_statement_lines SET
+            match_status = $2,
+            match_note = $3,
+            matched_by = $4,
+            matched_at = CASE WHEN $2 = 'unmatched' THEN NULL ELSE now() END
+        WHERE
+            ulid = $1",
+        )
+        .bind(line_ulid)
+        .bind(match_status)
+        .bind(note)
+        .bind(admin_ulid)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn select_one_bank_statement_line(
+        &self,
+        line_ulid: Uuid,
+    ) -> GlobeliseResult<BankStatementLine> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            l.*, s.statement_id, s.account
+        FROM
+            bank_statement_lines l
+        JOIN
+            bank_statements s
+        ON
+            l.statement_ulid = s.ulid
+        WHERE
+            l.ulid = $1",
+        )
+        .bind(line_ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the bank statement line"))?;
+
+        Ok(result)
+    }
+
+    pub async fn select_unmatched_bank_statement_lines(
+        &self,
+        query: UnmatchedBankStatementLinesQuery,
+    ) -> GlobeliseResult<Vec<BankStatementLine>> {
+        let (limit, offset) = calc_limit_and_offset(query.per_page, query.page);
+
+        let result = sqlx::query_as(
+            "
+        SELECT
+            l.*, s.statement_id, s.account
+        FROM
+            bank_statement_lines l
+        JOIN
+            bank_statements s
+        ON
+            l.statement_ulid = s.ulid
+        WHERE
+            l.match_status = 'unmatched' AND
+            ($3 IS NULL OR l.credit_debit = $3)
+        ORDER BY
+            l.created_at, l.line_number
+        LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .bind(query.credit_debit)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    const STATEMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr>
+      <MsgId>STMT-20220906</MsgId>
+      <CreDtTm>2022-09-06T18:00:00</CreDtTm>
+    </GrpHdr>
+    <Stmt>
+      <Id>STMT-20220906-1</Id>
+      <Acct>
+        <Id>
+          <IBAN>NL91ABNA0417164300</IBAN>
+        </Id>
+        <Ccy>EUR</Ccy>
+      </Acct>
+      <Bal>
+        <Tp>
+          <CdOrPrtry>
+            <Cd>OPBD</Cd>
+          </CdOrPrtry>
+        </Tp>
+        <Amt Ccy="EUR">1000.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+      </Bal>
+      <Bal>
+        <Tp>
+          <CdOrPrtry>
+            <Cd>CLBD</Cd>
+          </CdOrPrtry>
+        </Tp>
+        <Amt Ccy="EUR">499.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+      </Bal>
+      <Ntry>
+        <Amt Ccy="EUR">250.50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <BookgDt>
+          <Dt>2022-09-06</Dt>
+        </BookgDt>
+        <ValDt>
+          <DtTm>2022-09-07T10:00:00</DtTm>
+        </ValDt>
+        <AcctSvcrRef>BANKREF1</AcctSvcrRef>
+        <NtryDtls>
+          <TxDtls>
+            <Refs>
+              <EndToEndId>0F8FAD5BD9CB469FA16570867728950E</EndToEndId>
+            </Refs>
+            <RltdPties>
+              <Cdtr>
+                <Nm>Anna Müller</Nm>
+              </Cdtr>
+            </RltdPties>
+            <RmtInf>
+              <Ustrd>Globelise Salary Payment</Ustrd>
+            </RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">300.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <BookgDt>
+          <Dt>2022-09-06</Dt>
+        </BookgDt>
+        <AcctSvcrRef>BANKREF2</AcctSvcrRef>
+        <NtryDtls>
+          <TxDtls>
+            <Refs>
+              <AcctSvcrRef>BANKREF2-1</AcctSvcrRef>
+              <EndToEndId>7C9E6679742540DE944BE07FC1F90AE7</EndToEndId>
+            </Refs>
+            <Amt Ccy="EUR">100.00</Amt>
+          </TxDtls>
+          <TxDtls>
+            <Refs>
+              <EndToEndId>NOTPROVIDED</EndToEndId>
+            </Refs>
+            <AmtDtls>
+              <TxAmt>
+                <Amt Ccy="EUR">200.00</Amt>
+              </TxAmt>
+            </AmtDtls>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">9.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <AcctSvcrRef>BANKREF3</AcctSvcrRef>
+        <AddtlNtryInf>Interest</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">40.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <BookgDt>
+          <Dt>2022-09-06</Dt>
+        </BookgDt>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties>
+              <Dbtr>
+                <Nm>Client B.V.</Nm>
+              </Dbtr>
+            </RltdPties>
+            <RmtInf>
+              <Strd>
+                <CdtrRefInf>
+                  <Ref>INV-7</Ref>
+                </CdtrRefInf>
+              </Strd>
+            </RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#;
+
+    #[test]
+    fn camt053_statements_are_read_with_their_balances() {
+        let statements = parse_bank_statements(STATEMENT).unwrap();
+        assert_eq!(statements.len(), 1);
+
+        let statement = &statements[0];
+        assert_eq!(statement.format, "camt.053");
+        assert_eq!(statement.statement_id, "STMT-20220906-1");
+        assert_eq!(statement.account, "NL91ABNA0417164300");
+        assert_eq!(statement.currency.as_deref(), Some("EUR"));
+        assert_eq!(statement.opening_balance, Some(dec("1000.00")));
+        assert_eq!(statement.closing_balance, Some(dec("499.00")));
+        // The batch booking is split into its two transfers.
+        assert_eq!(statement.lines.len(), 5);
+    }
+
+    #[test]
+    fn camt053_lines_keep_their_references() {
+        let statements = parse_bank_statements(STATEMENT).unwrap();
+        let lines = &statements[0].lines;
+
+        let salary = &lines[0];
+        assert_eq!(salary.booking_date.as_deref(), Some("2022-09-06"));
+        assert_eq!(salary.value_date.as_deref(), Some("2022-09-07"));
+        assert_eq!(salary.credit_debit, "DBIT");
+        assert_eq!(salary.amount, dec("250.50"));
+        assert_eq!(salary.currency, "EUR");
+        assert_eq!(
+            salary.end_to_end_id.as_deref(),
+            Some("0F8FAD5BD9CB469FA16570867728950E")
+        );
+        assert_eq!(salary.reference.as_deref(), Some("BANKREF1"));
+        assert_eq!(
+            salary.remittance_information.as_deref(),
+            Some("Globelise Salary Payment")
+        );
+        assert_eq!(salary.counterparty_name.as_deref(), Some("Anna Müller"));
+
+        // Transactions of a batch booking have their own amounts and references.
+        assert_eq!(lines[1].amount, dec("100.00"));
+        assert_eq!(lines[1].reference.as_deref(), Some("BANKREF2-1"));
+        assert_eq!(lines[2].amount, dec("200.00"));
+        assert_eq!(lines[2].end_to_end_id, None);
+        assert_eq!(lines[2].reference.as_deref(), Some("BANKREF2"));
+
+        let interest = &lines[3];
+        assert_eq!(interest.credit_debit, "CRDT");
+        assert_eq!(interest.amount, dec("9.50"));
+        assert_eq!(interest.booking_date, None);
+        assert_eq!(interest.reference.as_deref(), Some("BANKREF3"));
+        assert_eq!(interest.remittance_information.as_deref(), Some("Interest"));
+
+        let payment = &lines[4];
+        assert_eq!(payment.remittance_information.as_deref(), Some("INV-7"));
+        assert_eq!(payment.counterparty_name.as_deref(), Some("Client B.V."));
+    }
+
+    #[test]
+    fn malformed_camt053_statements_are_rejected() {
+        assert!(parse_bank_statements("<Document><BkToCstmrStmt>").is_err());
+        assert!(
+            parse_bank_statements("<Document><BkToCstmrStmt></BkToCstmrStmt></Document>").is_err()
+        );
+
+        // A batch booking without the amounts of its transactions cannot be split.
+        let batch_without_amounts = STATEMENT
+            .replace(r#"<Amt Ccy="EUR">100.00</Amt>"#, "")
+            .replace(
+                r#"<AmtDtls>
+              <TxAmt>
+                <Amt Ccy="EUR">200.00</Amt>
+              </TxAmt>
+            </AmtDtls>"#,
+                "",
+            );
+        assert!(parse_bank_statements(&batch_without_amounts).is_err());
+
+        let unknown_indicator =
+            STATEMENT.replacen("<CdtDbtInd>DBIT</CdtDbtInd>", "<CdtDbtInd>X</CdtDbtInd>", 1);
+        assert!(parse_bank_statements(&unknown_indicator).is_err());
+    }
+
+    #[test]
+    fn transfers_are_found_by_the_end_to_end_id_we_sent() {
+        let line = |end_to_end_id: &str| ParsedStatementLine {
+            end_to_end_id: Some(end_to_end_id.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            transfer_end_to_end_id(&line(" 0f8fad5bd9cb469f ")).as_deref(),
+            Some("0F8FAD5BD9CB469F")
+        );
+        assert_eq!(
+            transfer_end_to_end_id(&line("0F8FAD5BD9CB469FA16570867728950E")).as_deref(),
+            Some("0F8FAD5BD9CB469FA16570867728950E")
+        );
+        assert_eq!(transfer_end_to_end_id(&line("0F8FAD5BD9CB")), None);
+        assert_eq!(transfer_end_to_end_id(&line("PAYROLL-SEPTEMBER-1")), None);
+        assert_eq!(
+            transfer_end_to_end_id(&ParsedStatementLine::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn invoices_are_found_by_their_references() {
+        let invoice_ulid = Uuid::new_v4();
+        let line = ParsedStatementLine {
+            reference: Some(format!("{}", invoice_ulid)),
+            remittance_information: Some("Payment inv-12, INV#13; INV 14/invoice".to_string()),
+            ..Default::default()
+        };
+
+        let (invoice_ulids, invoice_ids) = invoice_references(&line);
+        assert_eq!(invoice_ulids, [invoice_ulid]);
+        assert_eq!(invoice_ids, [12, 13]);
+    }
+
+    async fn connect() -> Database {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+
+        Database(pool)
+    }
+
+    /// Stores a statement with the lines and matches them one by one.
+    async fn import(
+        database: &Database,
+        admin_ulid: Uuid,
+        lines: Vec<ParsedStatementLine>,
+    ) -> Vec<(bool, BankStatementLine)> {
+        let statement = ParsedStatement {
+            format: "camt.053",
+            statement_id: Uuid::new_v4().to_string(),
+            account: "NL91ABNA0417164300".to_string(),
+            lines,
+            ..Default::default()
+        };
+        let (_, line_ulids) = database
+            .insert_bank_statement(&statement, admin_ulid)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut result = vec![];
+        for (line_ulid, line) in line_ulids.into_iter().zip(&statement.lines) {
+            let matched = auto_match_line(database, line_ulid, line).await.unwrap();
+            result.push((
+                matched,
+                database
+                    .select_one_bank_statement_line(line_ulid)
+                    .await
+                    .unwrap(),
+            ));
+        }
+        result
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn lines_are_matched_to_transfers_and_invoices_once() {
+        let database = connect().await;
+        let pool = &database.0;
+
+        let admin_ulid = Uuid::new_v4();
+        sqlx::query("INSERT INTO admin_users (ulid, email) VALUES ($1, $2)")
+            .bind(admin_ulid)
+            .bind(format!("{}@admin.example", admin_ulid))
+            .execute(pool)
+            .await
+            .unwrap();
+        let client_ulid = Uuid::new_v4();
+        let contractor_ulid = Uuid::new_v4();
+        for (ulid, column) in [
+            (client_ulid, "is_client"),
+            (contractor_ulid, "is_contractor"),
+        ] {
+            sqlx::query(&format!(
+                "INSERT INTO users (ulid, email, {}) VALUES ($1, $2, 't')",
+                column
+            ))
+            .bind(ulid)
+            .bind(format!("{}@user.example", ulid))
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        let file_ulid = Uuid::new_v4();
+        let record_ulid = Uuid::new_v4();
+        sqlx::query(
+            "
+        INSERT INTO uploaded_citibank_transfer_initiation_files (
+            ulid, title_identifier, status, client_ulid
+        ) VALUES (
+            $1, $2, 'sent', $3
+        )",
+        )
+        .bind(file_ulid)
+        .bind(format!("Payroll {}", file_ulid))
+        .bind(client_ulid)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "
+        INSERT INTO uploaded_citibank_transfer_initiation_files_records (
+            ulid, currency_code, country_code, employee_id, employee_name,
+            bank_name, bank_account_number, bank_code, bank_branch_code, swift_code,
+            amount, file_ulid, transaction_status
+        ) VALUES (
+            $1, 'SGD', 'SG', $2, 'Tan Wei Ming',
+            'DBS Bank', '0123456789', '7171', '081', 'DBSSSGSG',
+            1234.50, $3, 'acpt'
+        )",
+        )
+        .bind(record_ulid)
+        .bind(contractor_ulid)
+        .bind(file_ulid)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let invoice_group_ulid = Uuid::new_v4();
+        let invoice_ulid = Uuid::new_v4();
+        // Large enough not to clash with the invoices of other tests.
+        let invoice_id = (Uuid::new_v4().as_u128() >> 66) as i64;
+        sqlx::query(
+            "
+        INSERT INTO invoice_group (
+            ulid, invoice_name, invoice_status, invoice_due, invoice_date
+        ) VALUES (
+            $1, 'September', 'sent', now(), now()
+        )",
+        )
+        .bind(invoice_group_ulid)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "
+        INSERT INTO invoice_individual (
+            ulid, invoice_group_ulid, contractor_ulid, client_ulid, invoice_id,
+            invoice_tax_amount, invoice_amount_paid, terms_and_instructions, bill_to_name,
+            bill_to_address, currency
+        ) VALUES (
+            $1, $2, $3, $4, $5, 50, 0, '', 'Client', 'Singapore', 'SGD'
+        )",
+        )
+        .bind(invoice_ulid)
+        .bind(invoice_group_ulid)
+        .bind(contractor_ulid)
+        .bind(client_ulid)
+        .bind(invoice_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "
+        INSERT INTO invoice_items (
+            ulid, invoice_individual_ulid, item_name, item_unit_price, item_unit_quantity
+        ) VALUES (
+            $1, $2, 'Services', 250, 2
+        )",
+        )
+        .bind(Uuid::new_v4())
+        .bind(invoice_ulid)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let end_to_end_id = record_ulid.to_simple().to_string().to_uppercase();
+        let debit = |amount: &str| ParsedStatementLine {
+            credit_debit: "DBIT",
+            amount: dec(amount),
+            currency: "SGD".to_string(),
+            end_to_end_id: Some(end_to_end_id[..16].to_string()),
+            ..Default::default()
+        };
+        let credit = |amount: &str, currency: &str| ParsedStatementLine {
+            credit_debit: "CRDT",
+            amount: dec(amount),
+            currency: currency.to_string(),
+            remittance_information: Some(format!("Payment of INV-{}", invoice_id)),
+            ..Default::default()
+        };
+
+        let lines = import(
+            &database,
+            admin_ulid,
+            vec![
+                debit("1234.00"),
+                credit("550.00", "USD"),
+                debit("1234.50"),
+                credit("550.00", "SGD"),
+            ],
+        )
+        .await;
+
+        // Neither a different amount nor a different currency is a match.
+        assert!(!lines[0].0);
+        assert_eq!(lines[0].1.match_status, "unmatched");
+        assert!(!lines[1].0);
+        assert_eq!(
+            lines[1].1.match_note,
+            Some(format!(
+                "The reference names invoice {} but it is not billed in USD",
+                invoice_id
+            ))
+        );
+        assert!(lines[2].0);
+        assert_eq!(lines[2].1.matched_record_ulid, Some(record_ulid));
+        assert!(lines[3].0);
+        assert_eq!(lines[3].1.matched_invoice_ulid, Some(invoice_ulid));
+
+        let amount_paid: Decimal = sqlx::query_scalar(
+            "SELECT invoice_amount_paid FROM invoice_individual WHERE ulid = $1",
+        )
+        .bind(invoice_ulid)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        assert_eq!(amount_paid, dec("550"));
+
+        // The same transfer and invoice in another statement are not matched again.
+        let lines = import(
+            &database,
+            admin_ulid,
+            vec![debit("1234.50"), credit("550.00", "SGD")],
+        )
+        .await;
+        assert!(!lines[0].0);
+        assert_eq!(
+            lines[0].1.match_note.as_deref(),
+            Some("No unmatched transfer has this end-to-end ID and amount")
+        );
+        assert!(!lines[1].0);
+        assert_eq!(
+            lines[1].1.match_note,
+            Some(format!(
+                "The reference names invoice {} but it is already matched to another line",
+                invoice_id
+            ))
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Deserialize;
+use serde::Serialize;
+use serde_with::serde_as;
+
+/// A `camt.053` bank to customer statement.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Camt053File {
+    #[serde(rename = "BkToCstmrStmt")]
+    pub bk_to_cstmr_stmt: BkToCstmrStmt,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BkToCstmrStmt {
+    #[serde(rename = "Stmt", default)]
+    pub stmt: Vec<Stmt>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Stmt {
+    #[serde(rename = "Id")]
+    pub id: String,
+    #[serde(rename = "Acct")]
+    pub acct: Acct,
+    #[serde(rename = "Bal", default)]
+    pub bal: Vec<Bal>,
+    #[serde(rename = "Ntry", default)]
+    pub ntry: Vec<Ntry>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Acct {
+    #[serde(rename = "Id")]
+    pub id: AcctId,
+    #[serde(rename = "Ccy")]
+    pub ccy: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AcctId {
+    #[serde(rename = "IBAN")]
+    pub iban: Option<String>,
+    #[serde(rename = "Othr")]
+    pub othr: Option<Othr>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Othr {
+    #[serde(rename = "Id")]
+    pub id: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bal {
+    #[serde(rename = "Tp")]
+    pub tp: BalTp,
+    #[serde(rename = "Amt")]
+    pub amt: Amt,
+    #[serde(rename = "CdtDbtInd")]
+    pub cdt_dbt_ind: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BalTp {
+    #[serde(rename = "CdOrPrtry")]
+    pub cd_or_prtry: CdOrPrtry,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CdOrPrtry {
+    /// OPBD for the opening and CLBD for the closing balance
+    #[serde(rename = "Cd")]
+    pub cd: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Amt {
+    #[serde(rename = "Ccy")]
+    pub ccy: String,
+    #[serde(rename = "$value")]
+    pub value: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ntry {
+    #[serde(rename = "Amt")]
+    pub amt: Amt,
+    /// CRDT or DBIT
+    #[serde(rename = "CdtDbtInd")]
+    pub cdt_dbt_ind: String,
+    #[serde(rename = "BookgDt")]
+    pub bookg_dt: Option<DateAndDateTime>,
+    #[serde(rename = "ValDt")]
+    pub val_dt: Option<DateAndDateTime>,
+    #[serde(rename = "AcctSvcrRef")]
+    pub acct_svcr_ref: Option<String>,
+    #[serde(rename = "NtryDtls", default)]
+    pub ntry_dtls: Vec<NtryDtls>,
+    #[serde(rename = "AddtlNtryInf")]
+    pub addtl_ntry_inf: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DateAndDateTime {
+    #[serde(rename = "Dt")]
+    pub dt: Option<String>,
+    #[serde(rename = "DtTm")]
+    pub dt_tm: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NtryDtls {
+    #[serde(rename = "TxDtls", default)]
+    pub tx_dtls: Vec<TxDtls>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxDtls {
+    #[serde(rename = "Refs")]
+    pub refs: Option<Refs>,
+    /// Amount of this transaction in a batch booking, in camt.053.001.04 and later.
+    #[serde(rename = "Amt")]
+    pub amt: Option<Amt>,
+    #[serde(rename = "AmtDtls")]
+    pub amt_dtls: Option<AmtDtls>,
+    #[serde(rename = "RltdPties")]
+    pub rltd_pties: Option<RltdPties>,
+    #[serde(rename = "RmtInf")]
+    pub rmt_inf: Option<RmtInf>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Refs {
+    #[serde(rename = "AcctSvcrRef")]
+    pub acct_svcr_ref: Option<String>,
+    #[serde(rename = "EndToEndId")]
+    pub end_to_end_id: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AmtDtls {
+    #[serde(rename = "TxAmt")]
+    pub tx_amt: Option<TxAmt>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxAmt {
+    #[serde(rename = "Amt")]
+    pub amt: Amt,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RltdPties {
+    #[serde(rename = "Dbtr")]
+    pub dbtr: Option<NamedParty>,
+    #[serde(rename = "Cdtr")]
+    pub cdtr: Option<NamedParty>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NamedParty {
+    #[serde(rename = "Nm")]
+    pub nm: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RmtInf {
+    #[serde(rename = "Ustrd", default)]
+    pub ustrd: Vec<String>,
+    #[serde(rename = "Strd", default)]
+    pub strd: Vec<Strd>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Strd {
+    #[serde(rename = "CdtrRefInf")]
+    pub cdtr_ref_inf: Option<CdtrRefInf>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CdtrRefInf {
+    #[serde(rename = "Ref")]
+    pub r#ref: Option<String>,
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+use serde::Serialize;
+use serde_with::serde_as;
+
+/// A `camt.054.001.02` bank to customer debit/credit notification.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Camt054File {
+    #[serde(rename = "BkToCstmrDbtCdtNtfctn")]
+    pub bk_to_cstmr_dbt_cdt_ntfctn: BkToCstmrDbtCdtNtfctn,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BkToCstmrDbtCdtNtfctn {
+    #[serde(rename = "Ntfctn", default)]
+    pub ntfctn: Vec<Ntfctn>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ntfctn {
+    #[serde(rename = "Ntry", default)]
+    pub ntry: Vec<Ntry>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ntry {
+    /// CRDT or DBIT
+    #[serde(rename = "CdtDbtInd")]
+    pub cdt_dbt_ind: String,
+    /// BOOK, PDNG or INFO
+    #[serde(rename = "Sts")]
+    pub sts: String,
+    #[serde(rename = "NtryDtls", default)]
+    pub ntry_dtls: Vec<NtryDtls>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NtryDtls {
+    #[serde(rename = "TxDtls", default)]
+    pub tx_dtls: Vec<TxDtls>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxDtls {
+    #[serde(rename = "Refs")]
+    pub refs: Option<Refs>,
+    #[serde(rename = "RtrInf")]
+    pub rtr_inf: Option<RtrInf>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Refs {
+    #[serde(rename = "EndToEndId")]
+    pub end_to_end_id: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RtrInf {
+    #[serde(rename = "Rsn")]
+    pub rsn: Option<Rsn>,
+    #[serde(rename = "AddtlInf", default)]
+    pub addtl_inf: Vec<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rsn {
+    #[serde(rename = "Cd")]
+    pub cd: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_are_parsed() {
+        let file: Camt054File =
+            serde_xml_rs::from_str(include_str!("testdata/camt054.xml")).unwrap();
+
+        let entries = &file.bk_to_cstmr_dbt_cdt_ntfctn.ntfctn[0].ntry;
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].cdt_dbt_ind, "DBIT");
+        assert_eq!(entries[0].sts, "BOOK");
+        assert_eq!(
+            entries[0].ntry_dtls[0].tx_dtls[0]
+                .refs
+                .as_ref()
+                .and_then(|refs| refs.end_to_end_id.as_deref()),
+            Some("0F8FAD5BD9CB469FA16570867728950E")
+        );
+        assert!(entries[0].ntry_dtls[0].tx_dtls[0].rtr_inf.is_none());
+
+        let return_information = entries[1].ntry_dtls[0].tx_dtls[0].rtr_inf.as_ref().unwrap();
+        assert_eq!(
+            return_information
+                .rsn
+                .as_ref()
+                .and_then(|reason| reason.cd.as_deref()),
+            Some("AC04")
+        );
+        assert_eq!(return_information.addtl_inf, ["Account closed"]);
+        assert_eq!(entries[2].sts, "PDNG");
+    }
+}
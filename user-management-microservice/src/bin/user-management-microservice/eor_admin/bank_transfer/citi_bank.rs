@@ -1,13 +1,17 @@
 use axum::extract::ContentLengthLimit;
 use axum::extract::{Extension, Json, Query};
-use calamine::{open_workbook, Reader, Xlsx};
+use calamine::{DataType, Reader, Xlsx};
 use chrono;
-use common_utils::custom_serde::OffsetDateWrapper;
+use common_utils::custom_serde::{OffsetDateWrapper, OptionOffsetDateWrapper};
 use common_utils::token::Token;
 use common_utils::{
+    bank_details::BankDetails,
     calc_limit_and_offset,
     custom_serde::{UserType, FORM_DATA_LENGTH_LIMIT},
+    database::{job::Job, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
+    jobs::{self, JobContext, JobOutput},
+    money::Money,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use reqwest::header::HeaderMap;
@@ -16,27 +20,19 @@ use serde::{Deserialize, Serialize};
 use serde_with::TryFromInto;
 use serde_with::{base64::Base64, serde_as};
 use sqlx::FromRow;
-use ssh2::Session;
-use std::process::Command;
-use std::{
-    fmt::Write as FmtWrite,
-    fs::{self},
-    io::{prelude::*, Write},
-    net::TcpStream,
-    path::Path,
-    str,
-    sync::Arc,
-};
-use substring::Substring;
-use tokio::sync::Mutex;
+use std::io::Cursor;
 use umya_spreadsheet::*;
 use uuid::Uuid;
 
 use crate::database::{Database, SharedDatabase};
 
-use super::citibank_ack_file::CitiBankACKFile;
-use super::citibank_acpt_file::CitiBankACPTFile;
-use super::citibank_rjct_file::CitiBankRJCTFile;
+use super::citibank_status::enqueue_poll;
+use super::pain001::{
+    AccountIdentification, Amount, ChargeBearer, CodeOrProprietary, CreditTransferTransaction,
+    Document, FinancialInstitution, GroupHeader, Max140Text, Max34Text, Max35Text, Max70Text,
+    Party, PaymentInformation, PaymentTypeInformation, PostalAddress,
+};
+use super::transfer_approval::{ensure_transfer_records_editable, withdraw_transfer_approval};
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,6 +45,9 @@ pub struct DownloadCitibankTransferInitiationTemplateRequest {
     pub currency: String,
 }
 
+pub const UPLOAD_CITIBANK_TRANSFER_INITIATION_FILE_JOB: &str =
+    "upload-citibank-transfer-initiation-file";
+
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -57,6 +56,8 @@ pub struct UploadCitiBankTransferInitiationFiles {
     pub uploaded_file: Vec<u8>,
     pub title_identifier: String,
     pub client_ulid: Uuid,
+    /// The branch the transfers are for, which decides how they are sent to the bank.
+    pub branch_ulid: Option<Uuid>,
     pub debug: Option<bool>,
 }
 
@@ -124,14 +125,6 @@ pub struct GetContractorAccountDetailsCitibankTemplateResponse {
     pub branch_ulid: Uuid, //client's branch ulid this contractor belongs too
 }
 
-#[serde_as]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-pub struct InitCitibankTransferRequest {
-    pub file_ulid: Uuid,
-    pub template_name: String, //eg. template.xml
-}
-
 #[serde_as]
 #[derive(Debug, Serialize, FromRow, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
@@ -143,6 +136,12 @@ pub struct ListCitiBankTransferInitiationFilesResponse {
     #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
     pub created_at: sqlx::types::time::OffsetDateTime,
     pub entries: i64,
+    pub submitted_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub submitted_at: Option<sqlx::types::time::OffsetDateTime>,
+    pub approved_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub approved_at: Option<sqlx::types::time::OffsetDateTime>,
 }
 
 #[serde_as]
@@ -155,6 +154,7 @@ pub struct ListCitiBankTransferInitiationFilesResponseNoEntries {
     pub status: String,
     #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
     pub created_at: sqlx::types::time::OffsetDateTime,
+    pub branch_ulid: Option<Uuid>,
 }
 
 #[serde_as]
@@ -164,6 +164,7 @@ pub struct ListCitiBankTransferInitiationFilesRequest {
     pub ulid: Uuid,
     pub title_identifier: String,
     pub client_ulid: Uuid,
+    pub branch_ulid: Option<Uuid>,
     pub status: String,
 }
 
@@ -181,29 +182,95 @@ pub struct CitiBankPayRollRecord {
     pub bank_code: String,
     pub bank_branch_code: String,
     pub swift_code: String,
-    pub amount: f64,
+    pub amount: sqlx::types::Decimal,
     pub file_ulid: Uuid,
     pub transaction_status: String,
     pub transaction_status_description: Option<String>,
 }
 
+/// The fields of a record an admin can edit. The transaction status only ever comes from
+/// the bank.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateCitiBankPayRollRecordRequest {
+    pub ulid: Uuid,
+    pub currency_code: String,
+    pub country_code: String,
+    pub employee_id: Uuid,
+    pub employee_name: String,
+    pub bank_name: String,
+    pub bank_account_number: String,
+    pub bank_code: String,
+    pub bank_branch_code: String,
+    pub swift_code: String,
+    pub amount: sqlx::types::Decimal,
+    pub file_ulid: Uuid,
+}
+
+impl UpdateCitiBankPayRollRecordRequest {
+    /// Checks the record like a row of an uploaded spreadsheet.
+    fn into_record(self) -> GlobeliseResult<CitiBankPayRollRecord> {
+        let record = CitiBankPayRollRecord {
+            ulid: self.ulid,
+            currency_code: self.currency_code.trim().to_string(),
+            country_code: self.country_code.trim().to_string(),
+            employee_id: self.employee_id,
+            employee_name: self.employee_name.trim().to_string(),
+            bank_name: self.bank_name.trim().to_string(),
+            bank_account_number: self.bank_account_number.trim().to_string(),
+            bank_code: self.bank_code.trim().to_string(),
+            bank_branch_code: self.bank_branch_code.trim().to_string(),
+            swift_code: self.swift_code.trim().to_string(),
+            amount: self.amount,
+            file_ulid: self.file_ulid,
+            transaction_status: "pending".to_string(),
+            transaction_status_description: Some("pending".to_string()),
+        };
+        let money = record.money()?;
+        if !money.is_positive() {
+            return Err(GlobeliseError::bad_request("amount must be positive"));
+        }
+        record.bank_details().validate()?;
+
+        Ok(CitiBankPayRollRecord {
+            amount: money.amount(),
+            ..record
+        })
+    }
+}
+
+impl CitiBankPayRollRecord {
+    pub fn bank_details(&self) -> BankDetails<'_> {
+        BankDetails {
+            country: self.country_code.parse().ok(),
+            bank_account_number: &self.bank_account_number,
+            bank_code: Some(&self.bank_code),
+            branch_code: Some(&self.bank_branch_code),
+            swift_code: Some(&self.swift_code),
+            ..Default::default()
+        }
+    }
+
+    /// The amount to transfer, checked against the precision of its currency.
+    pub fn money(&self) -> GlobeliseResult<Money> {
+        let currency = self
+            .currency_code
+            .parse()
+            .map_err(|_| GlobeliseError::bad_request("currency-code is not a known currency"))?;
+        Money::new(self.amount, currency)
+    }
+}
+
 // ================ SUMMARY =========================
-//encrypt(raw) -> enc;
-//decrypt(enc) -> raw;
-//sftp_upload(local_file, remote_file);
-//sftp_download(local_file, remote_file);
-//sftp_list_remote_files(sftp_root_dir) -> Vec<String>
-//sftp_delete_remote_file(remote_file)
-//generate_xml(template.xml, local_file, record);
+//CitiBankRail -> encrypts and sends files over SFTP, see citibank_rail.rs
+//CitiBankTemplate::document(transaction_file, records) -> pain.001 Document
 //list_available_templates() -> Vec<String>
 //download_citibank_transfer_initiation_template() -> FILE.xlxs
-//upload_citibank_transfer_initiation_template(UploadCitiBankTransferInitiationFiles.xlxs)
-//parse_ack(local_file.xml) -> CitiBankACKFile
-//parse_acpt(local_file.xml) -> CitiBankACPTFile
-//parse_rjct(local_file.xml) -> CitiBankRJCTFile
+//upload_citibank_transfer_initiation_template(UploadCitiBankTransferInitiationFiles.xlxs) -> Job importing the file
 
-//init_citibank_transfer(file_ulid) -> pushes transction.xml file to citibank ftp folder
-//update_transaction_status() -> checks transaction status and updates the records
+//submit, review and approve a file -> see transfer_approval.rs
+//init_transfer(file_ulid) -> sends an approved file through the branch's payment rail, see payment_rail.rs
+//update_transaction_status() -> Job applying new status files, see citibank_status.rs
 
 pub async fn search_clients(
     _: Token<AdminAccessToken>,
@@ -229,394 +296,281 @@ pub async fn search_clients_branches(
     Ok(Json(result))
 }
 
-/* download remote files*/
+/* queue a download and apply of new status files*/
 pub async fn update_transaction_status(
-    _: Token<AdminAccessToken>,
-    Extension(database): Extension<SharedDatabase>,
-) -> GlobeliseResult<()> {
-    let sftp_root_dir = std::env::var("CITIBANK_SFTP_ROOT_DIR").expect("failed to get root dir");
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base_path not set");
-    let remote_files = sftp_list_remote_files(sftp_root_dir).await?;
-    // let guid = Uuid::new_v4().to_simple().to_string();
-    let sftp_drop_dir = std::env::var("CITIBANK_SFTP_DROP_DIR").expect("sftp_drop_dir_not set");
-    for file in remote_files {
-        //skip this directory
-        if file.replace('/', "") == sftp_drop_dir.replace('/', "") {
-            continue;
-        }
-        let file_name = file.replace("/GRONEXTPYRSFTP/", "");
-        let local_file = format!(
-            "{}citibank_temp_transaction_files/{}.xml",
-            &base_path, &file_name
-        );
-        sftp_download(local_file.to_string(), file.to_string()).await;
-        //sftp_delete_remote_file(file.to_string()).await?;
-    }
-
-    _update_transaction_status(database).await?;
-
-    Ok(())
-}
+    claims: Token<AdminAccessToken>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let job = enqueue_poll(&common_database, Some(claims.payload.ulid)).await?;
 
-/* update transaction status*/
-pub async fn _update_transaction_status(db: Arc<Mutex<Database>>) -> GlobeliseResult<()> {
-    let database = db.lock().await;
-
-    let root_dir = std::env::var("CITIBANK_BASE_PATH").expect("base_path not set");
-    let templates_folder = format!("{}citibank_temp_transaction_files/", &root_dir);
-    let files = std::fs::read_dir(Path::new(&templates_folder))?;
-    for file in files {
-        let file_name = file?.file_name().to_str().expect("").to_string();
-        let file_path = format!("{}{}", &templates_folder, &file_name);
-
-        let enc_data = std::fs::read_to_string(Path::new(&file_path)).expect("failed to read file");
-        let raw_data = decrypt(enc_data).await?;
-
-        let mut record_ulid: Uuid;
-        let mut transaction_status: String;
-        let mut transaction_status_description: String;
-
-        if file_name.contains("ACK") {
-            let citibank_response = parse_ack(raw_data).await?;
-            let file_ulid = Uuid::parse_str(
-                &citibank_response
-                    .cstmr_pmt_sts_rpt
-                    .orgnl_grp_inf_and_sts
-                    .orgnl_msg_id,
-            )
-            .unwrap();
-            transaction_status = "ack".to_string();
-            database
-                .update_status_uploaded_citibank_transfer_initiation_file(
-                    file_ulid,
-                    transaction_status,
-                )
-                .await?;
-        } else if file_name.contains("ACPT") {
-            let citibank_response = parse_acpt(raw_data).await?;
-
-            for record in citibank_response.cstmr_pmt_sts_rpt.orgnl_pmt_inf_and_sts {
-                record_ulid = Uuid::parse_str(&record.orgnl_pmt_inf_id).unwrap();
-                transaction_status = "acpt".to_string();
-                transaction_status_description = "transaction accepted".to_string();
-                database.update_uploaded_citibank_transfer_initiation_file_record_by_cititbank_transaction_response(record_ulid, transaction_status, transaction_status_description).await?;
-            }
-        } else if file_name.contains("RJCT") {
-            let citibank_response = parse_rjct(raw_data).await?;
-
-            for record in citibank_response.cstmr_pmt_sts_rpt.orgnl_pmt_inf_and_sts {
-                record_ulid = Uuid::parse_str(&record.orgnl_pmt_inf_id).unwrap();
-                transaction_status = "rcjt".to_string();
-                let mut reject_reason: String = "".to_string();
-
-                for reason in record.tx_inf_and_sts.sts_rsn_inf {
-                    for additional_info in reason.addtl_inf {
-                        write!(reject_reason, "{}, ", &additional_info)?;
-                    }
-                }
-
-                transaction_status_description = reject_reason;
-
-                database.update_uploaded_citibank_transfer_initiation_file_record_by_cititbank_transaction_response(record_ulid, transaction_status, transaction_status_description).await?;
-            }
-        }
-
-        fs::remove_file(Path::new(&file_path)).expect("error removing file");
-    }
-
-    Ok(())
+    Ok(Json(job))
 }
 
-//parse xml string to CitiBankACKFile object
-async fn parse_ack(src: String) -> GlobeliseResult<CitiBankACKFile> {
-    let item = serde_xml_rs::from_str(&src).unwrap();
-
-    Ok(item)
-}
-
-//parse xml string to CitiBankACPTFile object
-async fn parse_acpt(src: String) -> GlobeliseResult<CitiBankACPTFile> {
-    let item = serde_xml_rs::from_str(&src).unwrap();
+pub async fn list_available_templates(
+    _: Token<AdminAccessToken>,
+) -> GlobeliseResult<Json<Vec<String>>> {
+    let available_templates = CitiBankTemplate::ALL
+        .iter()
+        .map(|template| template.name().to_string())
+        .collect();
 
-    Ok(item)
+    Ok(Json(available_templates))
 }
 
-//parse xml string to CitiBankRJCTFile object
-async fn parse_rjct(src: String) -> GlobeliseResult<CitiBankRJCTFile> {
-    let item = serde_xml_rs::from_str(&src).unwrap();
-
-    Ok(item)
+/// The Citibank debtor accounts that payroll files can be sent from.
+#[derive(Debug, Clone, Copy)]
+pub enum CitiBankTemplate {
+    SingaporeSgd,
+    VietnamVnd,
+    VietnamUsd,
 }
 
-async fn encrypt(raw_data: String) -> GlobeliseResult<String> {
-    // encrypt using openpgp cli
-    //source: https://www.openpgp.org/
-    //source: https://www.gpg4win.org/
-    //source: https://www.gnupg.org/gph/en/manual/x56.html
+impl CitiBankTemplate {
+    pub const ALL: [CitiBankTemplate; 3] = [
+        CitiBankTemplate::SingaporeSgd,
+        CitiBankTemplate::VietnamVnd,
+        CitiBankTemplate::VietnamUsd,
+    ];
+
+    /// Citibank only keeps the first 16 characters of the end-to-end ID.
+    const END_TO_END_ID_LENGTH: usize = 16;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CitiBankTemplate::SingaporeSgd => "citi_bank_sg.xml",
+            CitiBankTemplate::VietnamVnd => "citi_bank_vn.xml",
+            CitiBankTemplate::VietnamUsd => "citi_bank_usd_vn.xml",
+        }
+    }
 
-    //generate guid for unique file name
-    let guid = Uuid::new_v4().to_simple().to_string();
-    //
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base path not set");
-    //
-    let path_raw_data = format!("{}citibank_temp/{}.txt", &base_path, &guid);
-    let path_enc_data = format!("{}citibank_temp/{}.txt.asc", &base_path, &guid);
-    //write plain text file
-    fs::write(Path::new(&path_raw_data), raw_data.as_bytes())?;
-    //
-    let enc_key = std::env::var("CITIBANK_SFTP_ENCRYPTION_KEY")?;
-    let sign_key = std::env::var("CITIBANK_SFTP_SIGN_KEY")?;
-
-    //gpg cli encrypt raw data
-    let mut cmd = Command::new("gpg")
-        .arg("-e")
-        .arg("-a")
-        .arg("-s")
-        .arg("--default-key")
-        .arg(sign_key)
-        .arg(format!("-r {}", enc_key))
-        .arg("--openpgp")
-        .arg(Path::new(&path_raw_data))
-        .spawn()
-        .expect("failed to encrypt file");
-    cmd.wait()?;
-    //
-    let encrypted_data = fs::read_to_string(Path::new(&path_enc_data))?;
-    //delete these files
-    fs::remove_file(Path::new(&path_raw_data))?;
-    fs::remove_file(Path::new(&path_enc_data))?;
-
-    // Ok(raw_data)
-    Ok(encrypted_data)
-}
+    pub fn from_name(name: &str) -> GlobeliseResult<Self> {
+        CitiBankTemplate::ALL
+            .into_iter()
+            .find(|template| template.name() == name)
+            .ok_or_else(|| GlobeliseError::bad_request(format!("Unknown template '{}'", name)))
+    }
 
-async fn decrypt(enc_data: String) -> GlobeliseResult<String> {
-    // decrypt using openpgp cli
-    //source: https://www.openpgp.org/
-    //source: https://www.gpg4win.org/
-    //source: https://www.gnupg.org/gph/en/manual/x56.html
+    pub fn currency(&self) -> &'static str {
+        match self {
+            CitiBankTemplate::SingaporeSgd => "SGD",
+            CitiBankTemplate::VietnamVnd => "VND",
+            CitiBankTemplate::VietnamUsd => "USD",
+        }
+    }
 
-    //generate guid for unique file name
-    let guid_raw = Uuid::new_v4().to_simple().to_string();
-    let guid_enc = Uuid::new_v4().to_simple().to_string();
-    //
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base path not set");
-    //
-    let path_raw_data = format!("{}citibank_temp/{}.txt", &base_path, &guid_raw);
-    let path_enc_data = format!("{}citibank_temp/{}.txt", &base_path, &guid_enc);
-    //write enc text file
-    fs::write(Path::new(&path_enc_data), enc_data.as_bytes())?;
-
-    //gpg cli decrypt enc data
-    let mut cmd = Command::new("gpg")
-        .arg("-d")
-        .arg("-o")
-        .arg(Path::new(&path_raw_data))
-        .arg(Path::new(&path_enc_data))
-        .spawn()
-        .expect("failed to decrypt file");
-    cmd.wait()?;
-    //
-    let decrypted_data = fs::read_to_string(Path::new(&path_raw_data))?;
-    //delete these files
-    fs::remove_file(Path::new(&path_raw_data))?;
-    fs::remove_file(Path::new(&path_enc_data))?;
-
-    Ok(decrypted_data)
-}
+    pub fn country(&self) -> &'static str {
+        match self {
+            CitiBankTemplate::SingaporeSgd => "SG",
+            CitiBankTemplate::VietnamVnd | CitiBankTemplate::VietnamUsd => "VN",
+        }
+    }
 
-async fn sftp_upload(local_file: String, remote_file: String) -> GlobeliseResult<()> {
-    // Connect to the SSH server
-    let sftp_username = std::env::var("CITIBANK_SFTP_USERNAME")?;
-    let sftp_password = std::env::var("CITIBANK_SFTP_PASSWORD")?;
-    let sftp_host = std::env::var("CITIBANK_SFTP_HOST")?;
-    let sftp_port = std::env::var("CITIBANK_SFTP_PORT")?;
-    let tcp = TcpStream::connect(format!("{}:{}", &sftp_host, &sftp_port))?;
-    //
-    let data = std::fs::read_to_string(Path::new(&local_file)).expect("failed to read data");
-    //
-    let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
-    sess.userauth_password(&sftp_username, &sftp_password)?;
-    //
-    let sftp_client = sess.sftp()?;
-    let mut sftp_file = sftp_client.create(Path::new(&remote_file))?;
-    sftp_file.write_all(data.as_bytes())?;
+    /// Checks that a record can be paid with this template.
+    pub fn validate_record(&self, record: &CitiBankPayRollRecord) -> GlobeliseResult<()> {
+        self.payment_information(record, "1970-01-01").map(|_| ())
+    }
 
-    Ok(())
-}
+    fn include_control_sum(&self) -> bool {
+        matches!(self, CitiBankTemplate::SingaporeSgd)
+    }
 
-async fn sftp_download(local_file: String, remote_file: String) {
-    // Connect to the SSH server
-    let sftp_username =
-        std::env::var("CITIBANK_SFTP_USERNAME").expect("CITIBANK_SFTP_USERNAME not set");
-    let sftp_password =
-        std::env::var("CITIBANK_SFTP_PASSWORD").expect("CITIBANK_SFTP_PASSWORD not set");
-    let sftp_host = std::env::var("CITIBANK_SFTP_HOST").expect("CITIBANK_SFTP_HOST not set");
-    let sftp_port = std::env::var("CITIBANK_SFTP_PORT").expect("CITIBANK_SFTP_PORT not set");
-    let tcp = TcpStream::connect(format!("{}:{}", &sftp_host, &sftp_port))
-        .expect("tcp connection failed");
-    let mut sess = Session::new().expect("");
-    sess.set_tcp_stream(tcp);
-    sess.handshake().expect("");
-    sess.userauth_password(&sftp_username, &sftp_password)
-        .expect("");
-    let sftp_client = sess.sftp().expect("");
-
-    let mut sftp_file = sftp_client
-        .open(Path::new(&remote_file))
-        .expect("failed to open file");
-    let mut content_data = "".to_string();
-    sftp_file
-        .read_to_string(&mut content_data)
-        .expect("error reading file");
-    std::fs::write(local_file, content_data).expect("failed to write to file");
-}
+    fn payment_information(
+        &self,
+        record: &CitiBankPayRollRecord,
+        requested_execution_date: &str,
+    ) -> GlobeliseResult<PaymentInformation> {
+        if record.currency_code != self.currency() {
+            return Err(GlobeliseError::bad_request(format!(
+                "Record for {} is in {} but template {} pays in {}",
+                record.employee_name,
+                record.currency_code,
+                self.name(),
+                self.currency()
+            )));
+        }
 
-async fn sftp_list_remote_files(sftp_root_dir: String) -> GlobeliseResult<Vec<String>> {
-    // Connect to the SSH server
-    let sftp_username = std::env::var("CITIBANK_SFTP_USERNAME")?;
-    let sftp_password = std::env::var("CITIBANK_SFTP_PASSWORD")?;
-    let sftp_host = std::env::var("CITIBANK_SFTP_HOST")?;
-    let sftp_port = std::env::var("CITIBANK_SFTP_PORT")?;
-    let tcp = TcpStream::connect(format!("{}:{}", &sftp_host, &sftp_port))?;
-    let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
-    sess.userauth_password(&sftp_username, &sftp_password)?;
-    let sftp_client = sess.sftp()?;
-    let mut remote_files = Vec::new();
-    let path_buf = sftp_client
-        .readdir(Path::new(&sftp_root_dir))
-        .expect("failed to read dir");
-    for file in path_buf {
-        let file_name = file.0.as_os_str().to_str().expect("").to_string();
-        remote_files.push(file_name.to_string());
+        let record_id = record.ulid.to_simple().to_string().to_uppercase();
+        let end_to_end_id = record_id
+            .chars()
+            .take(Self::END_TO_END_ID_LENGTH)
+            .collect::<String>();
+        let creditor_name = Max140Text::new("Employee name", &record.employee_name)?;
+        let creditor_account = AccountIdentification::Other(Max34Text::new(
+            "Bank account number",
+            &record.bank_account_number,
+        )?);
+        let remittance_information = Some(Max140Text::new(
+            "Remittance information",
+            "Globelise Salary Payment",
+        )?);
+
+        let (payment_type_information, debtor_name, debtor_account, debtor_agent, transaction) =
+            match self {
+                CitiBankTemplate::SingaporeSgd => (
+                    PaymentTypeInformation {
+                        service_level: Some(CodeOrProprietary::Code(Max35Text::new(
+                            "SvcLvl", "NURG",
+                        )?)),
+                        local_instrument: Some(CodeOrProprietary::Proprietary(Max35Text::new(
+                            "LclInstrm",
+                            "CITI422",
+                        )?)),
+                        category_purpose: Some(CodeOrProprietary::Code(Max35Text::new(
+                            "CtgyPurp", "SALA",
+                        )?)),
+                    },
+                    "Gronext Technologies Pte. Ltd.",
+                    "41721006",
+                    FinancialInstitution {
+                        bic: Some("CITISGSG".to_string()),
+                        postal_address: Some(country_address("SG")),
+                        ..Default::default()
+                    },
+                    CreditTransferTransaction {
+                        end_to_end_id: Max35Text::new("EndToEndId", end_to_end_id)?,
+                        amount: Amount::new(self.currency(), record.amount)?,
+                        charge_bearer: None,
+                        creditor_agent: FinancialInstitution {
+                            clearing_system_member_id: Some(Max35Text::new(
+                                "Bank code and branch code",
+                                format!("{}{}", record.bank_code, record.bank_branch_code),
+                            )?),
+                            postal_address: Some(country_address("SG")),
+                            ..Default::default()
+                        },
+                        creditor: Party {
+                            name: Some(creditor_name),
+                            postal_address: Some(country_address("SG")),
+                        },
+                        creditor_account,
+                        purpose: Some(CodeOrProprietary::Proprietary(Max35Text::new(
+                            "Purp", "22",
+                        )?)),
+                        remittance_information,
+                    },
+                ),
+                CitiBankTemplate::VietnamVnd | CitiBankTemplate::VietnamUsd => (
+                    PaymentTypeInformation {
+                        service_level: Some(CodeOrProprietary::Code(Max35Text::new(
+                            "SvcLvl", "NURG",
+                        )?)),
+                        local_instrument: Some(CodeOrProprietary::Proprietary(Max35Text::new(
+                            "LclInstrm",
+                            "CITI526",
+                        )?)),
+                        category_purpose: None,
+                    },
+                    "UNICORN MARKET PLACE VIETNAM CO LTD",
+                    match self {
+                        CitiBankTemplate::VietnamUsd => "202661017",
+                        _ => "202661009",
+                    },
+                    FinancialInstitution {
+                        bic: Some("CITIVNVX".to_string()),
+                        ..Default::default()
+                    },
+                    CreditTransferTransaction {
+                        end_to_end_id: Max35Text::new("EndToEndId", end_to_end_id)?,
+                        amount: Amount::new(self.currency(), record.amount)?,
+                        charge_bearer: Some(ChargeBearer::Debtor),
+                        creditor_agent: FinancialInstitution {
+                            clearing_system_member_id: Some(Max35Text::new(
+                                "Clearing system member ID",
+                                "79654001",
+                            )?),
+                            name: Some(Max140Text::new("Bank name", &record.bank_name)?),
+                            postal_address: Some(PostalAddress {
+                                country: Some("VN".to_string()),
+                                address_lines: vec![Max70Text::new("Bank address", "N/A")?],
+                            }),
+                            ..Default::default()
+                        },
+                        creditor: Party {
+                            name: Some(creditor_name),
+                            postal_address: None,
+                        },
+                        creditor_account,
+                        purpose: None,
+                        remittance_information,
+                    },
+                ),
+            };
+
+        Ok(PaymentInformation {
+            payment_information_id: Max35Text::new("PmtInfId", record_id)?,
+            payment_type_information: Some(payment_type_information),
+            requested_execution_date: requested_execution_date.to_string(),
+            debtor: Party {
+                name: Some(Max140Text::new("Debtor name", debtor_name)?),
+                postal_address: None,
+            },
+            debtor_account: AccountIdentification::Other(Max34Text::new(
+                "Debtor account",
+                debtor_account,
+            )?),
+            debtor_agent,
+            transactions: vec![transaction],
+        })
     }
 
-    Ok(remote_files)
-}
+    /// Builds the payment file for the records of an uploaded transfer initiation file.
+    ///
+    /// Every record becomes its own payment information block so that Citibank reports
+    /// the status of each record separately.
+    pub fn document(
+        &self,
+        transaction_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+    ) -> GlobeliseResult<Document> {
+        self.document_created_at(
+            transaction_file,
+            records,
+            chrono::offset::Local::now().naive_local(),
+        )
+    }
 
-pub async fn list_available_templates(
-    _: Token<AdminAccessToken>,
-) -> GlobeliseResult<Json<Vec<String>>> {
-    let mut available_templates = Vec::new();
-    let root_dir = std::env::var("CITIBANK_BASE_PATH").expect("failed to get root dir");
-    let templates_folder = format!("{}citibank_templates/", &root_dir);
-    let files = std::fs::read_dir(Path::new(&templates_folder))?;
-    for file in files {
-        let file_name = file?.file_name().to_str().expect("").to_string();
-        //skip this citibank_transfer_command_template_file
-        if file_name == "citibank_transfer_command_template_file.xlsx" {
-            continue;
-        }
-        //skip this item file
-        if file_name.contains("item") {
-            continue;
+    /// Builds the payment file as if it was created at the given time, which is also the
+    /// requested execution date.
+    pub fn document_created_at(
+        &self,
+        transaction_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+        now: chrono::NaiveDateTime,
+    ) -> GlobeliseResult<Document> {
+        if records.is_empty() {
+            return Err(GlobeliseError::bad_request(
+                "This file does not have any records",
+            ));
         }
-        available_templates.push(file_name.to_string());
-    }
 
-    Ok(Json(available_templates))
+        let requested_execution_date = now.format("%Y-%m-%d").to_string();
+
+        Ok(Document {
+            group_header: GroupHeader {
+                message_id: Max35Text::new(
+                    "MsgId",
+                    transaction_file.ulid.to_simple().to_string().to_uppercase(),
+                )?,
+                creation_date_time: now.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                include_control_sum: self.include_control_sum(),
+                initiating_party: Party {
+                    name: Some(Max140Text::new("Initiating party", "Globelise")?),
+                    postal_address: None,
+                },
+            },
+            payment_information: records
+                .iter()
+                .map(|record| self.payment_information(record, &requested_execution_date))
+                .collect::<GlobeliseResult<_>>()?,
+        })
+    }
 }
 
-// TODO remove this if not needed
-//leaving this here incase i need it in production
-// async fn sftp_delete_remote_file(remote_file: String) -> GlobeliseResult<()> {
-//     // Connect to the SSH server
-//     let sftp_username = std::env::var("CITIBANK_SFTP_USERNAME")?;
-//     let sftp_password = std::env::var("CITIBANK_SFTP_PASSWORD")?;
-//     let sftp_host = std::env::var("CITIBANK_SFTP_HOST")?;
-//     let sftp_port = std::env::var("CITIBANK_SFTP_PORT")?;
-//     let tcp = TcpStream::connect(format!("{}:{}", &sftp_host, &sftp_port))?;
-//     let mut sess = Session::new()?;
-//     sess.set_tcp_stream(tcp);
-//     sess.handshake()?;
-//     sess.userauth_password(&sftp_username, &sftp_password)?;
-//     let sftp_client = sess.sftp()?;
-//     sftp_client.unlink(Path::new(&remote_file))?;
-
-//     Ok(())
-// }
-
-async fn generate_xml(
-    template_name: String,
-    local_file: String,
-    transaction_file: ListCitiBankTransferInitiationFilesResponseNoEntries,
-    records: Vec<CitiBankPayRollRecord>,
-) {
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base path not set");
-    //max 35 chars
-    let guid = transaction_file.ulid.to_simple().to_string().to_uppercase();
-
-    let items_string_template = fs::read_to_string(Path::new(&format!(
-        "{}citibank_templates/{}",
-        &base_path,
-        &template_name.replace(".xml", "_item.xml")
-    )))
-    .unwrap();
-
-    let mut items = "".to_string();
-
-    let mut data = fs::read_to_string(Path::new(&format!(
-        "{}citibank_templates/{}",
-        &base_path, &template_name
-    )))
-    .unwrap();
-
-    let mut control_sum_total = 0.0;
-    let mut number_of_transactions = 0;
-    let now_date_string = chrono::offset::Local::now().format("%Y-%m-%d").to_string();
-
-    //create records
-    for record in records {
-        let mut item = items_string_template.clone();
-        item = str::replace(
-            &item,
-            "{{PmtInfId}}",
-            &record.ulid.to_simple().to_string().to_uppercase(),
-        );
-        // item = str::replace(&item, "{{PmtInfId}}", &Uuid::new_v4().to_simple().to_string().to_uppercase());
-        item = str::replace(&item, "{{ReqdExctnDt}}", &now_date_string);
-        item = str::replace(
-            &item,
-            "{{EndToEndId}}",
-            record
-                .ulid
-                .to_simple()
-                .to_string()
-                .to_uppercase()
-                .substring(0, 16),
-        ); //max 15 chars
-        item = str::replace(&item, "{{InstdAmt}}", &record.amount.to_string());
-        item = str::replace(&item, "{{bank_code}}", &record.bank_code);
-        item = str::replace(&item, "{{branch_code}}", &record.bank_branch_code);
-        item = str::replace(&item, "{{Cdtr_Nm}}", &record.employee_name);
-        item = str::replace(&item, "{{CdtrAcct_Id}}", &record.bank_account_number);
-        item = str::replace(&item, "{{RmtInf_Ustrd}}", "Globelise Salary Payment");
-        item = str::replace(&item, "{{bene_bank_name}}", &record.bank_name);
-
-        control_sum_total += record.amount;
-        number_of_transactions += 1;
-
-        items.push_str(item.trim());
+fn country_address(country: &str) -> PostalAddress {
+    PostalAddress {
+        country: Some(country.to_string()),
+        address_lines: vec![],
     }
-
-    data = str::replace(&data, "{{MsgId}}", &guid); //unique ID
-                                                    // data = str::replace(&data, "{{MsgId}}", &Uuid::new_v4().to_simple().to_string().to_uppercase()); //unique ID
-    data = str::replace(
-        &data,
-        "{{CreDtTm}}",
-        &chrono::offset::Local::now()
-            .format("%Y-%m-%dT%H:%M:%S")
-            .to_string(),
-    );
-    data = str::replace(&data, "{{CtrlSum}}", &format!("{:.2}", &control_sum_total)); //transfer amount total
-    data = str::replace(&data, "{{NbOfTxs}}", &number_of_transactions.to_string()); //number of records
-    data = str::replace(&data, "{{items}}", items.trim()); //transaction items
-
-    std::fs::write(local_file, data.as_bytes()).expect("failed to write to file");
 }
 
 /**
@@ -786,149 +740,143 @@ pub async fn download_citibank_transfer_initiation_template(
 
 /**
  *  upload_citibank_transfer_initiation_template
+ *
+ *  queues the import of the file, see run_upload_job
  */
 pub async fn upload_citibank_transfer_initiation_template(
-    _: Token<AdminAccessToken>,
+    claims: Token<AdminAccessToken>,
     ContentLengthLimit(Json(request)): ContentLengthLimit<
         Json<UploadCitiBankTransferInitiationFiles>,
         FORM_DATA_LENGTH_LIMIT,
     >,
-    Extension(database): Extension<SharedDatabase>,
-) -> GlobeliseResult<()> {
-    let guid = Uuid::new_v4().to_simple().to_string();
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base path not set");
-    let file_name = format!("{}citibank_temp/{}.xlsx", &base_path, &guid);
-    std::fs::write(Path::new(&file_name), &request.uploaded_file).expect("failed to write file");
-
-    // read file
-    let mut excel: Xlsx<_> = open_workbook(&file_name)?;
-    if let Some(Ok(r)) = excel.worksheet_range("Sheet1") {
-        let mut index = 0;
-
-        let database = database.lock().await;
-        let file_ulid = Uuid::new_v4();
-        let record_file = ListCitiBankTransferInitiationFilesRequest {
-            ulid: file_ulid,
-            title_identifier: request.title_identifier,
-            client_ulid: request.client_ulid,
-            status: "pending".to_string(),
-        };
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let job = jobs::enqueue(
+        &common_database,
+        UPLOAD_CITIBANK_TRANSFER_INITIATION_FILE_JOB,
+        None,
+        &request,
+        // A retry would only fail the same way, the file is created in one transaction.
+        1,
+        Some(claims.payload.ulid),
+    )
+    .await?;
 
-        //db entry
-        database
-            .create_uploaded_citibank_transfer_initiation_file(record_file)
-            .await?;
+    Ok(Json(job))
+}
 
-        //read records and save into db
-        for row in r.rows() {
-            //skip first row with titles
-            if index != 0 {
-                let employee_id_ulid = Uuid::parse_str(row[2].get_string().unwrap_or_default())?;
-
-                let record = CitiBankPayRollRecord {
-                    ulid: Uuid::new_v4(),
-                    currency_code: row[0].get_string().unwrap_or_default().to_string(),
-                    country_code: row[1].get_string().unwrap_or_default().to_string(),
-                    employee_id: employee_id_ulid,
-                    employee_name: row[3].get_string().unwrap_or_default().to_string(),
-                    bank_name: row[4].get_string().unwrap_or_default().to_string(),
-                    bank_account_number: row[5]
-                        .get_string()
-                        .unwrap_or(&row[5].get_float().unwrap_or_default().to_string())
-                        .to_string()
-                        .parse()?,
-                    bank_code: row[6]
-                        .get_string()
-                        .unwrap_or(&row[6].get_float().unwrap_or_default().to_string())
-                        .to_string()
-                        .parse()?,
-                    bank_branch_code: row[7]
-                        .get_string()
-                        .unwrap_or(&row[7].get_float().unwrap_or_default().to_string())
-                        .to_string()
-                        .parse()?,
-                    swift_code: row[8]
-                        .get_string()
-                        .unwrap_or(&row[8].get_float().unwrap_or_default().to_string())
-                        .to_string(),
-                    amount: row[9]
-                        .get_string()
-                        .unwrap_or(&row[9].get_float().unwrap_or_default().to_string())
-                        .to_string()
-                        .parse()?,
-                    file_ulid,
-                    transaction_status: "pending".to_string(),
-                    transaction_status_description: Some("pending".to_string()),
-                };
-
-                database
-                    .create_uploaded_citibank_transfer_initiation_file_record(record)
-                    .await?;
-            }
-            index += 1;
-        }
-    }
+/// Imports the file queued by [`upload_citibank_transfer_initiation_template`] as a draft
+/// transfer file, whose ID is the result of the job.
+pub async fn run_upload_job(
+    context: JobContext,
+    database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let request: UploadCitiBankTransferInitiationFiles = context.payload()?;
 
-    //delete uploaded file
-    fs::remove_file(Path::new(&file_name))?;
+    let file_ulid = import_uploaded_file(&database, request).await?;
 
-    Ok(())
+    Ok(Some(JobOutput::json(&file_ulid)?))
 }
 
-/**
- * push the file to citibank
- */
-pub async fn init_citibank_transfer(
-    _: Token<AdminAccessToken>,
-    Json(request): Json<InitCitibankTransferRequest>,
-    Extension(database): Extension<SharedDatabase>,
-) -> GlobeliseResult<()> {
-    let database = database.lock().await;
+async fn import_uploaded_file(
+    database: &SharedDatabase,
+    request: UploadCitiBankTransferInitiationFiles,
+) -> GlobeliseResult<Uuid> {
+    let mut excel = Xlsx::new(Cursor::new(request.uploaded_file))
+        .map_err(|_| GlobeliseError::bad_request("The file is not a valid xlsx file"))?;
+    let rows = match excel.worksheet_range("Sheet1") {
+        Some(Ok(r)) => r,
+        _ => return Err(GlobeliseError::bad_request("The file has no Sheet1")),
+    };
+
+    // read every record before saving any, so a bad row does not leave half a file behind
+    let file_ulid = Uuid::new_v4();
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+    //skip first row with titles
+    for (index, row) in rows.rows().enumerate().skip(1) {
+        match uploaded_payroll_record(file_ulid, row) {
+            Ok(record) => records.push(record),
+            Err(e) => errors.push(format!("Row {}: {}", index + 1, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(GlobeliseError::bad_request(errors.join("\n")));
+    }
 
-    let transaction_file = database
-        .get_uploaded_citibank_transfer_initiation_file(request.file_ulid)
+    let database = database.lock().await;
+    let record_file = ListCitiBankTransferInitiationFilesRequest {
+        ulid: file_ulid,
+        title_identifier: request.title_identifier,
+        client_ulid: request.client_ulid,
+        branch_ulid: request.branch_ulid,
+        status: "draft".to_string(),
+    };
+
+    //db entry
+    database
+        .create_uploaded_citibank_transfer_initiation_file(record_file, records)
         .await?;
 
-    if transaction_file.status != "pending" {
-        return Err(GlobeliseError::bad_request(
-            "This file is already pushed to city bank",
-        ));
-    }
+    Ok(file_ulid)
+}
 
-    let records = database
-        .list_uploaded_citibank_transfer_initiation_files_records(request.file_ulid)
-        .await?;
+/// Reads one row of an uploaded transfer spreadsheet.
+fn uploaded_payroll_record(
+    file_ulid: Uuid,
+    row: &[DataType],
+) -> GlobeliseResult<CitiBankPayRollRecord> {
+    let cell = |column: usize| row.get(column).map(cell_text).unwrap_or_default();
+
+    let employee_id = Uuid::parse_str(&cell(2))
+        .map_err(|_| GlobeliseError::bad_request("employee-id is not a valid ID"))?;
+    let amount: sqlx::types::Decimal = cell(9)
+        .parse()
+        .map_err(|_| GlobeliseError::bad_request("amount is not a number"))?;
+
+    let record = CitiBankPayRollRecord {
+        ulid: Uuid::new_v4(),
+        currency_code: cell(0),
+        country_code: cell(1),
+        employee_id,
+        employee_name: cell(3),
+        bank_name: cell(4),
+        bank_account_number: cell(5),
+        bank_code: cell(6),
+        bank_branch_code: cell(7),
+        swift_code: cell(8),
+        amount,
+        file_ulid,
+        transaction_status: "pending".to_string(),
+        transaction_status_description: Some("pending".to_string()),
+    };
+    let money = record.money()?;
+    if !money.is_positive() {
+        return Err(GlobeliseError::bad_request("amount must be positive"));
+    }
+    record.bank_details().validate()?;
 
-    let base_path = std::env::var("CITIBANK_BASE_PATH").expect("base path not set");
-    let guid = Uuid::new_v4().to_simple().to_string();
-    let local_file = format!("{}citibank_temp/{}.xml", &base_path, &guid);
-
-    generate_xml(
-        request.template_name.to_string(),
-        local_file.to_string(),
-        transaction_file.clone(),
-        records,
-    )
-    .await;
-
-    let raw_data = std::fs::read_to_string(Path::new(&local_file)).expect("failed to read file");
-    let enc_data = encrypt(raw_data).await?;
-    // std::fs::write(Path::new(&local_file), raw_data.as_bytes())?;
-    std::fs::write(Path::new(&local_file), enc_data.as_bytes())?;
-    let sftp_drop_dir = std::env::var("CITIBANK_SFTP_DROP_DIR").expect("path not set");
-    let remote_file = format!("{}GRONEXT_PAYROLL_{}.xml", &sftp_drop_dir, &guid);
-    sftp_upload(local_file.to_string(), remote_file).await?;
-    //remove local_file after upload
-    fs::remove_file(Path::new(&local_file))?;
-    // update status
-    database
-        .update_status_uploaded_citibank_transfer_initiation_file(
-            request.file_ulid,
-            "sent".to_string(),
-        )
-        .await?;
+    Ok(CitiBankPayRollRecord {
+        amount: money.amount(),
+        ..record
+    })
+}
 
-    Ok(())
+/// Reads a cell as text.
+///
+/// Account numbers and bank codes typed into a spreadsheet are usually stored as numbers,
+/// which must not come back as "1234.0" or lose their digits to scientific notation.
+pub fn cell_text(cell: &DataType) -> String {
+    match cell {
+        DataType::String(value) => value.trim().to_string(),
+        DataType::Float(value) if value.fract() == 0.0 && value.abs() < 1e15 => {
+            format!("{:.0}", value)
+        }
+        DataType::Float(value) => value.to_string(),
+        DataType::Int(value) => value.to_string(),
+        DataType::Bool(value) => value.to_string(),
+        _ => String::new(),
+    }
 }
 
 //list all files for a client
@@ -961,44 +909,81 @@ pub async fn list_uploaded_citibank_transfer_initiation_files_records(
     Ok(Json(records))
 }
 
-//update single uploaded file record
+//update single uploaded file record, withdraws any approval of the file
 pub async fn update_uploaded_citibank_transfer_initiation_file_record(
-    _: Token<AdminAccessToken>,
-    Json(record): Json<CitiBankPayRollRecord>,
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<UpdateCitiBankPayRollRecordRequest>,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
+    let record = request.into_record()?;
+
     let database = database.lock().await;
+
+    let (file_ulid, status) = database
+        .select_transfer_record_file_status(record.ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the record"))?;
+    if file_ulid != record.file_ulid {
+        return Err(GlobeliseError::bad_request(
+            "Records cannot be moved to another file",
+        ));
+    }
+    ensure_transfer_records_editable(&status)?;
+
     database
-        .update_uploaded_citibank_transfer_initiation_file_record(record)
+        .update_uploaded_citibank_transfer_initiation_file_record(record, claims.payload.ulid)
         .await?;
 
     Ok(())
 }
 
-//delete single uploaded file record
+//delete single uploaded file record, withdraws any approval of the file
 pub async fn delete_uploaded_citibank_transfer_initiation_file_record(
-    _: Token<AdminAccessToken>,
+    claims: Token<AdminAccessToken>,
     axum::extract::Path(ulid): axum::extract::Path<Uuid>,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
+
+    let (file_ulid, status) = database
+        .select_transfer_record_file_status(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the record"))?;
+    ensure_transfer_records_editable(&status)?;
+
     database
-        .delete_uploaded_citibank_transfer_initiation_file_record(ulid)
+        .delete_uploaded_citibank_transfer_initiation_file_record(
+            ulid,
+            file_ulid,
+            claims.payload.ulid,
+        )
         .await?;
 
     Ok(())
 }
 
-//delete uploaded file
+//delete uploaded file, unless it has already been sent to the bank
 pub async fn delete_uploaded_citibank_transfer_initiation_file(
     _: Token<AdminAccessToken>,
     axum::extract::Path(ulid): axum::extract::Path<Uuid>,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
-    database
+
+    let status = database
+        .select_transfer_file_status(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+    ensure_transfer_records_editable(&status)?;
+
+    if !database
         .delete_uploaded_citibank_transfer_initiation_file(ulid)
-        .await?;
+        .await?
+    {
+        return Err(GlobeliseError::bad_request(
+            "The file was sent to the bank in the meantime",
+        ));
+    }
 
     Ok(())
 }
@@ -1090,51 +1075,139 @@ impl Database {
         Ok(result)
     }
 
+    pub async fn select_transfer_file_status(
+        &self,
+        file_ulid: Uuid,
+    ) -> GlobeliseResult<Option<String>> {
+        let result = sqlx::query_scalar(
+            "SELECT status FROM
+                            uploaded_citibank_transfer_initiation_files
+                    WHERE ulid = $1",
+        )
+        .bind(file_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_one_uploaded_citibank_transfer_initiation_file(
+        &self,
+        file_ulid: Uuid,
+    ) -> GlobeliseResult<Option<ListCitiBankTransferInitiationFilesResponseNoEntries>> {
+        let result = sqlx::query_as(
+            "SELECT * FROM 
+                            uploaded_citibank_transfer_initiation_files
+                    WHERE ulid = $1",
+        )
+        .bind(file_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Creates a file with its records, replacing a draft with the same title.
+    ///
+    /// Files with the same title that were already submitted are never replaced. Either the
+    /// whole file is created or nothing is.
     pub async fn create_uploaded_citibank_transfer_initiation_file(
         &self,
         request: ListCitiBankTransferInitiationFilesRequest,
+        records: Vec<CitiBankPayRollRecord>,
     ) -> GlobeliseResult<()> {
-        //remove old data with same title, cascades delete records
+        let mut transaction = self.0.begin().await?;
+
+        let existing_status: Option<String> = sqlx::query_scalar(
+            "SELECT status FROM
+                            uploaded_citibank_transfer_initiation_files
+                    WHERE title_identifier = $1 AND status <> 'draft'
+                    LIMIT 1
+                    FOR UPDATE",
+        )
+        .bind(&request.title_identifier)
+        .fetch_optional(&mut transaction)
+        .await?;
+        if let Some(status) = existing_status {
+            return Err(GlobeliseError::bad_request(format!(
+                "A {} file with this title already exists",
+                status
+            )));
+        }
+
+        //remove the draft with the same title, cascades delete records
         sqlx::query(
             "DELETE FROM
                             uploaded_citibank_transfer_initiation_files
-                    WHERE title_identifier = $1",
+                    WHERE title_identifier = $1 AND status = 'draft'",
         )
         .bind(&request.title_identifier)
-        .execute(&self.0)
+        .execute(&mut transaction)
         .await?;
 
         //creates new or updates files and records
         sqlx::query(
             "INSERT INTO
                             uploaded_citibank_transfer_initiation_files
-                    (ulid, title_identifier, status, client_ulid)
-                    VALUES($1, $2, $3, $4);",
+                    (ulid, title_identifier, status, client_ulid, branch_ulid)
+                    VALUES($1, $2, $3, $4, $5);",
         )
         .bind(&request.ulid)
         .bind(&request.title_identifier)
         .bind(&request.status)
         .bind(&request.client_ulid)
-        .execute(&self.0)
+        .bind(&request.branch_ulid)
+        .execute(&mut transaction)
         .await?;
 
+        for record in records {
+            sqlx::query(
+                "INSERT INTO
+                            uploaded_citibank_transfer_initiation_files_records
+                    (ulid, currency_code, country_code, employee_id, employee_name, bank_name, bank_account_number, 
+                     bank_code, bank_branch_code, swift_code, amount, file_ulid, transaction_status, transaction_status_description)
+                    VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);"
+            )
+            .bind(&record.ulid)
+            .bind(&record.currency_code)
+            .bind(&record.country_code)
+            .bind(&record.employee_id)
+            .bind(&record.employee_name)
+            .bind(&record.bank_name)
+            .bind(&record.bank_account_number)
+            .bind(&record.bank_code)
+            .bind(&record.bank_branch_code)
+            .bind(&record.swift_code)
+            .bind(&record.amount)
+            .bind(&request.ulid)
+            .bind(&record.transaction_status)
+            .bind(&record.transaction_status_description)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    /// Deletes a file that has not been sent to the bank.
+    ///
+    /// Returns whether the file was deleted.
     pub async fn delete_uploaded_citibank_transfer_initiation_file(
         &self,
         ulid: Uuid,
-    ) -> GlobeliseResult<()> {
-        sqlx::query(
+    ) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
             "DELETE FROM
                             uploaded_citibank_transfer_initiation_files
-                    WHERE ulid = $1",
+                    WHERE ulid = $1 AND status IN ('draft', 'submitted', 'approved')",
         )
         .bind(ulid)
         .execute(&self.0)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected() == 1)
     }
 
     pub async fn update_status_uploaded_citibank_transfer_initiation_file(
@@ -1181,41 +1254,13 @@ impl Database {
 
     // ===================== records
 
-    pub async fn create_uploaded_citibank_transfer_initiation_file_record(
-        &self,
-        record: CitiBankPayRollRecord,
-    ) -> GlobeliseResult<()> {
-        sqlx::query(
-                "INSERT INTO
-                            uploaded_citibank_transfer_initiation_files_records
-                    (ulid, currency_code, country_code, employee_id, employee_name, bank_name, bank_account_number, 
-                     bank_code, bank_branch_code, swift_code, amount, file_ulid, transaction_status, transaction_status_description)
-                    VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);"
-            )
-            .bind(&record.ulid)
-            .bind(&record.currency_code)
-            .bind(&record.country_code)
-            .bind(&record.employee_id)
-            .bind(&record.employee_name)
-            .bind(&record.bank_name)
-            .bind(&record.bank_account_number)
-            .bind(&record.bank_code)
-            .bind(&record.bank_branch_code)
-            .bind(&record.swift_code)
-            .bind(&record.amount)
-            .bind(&record.file_ulid)
-            .bind(&record.transaction_status)
-            .bind(&record.transaction_status_description)
-            .execute(&self.0)
-            .await?;
-
-        Ok(())
-    }
-
     pub async fn update_uploaded_citibank_transfer_initiation_file_record(
         &self,
         record: CitiBankPayRollRecord,
+        admin_ulid: Uuid,
     ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
         sqlx::query(
             "UPDATE 
                         uploaded_citibank_transfer_initiation_files_records
@@ -1229,10 +1274,7 @@ impl Database {
                         bank_code = $8, 
                         bank_branch_code = $9, 
                         swift_code = $10, 
-                        amount = $11, 
-                        file_ulid =  $12, 
-                        transaction_status = $13,
-                        transaction_status_description = $14
+                        amount = $11
                    WHERE ulid = $1",
         )
         .bind(&record.ulid)
@@ -1246,28 +1288,37 @@ impl Database {
         .bind(&record.bank_branch_code)
         .bind(&record.swift_code)
         .bind(&record.amount)
-        .bind(&record.file_ulid)
-        .bind(&record.transaction_status)
-        .bind(&record.transaction_status_description)
-        .execute(&self.0)
+        .execute(&mut transaction)
         .await?;
 
+        withdraw_transfer_approval(&mut transaction, record.file_ulid, admin_ulid).await?;
+
+        transaction.commit().await?;
+
         Ok(())
     }
 
     pub async fn delete_uploaded_citibank_transfer_initiation_file_record(
         &self,
         ulid: Uuid,
+        file_ulid: Uuid,
+        admin_ulid: Uuid,
     ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
         sqlx::query(
             "DELETE FROM
                             uploaded_citibank_transfer_initiation_files_records
                     WHERE ulid = $1",
         )
         .bind(ulid)
-        .execute(&self.0)
+        .execute(&mut transaction)
         .await?;
 
+        withdraw_transfer_approval(&mut transaction, file_ulid, admin_ulid).await?;
+
+        transaction.commit().await?;
+
         Ok(())
     }
 
@@ -1310,3 +1361,187 @@ impl Database {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::{time::OffsetDateTime, Decimal};
+
+    use super::super::pain001::validate_against_schema;
+    use super::*;
+
+    const FILE_ULID: &str = "3f2504e0-4f89-41d3-9a0c-0305e82c3301";
+
+    fn transaction_file() -> ListCitiBankTransferInitiationFilesResponseNoEntries {
+        ListCitiBankTransferInitiationFilesResponseNoEntries {
+            ulid: Uuid::parse_str(FILE_ULID).unwrap(),
+            title_identifier: "September payroll".to_string(),
+            client_ulid: Uuid::nil(),
+            status: "approved".to_string(),
+            created_at: OffsetDateTime::unix_epoch(),
+            branch_ulid: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        ulid: &str,
+        currency_code: &str,
+        country_code: &str,
+        employee_name: &str,
+        bank_name: &str,
+        bank_account_number: &str,
+        bank_code: &str,
+        bank_branch_code: &str,
+        swift_code: &str,
+        amount: &str,
+    ) -> CitiBankPayRollRecord {
+        CitiBankPayRollRecord {
+            ulid: Uuid::parse_str(ulid).unwrap(),
+            currency_code: currency_code.to_string(),
+            country_code: country_code.to_string(),
+            employee_id: Uuid::nil(),
+            employee_name: employee_name.to_string(),
+            bank_name: bank_name.to_string(),
+            bank_account_number: bank_account_number.to_string(),
+            bank_code: bank_code.to_string(),
+            bank_branch_code: bank_branch_code.to_string(),
+            swift_code: swift_code.to_string(),
+            amount: amount.parse::<Decimal>().unwrap(),
+            file_ulid: Uuid::parse_str(FILE_ULID).unwrap(),
+            transaction_status: "None".to_string(),
+            transaction_status_description: None,
+        }
+    }
+
+    fn records(template: CitiBankTemplate) -> Vec<CitiBankPayRollRecord> {
+        match template {
+            CitiBankTemplate::SingaporeSgd => vec![
+                record(
+                    "0f8fad5b-d9cb-469f-a165-70867728950e",
+                    "SGD",
+                    "SG",
+                    "Tan Wei Ming",
+                    "DBS Bank",
+                    "0123456789",
+                    "7171",
+                    "081",
+                    "DBSSSGSG",
+                    "1234.5",
+                ),
+                record(
+                    "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+                    "SGD",
+                    "SG",
+                    r#"O'Brien & <Sons> "Ltd""#,
+                    "DBS Bank",
+                    "9876543210",
+                    "7171",
+                    "081",
+                    "DBSSSGSG",
+                    "2000",
+                ),
+            ],
+            CitiBankTemplate::VietnamVnd => vec![record(
+                "16fd2706-8baf-433b-82eb-8c7fada847da",
+                "VND",
+                "VN",
+                "Nguyen Van An",
+                "Vietcombank",
+                "0071001234567",
+                "203",
+                "001",
+                "BFTVVNVX",
+                "15000000",
+            )],
+            CitiBankTemplate::VietnamUsd => vec![record(
+                "886313e1-3b8a-4372-9b90-0c9aee199e5d",
+                "USD",
+                "VN",
+                "Nguyen Van An",
+                "Vietcombank",
+                "0071001234567",
+                "203",
+                "001",
+                "BFTVVNVX",
+                "1500.25",
+            )],
+        }
+    }
+
+    fn golden(template: CitiBankTemplate) -> &'static str {
+        match template {
+            CitiBankTemplate::SingaporeSgd => include_str!("testdata/citi_bank_sg.xml"),
+            CitiBankTemplate::VietnamVnd => include_str!("testdata/citi_bank_vn.xml"),
+            CitiBankTemplate::VietnamUsd => include_str!("testdata/citi_bank_usd_vn.xml"),
+        }
+    }
+
+    fn created_at() -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd(2022, 9, 5).and_hms(10, 30, 0)
+    }
+
+    #[test]
+    fn documents_match_golden_files() {
+        for template in CitiBankTemplate::ALL {
+            let xml = template
+                .document_created_at(&transaction_file(), &records(template), created_at())
+                .unwrap()
+                .to_xml();
+            assert_eq!(xml, golden(template), "{}", template.name());
+        }
+    }
+
+    #[test]
+    fn golden_files_match_the_schema() {
+        for template in CitiBankTemplate::ALL {
+            validate_against_schema(golden(template)).unwrap();
+            template
+                .document_created_at(&transaction_file(), &records(template), created_at())
+                .unwrap()
+                .to_validated_xml()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let xml = CitiBankTemplate::SingaporeSgd
+            .document_created_at(
+                &transaction_file(),
+                &records(CitiBankTemplate::SingaporeSgd),
+                created_at(),
+            )
+            .unwrap()
+            .to_xml();
+        assert!(xml.contains("<Nm>O&apos;Brien &amp; &lt;Sons&gt; &quot;Ltd&quot;</Nm>"));
+        assert!(!xml.contains("<Sons>"));
+    }
+
+    #[test]
+    fn amounts_use_the_currency_precision() {
+        let xml = CitiBankTemplate::SingaporeSgd
+            .document_created_at(
+                &transaction_file(),
+                &records(CitiBankTemplate::SingaporeSgd),
+                created_at(),
+            )
+            .unwrap()
+            .to_xml();
+        assert!(xml.contains(r#"<InstdAmt Ccy="SGD">1234.50</InstdAmt>"#));
+        assert!(xml.contains("<CtrlSum>3234.50</CtrlSum>"));
+
+        let mut records = records(CitiBankTemplate::VietnamVnd);
+        records[0].amount = "1000.5".parse().unwrap();
+        assert!(CitiBankTemplate::VietnamVnd
+            .document_created_at(&transaction_file(), &records, created_at())
+            .is_err());
+    }
+
+    #[test]
+    fn records_in_another_currency_are_rejected() {
+        let records = records(CitiBankTemplate::VietnamUsd);
+        assert!(CitiBankTemplate::VietnamVnd
+            .validate_record(&records[0])
+            .is_err());
+    }
+}
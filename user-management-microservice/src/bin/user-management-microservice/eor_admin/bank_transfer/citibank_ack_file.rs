@@ -31,5 +31,5 @@ pub struct OrgnlGrpInfAndSts {
     #[serde(rename = "GrpSts")]
     pub grp_sts: String,
     #[serde(rename = "StsRsnInf")]
-    pub sts_rsn_inf: StsRsnInf,
+    pub sts_rsn_inf: Option<StsRsnInf>,
 }
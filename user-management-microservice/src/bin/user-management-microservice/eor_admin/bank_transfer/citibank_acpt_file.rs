@@ -12,7 +12,7 @@ pub struct CitiBankACPTFile {
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CstmrPmtStsRpt {
-    #[serde(rename = "OrgnlPmtInfAndSts")]
+    #[serde(rename = "OrgnlPmtInfAndSts", default)]
     pub orgnl_pmt_inf_and_sts: Vec<OrgnlPmtInfAndSts>,
 }
 
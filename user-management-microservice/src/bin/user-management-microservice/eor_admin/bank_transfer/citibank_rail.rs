@@ -0,0 +1,195 @@
+//! Citibank as a payment rail.
+//!
+//! Transfers are sent as pain.001 files, encrypted and signed with OpenPGP, to the Citibank
+//! SFTP server. Citibank answers with ACK files for whole transfer files and ACPT and RJCT
+//! files for individual records, which are picked up by `citibank_status.rs`.
+
+use axum::async_trait;
+use common_utils::error::{GlobeliseError, GlobeliseResult};
+use uuid::Uuid;
+
+use super::citi_bank::{
+    CitiBankPayRollRecord, CitiBankTemplate, ListCitiBankTransferInitiationFilesResponseNoEntries,
+};
+use super::citibank_ack_file::CitiBankACKFile;
+use super::citibank_acpt_file::CitiBankACPTFile;
+use super::citibank_rjct_file::CitiBankRJCTFile;
+use super::openpgp::citibank_pgp_keys;
+use super::payment_rail::{
+    parse_ulid, PaymentFile, PaymentRail, PaymentRailKind, StatusReport, TransferFileStatus,
+    TransferRecordStatus,
+};
+use super::sftp::citibank_sftp;
+
+/// The kinds of status files Citibank sends, told apart by their names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CitiBankStatusFileKind {
+    Ack,
+    Acpt,
+    Rjct,
+}
+
+impl CitiBankStatusFileKind {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        if file_name.contains("ACPT") {
+            Some(CitiBankStatusFileKind::Acpt)
+        } else if file_name.contains("RJCT") {
+            Some(CitiBankStatusFileKind::Rjct)
+        } else if file_name.contains("ACK") {
+            Some(CitiBankStatusFileKind::Ack)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CitiBankStatusFileKind::Ack => "ack",
+            CitiBankStatusFileKind::Acpt => "acpt",
+            CitiBankStatusFileKind::Rjct => "rjct",
+        }
+    }
+}
+
+pub struct CitiBankRail {
+    template: Option<CitiBankTemplate>,
+}
+
+impl CitiBankRail {
+    /// Creates the rail. Files can only be built and sent with a template.
+    pub fn new(template: Option<CitiBankTemplate>) -> Self {
+        Self { template }
+    }
+
+    fn template(&self) -> GlobeliseResult<CitiBankTemplate> {
+        self.template
+            .ok_or_else(|| GlobeliseError::bad_request("Citibank transfers need a template"))
+    }
+}
+
+#[async_trait]
+impl PaymentRail for CitiBankRail {
+    fn kind(&self) -> PaymentRailKind {
+        PaymentRailKind::CITIBANK
+    }
+
+    fn bank_name(&self) -> &'static str {
+        "Citibank"
+    }
+
+    fn currency(&self) -> Option<&'static str> {
+        self.template.map(|template| template.currency())
+    }
+
+    fn country(&self) -> Option<&'static str> {
+        self.template.map(|template| template.country())
+    }
+
+    fn validate_record(&self, record: &CitiBankPayRollRecord) -> GlobeliseResult<()> {
+        self.template()?.validate_record(record)
+    }
+
+    fn encode(
+        &self,
+        transfer_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+    ) -> GlobeliseResult<PaymentFile> {
+        let raw_data = self
+            .template()?
+            .document(transfer_file, records)?
+            .to_validated_xml()?;
+        let enc_data = citibank_pgp_keys()?.encrypt_and_sign("payroll.xml", raw_data.as_bytes())?;
+
+        Ok(PaymentFile {
+            file_name: format!("GRONEXT_PAYROLL_{}.xml", Uuid::new_v4().to_simple()),
+            content_type: "application/pgp-encrypted",
+            data: enc_data.into_bytes(),
+        })
+    }
+
+    async fn deliver(&self, file: PaymentFile) -> GlobeliseResult<Option<PaymentFile>> {
+        let sftp_drop_dir = std::env::var("CITIBANK_SFTP_DROP_DIR")?;
+        let remote_file = format!("{}{}", &sftp_drop_dir, &file.file_name);
+        citibank_sftp()?.upload(&remote_file, file.data).await?;
+
+        Ok(None)
+    }
+
+    /// Parses a decrypted ACK, ACPT or RJCT file.
+    fn parse_status_report(&self, file_name: &str, data: &str) -> GlobeliseResult<StatusReport> {
+        match CitiBankStatusFileKind::from_file_name(file_name) {
+            Some(CitiBankStatusFileKind::Ack) => parse_ack(data),
+            Some(CitiBankStatusFileKind::Acpt) => parse_acpt(data),
+            Some(CitiBankStatusFileKind::Rjct) => parse_rjct(data),
+            None => Err(GlobeliseError::bad_request("Not an ACK, ACPT or RJCT file")),
+        }
+    }
+}
+
+/// The bank's acknowledgement of a whole transfer initiation file.
+fn parse_ack(data: &str) -> GlobeliseResult<StatusReport> {
+    let file: CitiBankACKFile = serde_xml_rs::from_str(data)
+        .map_err(|e| GlobeliseError::bad_request(format!("Malformed ACK file: {}", e)))?;
+    let group = file.cstmr_pmt_sts_rpt.orgnl_grp_inf_and_sts;
+
+    Ok(StatusReport {
+        files: vec![TransferFileStatus {
+            file_ulid: parse_ulid(&group.orgnl_msg_id)?,
+            accepted: group.grp_sts != "RJCT",
+            reason: group.sts_rsn_inf.map(|reason| reason.addtl_inf),
+        }],
+        ..Default::default()
+    })
+}
+
+/// The bank's acceptance of individual transfers.
+fn parse_acpt(data: &str) -> GlobeliseResult<StatusReport> {
+    let file: CitiBankACPTFile = serde_xml_rs::from_str(data)
+        .map_err(|e| GlobeliseError::bad_request(format!("Malformed ACPT file: {}", e)))?;
+
+    let mut report = StatusReport::default();
+    for record in file.cstmr_pmt_sts_rpt.orgnl_pmt_inf_and_sts {
+        match parse_ulid(&record.orgnl_pmt_inf_id) {
+            Ok(record_ulid) => report.records.push(TransferRecordStatus {
+                record_ulid,
+                accepted: true,
+                reason: None,
+            }),
+            Err(e) => report.problems.push(e.to_string()),
+        }
+    }
+
+    Ok(report)
+}
+
+/// The bank's rejection of individual transfers.
+fn parse_rjct(data: &str) -> GlobeliseResult<StatusReport> {
+    let file: CitiBankRJCTFile = serde_xml_rs::from_str(data)
+        .map_err(|e| GlobeliseError::bad_request(format!("Malformed RJCT file: {}", e)))?;
+
+    let mut report = StatusReport::default();
+    for record in file.cstmr_pmt_sts_rpt.orgnl_pmt_inf_and_sts {
+        let record_ulid = match parse_ulid(&record.orgnl_pmt_inf_id) {
+            Ok(record_ulid) => record_ulid,
+            Err(e) => {
+                report.problems.push(e.to_string());
+                continue;
+            }
+        };
+        let reject_reason = record
+            .tx_inf_and_sts
+            .sts_rsn_inf
+            .into_iter()
+            .flat_map(|reason| reason.addtl_inf)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        report.records.push(TransferRecordStatus {
+            record_ulid,
+            accepted: false,
+            reason: Some(reject_reason),
+        });
+    }
+
+    Ok(report)
+}
@@ -12,7 +12,7 @@ pub struct CitiBankRJCTFile {
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CstmrPmtStsRpt {
-    #[serde(rename = "OrgnlPmtInfAndSts")]
+    #[serde(rename = "OrgnlPmtInfAndSts", default)]
     pub orgnl_pmt_inf_and_sts: Vec<OrgnlPmtInfAndSts>,
 }
 
@@ -32,13 +32,13 @@ pub struct TxInfAndSts {
     pub orgnl_end_to_end_id: String,
     #[serde(rename = "TxSts")]
     pub tx_sts: String,
-    #[serde(rename = "StsRsnInf")]
+    #[serde(rename = "StsRsnInf", default)]
     pub sts_rsn_inf: Vec<StsRsnInf>,
 }
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StsRsnInf {
-    #[serde(rename = "AddtlInf")]
+    #[serde(rename = "AddtlInf", default)]
     pub addtl_inf: Vec<String>,
 }
@@ -0,0 +1,694 @@
+//! Polling and ingestion of the status files that Citibank drops on the SFTP server.
+//!
+//! Every file is claimed in `citibank_status_files` before it is applied, so a file is
+//! only ever applied once. Files that cannot be read are retried on the next polls, since
+//! the bank may still be writing them, and are marked as failed after [`MAX_ATTEMPTS`].
+//!
+//! Polls run as background jobs, queued periodically or by an admin, and only one of them is
+//! queued or running at a time.
+
+use std::path::Path;
+
+use axum::extract::{Extension, Json};
+use common_utils::{
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    jobs::{self, JobContext, JobOutput},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+use super::{
+    citibank_rail::{CitiBankRail, CitiBankStatusFileKind},
+    openpgp::{citibank_pgp_keys, PgpKeys},
+    payment_rail::{apply_status_report, content_hash, PaymentRail, PaymentRailKind},
+    sftp::{citibank_sftp, SftpTransport},
+};
+
+/// Number of times a file is attempted before it is marked as failed.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Seconds between two polls when `CITIBANK_STATUS_POLL_INTERVAL_SECONDS` is not set.
+const DEFAULT_POLL_INTERVAL_SECONDS: u64 = 300;
+
+pub const POLL_CITIBANK_STATUS_FILES_JOB: &str = "poll-citibank-status-files";
+
+/// What happened to a file that was read successfully.
+struct AppliedStatusFile {
+    content_hash: String,
+    status: &'static str,
+    /// Problems with individual entries that did not stop the rest of the file from being applied.
+    error: Option<String>,
+}
+
+/// Queues a poll of the SFTP server for new status files forever.
+///
+/// The interval is configured with `CITIBANK_STATUS_POLL_INTERVAL_SECONDS`.
+pub async fn poll_citibank_status_files_periodically(common_database: CommonDatabase) {
+    let interval = std::env::var("CITIBANK_STATUS_POLL_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_POLL_INTERVAL_SECONDS);
+
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        if let Err(e) = enqueue_poll(&common_database, None).await {
+            println!("Could not queue a poll of Citibank status files: {:?}", e);
+        }
+    }
+}
+
+/// Queues a poll of the SFTP server now, instead of waiting for the next periodic one.
+pub async fn post_poll(
+    claims: Token<AdminAccessToken>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let job = enqueue_poll(&common_database, Some(claims.payload.ulid)).await?;
+
+    Ok(Json(job))
+}
+
+/// Returns the poll that is already queued or running, if there is one.
+pub async fn enqueue_poll(
+    common_database: &CommonDatabase,
+    created_by: Option<Uuid>,
+) -> GlobeliseResult<Job> {
+    // Files that could not be applied are retried by the next poll instead.
+    jobs::enqueue(
+        common_database,
+        POLL_CITIBANK_STATUS_FILES_JOB,
+        Some(POLL_CITIBANK_STATUS_FILES_JOB),
+        &(),
+        1,
+        created_by,
+    )
+    .await
+}
+
+pub async fn run_poll_job(
+    _: JobContext,
+    database: SharedDatabase,
+    common_database: CommonDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    poll_citibank_status_files(&database, &common_database).await?;
+
+    Ok(None)
+}
+
+/// Downloads and applies every status file that has not been applied yet.
+pub async fn poll_citibank_status_files(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+) -> GlobeliseResult<()> {
+    let sftp_root_dir = std::env::var("CITIBANK_SFTP_ROOT_DIR")?;
+
+    poll_status_files(
+        database,
+        common_database,
+        citibank_sftp()?,
+        citibank_pgp_keys()?,
+        &sftp_root_dir,
+    )
+    .await
+}
+
+/// Downloads and applies every status file in `sftp_root_dir` that has not been applied yet.
+async fn poll_status_files(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    sftp: &dyn SftpTransport,
+    keys: &PgpKeys,
+    sftp_root_dir: &str,
+) -> GlobeliseResult<()> {
+    let rail = CitiBankRail::new(None);
+
+    for remote_file in sftp.list_files(sftp_root_dir).await? {
+        let file_name = match Path::new(&remote_file).file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue,
+        };
+
+        let attempts = match database
+            .lock()
+            .await
+            .claim_citibank_status_file(&file_name, rail.kind())
+            .await?
+        {
+            Some(attempts) => attempts,
+            // Already applied, given up on, or being applied by another replica.
+            None => continue,
+        };
+
+        let kind = CitiBankStatusFileKind::from_file_name(&file_name);
+        let result = match kind {
+            Some(_) => {
+                apply_status_file(
+                    database,
+                    common_database,
+                    sftp,
+                    keys,
+                    &rail,
+                    &remote_file,
+                    &file_name,
+                )
+                .await
+            }
+            None => Ok(AppliedStatusFile {
+                content_hash: String::new(),
+                status: "ignored",
+                error: Some("Not an ACK, ACPT or RJCT file".to_string()),
+            }),
+        };
+
+        let database = database.lock().await;
+        match result {
+            Ok(applied) => {
+                database
+                    .update_citibank_status_file(
+                        &file_name,
+                        kind.map(|kind| kind.as_str()),
+                        Some(applied.content_hash).filter(|hash| !hash.is_empty()),
+                        applied.status,
+                        applied.error,
+                    )
+                    .await?
+            }
+            Err(e) => {
+                let status = if attempts >= MAX_ATTEMPTS {
+                    "failed"
+                } else {
+                    "retrying"
+                };
+                database
+                    .update_citibank_status_file(
+                        &file_name,
+                        kind.map(|kind| kind.as_str()),
+                        None,
+                        status,
+                        Some(e.to_string()),
+                    )
+                    .await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn apply_status_file(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    sftp: &dyn SftpTransport,
+    keys: &PgpKeys,
+    rail: &CitiBankRail,
+    remote_file: &str,
+    file_name: &str,
+) -> GlobeliseResult<AppliedStatusFile> {
+    let enc_data =
+        String::from_utf8(sftp.read(remote_file).await?).map_err(GlobeliseError::bad_request)?;
+    let raw_data = String::from_utf8(keys.decrypt_and_verify(&enc_data)?)
+        .map_err(GlobeliseError::bad_request)?;
+
+    // The same report can show up again under a different file name.
+    let content_hash = content_hash(&raw_data);
+    {
+        let database = database.lock().await;
+        if database
+            .is_citibank_status_file_content_processed(&content_hash)
+            .await?
+        {
+            return Ok(AppliedStatusFile {
+                content_hash,
+                status: "duplicate",
+                error: None,
+            });
+        }
+        if !database
+            .claim_citibank_status_file_content(file_name, &content_hash)
+            .await?
+        {
+            return Err(GlobeliseError::internal(
+                "The same report is being applied from another file",
+            ));
+        }
+    }
+
+    let report = rail.parse_status_report(file_name, &raw_data)?;
+    let error = apply_status_report(database, common_database, rail, report).await?;
+
+    Ok(AppliedStatusFile {
+        content_hash,
+        status: "processed",
+        error,
+    })
+}
+
+impl Database {
+    /// Claims a status file for processing.
+    ///
+    /// Returns the number of attempts including this one, or `None` if the file must not
+    /// be processed. Files stuck in `processing` for an hour are assumed to belong to a
+    /// replica that died and are claimed again.
+    pub async fn claim_citibank_status_file(
+        &self,
+        file_name: &str,
+        payment_rail: PaymentRailKind,
+    ) -> GlobeliseResult<Option<i32>> {
+        let result = sqlx::query_scalar(
+            "
+        INSERT INTO citibank_status_files (
+            file_name, payment_rail, status, attempts
+        ) VALUES (
+            $1, $2, 'processing', 1
+        ) ON CONFLICT (file_name) DO UPDATE SET
+            status = 'processing',
+            attempts = citibank_status_files.attempts + 1,
+            updated_at = now()
+        WHERE
+            citibank_status_files.status = 'retrying' OR
+            (citibank_status_files.status = 'processing' AND
+             citibank_status_files.updated_at < now() - INTERVAL '1 hour')
+        RETURNING
+            attempts",
+        )
+        .bind(file_name)
+        .bind(payment_rail)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Records the content hash of a file that is being processed.
+    ///
+    /// Returns `false` if another file with the same content is being processed. The unique
+    /// index on the hashes of files being processed or processed settles races between
+    /// replicas.
+    pub async fn claim_citibank_status_file_content(
+        &self,
+        file_name: &str,
+        content_hash: &str,
+    ) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
+            "
+        UPDATE
+            citibank_status_files
+        SET
+            content_hash = $2,
+            updated_at = now()
+        WHERE
+            file_name = $1 AND
+            status = 'processing' AND
+            NOT EXISTS (
+                SELECT
+                    1
+                FROM
+                    citibank_status_files other
+                WHERE
+                    other.content_hash = $2 AND
+                    other.file_name <> $1 AND
+                    other.status IN ('processing', 'processed')
+            )",
+        )
+        .bind(file_name)
+        .bind(content_hash)
+        .execute(&self.0)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn update_citibank_status_file(
+        &self,
+        file_name: &str,
+        file_kind: Option<&str>,
+        content_hash: Option<String>,
+        status: &str,
+        error: Option<String>,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        UPDATE
+            citibank_status_files
+        SET
+            file_kind = $2,
+            content_hash = $3,
+            status = $4,
+            error = $5,
+            updated_at = now()
+        WHERE
+            file_name = $1",
+        )
+        .bind(file_name)
+        .bind(file_kind)
+        .bind(content_hash)
+        .bind(status)
+        .bind(error)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_citibank_status_file_content_processed(
+        &self,
+        content_hash: &str,
+    ) -> GlobeliseResult<bool> {
+        let result = sqlx::query_scalar(
+            "
+        SELECT EXISTS (
+            SELECT
+                1
+            FROM
+                citibank_status_files
+            WHERE
+                content_hash = $1 AND
+                status = 'processed'
+        )",
+        )
+        .bind(content_hash)
+        .fetch_one(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlx::{postgres::PgPoolOptions, types::Decimal};
+    use tokio::sync::Mutex;
+
+    use super::super::{citi_bank::CitiBankTemplate, openpgp::tests::parties, sftp::LocalSftp};
+    use super::*;
+
+    const DROP_DIR: &str = "/inbound/";
+    const STATUS_DIR: &str = "/outbound";
+
+    fn status_report(body: String) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.002.001.03">
+  <CstmrPmtStsRpt>{}</CstmrPmtStsRpt>
+</Document>"#,
+            body
+        )
+    }
+
+    fn acpt_file(payment_information_id: &str) -> String {
+        status_report(format!(
+            "<OrgnlPmtInfAndSts><OrgnlPmtInfId>{}</OrgnlPmtInfId></OrgnlPmtInfAndSts>",
+            payment_information_id
+        ))
+    }
+
+    fn rjct_file(payment_information_id: &str, reason: &str) -> String {
+        status_report(format!(
+            "<OrgnlPmtInfAndSts>
+              <OrgnlPmtInfId>{0}</OrgnlPmtInfId>
+              <TxInfAndSts>
+                <OrgnlEndToEndId>{1}</OrgnlEndToEndId>
+                <TxSts>RJCT</TxSts>
+                <StsRsnInf><AddtlInf>{2}</AddtlInf></StsRsnInf>
+              </TxInfAndSts>
+            </OrgnlPmtInfAndSts>",
+            payment_information_id,
+            &payment_information_id[..16],
+            reason
+        ))
+    }
+
+    async fn connect() -> (SharedDatabase, CommonDatabase) {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+
+        (
+            Arc::new(Mutex::new(Database(pool.clone()))),
+            Arc::new(Mutex::new(common_utils::database::Database(pool))),
+        )
+    }
+
+    async fn record_status(database: &SharedDatabase, record_ulid: Uuid) -> (String, String) {
+        sqlx::query_as(
+            "
+        SELECT
+            transaction_status, transaction_status_description
+        FROM
+            uploaded_citibank_transfer_initiation_files_records
+        WHERE
+            ulid = $1",
+        )
+        .bind(record_ulid)
+        .fetch_one(&database.lock().await.0)
+        .await
+        .unwrap()
+    }
+
+    async fn status_file(database: &SharedDatabase, file_name: &str) -> (String, i32) {
+        sqlx::query_as(
+            "
+        SELECT
+            status, attempts
+        FROM
+            citibank_status_files
+        WHERE
+            file_name = $1",
+        )
+        .bind(file_name)
+        .fetch_one(&database.lock().await.0)
+        .await
+        .unwrap()
+    }
+
+    /// Sends a transfer file to a local directory standing in for the SFTP server, answers
+    /// it the way Citibank does and checks that polling applies every answer once.
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn status_files_from_the_sftp_server_are_applied_once() {
+        let (database, common_database) = connect().await;
+        let parties = parties();
+        let root = std::env::temp_dir().join(format!("citibank-sftp-{}", Uuid::new_v4()));
+        let sftp = LocalSftp::new(root.clone());
+        // Each run uses its own file names, since status files are tracked by name.
+        let run = Uuid::new_v4().to_simple().to_string();
+
+        let client_ulid = Uuid::new_v4();
+        let file_ulid = Uuid::new_v4();
+        let accepted_ulid = Uuid::new_v4();
+        let rejected_ulid = Uuid::new_v4();
+        {
+            let pool = &database.lock().await.0;
+            sqlx::query("INSERT INTO users (ulid, email, is_client) VALUES ($1, $2, 't')")
+                .bind(client_ulid)
+                .bind(format!("{}@client.example", client_ulid))
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                "
+            INSERT INTO uploaded_citibank_transfer_initiation_files (
+                ulid, title_identifier, status, client_ulid
+            ) VALUES (
+                $1, $2, 'sent', $3
+            )",
+            )
+            .bind(file_ulid)
+            .bind(format!("Payroll {}", run))
+            .bind(client_ulid)
+            .execute(pool)
+            .await
+            .unwrap();
+            for (record_ulid, employee_name) in [
+                (accepted_ulid, "Tan Wei Ming"),
+                (rejected_ulid, "Lim Mei Ling"),
+            ] {
+                sqlx::query(
+                    "
+                INSERT INTO uploaded_citibank_transfer_initiation_files_records (
+                    ulid, currency_code, country_code, employee_id, employee_name,
+                    bank_name, bank_account_number, bank_code, bank_branch_code, swift_code,
+                    amount, file_ulid, transaction_status
+                ) VALUES (
+                    $1, 'SGD', 'SG', $2, $3,
+                    'DBS Bank', '0123456789', '7171', '081', 'DBSSSGSG',
+                    $4, $5, 'None'
+                )",
+                )
+                .bind(record_ulid)
+                .bind(Uuid::new_v4())
+                .bind(employee_name)
+                .bind(Decimal::new(123450, 2))
+                .bind(file_ulid)
+                .execute(pool)
+                .await
+                .unwrap();
+            }
+        }
+
+        // Upload the payment file the way the Citibank rail does.
+        let (transfer_file, records) = {
+            let database = database.lock().await;
+            (
+                database
+                    .get_uploaded_citibank_transfer_initiation_file(file_ulid)
+                    .await
+                    .unwrap(),
+                database
+                    .list_uploaded_citibank_transfer_initiation_files_records(file_ulid)
+                    .await
+                    .unwrap(),
+            )
+        };
+        let xml = CitiBankTemplate::SingaporeSgd
+            .document(&transfer_file, &records)
+            .unwrap()
+            .to_validated_xml()
+            .unwrap();
+        let payment_file = format!("{}GRONEXT_PAYROLL_{}.xml", DROP_DIR, run);
+        sftp.upload(
+            &payment_file,
+            parties
+                .globelise
+                .encrypt_and_sign("payroll.xml", xml.as_bytes())
+                .unwrap()
+                .into_bytes(),
+        )
+        .await
+        .unwrap();
+
+        // The bank reads the file and answers with one ACPT and one RJCT file.
+        let received = String::from_utf8(
+            parties
+                .bank
+                .decrypt_and_verify(
+                    &String::from_utf8(sftp.read(&payment_file).await.unwrap()).unwrap(),
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(received, xml);
+        let accepted_id = accepted_ulid.to_simple().to_string().to_uppercase();
+        let rejected_id = rejected_ulid.to_simple().to_string().to_uppercase();
+        assert!(received.contains(&accepted_id) && received.contains(&rejected_id));
+
+        let acpt_name = format!("GRONEXT_ACPT_{}.xml", run);
+        let rjct_name = format!("GRONEXT_RJCT_{}.xml", run);
+        let answers = [
+            (&acpt_name, acpt_file(&accepted_id)),
+            (&rjct_name, rjct_file(&rejected_id, "Invalid account")),
+        ];
+        for (file_name, report) in &answers {
+            let enc_data = parties
+                .bank
+                .encrypt_and_sign(file_name, report.as_bytes())
+                .unwrap();
+            sftp.upload(
+                &format!("{}/{}", STATUS_DIR, file_name),
+                enc_data.into_bytes(),
+            )
+            .await
+            .unwrap();
+        }
+
+        poll_status_files(
+            &database,
+            &common_database,
+            &sftp,
+            &parties.globelise,
+            STATUS_DIR,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            record_status(&database, accepted_ulid).await,
+            ("acpt".to_string(), "transaction accepted".to_string())
+        );
+        assert_eq!(
+            record_status(&database, rejected_ulid).await,
+            ("rcjt".to_string(), "Invalid account".to_string())
+        );
+        assert_eq!(
+            status_file(&database, &acpt_name).await,
+            ("processed".to_string(), 1)
+        );
+        assert_eq!(
+            status_file(&database, &rjct_name).await,
+            ("processed".to_string(), 1)
+        );
+
+        // Polling again leaves the applied files alone, and the same report under another
+        // name is recognised as a duplicate.
+        let copy_name = format!("GRONEXT_ACPT_{}_COPY.xml", run);
+        let enc_data = parties
+            .bank
+            .encrypt_and_sign(&copy_name, answers[0].1.as_bytes())
+            .unwrap();
+        sftp.upload(
+            &format!("{}/{}", STATUS_DIR, copy_name),
+            enc_data.into_bytes(),
+        )
+        .await
+        .unwrap();
+
+        poll_status_files(
+            &database,
+            &common_database,
+            &sftp,
+            &parties.globelise,
+            STATUS_DIR,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            status_file(&database, &acpt_name).await,
+            ("processed".to_string(), 1)
+        );
+        assert_eq!(
+            status_file(&database, &copy_name).await,
+            ("duplicate".to_string(), 1)
+        );
+
+        // Files that cannot be decrypted and verified are retried and never applied.
+        let forged_name = format!("GRONEXT_ACPT_{}_FORGED.xml", run);
+        let enc_data = parties
+            .impostor
+            .encrypt_and_sign(&forged_name, acpt_file(&rejected_id).as_bytes())
+            .unwrap();
+        sftp.upload(
+            &format!("{}/{}", STATUS_DIR, forged_name),
+            enc_data.into_bytes(),
+        )
+        .await
+        .unwrap();
+
+        poll_status_files(
+            &database,
+            &common_database,
+            &sftp,
+            &parties.globelise,
+            STATUS_DIR,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            status_file(&database, &forged_name).await,
+            ("retrying".to_string(), 1)
+        );
+        assert_eq!(record_status(&database, rejected_ulid).await.0, "rcjt");
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
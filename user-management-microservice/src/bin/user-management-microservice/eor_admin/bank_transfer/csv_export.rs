@@ -0,0 +1,142 @@
+//! Plain CSV bulk payment files, for banks that take neither Citibank nor SEPA files.
+//!
+//! The file is returned for an admin to upload to the bank's portal. These banks send no
+//! status reports we can read, so the records stay pending. Admins usually open the file in a
+//! spreadsheet first, so text that a spreadsheet would run as a formula is neutralised.
+
+use axum::async_trait;
+use common_utils::error::{GlobeliseError, GlobeliseResult};
+
+use super::citi_bank::{
+    CitiBankPayRollRecord, ListCitiBankTransferInitiationFilesResponseNoEntries,
+};
+use super::pain001::Amount;
+use super::payment_rail::{PaymentFile, PaymentRail, PaymentRailKind, StatusReport};
+
+const HEADERS: [&str; 11] = [
+    "Reference",
+    "Employee ID",
+    "Employee Name",
+    "Bank Name",
+    "Bank Account Number",
+    "Bank Code",
+    "Branch Code",
+    "SWIFT Code",
+    "Country Code",
+    "Currency",
+    "Amount",
+];
+
+pub struct CsvRail;
+
+#[async_trait]
+impl PaymentRail for CsvRail {
+    fn kind(&self) -> PaymentRailKind {
+        PaymentRailKind::CSV
+    }
+
+    fn bank_name(&self) -> &'static str {
+        "The bank"
+    }
+
+    fn currency(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn country(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn validate_record(&self, record: &CitiBankPayRollRecord) -> GlobeliseResult<()> {
+        Amount::new(&record.currency_code, record.amount).map(|_| ())
+    }
+
+    fn encode(
+        &self,
+        transfer_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+    ) -> GlobeliseResult<PaymentFile> {
+        if records.is_empty() {
+            return Err(GlobeliseError::bad_request(
+                "This file does not have any records",
+            ));
+        }
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .write_record(HEADERS)
+            .map_err(GlobeliseError::internal)?;
+        for record in records {
+            let amount = Amount::new(&record.currency_code, record.amount)?;
+            writer
+                .write_record([
+                    record.ulid.to_simple().to_string().to_uppercase(),
+                    record.employee_id.to_string(),
+                    spreadsheet_text(&record.employee_name),
+                    spreadsheet_text(&record.bank_name),
+                    spreadsheet_text(&record.bank_account_number),
+                    spreadsheet_text(&record.bank_code),
+                    spreadsheet_text(&record.bank_branch_code),
+                    spreadsheet_text(&record.swift_code),
+                    spreadsheet_text(&record.country_code),
+                    amount.currency().to_string(),
+                    amount.value().to_string(),
+                ])
+                .map_err(GlobeliseError::internal)?;
+        }
+        let data = writer.into_inner().map_err(GlobeliseError::internal)?;
+
+        Ok(PaymentFile {
+            file_name: format!(
+                "PAYROLL_{}.csv",
+                transfer_file.ulid.to_simple().to_string().to_uppercase()
+            ),
+            content_type: "text/csv",
+            data,
+        })
+    }
+
+    async fn deliver(&self, file: PaymentFile) -> GlobeliseResult<Option<PaymentFile>> {
+        Ok(Some(file))
+    }
+
+    fn parse_status_report(&self, _: &str, _: &str) -> GlobeliseResult<StatusReport> {
+        Err(GlobeliseError::bad_request(
+            "CSV bulk payments have no status reports",
+        ))
+    }
+}
+
+/// Prefixes text that a spreadsheet would read as a formula with a quote, which makes the
+/// spreadsheet show it as plain text.
+fn spreadsheet_text(value: &str) -> String {
+    match value.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') => format!("'{}", value),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_are_neutralised() {
+        assert_eq!(
+            spreadsheet_text("=HYPERLINK(\"http://evil\")"),
+            "'=HYPERLINK(\"http://evil\")"
+        );
+        assert_eq!(spreadsheet_text("+65 1234"), "'+65 1234");
+        assert_eq!(spreadsheet_text("-1+1"), "'-1+1");
+        assert_eq!(spreadsheet_text("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(spreadsheet_text("\t=1"), "'\t=1");
+    }
+
+    #[test]
+    fn plain_text_is_kept() {
+        assert_eq!(spreadsheet_text("Tan Wei Ming"), "Tan Wei Ming");
+        assert_eq!(spreadsheet_text("0123456789"), "0123456789");
+        assert_eq!(spreadsheet_text("A=B"), "A=B");
+        assert_eq!(spreadsheet_text(""), "");
+    }
+}
@@ -1,4 +1,18 @@
+pub mod bank_statement;
+pub mod camt053_file;
+pub mod camt054_file;
 pub mod citi_bank;
 pub mod citibank_ack_file;
 pub mod citibank_acpt_file;
+pub mod citibank_rail;
 pub mod citibank_rjct_file;
+pub mod citibank_status;
+pub mod csv_export;
+pub mod mt940;
+pub mod openpgp;
+pub mod pain001;
+pub mod payment_rail;
+pub mod payroll_batch;
+pub mod sepa;
+pub mod sftp;
+pub mod transfer_approval;
@@ -0,0 +1,371 @@
+//! Parser for SWIFT MT940 customer statements, for banks that do not offer camt.053.
+//!
+//! Only the fields needed for reconciliation are read: `:20:` and `:28C:` identify the
+//! statement, `:25:` the account, `:60a:` and `:62a:` the balances, and every `:61:` line
+//! with the `:86:` information that follows it becomes a statement line.
+
+use common_utils::error::{GlobeliseError, GlobeliseResult};
+use sqlx::types::Decimal;
+
+use super::bank_statement::{ParsedStatement, ParsedStatementLine};
+
+pub fn parse_mt940(data: &str) -> GlobeliseResult<Vec<ParsedStatement>> {
+    let mut statements = Vec::new();
+    let mut current: Option<ParsedStatement> = None;
+
+    for (tag, value) in fields(data) {
+        if tag == "20" {
+            statements.extend(current.take());
+            current = Some(ParsedStatement {
+                format: "mt940",
+                statement_id: value.trim().to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let statement = current.as_mut().ok_or_else(|| {
+            GlobeliseError::bad_request(format!("MT940 field :{}: comes before :20:", tag))
+        })?;
+        match tag.as_str() {
+            "25" => statement.account = value.trim().to_string(),
+            "28C" => {
+                statement.statement_id = format!("{}/{}", statement.statement_id, value.trim())
+            }
+            "60F" | "60M" => {
+                let (currency, balance) = parse_balance(&value)?;
+                statement.currency = Some(currency);
+                statement.opening_balance = Some(balance);
+            }
+            "62F" | "62M" => statement.closing_balance = Some(parse_balance(&value)?.1),
+            "61" => {
+                let currency = statement.currency.clone().unwrap_or_default();
+                statement.lines.push(parse_line(&value, currency)?);
+            }
+            "86" => {
+                if let Some(line) = statement.lines.last_mut() {
+                    let information = value.replace('\n', " ");
+                    if line.end_to_end_id.is_none() {
+                        line.end_to_end_id = end_to_end_reference(&information);
+                    }
+                    line.remittance_information = Some(information);
+                }
+            }
+            _ => {}
+        }
+    }
+    statements.extend(current);
+
+    if statements.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "The file does not contain an MT940 statement",
+        ));
+    }
+    if let Some(statement) = statements.iter().find(|s| s.account.is_empty()) {
+        return Err(GlobeliseError::bad_request(format!(
+            "MT940 statement {} has no account (:25:)",
+            statement.statement_id
+        )));
+    }
+
+    Ok(statements)
+}
+
+/// Splits the text blocks of the messages into `(tag, value)` fields.
+///
+/// Values that span several lines keep their line breaks.
+fn fields(data: &str) -> Vec<(String, String)> {
+    // Messages wrapped in SWIFT blocks only carry the statement in block 4.
+    let text = if data.contains("{4:") {
+        data.split("{4:")
+            .skip(1)
+            .map(|block| block.split("-}").next().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        data.to_string()
+    };
+
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end();
+        match field_start(line) {
+            Some((tag, value)) => fields.push((tag.to_string(), value.to_string())),
+            // The end of a message.
+            None if line == "-" => {}
+            None => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// Recognises a line like `:61:...` or `:28C:...`.
+fn field_start(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix(':')?;
+    let (tag, value) = rest.split_once(':')?;
+    let valid_tag = (2..=3).contains(&tag.len())
+        && tag.chars().take(2).all(|c| c.is_ascii_digit())
+        && tag.chars().skip(2).all(|c| c.is_ascii_uppercase());
+    valid_tag.then(|| (tag, value))
+}
+
+/// Parses a balance like `C220801EUR1234,56` into its currency and signed amount.
+fn parse_balance(value: &str) -> GlobeliseResult<(String, Decimal)> {
+    let invalid = || GlobeliseError::bad_request(format!("Invalid MT940 balance '{}'", value));
+    let value = value.trim();
+    if value.len() < 11 || !value.is_ascii() {
+        return Err(invalid());
+    }
+
+    let currency = value[7..10].to_string();
+    let amount = parse_amount(&value[10..]).ok_or_else(invalid)?;
+    match &value[..1] {
+        "C" => Ok((currency, amount)),
+        "D" => Ok((currency, -amount)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a statement line like `2208010801DR1234,56NTRFREF123//BANKREF`.
+fn parse_line(value: &str, currency: String) -> GlobeliseResult<ParsedStatementLine> {
+    let first_line = value.lines().next().unwrap_or_default();
+    let invalid =
+        || GlobeliseError::bad_request(format!("Invalid MT940 statement line '{}'", first_line));
+    let chars = first_line.chars().collect::<Vec<_>>();
+    let is_digits = |from: usize, to: usize| {
+        chars.len() >= to && chars[from..to].iter().all(|c| c.is_ascii_digit())
+    };
+
+    if !is_digits(0, 6) {
+        return Err(invalid());
+    }
+    let value_date = date(&chars[..6]);
+    let mut i = 6;
+
+    // The optional entry date only has a month and a day.
+    let booking_date = if is_digits(i, i + 4) {
+        i += 4;
+        Some(format!(
+            "{}-{}",
+            &value_date[..4],
+            chars[i - 4..i]
+                .chunks(2)
+                .map(|part| part.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("-")
+        ))
+    } else {
+        None
+    };
+
+    // A reversed credit takes money out of the account, a reversed debit puts it back.
+    let mark = chars[i..].iter().take(2).collect::<String>();
+    let credit_debit = if mark == "RC" {
+        i += 2;
+        "DBIT"
+    } else if mark == "RD" {
+        i += 2;
+        "CRDT"
+    } else if mark.starts_with('C') {
+        i += 1;
+        "CRDT"
+    } else if mark.starts_with('D') {
+        i += 1;
+        "DBIT"
+    } else {
+        return Err(invalid());
+    };
+
+    // The optional funds code.
+    if chars.get(i).map_or(false, |c| c.is_ascii_alphabetic()) {
+        i += 1;
+    }
+
+    let amount_start = i;
+    while chars
+        .get(i)
+        .map_or(false, |c| c.is_ascii_digit() || *c == ',')
+    {
+        i += 1;
+    }
+    let amount =
+        parse_amount(&chars[amount_start..i].iter().collect::<String>()).ok_or_else(invalid)?;
+
+    // The transaction type, e.g. NTRF.
+    if chars.len() < i + 4 {
+        return Err(invalid());
+    }
+    i += 4;
+
+    let references = chars[i..].iter().collect::<String>();
+    let (customer_reference, bank_reference) = match references.split_once("//") {
+        Some((customer_reference, bank_reference)) => (
+            customer_reference.to_string(),
+            Some(bank_reference.to_string()),
+        ),
+        None => (references, None),
+    };
+    let customer_reference =
+        Some(customer_reference).filter(|reference| !reference.is_empty() && reference != "NONREF");
+
+    Ok(ParsedStatementLine {
+        booking_date: booking_date.or_else(|| Some(value_date.clone())),
+        value_date: Some(value_date),
+        credit_debit,
+        amount,
+        currency,
+        end_to_end_id: customer_reference.clone(),
+        reference: bank_reference.or(customer_reference),
+        ..Default::default()
+    })
+}
+
+/// Turns `YYMMDD` into `YYYY-MM-DD`.
+fn date(chars: &[char]) -> String {
+    let digits = chars.iter().collect::<String>();
+    format!("20{}-{}-{}", &digits[..2], &digits[2..4], &digits[4..6])
+}
+
+/// Parses an amount with a decimal comma, like `1234,56` or `1234,`.
+fn parse_amount(value: &str) -> Option<Decimal> {
+    let value = value.trim().replace(',', ".");
+    let value = value.strip_suffix('.').unwrap_or(&value);
+    value.parse().ok()
+}
+
+/// Finds the end-to-end reference in structured `:86:` information, e.g. `EREF+ABC123`.
+fn end_to_end_reference(information: &str) -> Option<String> {
+    let (_, rest) = information.split_once("EREF+")?;
+    let reference = rest
+        .split(|c: char| c.is_whitespace() || c == '?')
+        .next()
+        .unwrap_or_default();
+    Some(reference.to_string())
+        .filter(|reference| !reference.is_empty() && reference != "NOTPROVIDED")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    const STATEMENT: &str = "\
+:20:STMT20220801
+:25:DE89370400440532013000
+:28C:00001/001
+:60F:C220801EUR1000,00
+:61:2208010801D250,50NTRFINV-42//BANKREF1
+:86:Salary August EREF+E2E-0001 SVWZ+Payroll
+:61:220802C1000,NTRFNONREF
+:86:Top up
+EREF+E2E-0002
+:62F:C220802EUR1749,50
+-
+";
+
+    #[test]
+    fn statements_are_read_with_their_balances() {
+        let statements = parse_mt940(STATEMENT).unwrap();
+        assert_eq!(statements.len(), 1);
+
+        let statement = &statements[0];
+        assert_eq!(statement.format, "mt940");
+        assert_eq!(statement.statement_id, "STMT20220801/00001/001");
+        assert_eq!(statement.account, "DE89370400440532013000");
+        assert_eq!(statement.currency.as_deref(), Some("EUR"));
+        assert_eq!(statement.opening_balance, Some(dec("1000.00")));
+        assert_eq!(statement.closing_balance, Some(dec("1749.50")));
+        assert_eq!(statement.lines.len(), 2);
+    }
+
+    #[test]
+    fn statement_lines_keep_their_references() {
+        let statements = parse_mt940(STATEMENT).unwrap();
+        let lines = &statements[0].lines;
+
+        let debit = &lines[0];
+        assert_eq!(debit.value_date.as_deref(), Some("2022-08-01"));
+        assert_eq!(debit.booking_date.as_deref(), Some("2022-08-01"));
+        assert_eq!(debit.credit_debit, "DBIT");
+        assert_eq!(debit.amount, dec("250.50"));
+        assert_eq!(debit.currency, "EUR");
+        assert_eq!(debit.end_to_end_id.as_deref(), Some("INV-42"));
+        assert_eq!(debit.reference.as_deref(), Some("BANKREF1"));
+        assert_eq!(
+            debit.remittance_information.as_deref(),
+            Some("Salary August EREF+E2E-0001 SVWZ+Payroll")
+        );
+
+        // Without a customer reference, the end-to-end reference comes from :86:.
+        let credit = &lines[1];
+        assert_eq!(credit.value_date.as_deref(), Some("2022-08-02"));
+        assert_eq!(credit.booking_date.as_deref(), Some("2022-08-02"));
+        assert_eq!(credit.credit_debit, "CRDT");
+        assert_eq!(credit.amount, dec("1000"));
+        assert_eq!(credit.end_to_end_id.as_deref(), Some("E2E-0002"));
+        assert_eq!(credit.reference, None);
+        assert_eq!(
+            credit.remittance_information.as_deref(),
+            Some("Top up EREF+E2E-0002")
+        );
+    }
+
+    #[test]
+    fn reversals_move_money_the_other_way() {
+        let reversed_credit = parse_line("220803RC100,00NTRFREF1", "SGD".to_string()).unwrap();
+        assert_eq!(reversed_credit.credit_debit, "DBIT");
+        assert_eq!(reversed_credit.amount, dec("100"));
+
+        let reversed_debit = parse_line("220803RD100,00NTRFREF2", "SGD".to_string()).unwrap();
+        assert_eq!(reversed_debit.credit_debit, "CRDT");
+
+        // With a funds code between the mark and the amount.
+        let line = parse_line("220803DR5,NMSCNONREF", "SGD".to_string()).unwrap();
+        assert_eq!(line.credit_debit, "DBIT");
+        assert_eq!(line.amount, dec("5"));
+        assert_eq!(line.end_to_end_id, None);
+    }
+
+    #[test]
+    fn swift_blocks_and_several_statements_are_read() {
+        let data = "{1:F01BANKSGSGAXXX0000000000}{2:O940}{4:
+:20:A
+:25:SG-ACCOUNT
+:60F:D220801SGD10,00
+:62F:C220801SGD0,
+-}{5:}
+{1:F01BANKSGSGAXXX0000000000}{2:O940}{4:
+:20:B
+:25:SG-ACCOUNT
+:60M:C220802SGD0,
+:62M:C220802SGD0,
+-}";
+
+        let statements = parse_mt940(data).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].statement_id, "A");
+        assert_eq!(statements[0].currency.as_deref(), Some("SGD"));
+        assert_eq!(statements[0].opening_balance, Some(dec("-10")));
+        assert_eq!(statements[0].closing_balance, Some(dec("0")));
+        assert_eq!(statements[1].statement_id, "B");
+        assert!(statements[1].lines.is_empty());
+    }
+
+    #[test]
+    fn malformed_statements_are_rejected() {
+        assert!(parse_mt940("").is_err());
+        assert!(parse_mt940(":25:ACCOUNT\n:20:A\n").is_err());
+        assert!(parse_mt940(":20:A\n:60F:C220801SGD1,00\n").is_err());
+        assert!(parse_mt940(":20:A\n:25:ACCOUNT\n:60F:X220801SGD1,00\n").is_err());
+        assert!(parse_mt940(":20:A\n:25:ACCOUNT\n:61:NOTALINE\n").is_err());
+        assert!(parse_mt940(":20:A\n:25:ACCOUNT\n:61:220801C1,00NT\n").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! In-process OpenPGP encryption and signing of the files exchanged with the bank.
+//!
+//! Payment files are signed with our secret key and encrypted to the bank's public key.
+//! Files from the bank are decrypted with our secret key and must carry a valid signature
+//! from the bank's public key. Nothing is written to disk along the way.
+
+use common_utils::error::{GlobeliseError, GlobeliseResult};
+use once_cell::sync::OnceCell;
+use pgp::{
+    composed::message::Message,
+    crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm},
+    types::CompressionAlgorithm,
+    Deserializable, SignedPublicKey, SignedPublicSubKey, SignedSecretKey,
+};
+
+static CITIBANK_PGP_KEYS: OnceCell<PgpKeys> = OnceCell::new();
+
+/// Returns the keys for the files exchanged with Citibank, loading them on first use.
+pub fn citibank_pgp_keys() -> GlobeliseResult<&'static PgpKeys> {
+    CITIBANK_PGP_KEYS.get_or_try_init(PgpKeys::from_env)
+}
+
+/// Our key pair and the bank's public key.
+pub struct PgpKeys {
+    secret_key: SignedSecretKey,
+    passphrase: String,
+    bank_public_key: SignedPublicKey,
+}
+
+impl PgpKeys {
+    /// Creates the keys from ASCII armored key blocks.
+    pub fn from_armored(
+        secret_key: &str,
+        passphrase: String,
+        bank_public_key: &str,
+    ) -> GlobeliseResult<Self> {
+        let (secret_key, _) = SignedSecretKey::from_string(secret_key)
+            .map_err(|e| GlobeliseError::internal(format!("Invalid PGP secret key: {}", e)))?;
+        secret_key
+            .verify()
+            .map_err(|e| GlobeliseError::internal(format!("Invalid PGP secret key: {}", e)))?;
+
+        let (bank_public_key, _) = SignedPublicKey::from_string(bank_public_key)
+            .map_err(|e| GlobeliseError::internal(format!("Invalid bank PGP public key: {}", e)))?;
+        bank_public_key
+            .verify()
+            .map_err(|e| GlobeliseError::internal(format!("Invalid bank PGP public key: {}", e)))?;
+
+        Ok(Self {
+            secret_key,
+            passphrase,
+            bank_public_key,
+        })
+    }
+
+    /// Loads the keys from the files configured in the environment.
+    ///
+    /// - `CITIBANK_PGP_SECRET_KEY_FILE`: our armored secret key
+    /// - `CITIBANK_PGP_SECRET_KEY_PASSPHRASE`: passphrase of our secret key, if any
+    /// - `CITIBANK_PGP_PUBLIC_KEY_FILE`: the bank's armored public key
+    pub fn from_env() -> GlobeliseResult<Self> {
+        let secret_key_file = std::env::var("CITIBANK_PGP_SECRET_KEY_FILE")?;
+        let passphrase = std::env::var("CITIBANK_PGP_SECRET_KEY_PASSPHRASE").unwrap_or_default();
+        let bank_public_key_file = std::env::var("CITIBANK_PGP_PUBLIC_KEY_FILE")?;
+
+        Self::from_armored(
+            &std::fs::read_to_string(&secret_key_file)?,
+            passphrase,
+            &std::fs::read_to_string(&bank_public_key_file)?,
+        )
+    }
+
+    /// Signs the data with our key and encrypts it to the bank's key.
+    ///
+    /// Returns the ASCII armored message.
+    pub fn encrypt_and_sign(&self, file_name: &str, data: &[u8]) -> GlobeliseResult<String> {
+        let encryption_key = self.bank_encryption_subkey()?;
+        let passphrase = self.passphrase.clone();
+
+        let message = Message::new_literal_bytes(file_name, data)
+            .sign(&self.secret_key, || passphrase, HashAlgorithm::SHA2_256)
+            .map_err(|e| GlobeliseError::internal(format!("Cannot sign file: {}", e)))?
+            .compress(CompressionAlgorithm::ZLIB)
+            .map_err(|e| GlobeliseError::internal(format!("Cannot compress file: {}", e)))?
+            .encrypt_to_keys(
+                &mut rand::thread_rng(),
+                SymmetricKeyAlgorithm::AES256,
+                &[encryption_key],
+            )
+            .map_err(|e| GlobeliseError::internal(format!("Cannot encrypt file: {}", e)))?;
+
+        message
+            .to_armored_string(None)
+            .map_err(|e| GlobeliseError::internal(format!("Cannot armor file: {}", e)))
+    }
+
+    /// Decrypts an ASCII armored message with our key and checks that it is signed by the bank.
+    pub fn decrypt_and_verify(&self, armored: &str) -> GlobeliseResult<Vec<u8>> {
+        let (message, _) = Message::from_string(armored)
+            .map_err(|e| GlobeliseError::bad_request(format!("Invalid PGP message: {}", e)))?;
+
+        let passphrase = self.passphrase.clone();
+        let (mut decrypter, _) = message
+            .decrypt(String::new, || passphrase, &[&self.secret_key])
+            .map_err(|e| GlobeliseError::bad_request(format!("Cannot decrypt file: {}", e)))?;
+        let message = decrypter
+            .next()
+            .ok_or_else(|| GlobeliseError::bad_request("Encrypted file is empty"))?
+            .and_then(Message::decompress)
+            .map_err(|e| GlobeliseError::bad_request(format!("Cannot decrypt file: {}", e)))?;
+
+        // `verify` accepts a message without any signature, so insist on a signed one.
+        if !matches!(message, Message::Signed { .. }) {
+            return Err(GlobeliseError::bad_request("File is not signed"));
+        }
+        let verified = message.verify(&self.bank_public_key).is_ok()
+            || self
+                .bank_public_key
+                .public_subkeys
+                .iter()
+                .any(|subkey| message.verify(subkey).is_ok());
+        if !verified {
+            return Err(GlobeliseError::bad_request(
+                "File is not signed by the bank's key",
+            ));
+        }
+
+        message
+            .get_content()
+            .map_err(|e| GlobeliseError::bad_request(format!("Cannot read file: {}", e)))?
+            .ok_or_else(|| GlobeliseError::bad_request("Decrypted file is empty"))
+    }
+
+    fn bank_encryption_subkey(&self) -> GlobeliseResult<&SignedPublicSubKey> {
+        self.bank_public_key
+            .public_subkeys
+            .iter()
+            .find(|subkey| {
+                subkey.signatures.iter().any(|signature| {
+                    let flags = signature.key_flags();
+                    flags.encrypt_comms() || flags.encrypt_storage()
+                })
+            })
+            .ok_or_else(|| {
+                GlobeliseError::internal("Bank PGP public key does not have an encryption subkey")
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use pgp::{
+        composed::{KeyType, SecretKeyParamsBuilder, SubkeyParamsBuilder},
+        ser::Serialize,
+    };
+
+    use super::*;
+
+    /// Generates an armored secret key and its armored public key.
+    pub fn generate_key(user_id: &str) -> (String, String) {
+        let params = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::EdDSA)
+            .can_sign(true)
+            .can_create_certificates(true)
+            .primary_user_id(user_id.to_string())
+            .subkey(
+                SubkeyParamsBuilder::default()
+                    .key_type(KeyType::ECDH)
+                    .can_encrypt(true)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let secret_key = params.generate().unwrap().sign(String::new).unwrap();
+        let public_key = secret_key
+            .public_key()
+            .sign(&secret_key, String::new)
+            .unwrap();
+
+        (
+            secret_key.to_armored_string(None).unwrap(),
+            public_key.to_armored_string(None).unwrap(),
+        )
+    }
+
+    /// Our keys, the bank's keys and the keys of someone pretending to be us.
+    pub struct Parties {
+        pub globelise: PgpKeys,
+        pub bank: PgpKeys,
+        pub impostor: PgpKeys,
+    }
+
+    pub fn parties() -> Parties {
+        let (globelise_secret, globelise_public) =
+            generate_key("Globelise <payroll@globelise.com>");
+        let (bank_secret, bank_public) = generate_key("Bank <files@bank.example>");
+        let (impostor_secret, _) = generate_key("Impostor <files@impostor.example>");
+
+        Parties {
+            globelise: PgpKeys::from_armored(&globelise_secret, String::new(), &bank_public)
+                .unwrap(),
+            bank: PgpKeys::from_armored(&bank_secret, String::new(), &globelise_public).unwrap(),
+            impostor: PgpKeys::from_armored(&impostor_secret, String::new(), &bank_public).unwrap(),
+        }
+    }
+
+    #[test]
+    fn encrypted_files_round_trip() {
+        let parties = parties();
+        let data = b"<Document>payroll</Document>";
+
+        let armored = parties
+            .globelise
+            .encrypt_and_sign("payroll.xml", data)
+            .unwrap();
+        assert!(armored.starts_with("-----BEGIN PGP MESSAGE-----"));
+        assert_eq!(parties.bank.decrypt_and_verify(&armored).unwrap(), data);
+
+        let reply = parties.bank.encrypt_and_sign("ACK.xml", b"ACK").unwrap();
+        assert_eq!(
+            parties.globelise.decrypt_and_verify(&reply).unwrap(),
+            b"ACK"
+        );
+    }
+
+    #[test]
+    fn files_from_another_signer_are_rejected() {
+        let parties = parties();
+
+        let armored = parties
+            .impostor
+            .encrypt_and_sign("payroll.xml", b"payroll")
+            .unwrap();
+        assert!(parties.bank.decrypt_and_verify(&armored).is_err());
+    }
+
+    #[test]
+    fn unsigned_files_are_rejected() {
+        let parties = parties();
+
+        let unsigned = Message::new_literal_bytes("payroll.xml", b"payroll")
+            .compress(CompressionAlgorithm::ZLIB)
+            .unwrap()
+            .encrypt_to_keys(
+                &mut rand::thread_rng(),
+                SymmetricKeyAlgorithm::AES256,
+                &[parties.globelise.bank_encryption_subkey().unwrap()],
+            )
+            .unwrap()
+            .to_armored_string(None)
+            .unwrap();
+
+        assert!(parties.bank.decrypt_and_verify(&unsigned).is_err());
+    }
+
+    #[test]
+    fn tampered_files_are_rejected() {
+        let parties = parties();
+
+        let armored = parties
+            .globelise
+            .encrypt_and_sign("payroll.xml", b"payroll")
+            .unwrap();
+        let (message, _) = Message::from_string(&armored).unwrap();
+        let mut bytes = message.to_bytes().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        let tampered = Message::from_bytes(&bytes[..])
+            .unwrap()
+            .to_armored_string(None)
+            .unwrap();
+
+        assert!(parties.bank.decrypt_and_verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        let parties = parties();
+
+        assert!(parties.bank.decrypt_and_verify("not a message").is_err());
+    }
+}
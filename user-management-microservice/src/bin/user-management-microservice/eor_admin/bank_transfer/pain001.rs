@@ -0,0 +1,572 @@
+//! Typed model of an ISO 20022 `pain.001.001.03` customer credit transfer initiation.
+//!
+//! Every free text field goes through [`Text`], which enforces the length limits of the
+//! schema, and every amount goes through [`Amount`], which enforces the precision of its
+//! currency. [`Document::to_xml`] escapes all values, and [`validate_against_schema`]
+//! checks the result against the bundled XSD before it leaves the service.
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Display},
+};
+
+use common_utils::{
+    error::{GlobeliseError, GlobeliseResult},
+    money::minor_units,
+};
+use libxml::{
+    parser::Parser,
+    schemas::{SchemaParserContext, SchemaValidationContext},
+};
+use sqlx::types::Decimal;
+
+/// The official `pain.001.001.03` schema.
+static PAIN_001_001_03_XSD: &str = include_str!("pain.001.001.03.xsd");
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:pain.001.001.03";
+
+/// Text that is between 1 and `MAX` characters long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text<const MAX: usize>(String);
+
+pub type Max34Text = Text<34>;
+pub type Max35Text = Text<35>;
+pub type Max70Text = Text<70>;
+pub type Max140Text = Text<140>;
+
+impl<const MAX: usize> Text<MAX> {
+    /// Creates the text, rejecting values that are empty, too long or contain characters
+    /// that cannot appear in an XML document.
+    ///
+    /// `field` is only used to describe the problem in the error.
+    pub fn new<S: ToString>(field: &str, value: S) -> GlobeliseResult<Self> {
+        let value = value.to_string().trim().to_string();
+        if value.is_empty() {
+            return Err(GlobeliseError::bad_request(format!(
+                "{} must not be empty",
+                field
+            )));
+        }
+        if value.chars().count() > MAX {
+            return Err(GlobeliseError::bad_request(format!(
+                "{} must be at most {} characters long, got '{}'",
+                field, MAX, value
+            )));
+        }
+        if value.chars().any(|c| c.is_control()) {
+            return Err(GlobeliseError::bad_request(format!(
+                "{} contains control characters",
+                field
+            )));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<const MAX: usize> Display for Text<MAX> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A positive amount of money in a given currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Amount {
+    currency: String,
+    value: Decimal,
+}
+
+impl Amount {
+    /// Creates the amount, rejecting non-positive values and values with more decimal
+    /// places than the currency allows.
+    pub fn new(currency: &str, value: Decimal) -> GlobeliseResult<Self> {
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(GlobeliseError::bad_request(format!(
+                "'{}' is not a valid currency code",
+                currency
+            )));
+        }
+        if value <= Decimal::ZERO {
+            return Err(GlobeliseError::bad_request(format!(
+                "Amount must be positive, got {}",
+                value
+            )));
+        }
+
+        let minor_units = minor_units(currency);
+        if value.normalize().scale() > minor_units {
+            return Err(GlobeliseError::bad_request(format!(
+                "{} amounts can have at most {} decimal places, got {}",
+                currency, minor_units, value
+            )));
+        }
+
+        let mut value = value;
+        value.rescale(minor_units);
+
+        Ok(Self {
+            currency: currency.to_string(),
+            value,
+        })
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn value(&self) -> Decimal {
+        self.value
+    }
+}
+
+/// A code from an external ISO 20022 code list or a proprietary value.
+#[derive(Debug, Clone)]
+pub enum CodeOrProprietary {
+    Code(Max35Text),
+    Proprietary(Max35Text),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChargeBearer {
+    Debtor,
+    /// As agreed in the service level, which is what SEPA requires.
+    FollowingServiceLevel,
+}
+
+impl ChargeBearer {
+    fn code(&self) -> &'static str {
+        match self {
+            ChargeBearer::Debtor => "DEBT",
+            ChargeBearer::FollowingServiceLevel => "SLEV",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PostalAddress {
+    pub country: Option<String>,
+    pub address_lines: Vec<Max70Text>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Party {
+    pub name: Option<Max140Text>,
+    pub postal_address: Option<PostalAddress>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AccountIdentification {
+    Iban(String),
+    Other(Max34Text),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FinancialInstitution {
+    pub bic: Option<String>,
+    pub clearing_system_member_id: Option<Max35Text>,
+    pub name: Option<Max140Text>,
+    pub postal_address: Option<PostalAddress>,
+    /// Any other identification, e.g. `NOTPROVIDED` when the BIC is unknown.
+    pub other_id: Option<Max35Text>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PaymentTypeInformation {
+    pub service_level: Option<CodeOrProprietary>,
+    pub local_instrument: Option<CodeOrProprietary>,
+    pub category_purpose: Option<CodeOrProprietary>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreditTransferTransaction {
+    pub end_to_end_id: Max35Text,
+    pub amount: Amount,
+    pub charge_bearer: Option<ChargeBearer>,
+    pub creditor_agent: FinancialInstitution,
+    pub creditor: Party,
+    pub creditor_account: AccountIdentification,
+    pub purpose: Option<CodeOrProprietary>,
+    pub remittance_information: Option<Max140Text>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentInformation {
+    pub payment_information_id: Max35Text,
+    pub payment_type_information: Option<PaymentTypeInformation>,
+    /// Formatted as `YYYY-MM-DD`.
+    pub requested_execution_date: String,
+    pub debtor: Party,
+    pub debtor_account: AccountIdentification,
+    pub debtor_agent: FinancialInstitution,
+    pub transactions: Vec<CreditTransferTransaction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupHeader {
+    pub message_id: Max35Text,
+    /// Formatted as `YYYY-MM-DDThh:mm:ss`.
+    pub creation_date_time: String,
+    /// Whether to include the sum of all amounts in the header.
+    pub include_control_sum: bool,
+    pub initiating_party: Party,
+}
+
+/// A `pain.001.001.03` document.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub group_header: GroupHeader,
+    pub payment_information: Vec<PaymentInformation>,
+}
+
+impl Document {
+    fn transactions(&self) -> impl Iterator<Item = &CreditTransferTransaction> {
+        self.payment_information
+            .iter()
+            .flat_map(|payment| payment.transactions.iter())
+    }
+
+    /// Number of credit transfers in the document.
+    pub fn number_of_transactions(&self) -> usize {
+        self.transactions().count()
+    }
+
+    /// Sum of all amounts in the document, regardless of currency.
+    pub fn control_sum(&self) -> Decimal {
+        self.transactions()
+            .map(|transaction| transaction.amount.value())
+            .sum()
+    }
+
+    /// Serialises the document and checks it against the schema.
+    pub fn to_validated_xml(&self) -> GlobeliseResult<String> {
+        let xml = self.to_xml();
+        validate_against_schema(&xml)?;
+        Ok(xml)
+    }
+
+    /// Serialises the document.
+    pub fn to_xml(&self) -> String {
+        let mut w = XmlWriter::default();
+        w.buf.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        w.start_with_attribute("Document", "xmlns", NAMESPACE);
+        w.start("CstmrCdtTrfInitn");
+
+        let header = &self.group_header;
+        w.start("GrpHdr");
+        w.element("MsgId", header.message_id.as_str());
+        w.element("CreDtTm", &header.creation_date_time);
+        w.element("NbOfTxs", &self.number_of_transactions().to_string());
+        if header.include_control_sum {
+            w.element("CtrlSum", &self.control_sum().to_string());
+        }
+        w.party("InitgPty", &header.initiating_party);
+        w.end("GrpHdr");
+
+        for payment in &self.payment_information {
+            w.start("PmtInf");
+            w.element("PmtInfId", payment.payment_information_id.as_str());
+            w.element("PmtMtd", "TRF");
+            if let Some(info) = &payment.payment_type_information {
+                w.start("PmtTpInf");
+                w.optional_choice("SvcLvl", info.service_level.as_ref());
+                w.optional_choice("LclInstrm", info.local_instrument.as_ref());
+                w.optional_choice("CtgyPurp", info.category_purpose.as_ref());
+                w.end("PmtTpInf");
+            }
+            w.element("ReqdExctnDt", &payment.requested_execution_date);
+            w.party("Dbtr", &payment.debtor);
+            w.account("DbtrAcct", &payment.debtor_account);
+            w.agent("DbtrAgt", &payment.debtor_agent);
+
+            for transaction in &payment.transactions {
+                w.start("CdtTrfTxInf");
+                w.start("PmtId");
+                w.element("EndToEndId", transaction.end_to_end_id.as_str());
+                w.end("PmtId");
+                w.start("Amt");
+                w.element_with_attribute(
+                    "InstdAmt",
+                    "Ccy",
+                    transaction.amount.currency(),
+                    &transaction.amount.value().to_string(),
+                );
+                w.end("Amt");
+                if let Some(charge_bearer) = transaction.charge_bearer {
+                    w.element("ChrgBr", charge_bearer.code());
+                }
+                w.agent("CdtrAgt", &transaction.creditor_agent);
+                w.party("Cdtr", &transaction.creditor);
+                w.account("CdtrAcct", &transaction.creditor_account);
+                w.optional_choice("Purp", transaction.purpose.as_ref());
+                if let Some(remittance_information) = &transaction.remittance_information {
+                    w.start("RmtInf");
+                    w.element("Ustrd", remittance_information.as_str());
+                    w.end("RmtInf");
+                }
+                w.end("CdtTrfTxInf");
+            }
+
+            w.end("PmtInf");
+        }
+
+        w.end("CstmrCdtTrfInitn");
+        w.end("Document");
+        w.buf.push('\n');
+        w.buf
+    }
+}
+
+thread_local! {
+    /// The parsed schema, loaded once per thread since libxml contexts cannot be shared
+    /// between threads.
+    static PAIN_001_001_03_SCHEMA: RefCell<Option<SchemaValidationContext>> = RefCell::new(None);
+}
+
+/// Checks a serialised document against the bundled `pain.001.001.03` schema.
+pub fn validate_against_schema(xml: &str) -> GlobeliseResult<()> {
+    let document = Parser::default()
+        .parse_string(xml)
+        .map_err(|e| GlobeliseError::internal(format!("Cannot parse pain.001 file: {:?}", e)))?;
+
+    PAIN_001_001_03_SCHEMA.with(|schema| {
+        let mut schema = schema.borrow_mut();
+        let validation_context = match &mut *schema {
+            Some(validation_context) => validation_context,
+            None => schema.insert(load_schema()?),
+        };
+
+        validation_context
+            .validate_document(&document)
+            .map_err(|errors| {
+                GlobeliseError::internal(format!(
+                    "Generated pain.001 file does not match the schema: {}",
+                    describe_schema_errors(errors)
+                ))
+            })
+    })
+}
+
+fn load_schema() -> GlobeliseResult<SchemaValidationContext> {
+    let mut schema_parser = SchemaParserContext::from_buffer(PAIN_001_001_03_XSD);
+    SchemaValidationContext::from_parser(&mut schema_parser).map_err(|errors| {
+        GlobeliseError::internal(format!(
+            "Cannot load pain.001 schema: {}",
+            describe_schema_errors(errors)
+        ))
+    })
+}
+
+fn describe_schema_errors(errors: Vec<libxml::error::StructuredError>) -> String {
+    errors
+        .into_iter()
+        .filter_map(|error| error.message)
+        .map(|message| message.trim().to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Escapes text for use in XML element content and attribute values.
+pub fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Minimal indenting XML writer.
+#[derive(Default)]
+struct XmlWriter {
+    buf: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn indent(&mut self) {
+        self.buf.push('\n');
+        for _ in 0..self.depth {
+            self.buf.push_str("  ");
+        }
+    }
+
+    fn start(&mut self, tag: &str) {
+        self.indent();
+        self.buf.push_str(&format!("<{}>", tag));
+        self.depth += 1;
+    }
+
+    fn start_with_attribute(&mut self, tag: &str, name: &str, value: &str) {
+        self.indent();
+        self.buf
+            .push_str(&format!(r#"<{} {}="{}">"#, tag, name, escape_xml(value)));
+        self.depth += 1;
+    }
+
+    fn end(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.buf.push_str(&format!("</{}>", tag));
+    }
+
+    fn element(&mut self, tag: &str, value: &str) {
+        self.indent();
+        self.buf
+            .push_str(&format!("<{}>{}</{}>", tag, escape_xml(value), tag));
+    }
+
+    fn element_with_attribute(&mut self, tag: &str, name: &str, attribute: &str, value: &str) {
+        self.indent();
+        self.buf.push_str(&format!(
+            r#"<{} {}="{}">{}</{}>"#,
+            tag,
+            name,
+            escape_xml(attribute),
+            escape_xml(value),
+            tag
+        ));
+    }
+
+    fn optional_choice(&mut self, tag: &str, choice: Option<&CodeOrProprietary>) {
+        if let Some(choice) = choice {
+            self.start(tag);
+            match choice {
+                CodeOrProprietary::Code(code) => self.element("Cd", code.as_str()),
+                CodeOrProprietary::Proprietary(value) => self.element("Prtry", value.as_str()),
+            }
+            self.end(tag);
+        }
+    }
+
+    fn postal_address(&mut self, address: &PostalAddress) {
+        self.start("PstlAdr");
+        if let Some(country) = &address.country {
+            self.element("Ctry", country);
+        }
+        for line in &address.address_lines {
+            self.element("AdrLine", line.as_str());
+        }
+        self.end("PstlAdr");
+    }
+
+    fn party(&mut self, tag: &str, party: &Party) {
+        self.start(tag);
+        if let Some(name) = &party.name {
+            self.element("Nm", name.as_str());
+        }
+        if let Some(address) = &party.postal_address {
+            self.postal_address(address);
+        }
+        self.end(tag);
+    }
+
+    fn account(&mut self, tag: &str, account: &AccountIdentification) {
+        self.start(tag);
+        self.start("Id");
+        match account {
+            AccountIdentification::Iban(iban) => self.element("IBAN", iban),
+            AccountIdentification::Other(id) => {
+                self.start("Othr");
+                self.element("Id", id.as_str());
+                self.end("Othr");
+            }
+        }
+        self.end("Id");
+        self.end(tag);
+    }
+
+    fn agent(&mut self, tag: &str, agent: &FinancialInstitution) {
+        self.start(tag);
+        self.start("FinInstnId");
+        if let Some(bic) = &agent.bic {
+            self.element("BIC", bic);
+        }
+        if let Some(member_id) = &agent.clearing_system_member_id {
+            self.start("ClrSysMmbId");
+            self.element("MmbId", member_id.as_str());
+            self.end("ClrSysMmbId");
+        }
+        if let Some(name) = &agent.name {
+            self.element("Nm", name.as_str());
+        }
+        if let Some(address) = &agent.postal_address {
+            self.postal_address(address);
+        }
+        if let Some(other_id) = &agent.other_id {
+            self.start("Othr");
+            self.element("Id", other_id.as_str());
+            self.end("Othr");
+        }
+        self.end("FinInstnId");
+        self.end(tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(currency: &str, value: &str) -> GlobeliseResult<Amount> {
+        Amount::new(currency, value.parse().unwrap())
+    }
+
+    #[test]
+    fn amounts_are_padded_to_the_currency_precision() {
+        assert_eq!(amount("SGD", "10.5").unwrap().value().to_string(), "10.50");
+        assert_eq!(amount("VND", "1000").unwrap().value().to_string(), "1000");
+        assert_eq!(amount("KWD", "1.5").unwrap().value().to_string(), "1.500");
+        // Trailing zeros do not count as extra precision.
+        assert_eq!(
+            amount("SGD", "10.500").unwrap().value().to_string(),
+            "10.50"
+        );
+    }
+
+    #[test]
+    fn amounts_with_extra_precision_are_rejected() {
+        assert!(amount("SGD", "10.005").is_err());
+        assert!(amount("VND", "1000.5").is_err());
+    }
+
+    #[test]
+    fn non_positive_amounts_and_bad_currencies_are_rejected() {
+        assert!(amount("SGD", "0").is_err());
+        assert!(amount("SGD", "-1").is_err());
+        assert!(amount("sgd", "1").is_err());
+        assert!(amount("SGDX", "1").is_err());
+    }
+
+    #[test]
+    fn text_length_is_enforced() {
+        assert!(Max35Text::new("MsgId", "").is_err());
+        assert!(Max35Text::new("MsgId", "A".repeat(35)).is_ok());
+        assert!(Max35Text::new("MsgId", "A".repeat(36)).is_err());
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn invalid_documents_fail_schema_validation() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.03"><CstmrCdtTrfInitn/></Document>"#;
+        assert!(validate_against_schema(xml).is_err());
+        // The cached schema is reused for the next document.
+        assert!(validate_against_schema(xml).is_err());
+    }
+}
@@ -0,0 +1,726 @@
+//! Payment rails: the ways a transfer batch can reach the bank that pays it out.
+//!
+//! A rail checks the records of a batch, encodes the batch into the file the bank expects,
+//! hands the file to the bank and parses the status reports the bank sends back. Every
+//! client branch uses one rail, Citibank unless configured otherwise. Rails that are not
+//! connected to their bank return the file so that an admin can upload it to the bank's
+//! portal, and take the bank's status reports as uploads.
+
+use axum::async_trait;
+use axum::extract::{ContentLengthLimit, Extension, Json, Path};
+use common_utils::{
+    custom_serde::FORM_DATA_LENGTH_LIMIT,
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    impl_enum_asfrom_str,
+    jobs::{self, JobContext, JobOutput},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+use super::citi_bank::{
+    CitiBankPayRollRecord, CitiBankTemplate, ListCitiBankTransferInitiationFilesResponseNoEntries,
+};
+use super::citibank_rail::CitiBankRail;
+use super::csv_export::CsvRail;
+use super::sepa::{SepaDebtor, SepaRail};
+
+impl_enum_asfrom_str!(PaymentRailKind, CITIBANK, SEPA, CSV);
+
+pub const SEND_TRANSFER_FILE_JOB: &str = "send-transfer-file";
+
+/// A file to hand to a bank.
+#[serde_as]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PaymentFile {
+    pub file_name: String,
+    pub content_type: &'static str,
+    #[serde_as(as = "Base64")]
+    pub data: Vec<u8>,
+}
+
+/// What a bank reported about the files and records it was sent.
+#[derive(Debug, Default)]
+pub struct StatusReport {
+    pub files: Vec<TransferFileStatus>,
+    pub records: Vec<TransferRecordStatus>,
+    /// Entries of the report that could not be understood.
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct TransferFileStatus {
+    pub file_ulid: Uuid,
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TransferRecordStatus {
+    pub record_ulid: Uuid,
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+#[async_trait]
+pub trait PaymentRail: Send + Sync {
+    fn kind(&self) -> PaymentRailKind;
+
+    /// Name of the bank in notifications.
+    fn bank_name(&self) -> &'static str;
+
+    /// The only currency the rail pays in, if it is limited to one.
+    fn currency(&self) -> Option<&'static str>;
+
+    /// The only country the rail pays into, if it is limited to one.
+    fn country(&self) -> Option<&'static str>;
+
+    /// Checks that a record can be paid through this rail.
+    fn validate_record(&self, record: &CitiBankPayRollRecord) -> GlobeliseResult<()>;
+
+    /// Encodes the records of a transfer file into the file the bank expects.
+    fn encode(
+        &self,
+        transfer_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+    ) -> GlobeliseResult<PaymentFile>;
+
+    /// Hands an encoded file to the bank.
+    ///
+    /// Returns the file if an admin has to take it to the bank instead.
+    async fn deliver(&self, file: PaymentFile) -> GlobeliseResult<Option<PaymentFile>>;
+
+    /// Parses a status report received from the bank.
+    fn parse_status_report(&self, file_name: &str, data: &str) -> GlobeliseResult<StatusReport>;
+}
+
+impl PaymentRailKind {
+    /// Returns the rail without any branch configuration, which is enough to parse its
+    /// status reports.
+    pub fn rail(&self) -> Box<dyn PaymentRail> {
+        match self {
+            PaymentRailKind::CITIBANK => Box::new(CitiBankRail::new(None)),
+            PaymentRailKind::SEPA => Box::new(SepaRail::new(None)),
+            PaymentRailKind::CSV => Box::new(CsvRail),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "kebab-case")]
+pub struct BranchPaymentRail {
+    pub branch_ulid: Uuid,
+    pub payment_rail: PaymentRailKind,
+    pub debtor_name: Option<String>,
+    pub debtor_iban: Option<String>,
+    pub debtor_bic: Option<String>,
+}
+
+impl BranchPaymentRail {
+    /// The rail of a branch that has not chosen one.
+    pub fn default_for(branch_ulid: Uuid) -> Self {
+        Self {
+            branch_ulid,
+            payment_rail: PaymentRailKind::CITIBANK,
+            debtor_name: None,
+            debtor_iban: None,
+            debtor_bic: None,
+        }
+    }
+
+    /// Returns the rail that sends the transfers of the branch.
+    ///
+    /// Citibank transfers are sent from the debtor account of a template, which other rails
+    /// ignore.
+    pub fn rail(&self, template_name: Option<&str>) -> GlobeliseResult<Box<dyn PaymentRail>> {
+        Ok(match self.payment_rail {
+            PaymentRailKind::CITIBANK => citibank_rail(template_name)?,
+            PaymentRailKind::SEPA => Box::new(SepaRail::new(Some(SepaDebtor::new(
+                self.debtor_name.as_deref().unwrap_or_default(),
+                self.debtor_iban.as_deref().unwrap_or_default(),
+                self.debtor_bic.as_deref(),
+            )?))),
+            PaymentRailKind::CSV => Box::new(CsvRail),
+        })
+    }
+}
+
+fn citibank_rail(template_name: Option<&str>) -> GlobeliseResult<Box<dyn PaymentRail>> {
+    let template_name = template_name
+        .ok_or_else(|| GlobeliseError::bad_request("Citibank transfers need a template"))?;
+
+    Ok(Box::new(CitiBankRail::new(Some(
+        CitiBankTemplate::from_name(template_name)?,
+    ))))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InitTransferRequest {
+    pub file_ulid: Uuid,
+    /// Only used by Citibank, eg. citi_bank_sg.xml
+    pub template_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InitTransferResponse {
+    pub payment_rail: PaymentRailKind,
+    /// The file to upload to the bank, for rails that are not connected to it.
+    pub file: Option<PaymentFile>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UploadStatusReportRequest {
+    pub payment_rail: PaymentRailKind,
+    pub file_name: String,
+    #[serde_as(as = "Base64")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UploadStatusReportResponse {
+    /// processed or duplicate
+    pub status: &'static str,
+    /// Entries of the report that could not be applied.
+    pub error: Option<String>,
+}
+
+pub async fn list_payment_rails(
+    _: Token<AdminAccessToken>,
+) -> GlobeliseResult<Json<Vec<PaymentRailKind>>> {
+    Ok(Json(PaymentRailKind::CITIBANK.as_array().to_vec()))
+}
+
+pub async fn get_branch_payment_rail(
+    _: Token<AdminAccessToken>,
+    Path(branch_ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<BranchPaymentRail>> {
+    let database = database.lock().await;
+
+    let result = database.select_one_branch_payment_rail(branch_ulid).await?;
+
+    Ok(Json(result))
+}
+
+pub async fn post_branch_payment_rail(
+    _: Token<AdminAccessToken>,
+    Json(body): Json<BranchPaymentRail>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    if let PaymentRailKind::SEPA = body.payment_rail {
+        // Fails on an incomplete or invalid debtor account.
+        body.rail(None)?;
+    }
+
+    let database = database.lock().await;
+
+    database.upsert_branch_payment_rail(&body).await?;
+
+    Ok(())
+}
+
+/// Queues the sending of an approved transfer file to the bank, see [`run_send_job`].
+///
+/// The file is claimed by moving it to `sending` first, so that it is only ever sent once
+/// even when the request is repeated. It goes back to `approved` if it cannot be sent.
+pub async fn init_transfer(
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<InitTransferRequest>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    {
+        let database = database.lock().await;
+
+        let transaction_file = match database
+            .claim_transfer_for_sending(request.file_ulid, SEND_TRANSFER_FILE_JOB)
+            .await?
+        {
+            Some(transaction_file) => transaction_file,
+            None => {
+                let status = database
+                    .select_transfer_file_status(request.file_ulid)
+                    .await?
+                    .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+                return Err(GlobeliseError::bad_request(format!(
+                    "Only approved files can be sent to the bank, this file is {}",
+                    status
+                )));
+            }
+        };
+
+        // Fails on a missing template or rail configuration before anything is queued.
+        if let Err(e) = prepare_transfer(&database, &transaction_file, &request).await {
+            database
+                .update_status_uploaded_citibank_transfer_initiation_file(
+                    request.file_ulid,
+                    "approved".to_string(),
+                )
+                .await?;
+            return Err(e);
+        }
+    }
+
+    let job = jobs::enqueue(
+        &common_database,
+        SEND_TRANSFER_FILE_JOB,
+        Some(&request.file_ulid.to_string()),
+        &request,
+        // A retry could pay the same records twice.
+        1,
+        Some(claims.payload.ulid),
+    )
+    .await;
+    if job.is_err() {
+        database
+            .lock()
+            .await
+            .update_status_uploaded_citibank_transfer_initiation_file(
+                request.file_ulid,
+                "approved".to_string(),
+            )
+            .await?;
+    }
+
+    Ok(Json(job?))
+}
+
+/// Sends the file claimed by [`init_transfer`] through the rail of its branch.
+///
+/// The result of the job is an [`InitTransferResponse`], with the file to upload to the
+/// bank for rails that are not connected to it.
+pub async fn run_send_job(
+    context: JobContext,
+    database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let request: InitTransferRequest = context.payload()?;
+
+    let transaction_file = database
+        .lock()
+        .await
+        .get_uploaded_citibank_transfer_initiation_file(request.file_ulid)
+        .await?;
+    if transaction_file.status != "sending" {
+        return Err(GlobeliseError::bad_request(format!(
+            "The file is no longer being sent, it is {}",
+            transaction_file.status
+        )));
+    }
+
+    let result = send_transfer(&database, &transaction_file, &request).await;
+
+    let status = if result.is_ok() { "sent" } else { "approved" };
+    database
+        .lock()
+        .await
+        .update_status_uploaded_citibank_transfer_initiation_file(
+            request.file_ulid,
+            status.to_string(),
+        )
+        .await?;
+
+    Ok(Some(JobOutput::json(&result?)?))
+}
+
+async fn send_transfer(
+    database: &SharedDatabase,
+    transaction_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+    request: &InitTransferRequest,
+) -> GlobeliseResult<InitTransferResponse> {
+    let (rail, records) =
+        prepare_transfer(&*database.lock().await, transaction_file, request).await?;
+
+    let file = rail.encode(transaction_file, &records)?;
+    let file = rail.deliver(file).await?;
+
+    Ok(InitTransferResponse {
+        payment_rail: rail.kind(),
+        file,
+    })
+}
+
+/// Finds the rail a claimed file is sent through and the records to send.
+async fn prepare_transfer(
+    database: &Database,
+    transaction_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+    request: &InitTransferRequest,
+) -> GlobeliseResult<(Box<dyn PaymentRail>, Vec<CitiBankPayRollRecord>)> {
+    let rail = match transaction_file.branch_ulid {
+        Some(branch_ulid) => database
+            .select_one_branch_payment_rail(branch_ulid)
+            .await?
+            .rail(request.template_name.as_deref())?,
+        // Uploaded without a branch, these have always gone to Citibank.
+        None => citibank_rail(request.template_name.as_deref())?,
+    };
+
+    let records = database
+        .list_uploaded_citibank_transfer_initiation_files_records(transaction_file.ulid)
+        .await?;
+
+    Ok((rail, records))
+}
+
+/// Applies a status report downloaded from the portal of a bank.
+pub async fn upload_status_report(
+    _: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(request)): ContentLengthLimit<
+        Json<UploadStatusReportRequest>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<UploadStatusReportResponse>> {
+    let rail = request.payment_rail.rail();
+    let raw_data = String::from_utf8(request.data).map_err(GlobeliseError::bad_request)?;
+    // Keeps the names of uploaded reports apart from the files on the Citibank server.
+    let file_name = format!("{}/{}", rail.kind().as_str(), request.file_name);
+
+    if database
+        .lock()
+        .await
+        .claim_citibank_status_file(&file_name, rail.kind())
+        .await?
+        .is_none()
+    {
+        return Err(GlobeliseError::bad_request(
+            "A status report with this name has already been uploaded",
+        ));
+    }
+
+    let hash = content_hash(&raw_data);
+    let claimed = {
+        let database = database.lock().await;
+        if database
+            .is_citibank_status_file_content_processed(&hash)
+            .await?
+        {
+            None
+        } else {
+            Some(
+                database
+                    .claim_citibank_status_file_content(&file_name, &hash)
+                    .await?,
+            )
+        }
+    };
+    let result = match claimed {
+        None => Ok(UploadStatusReportResponse {
+            status: "duplicate",
+            error: None,
+        }),
+        Some(false) => Err(GlobeliseError::bad_request(
+            "The same report is being applied from another file",
+        )),
+        Some(true) => match rail.parse_status_report(&request.file_name, &raw_data) {
+            Ok(report) => apply_status_report(&database, &common_database, rail.as_ref(), report)
+                .await
+                .map(|error| UploadStatusReportResponse {
+                    status: "processed",
+                    error,
+                }),
+            Err(e) => Err(e),
+        },
+    };
+
+    let database = database.lock().await;
+    match result {
+        Ok(response) => {
+            database
+                .update_citibank_status_file(
+                    &file_name,
+                    None,
+                    Some(hash),
+                    response.status,
+                    response.error.clone(),
+                )
+                .await?;
+            Ok(Json(response))
+        }
+        Err(e) => {
+            // Lets the admin upload the report again once the problem is fixed.
+            database
+                .update_citibank_status_file(
+                    &file_name,
+                    None,
+                    None,
+                    "retrying",
+                    Some(e.to_string()),
+                )
+                .await?;
+            Err(e)
+        }
+    }
+}
+
+/// Applies what a bank reported to the transfer files and records.
+///
+/// Returns the problems with individual entries, which do not stop the rest of the report
+/// from being applied.
+pub async fn apply_status_report(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    rail: &dyn PaymentRail,
+    report: StatusReport,
+) -> GlobeliseResult<Option<String>> {
+    let mut problems = report.problems;
+
+    for file in report.files {
+        let transfer_file = match database
+            .lock()
+            .await
+            .select_one_uploaded_citibank_transfer_initiation_file(file.file_ulid)
+            .await?
+        {
+            Some(transfer_file) => transfer_file,
+            None => {
+                problems.push(format!("Unknown file {}", file.file_ulid));
+                continue;
+            }
+        };
+
+        let (status, message) = if file.accepted {
+            (
+                "ack",
+                format!(
+                    "{} accepted the transfer batch {}",
+                    rail.bank_name(),
+                    transfer_file.title_identifier
+                ),
+            )
+        } else {
+            (
+                "rjct",
+                format!(
+                    "{} rejected the transfer batch {}: {}",
+                    rail.bank_name(),
+                    transfer_file.title_identifier,
+                    file.reason.unwrap_or_default()
+                ),
+            )
+        };
+
+        database
+            .lock()
+            .await
+            .update_status_uploaded_citibank_transfer_initiation_file(
+                file.file_ulid,
+                status.to_string(),
+            )
+            .await?;
+        notify(common_database, transfer_file.client_ulid, message).await?;
+    }
+
+    for record in report.records {
+        let reason = record.reason.unwrap_or_default();
+        let summary = {
+            let database = database.lock().await;
+            let (status, description) = if record.accepted {
+                ("acpt", "transaction accepted".to_string())
+            } else {
+                ("rcjt", reason.clone())
+            };
+            database
+                .update_uploaded_citibank_transfer_initiation_file_record_by_cititbank_transaction_response(
+                    record.record_ulid,
+                    status.to_string(),
+                    description,
+                )
+                .await?;
+            database
+                .select_one_transfer_record_summary(record.record_ulid)
+                .await?
+        };
+
+        match summary {
+            Some(_) if record.accepted => {}
+            Some(summary) => {
+                let message = format!(
+                    "{} rejected the transfer to {} in batch {}: {}",
+                    rail.bank_name(),
+                    summary.employee_name,
+                    summary.title_identifier,
+                    reason
+                );
+                notify(common_database, summary.client_ulid, message).await?;
+            }
+            None => problems.push(format!("Unknown record {}", record.record_ulid)),
+        }
+    }
+
+    Ok(Some(problems.join("; ")).filter(|problems| !problems.is_empty()))
+}
+
+/// Reads one of our IDs back from a bank file.
+pub fn parse_ulid(id: &str) -> GlobeliseResult<Uuid> {
+    Uuid::parse_str(id.trim())
+        .map_err(|_| GlobeliseError::bad_request(format!("'{}' is not one of our IDs", id)))
+}
+
+/// Hex encoded SHA-256 of a status report, to recognise a report sent twice.
+pub fn content_hash(raw_data: &str) -> String {
+    digest(&SHA256, raw_data.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Notifies the client and every admin.
+async fn notify(
+    common_database: &CommonDatabase,
+    client_ulid: Uuid,
+    message: String,
+) -> GlobeliseResult<()> {
+    let database = common_database.lock().await;
+    let notification_ulid = database.create_one_user_notification(message).await?;
+    database
+        .create_one_user_see_notification_for_specific_users(&[client_ulid], notification_ulid)
+        .await?;
+    database
+        .create_one_admin_see_notification_for_all_admins(notification_ulid)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, FromRow)]
+pub struct TransferRecordSummary {
+    pub employee_name: String,
+    pub title_identifier: String,
+    pub client_ulid: Uuid,
+}
+
+impl Database {
+    /// Moves an approved file to `sending`.
+    ///
+    /// A file left in `sending` without a queued or running job of `job_kind` for it, e.g.
+    /// because the service stopped while sending it, can be claimed again.
+    ///
+    /// Returns `None` if the file cannot be claimed, e.g. because another request is already
+    /// sending it.
+    pub async fn claim_transfer_for_sending(
+        &self,
+        file_ulid: Uuid,
+        job_kind: &str,
+    ) -> GlobeliseResult<Option<ListCitiBankTransferInitiationFilesResponseNoEntries>> {
+        let result = sqlx::query_as(
+            "
+        UPDATE
+            uploaded_citibank_transfer_initiation_files
+        SET
+            status = 'sending'
+        WHERE
+            ulid = $1 AND (
+                status = 'approved' OR (
+                    status = 'sending' AND
+                    NOT EXISTS (
+                        SELECT
+                            1
+                        FROM
+                            jobs
+                        WHERE
+                            kind = $2 AND
+                            unique_key = $1::text AND
+                            status IN ('queued', 'running')
+                    )
+                )
+            )
+        RETURNING
+            ulid, title_identifier, client_ulid, status, created_at, branch_ulid",
+        )
+        .bind(file_ulid)
+        .bind(job_kind)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Returns the rail of a branch, Citibank if it has not chosen one.
+    pub async fn select_one_branch_payment_rail(
+        &self,
+        branch_ulid: Uuid,
+    ) -> GlobeliseResult<BranchPaymentRail> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            branch_ulid, payment_rail, debtor_name, debtor_iban, debtor_bic
+        FROM
+            entity_client_branch_payment_rails
+        WHERE
+            branch_ulid = $1",
+        )
+        .bind(branch_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result.unwrap_or_else(|| BranchPaymentRail::default_for(branch_ulid)))
+    }
+
+    pub async fn upsert_branch_payment_rail(
+        &self,
+        rail: &BranchPaymentRail,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        INSERT INTO entity_client_branch_payment_rails (
+            branch_ulid, payment_rail, debtor_name, debtor_iban, debtor_bic
+        ) VALUES (
+            $1, $2, $3, $4, $5
+        ) ON CONFLICT (branch_ulid) DO UPDATE SET
+            payment_rail = $2,
+            debtor_name = $3,
+            debtor_iban = $4,
+            debtor_bic = $5,
+            updated_at = now()",
+        )
+        .bind(rail.branch_ulid)
+        .bind(rail.payment_rail)
+        .bind(&rail.debtor_name)
+        .bind(&rail.debtor_iban)
+        .bind(&rail.debtor_bic)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn select_one_transfer_record_summary(
+        &self,
+        record_ulid: Uuid,
+    ) -> GlobeliseResult<Option<TransferRecordSummary>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            r.employee_name, f.title_identifier, f.client_ulid
+        FROM
+            uploaded_citibank_transfer_initiation_files_records r
+        JOIN
+            uploaded_citibank_transfer_initiation_files f
+        ON
+            r.file_ulid = f.ulid
+        WHERE
+            r.ulid = $1",
+        )
+        .bind(record_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,403 @@
+//! Transfer batches built from stored payroll data instead of uploaded spreadsheets.
+//!
+//! Every active contract of a branch that overlaps the pay period becomes one record, paying
+//! the net pay of the contract for the period to the contractor's stored bank account, see
+//! `eor_admin/net_pay.rs`. The pay items of a contractor with several contracts are applied to
+//! the first of them only. Contracts that cannot be paid are reported back one by one and no
+//! batch is created until all of them can be.
+//!
+//! Batches are created by a background job, whose result is the
+//! [`CreatePayrollTransferBatchResponse`].
+
+use axum::extract::{Extension, Json};
+use common_utils::{
+    custom_serde::OffsetDateWrapper,
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    jobs::{self, JobContext, JobOutput},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    database::{Database, SharedDatabase},
+    eor_admin::net_pay::{net_pay, prorated_gross_pay, ContractorPayItem, PayItemsByContractor},
+};
+
+use super::citi_bank::{CitiBankPayRollRecord, ListCitiBankTransferInitiationFilesRequest};
+use super::payment_rail::PaymentRail;
+
+pub const CREATE_PAYROLL_TRANSFER_BATCH_JOB: &str = "create-payroll-transfer-batch";
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CreatePayrollTransferBatchRequest {
+    pub client_ulid: Uuid,
+    pub branch_ulid: Uuid,
+    /// Only used when the branch pays through Citibank.
+    pub template_name: Option<String>,
+    pub title_identifier: String,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub begin_period: sqlx::types::time::OffsetDateTime,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub end_period: sqlx::types::time::OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CreatePayrollTransferBatchResponse {
+    /// The new batch, if every contract could be paid.
+    pub file_ulid: Option<Uuid>,
+    pub entries: i64,
+    /// Sum of the net pay of every record.
+    pub total_amount: sqlx::types::Decimal,
+    pub errors: Vec<PayrollTransferBatchError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PayrollTransferBatchError {
+    pub contract_ulid: Uuid,
+    pub contract_name: String,
+    pub contractor_ulid: Option<Uuid>,
+    pub contractor_name: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct PayableContract {
+    pub contract_ulid: Uuid,
+    pub contract_name: String,
+    pub contractor_ulid: Option<Uuid>,
+    pub contractor_name: Option<String>,
+    pub contractor_country: Option<String>,
+    pub currency: Option<String>,
+    /// Gross pay for a whole pay period.
+    pub gross_amount: sqlx::types::Decimal,
+    pub begin_at: sqlx::types::time::OffsetDateTime,
+    pub end_at: sqlx::types::time::OffsetDateTime,
+    pub bank_name: Option<String>,
+    pub bank_account_number: Option<String>,
+    pub bank_code: Option<String>,
+    pub branch_code: Option<String>,
+}
+
+/// Queues the creation of a draft transfer batch paying the contractors of a branch for a
+/// period.
+pub async fn create_payroll_transfer_batch(
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<CreatePayrollTransferBatchRequest>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    if request.begin_period > request.end_period {
+        return Err(GlobeliseError::bad_request(
+            "The pay period ends before it begins",
+        ));
+    }
+
+    let job = jobs::enqueue(
+        &common_database,
+        CREATE_PAYROLL_TRANSFER_BATCH_JOB,
+        None,
+        &request,
+        // A retry could create the batch a second time.
+        1,
+        Some(claims.payload.ulid),
+    )
+    .await?;
+
+    Ok(Json(job))
+}
+
+/// Creates the batch queued by [`create_payroll_transfer_batch`].
+pub async fn run_create_batch_job(
+    context: JobContext,
+    database: SharedDatabase,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let request: CreatePayrollTransferBatchRequest = context.payload()?;
+
+    let response = create_batch(&context, &database, request).await?;
+
+    Ok(Some(JobOutput::json(&response)?))
+}
+
+async fn create_batch(
+    context: &JobContext,
+    database: &SharedDatabase,
+    request: CreatePayrollTransferBatchRequest,
+) -> GlobeliseResult<CreatePayrollTransferBatchResponse> {
+    let (rail, contracts, pay_items, existing_status) = {
+        let database = database.lock().await;
+
+        let rail = database
+            .select_one_branch_payment_rail(request.branch_ulid)
+            .await?
+            .rail(request.template_name.as_deref())?;
+
+        let contracts = database
+            .select_many_payable_contracts(
+                request.client_ulid,
+                request.branch_ulid,
+                request.begin_period,
+                request.end_period,
+            )
+            .await?;
+
+        let pay_items = database
+            .select_many_contractor_pay_items(request.branch_ulid)
+            .await?;
+
+        let existing_status = database
+            .select_transfer_file_status_by_title(&request.title_identifier)
+            .await?;
+
+        (rail, contracts, pay_items, existing_status)
+    };
+    if contracts.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "This branch has no active contracts in this period",
+        ));
+    }
+
+    let file_ulid = Uuid::new_v4();
+    let mut records = Vec::with_capacity(contracts.len());
+    let mut errors = Vec::new();
+    let total = contracts.len();
+    let mut pay_items = PayItemsByContractor::new(&pay_items);
+    for (index, contract) in contracts.into_iter().enumerate() {
+        context.progress(index, total).await?;
+        let contract_pay_items = contract
+            .contractor_ulid
+            .map(|contractor_ulid| pay_items.take(contractor_ulid))
+            .unwrap_or_default();
+        match payroll_record(
+            rail.as_ref(),
+            file_ulid,
+            &request,
+            &contract,
+            &contract_pay_items,
+        ) {
+            Ok(record) => records.push(record),
+            Err(e) => errors.push(PayrollTransferBatchError {
+                contract_ulid: contract.contract_ulid,
+                contract_name: contract.contract_name,
+                contractor_ulid: contract.contractor_ulid,
+                contractor_name: contract.contractor_name,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    let entries = records.len() as i64;
+    let total_amount: sqlx::types::Decimal = records.iter().map(|record| record.amount).sum();
+    if !errors.is_empty() {
+        return Ok(CreatePayrollTransferBatchResponse {
+            file_ulid: None,
+            entries,
+            total_amount,
+            errors,
+        });
+    }
+
+    if let Some(status) = existing_status {
+        if status != "draft" {
+            return Err(GlobeliseError::bad_request(format!(
+                "A {} file with this title already exists",
+                status
+            )));
+        }
+    }
+
+    database
+        .lock()
+        .await
+        .create_uploaded_citibank_transfer_initiation_file(
+            ListCitiBankTransferInitiationFilesRequest {
+                ulid: file_ulid,
+                title_identifier: request.title_identifier,
+                client_ulid: request.client_ulid,
+                branch_ulid: Some(request.branch_ulid),
+                status: "draft".to_string(),
+            },
+            records,
+        )
+        .await?;
+
+    Ok(CreatePayrollTransferBatchResponse {
+        file_ulid: Some(file_ulid),
+        entries,
+        total_amount,
+        errors,
+    })
+}
+
+/// Turns a contract into a transfer record, or explains why it cannot be paid.
+fn payroll_record(
+    rail: &dyn PaymentRail,
+    file_ulid: Uuid,
+    request: &CreatePayrollTransferBatchRequest,
+    contract: &PayableContract,
+    pay_items: &[&ContractorPayItem],
+) -> GlobeliseResult<CitiBankPayRollRecord> {
+    let employee_id = contract
+        .contractor_ulid
+        .ok_or_else(|| GlobeliseError::bad_request("The contract has no contractor"))?;
+    let employee_name = contract
+        .contractor_name
+        .clone()
+        .ok_or_else(|| GlobeliseError::bad_request("The contractor has not been onboarded"))?;
+    let bank_account_number = contract
+        .bank_account_number
+        .clone()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| GlobeliseError::bad_request("Missing bank account number"))?;
+    let bank_code = contract
+        .bank_code
+        .clone()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| GlobeliseError::bad_request("Missing bank code"))?;
+
+    let currency_code = contract.currency.clone().unwrap_or_default();
+    if let Some(currency) = rail.currency() {
+        if currency_code != currency {
+            return Err(GlobeliseError::bad_request(format!(
+                "Unsupported currency '{}', this branch pays in {}",
+                currency_code, currency
+            )));
+        }
+    }
+    let currency = currency_code.parse().map_err(|_| {
+        GlobeliseError::bad_request(format!("Unknown currency '{}'", currency_code))
+    })?;
+    let gross = prorated_gross_pay(
+        contract.gross_amount,
+        currency,
+        contract.begin_at,
+        contract.end_at,
+        request.begin_period,
+        request.end_period,
+    );
+    let net_pay = net_pay(gross, pay_items.iter().copied())?;
+    if !net_pay.net.is_positive() {
+        return Err(GlobeliseError::bad_request(format!(
+            "The net pay for this period is {}",
+            net_pay.net
+        )));
+    }
+
+    let record = CitiBankPayRollRecord {
+        ulid: Uuid::new_v4(),
+        currency_code,
+        country_code: rail
+            .country()
+            .map(|country| country.to_string())
+            .or_else(|| contract.contractor_country.clone())
+            .unwrap_or_default(),
+        employee_id,
+        employee_name,
+        bank_name: contract.bank_name.clone().unwrap_or_default(),
+        bank_account_number,
+        bank_code,
+        bank_branch_code: contract.branch_code.clone().unwrap_or_default(),
+        // None of the rails need the creditor's SWIFT code.
+        swift_code: String::new(),
+        amount: net_pay.net.amount(),
+        file_ulid,
+        transaction_status: "pending".to_string(),
+        transaction_status_description: Some("pending".to_string()),
+    };
+    record.bank_details().validate()?;
+    rail.validate_record(&record)?;
+
+    Ok(record)
+}
+
+impl Database {
+    /// Active contracts of a branch that overlap the period, with the contractor's bank details.
+    ///
+    /// The amount is the gross pay for a whole period, see [`prorated_gross_pay`].
+    pub async fn select_many_payable_contracts(
+        &self,
+        client_ulid: Uuid,
+        branch_ulid: Uuid,
+        begin_period: sqlx::types::time::OffsetDateTime,
+        end_period: sqlx::types::time::OffsetDateTime,
+    ) -> GlobeliseResult<Vec<PayableContract>> {
+        let result = sqlx::query_as(
+            "
+        WITH contractor_names AS (
+            SELECT
+                ulid, company_name AS name, country
+            FROM
+                entity_contractor_account_details
+            UNION
+            SELECT
+                ulid, CONCAT(first_name, ' ', last_name) AS name, country
+            FROM
+                individual_contractor_account_details
+        ), contractor_bank_details AS (
+            SELECT
+                ulid, bank_name, bank_account_number, bank_code, branch_code
+            FROM
+                entity_contractor_bank_details
+            UNION
+            SELECT
+                ulid, bank_name, bank_account_number, bank_code, branch_code
+            FROM
+                individual_contractor_bank_details
+        )
+        SELECT
+            c.ulid AS contract_ulid, c.contract_name, c.contractor_ulid,
+            n.name AS contractor_name, n.country AS contractor_country, c.currency,
+            c.contract_amount AS gross_amount, c.begin_at, c.end_at,
+            b.bank_name, b.bank_account_number, b.bank_code, b.branch_code
+        FROM
+            contracts c
+        LEFT JOIN
+            contractor_names n ON n.ulid = c.contractor_ulid
+        LEFT JOIN
+            contractor_bank_details b ON b.ulid = c.contractor_ulid
+        WHERE
+            c.client_ulid = $1 AND
+            c.branch_ulid = $2 AND
+            c.contract_status = 'ACTIVE' AND
+            c.begin_at <= $4 AND
+            c.end_at >= $3
+        ORDER BY
+            n.name, c.contractor_ulid, c.begin_at, c.ulid",
+        )
+        .bind(client_ulid)
+        .bind(branch_ulid)
+        .bind(begin_period)
+        .bind(end_period)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_transfer_file_status_by_title(
+        &self,
+        title_identifier: &str,
+    ) -> GlobeliseResult<Option<String>> {
+        let result = sqlx::query_scalar(
+            "
+        SELECT
+            status
+        FROM
+            uploaded_citibank_transfer_initiation_files
+        WHERE
+            title_identifier = $1",
+        )
+        .bind(title_identifier)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,458 @@
+//! SEPA credit transfers through any bank in the SEPA zone.
+//!
+//! Transfer files are encoded as pain.001 files following the SEPA rulebook: euro only,
+//! IBANs for both accounts and charges shared as agreed. The bank is not connected, so the
+//! files are returned for an admin to upload to the bank's portal, and the camt.054
+//! debit notifications the bank makes available are uploaded back.
+
+use axum::async_trait;
+use common_utils::{
+    bank_details::{compact, validate_bic, validate_iban},
+    error::{GlobeliseError, GlobeliseResult},
+};
+
+use super::camt054_file::Camt054File;
+use super::citi_bank::{
+    CitiBankPayRollRecord, ListCitiBankTransferInitiationFilesResponseNoEntries,
+};
+use super::pain001::{
+    AccountIdentification, Amount, ChargeBearer, CodeOrProprietary, CreditTransferTransaction,
+    Document, FinancialInstitution, GroupHeader, Max140Text, Max35Text, Party, PaymentInformation,
+    PaymentTypeInformation,
+};
+use super::payment_rail::{
+    parse_ulid, PaymentFile, PaymentRail, PaymentRailKind, StatusReport, TransferRecordStatus,
+};
+
+/// The account a branch pays from.
+pub struct SepaDebtor {
+    name: Max140Text,
+    iban: String,
+    bic: Option<String>,
+}
+
+impl SepaDebtor {
+    pub fn new(name: &str, iban: &str, bic: Option<&str>) -> GlobeliseResult<Self> {
+        let name = Max140Text::new("Debtor name", name)?;
+        validate_iban(iban)
+            .map_err(|message| GlobeliseError::bad_request(format!("debtor-iban: {}", message)))?;
+        let bic = match bic.filter(|bic| !bic.trim().is_empty()) {
+            Some(bic) => {
+                validate_bic(bic).map_err(|message| {
+                    GlobeliseError::bad_request(format!("debtor-bic: {}", message))
+                })?;
+                Some(compact(bic))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            name,
+            iban: compact(iban),
+            bic,
+        })
+    }
+}
+
+pub struct SepaRail {
+    debtor: Option<SepaDebtor>,
+}
+
+impl SepaRail {
+    /// Creates the rail. Files can only be built with the account to pay from.
+    pub fn new(debtor: Option<SepaDebtor>) -> Self {
+        Self { debtor }
+    }
+
+    fn debtor(&self) -> GlobeliseResult<&SepaDebtor> {
+        self.debtor.as_ref().ok_or_else(|| {
+            GlobeliseError::bad_request("The SEPA account to pay from is not configured")
+        })
+    }
+
+    /// Builds a single batch paying every record of the file as if it was created at the
+    /// given time, which is also the requested execution date.
+    fn document_created_at(
+        &self,
+        transfer_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+        now: chrono::NaiveDateTime,
+    ) -> GlobeliseResult<Document> {
+        let debtor = self.debtor()?;
+        if records.is_empty() {
+            return Err(GlobeliseError::bad_request(
+                "This file does not have any records",
+            ));
+        }
+
+        let file_id = transfer_file.ulid.to_simple().to_string().to_uppercase();
+
+        Ok(Document {
+            group_header: GroupHeader {
+                message_id: Max35Text::new("MsgId", &file_id)?,
+                creation_date_time: now.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                include_control_sum: true,
+                initiating_party: Party {
+                    name: Some(debtor.name.clone()),
+                    postal_address: None,
+                },
+            },
+            payment_information: vec![PaymentInformation {
+                payment_information_id: Max35Text::new("PmtInfId", &file_id)?,
+                payment_type_information: Some(PaymentTypeInformation {
+                    service_level: Some(CodeOrProprietary::Code(Max35Text::new("SvcLvl", "SEPA")?)),
+                    local_instrument: None,
+                    category_purpose: Some(CodeOrProprietary::Code(Max35Text::new(
+                        "CtgyPurp", "SALA",
+                    )?)),
+                }),
+                requested_execution_date: now.format("%Y-%m-%d").to_string(),
+                debtor: Party {
+                    name: Some(debtor.name.clone()),
+                    postal_address: None,
+                },
+                debtor_account: AccountIdentification::Iban(debtor.iban.clone()),
+                debtor_agent: match &debtor.bic {
+                    Some(bic) => FinancialInstitution {
+                        bic: Some(bic.clone()),
+                        ..Default::default()
+                    },
+                    None => FinancialInstitution {
+                        other_id: Some(Max35Text::new("Debtor agent", "NOTPROVIDED")?),
+                        ..Default::default()
+                    },
+                },
+                transactions: records
+                    .iter()
+                    .map(|record| self.credit_transfer(record))
+                    .collect::<GlobeliseResult<_>>()?,
+            }],
+        })
+    }
+
+    fn credit_transfer(
+        &self,
+        record: &CitiBankPayRollRecord,
+    ) -> GlobeliseResult<CreditTransferTransaction> {
+        if record.currency_code != "EUR" {
+            return Err(GlobeliseError::bad_request(format!(
+                "Record for {} is in {} but SEPA only pays in EUR",
+                record.employee_name, record.currency_code
+            )));
+        }
+        validate_iban(&record.bank_account_number).map_err(|message| {
+            GlobeliseError::bad_request(format!("bank-account-number: {}", message))
+        })?;
+
+        let creditor_agent = if record.swift_code.trim().is_empty() {
+            FinancialInstitution {
+                other_id: Some(Max35Text::new("Creditor agent", "NOTPROVIDED")?),
+                ..Default::default()
+            }
+        } else {
+            validate_bic(&record.swift_code).map_err(|message| {
+                GlobeliseError::bad_request(format!("swift-code: {}", message))
+            })?;
+            FinancialInstitution {
+                bic: Some(compact(&record.swift_code)),
+                ..Default::default()
+            }
+        };
+
+        Ok(CreditTransferTransaction {
+            // Read back from the bank's notifications to find the record.
+            end_to_end_id: Max35Text::new(
+                "EndToEndId",
+                record.ulid.to_simple().to_string().to_uppercase(),
+            )?,
+            amount: Amount::new("EUR", record.amount)?,
+            charge_bearer: Some(ChargeBearer::FollowingServiceLevel),
+            creditor_agent,
+            creditor: Party {
+                name: Some(Max140Text::new("Employee name", &record.employee_name)?),
+                postal_address: None,
+            },
+            creditor_account: AccountIdentification::Iban(compact(&record.bank_account_number)),
+            purpose: Some(CodeOrProprietary::Code(Max35Text::new("Purp", "SALA")?)),
+            remittance_information: Some(Max140Text::new(
+                "Remittance information",
+                "Globelise Salary Payment",
+            )?),
+        })
+    }
+}
+
+#[async_trait]
+impl PaymentRail for SepaRail {
+    fn kind(&self) -> PaymentRailKind {
+        PaymentRailKind::SEPA
+    }
+
+    fn bank_name(&self) -> &'static str {
+        "The bank"
+    }
+
+    fn currency(&self) -> Option<&'static str> {
+        Some("EUR")
+    }
+
+    fn country(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn validate_record(&self, record: &CitiBankPayRollRecord) -> GlobeliseResult<()> {
+        self.credit_transfer(record).map(|_| ())
+    }
+
+    /// Builds a single batch paying every record of the file.
+    fn encode(
+        &self,
+        transfer_file: &ListCitiBankTransferInitiationFilesResponseNoEntries,
+        records: &[CitiBankPayRollRecord],
+    ) -> GlobeliseResult<PaymentFile> {
+        let document = self.document_created_at(
+            transfer_file,
+            records,
+            chrono::offset::Local::now().naive_local(),
+        )?;
+
+        Ok(PaymentFile {
+            file_name: format!(
+                "SEPA_PAYROLL_{}.xml",
+                transfer_file.ulid.to_simple().to_string().to_uppercase()
+            ),
+            content_type: "application/xml",
+            data: document.to_validated_xml()?.into_bytes(),
+        })
+    }
+
+    async fn deliver(&self, file: PaymentFile) -> GlobeliseResult<Option<PaymentFile>> {
+        Ok(Some(file))
+    }
+
+    /// Parses a camt.054 notification.
+    ///
+    /// Booked debits are accepted transfers and returned ones are rejected. Entries that
+    /// are not our transfers are skipped, since the notification covers the whole account.
+    fn parse_status_report(&self, _: &str, data: &str) -> GlobeliseResult<StatusReport> {
+        let file: Camt054File = serde_xml_rs::from_str(data)
+            .map_err(|e| GlobeliseError::bad_request(format!("Malformed camt.054 file: {}", e)))?;
+
+        let mut report = StatusReport::default();
+        for entry in file
+            .bk_to_cstmr_dbt_cdt_ntfctn
+            .ntfctn
+            .into_iter()
+            .flat_map(|notification| notification.ntry)
+        {
+            let booked_debit = entry.sts == "BOOK" && entry.cdt_dbt_ind == "DBIT";
+            for transaction in entry
+                .ntry_dtls
+                .into_iter()
+                .flat_map(|details| details.tx_dtls)
+            {
+                let record_ulid = match transaction
+                    .refs
+                    .and_then(|refs| refs.end_to_end_id)
+                    .and_then(|id| parse_ulid(&id).ok())
+                {
+                    Some(record_ulid) => record_ulid,
+                    None => continue,
+                };
+
+                if let Some(return_information) = transaction.rtr_inf {
+                    let reason = return_information
+                        .rsn
+                        .and_then(|reason| reason.cd)
+                        .into_iter()
+                        .chain(return_information.addtl_inf)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    report.records.push(TransferRecordStatus {
+                        record_ulid,
+                        accepted: false,
+                        reason: Some(reason),
+                    });
+                } else if booked_debit {
+                    report.records.push(TransferRecordStatus {
+                        record_ulid,
+                        accepted: true,
+                        reason: None,
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::{time::OffsetDateTime, Decimal};
+    use uuid::Uuid;
+
+    use super::*;
+
+    const FILE_ULID: &str = "3f2504e0-4f89-41d3-9a0c-0305e82c3301";
+    const PAID_ULID: &str = "0f8fad5b-d9cb-469f-a165-70867728950e";
+    const RETURNED_ULID: &str = "7c9e6679-7425-40de-944b-e07fc1f90ae7";
+    const PENDING_ULID: &str = "16fd2706-8baf-433b-82eb-8c7fada847da";
+
+    fn rail() -> SepaRail {
+        SepaRail::new(Some(
+            SepaDebtor::new("Globelise B.V.", "NL91 ABNA 0417 1643 00", Some("ABNANL2A")).unwrap(),
+        ))
+    }
+
+    fn transfer_file() -> ListCitiBankTransferInitiationFilesResponseNoEntries {
+        ListCitiBankTransferInitiationFilesResponseNoEntries {
+            ulid: Uuid::parse_str(FILE_ULID).unwrap(),
+            title_identifier: "September payroll".to_string(),
+            client_ulid: Uuid::nil(),
+            status: "approved".to_string(),
+            created_at: OffsetDateTime::unix_epoch(),
+            branch_ulid: None,
+        }
+    }
+
+    fn record(
+        ulid: &str,
+        currency_code: &str,
+        employee_name: &str,
+        iban: &str,
+        bic: &str,
+        amount: &str,
+    ) -> CitiBankPayRollRecord {
+        CitiBankPayRollRecord {
+            ulid: Uuid::parse_str(ulid).unwrap(),
+            currency_code: currency_code.to_string(),
+            country_code: iban[..2].to_string(),
+            employee_id: Uuid::nil(),
+            employee_name: employee_name.to_string(),
+            bank_name: String::new(),
+            bank_account_number: iban.to_string(),
+            bank_code: String::new(),
+            bank_branch_code: String::new(),
+            swift_code: bic.to_string(),
+            amount: amount.parse::<Decimal>().unwrap(),
+            file_ulid: Uuid::parse_str(FILE_ULID).unwrap(),
+            transaction_status: "None".to_string(),
+            transaction_status_description: None,
+        }
+    }
+
+    fn records() -> Vec<CitiBankPayRollRecord> {
+        vec![
+            record(
+                PAID_ULID,
+                "EUR",
+                "Anna Müller",
+                "DE89370400440532013000",
+                "COBADEFFXXX",
+                "1234.5",
+            ),
+            // Without a BIC, the bank finds the creditor's bank from the IBAN.
+            record(
+                RETURNED_ULID,
+                "EUR",
+                "Jean Dupont",
+                "FR1420041010050500013M02606",
+                "",
+                "2000",
+            ),
+        ]
+    }
+
+    fn created_at() -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd(2022, 9, 5).and_hms(10, 30, 0)
+    }
+
+    #[test]
+    fn document_matches_the_golden_file() {
+        let xml = rail()
+            .document_created_at(&transfer_file(), &records(), created_at())
+            .unwrap()
+            .to_validated_xml()
+            .unwrap();
+
+        assert_eq!(xml, include_str!("testdata/sepa.xml"));
+    }
+
+    #[test]
+    fn encoded_files_are_returned_for_upload() {
+        let file = rail().encode(&transfer_file(), &records()).unwrap();
+
+        assert_eq!(
+            file.file_name,
+            "SEPA_PAYROLL_3F2504E04F8941D39A0C0305E82C3301.xml"
+        );
+        assert_eq!(file.content_type, "application/xml");
+        let xml = String::from_utf8(file.data).unwrap();
+        assert!(xml.contains(r#"<InstdAmt Ccy="EUR">1234.50</InstdAmt>"#));
+    }
+
+    #[test]
+    fn only_valid_euro_transfers_are_encoded() {
+        let rail = rail();
+
+        let mut record = records().remove(0);
+        record.currency_code = "SGD".to_string();
+        assert!(rail.validate_record(&record).is_err());
+
+        let mut record = records().remove(0);
+        record.bank_account_number = "DE89370400440532013001".to_string();
+        assert!(rail.validate_record(&record).is_err());
+
+        let mut record = records().remove(0);
+        record.swift_code = "COBA".to_string();
+        assert!(rail.validate_record(&record).is_err());
+
+        assert!(rail.encode(&transfer_file(), &[]).is_err());
+        assert!(SepaRail::new(None)
+            .encode(&transfer_file(), &records())
+            .is_err());
+    }
+
+    #[test]
+    fn debit_notifications_update_our_records() {
+        let report = rail()
+            .parse_status_report("camt054.xml", include_str!("testdata/camt054.xml"))
+            .unwrap();
+
+        let statuses = report
+            .records
+            .iter()
+            .map(|status| {
+                (
+                    status.record_ulid.to_string(),
+                    status.accepted,
+                    status.reason.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The pending debit and the entry that is not one of our transfers are skipped.
+        assert_eq!(
+            statuses,
+            [
+                (PAID_ULID.to_string(), true, None),
+                (
+                    RETURNED_ULID.to_string(),
+                    false,
+                    Some("AC04, Account closed".to_string())
+                ),
+            ]
+        );
+        assert!(!statuses
+            .iter()
+            .any(|(record_ulid, _, _)| record_ulid == PENDING_ULID));
+        assert!(report.files.is_empty());
+    }
+
+    #[test]
+    fn malformed_notifications_are_rejected() {
+        assert!(rail()
+            .parse_status_report("camt054.xml", "<Document><BkToCstmrDbtCdtNtfctn>")
+            .is_err());
+    }
+}
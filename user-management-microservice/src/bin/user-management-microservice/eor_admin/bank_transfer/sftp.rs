@@ -0,0 +1,422 @@
+//! SFTP access to the bank's file exchange.
+//!
+//! The remote transport keeps a small pool of authenticated connections and runs the
+//! blocking `ssh2` calls on the blocking thread pool. The server's host key must be pinned
+//! in a known hosts file. Setting `CITIBANK_SFTP_LOCAL_DIR` replaces the server with a
+//! local directory, so the whole Citibank flow can be run without network access.
+//!
+//! Files are uploaded under a hidden partial name and renamed once they are complete, so that
+//! the bank never picks up a file that is still being written.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::async_trait;
+use common_utils::error::{GlobeliseError, GlobeliseResult};
+use once_cell::sync::OnceCell;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+
+/// Number of times an operation is attempted before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry. It doubles with every further retry.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Number of idle connections kept open.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// Appended to the hidden name a file is uploaded under before it is complete.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Timeout for connecting and for every blocking call on a connection.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[async_trait]
+pub trait SftpTransport: Send + Sync {
+    /// Creates or overwrites a remote file.
+    async fn upload(&self, remote_file: &str, data: Vec<u8>) -> GlobeliseResult<()>;
+
+    /// Reads a whole remote file.
+    async fn read(&self, remote_file: &str) -> GlobeliseResult<Vec<u8>>;
+
+    /// Lists the files, but not the directories, in a remote directory.
+    async fn list_files(&self, remote_dir: &str) -> GlobeliseResult<Vec<String>>;
+}
+
+static CITIBANK_SFTP: OnceCell<Box<dyn SftpTransport>> = OnceCell::new();
+
+/// Returns the transport to the Citibank SFTP server, creating it on first use.
+pub fn citibank_sftp() -> GlobeliseResult<&'static dyn SftpTransport> {
+    CITIBANK_SFTP
+        .get_or_try_init(|| -> GlobeliseResult<Box<dyn SftpTransport>> {
+            match std::env::var("CITIBANK_SFTP_LOCAL_DIR") {
+                Ok(root) => Ok(Box::new(LocalSftp::new(root))),
+                Err(_) => Ok(Box::new(RemoteSftp::new(RemoteSftpConfig::from_env()?))),
+            }
+        })
+        .map(|transport| transport.as_ref())
+}
+
+enum RemoteSftpAuth {
+    PublicKey {
+        private_key_file: PathBuf,
+        passphrase: Option<String>,
+    },
+    Password(String),
+}
+
+pub struct RemoteSftpConfig {
+    host: String,
+    port: u16,
+    username: String,
+    auth: RemoteSftpAuth,
+    known_hosts_file: PathBuf,
+}
+
+impl RemoteSftpConfig {
+    /// Reads the configuration from the environment.
+    ///
+    /// - `CITIBANK_SFTP_HOST`, `CITIBANK_SFTP_PORT` (defaults to 22) and `CITIBANK_SFTP_USERNAME`
+    /// - `CITIBANK_SFTP_PRIVATE_KEY_FILE` and, if the key is encrypted,
+    ///   `CITIBANK_SFTP_PRIVATE_KEY_PASSPHRASE`; `CITIBANK_SFTP_PASSWORD` is only used
+    ///   when no private key is configured
+    /// - `CITIBANK_SFTP_KNOWN_HOSTS_FILE`: an OpenSSH known hosts file with the server's key
+    pub fn from_env() -> GlobeliseResult<Self> {
+        let host = std::env::var("CITIBANK_SFTP_HOST")?;
+        let port = match std::env::var("CITIBANK_SFTP_PORT") {
+            Ok(port) => port.parse().map_err(|e| {
+                GlobeliseError::internal(format!("Invalid CITIBANK_SFTP_PORT: {}", e))
+            })?,
+            Err(_) => 22,
+        };
+        let username = std::env::var("CITIBANK_SFTP_USERNAME")?;
+        let auth = match std::env::var("CITIBANK_SFTP_PRIVATE_KEY_FILE") {
+            Ok(private_key_file) => RemoteSftpAuth::PublicKey {
+                private_key_file: private_key_file.into(),
+                passphrase: std::env::var("CITIBANK_SFTP_PRIVATE_KEY_PASSPHRASE").ok(),
+            },
+            Err(_) => RemoteSftpAuth::Password(std::env::var("CITIBANK_SFTP_PASSWORD")?),
+        };
+        let known_hosts_file = std::env::var("CITIBANK_SFTP_KNOWN_HOSTS_FILE")?.into();
+
+        Ok(Self {
+            host,
+            port,
+            username,
+            auth,
+            known_hosts_file,
+        })
+    }
+}
+
+struct Connection {
+    // Kept so that the session outlives the SFTP channel.
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl Connection {
+    fn open(config: &RemoteSftpConfig) -> GlobeliseResult<Self> {
+        let address = (config.host.as_str(), config.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                GlobeliseError::internal(format!("Cannot resolve SFTP host {}", config.host))
+            })?;
+        let tcp = TcpStream::connect_timeout(&address, TIMEOUT)?;
+
+        let mut session = Session::new()?;
+        session.set_timeout(TIMEOUT.as_millis() as u32);
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        verify_host_key(&session, config)?;
+
+        match &config.auth {
+            RemoteSftpAuth::PublicKey {
+                private_key_file,
+                passphrase,
+            } => session.userauth_pubkey_file(
+                &config.username,
+                None,
+                private_key_file,
+                passphrase.as_deref(),
+            )?,
+            RemoteSftpAuth::Password(password) => {
+                session.userauth_password(&config.username, password)?
+            }
+        }
+        if !session.authenticated() {
+            return Err(GlobeliseError::internal(
+                "SFTP server did not accept our credentials",
+            ));
+        }
+
+        let sftp = session.sftp()?;
+
+        Ok(Self {
+            _session: session,
+            sftp,
+        })
+    }
+}
+
+/// Refuses to talk to a server whose key is not the one pinned in the known hosts file.
+fn verify_host_key(session: &Session, config: &RemoteSftpConfig) -> GlobeliseResult<()> {
+    let (host_key, _) = session
+        .host_key()
+        .ok_or_else(|| GlobeliseError::internal("SFTP server did not send a host key"))?;
+
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.read_file(&config.known_hosts_file, KnownHostFileKind::OpenSSH)?;
+
+    match known_hosts.check_port(&config.host, config.port, host_key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(GlobeliseError::internal(format!(
+            "Host key of SFTP server {} does not match the pinned key",
+            config.host
+        ))),
+        CheckResult::NotFound => Err(GlobeliseError::internal(format!(
+            "SFTP server {} is not in the known hosts file",
+            config.host
+        ))),
+        CheckResult::Failure => Err(GlobeliseError::internal(format!(
+            "Cannot check the host key of SFTP server {}",
+            config.host
+        ))),
+    }
+}
+
+pub struct RemoteSftp {
+    config: Arc<RemoteSftpConfig>,
+    idle_connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl RemoteSftp {
+    pub fn new(config: RemoteSftpConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            idle_connections: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Runs a blocking operation on a pooled connection, retrying with backoff on failure.
+    ///
+    /// A connection is only put back into the pool after a successful operation, so a
+    /// broken connection is replaced by a fresh one on the next attempt.
+    async fn run<T, F>(&self, operation: F) -> GlobeliseResult<T>
+    where
+        T: Send + 'static,
+        F: Fn(&Sftp) -> GlobeliseResult<T> + Send + Sync + 'static,
+    {
+        let operation = Arc::new(operation);
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+
+        loop {
+            let config = self.config.clone();
+            let idle_connections = self.idle_connections.clone();
+            let operation = operation.clone();
+
+            let result = tokio::task::spawn_blocking(move || {
+                let idle_connection = idle_connections
+                    .lock()
+                    .ok()
+                    .and_then(|mut connections| connections.pop());
+                let connection = match idle_connection {
+                    Some(connection) => connection,
+                    None => Connection::open(&config)?,
+                };
+
+                let result = operation(&connection.sftp);
+                if result.is_ok() {
+                    if let Ok(mut connections) = idle_connections.lock() {
+                        if connections.len() < MAX_IDLE_CONNECTIONS {
+                            connections.push(connection);
+                        }
+                    }
+                }
+                result
+            })
+            .await
+            .map_err(GlobeliseError::internal)?;
+
+            match result {
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    println!(
+                        "SFTP operation failed (attempt {} of {}): {:?}",
+                        attempt, MAX_ATTEMPTS, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SftpTransport for RemoteSftp {
+    async fn upload(&self, remote_file: &str, data: Vec<u8>) -> GlobeliseResult<()> {
+        let remote_file = PathBuf::from(remote_file);
+        let partial_file = partial_path(&remote_file)?;
+        self.run(move |sftp| {
+            let mut file = sftp.create(&partial_file)?;
+            file.write_all(&data)?;
+            file.close()?;
+            sftp.rename(&partial_file, &remote_file, None)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read(&self, remote_file: &str) -> GlobeliseResult<Vec<u8>> {
+        let remote_file = PathBuf::from(remote_file);
+        self.run(move |sftp| {
+            let mut data = Vec::new();
+            sftp.open(&remote_file)?.read_to_end(&mut data)?;
+            Ok(data)
+        })
+        .await
+    }
+
+    async fn list_files(&self, remote_dir: &str) -> GlobeliseResult<Vec<String>> {
+        let remote_dir = PathBuf::from(remote_dir);
+        self.run(move |sftp| {
+            Ok(sftp
+                .readdir(&remote_dir)?
+                .into_iter()
+                .filter(|(path, stat)| !stat.is_dir() && !is_partial(path))
+                .map(|(path, _)| path.to_string_lossy().to_string())
+                .collect())
+        })
+        .await
+    }
+}
+
+/// The hidden name a file is written under until it is complete.
+fn partial_path(path: &Path) -> GlobeliseResult<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| {
+            GlobeliseError::internal(format!("Invalid SFTP file {}", path.to_string_lossy()))
+        })?
+        .to_string_lossy();
+    Ok(path.with_file_name(format!(".{}{}", file_name, PARTIAL_SUFFIX)))
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .map_or(false, |file_name| {
+            file_name.starts_with('.') && file_name.ends_with(PARTIAL_SUFFIX)
+        })
+}
+
+/// Stands in for the SFTP server with a local directory.
+///
+/// Remote paths are resolved relative to the root directory.
+pub struct LocalSftp {
+    root: PathBuf,
+}
+
+impl LocalSftp {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn local_path(&self, remote_path: &str) -> GlobeliseResult<PathBuf> {
+        let mut path = self.root.clone();
+        for component in Path::new(remote_path).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(GlobeliseError::bad_request(format!(
+                        "Invalid SFTP path {}",
+                        remote_path
+                    )))
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl SftpTransport for LocalSftp {
+    async fn upload(&self, remote_file: &str, data: Vec<u8>) -> GlobeliseResult<()> {
+        let path = self.local_path(remote_file)?;
+        let partial_path = partial_path(&path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&partial_path, data).await?;
+        tokio::fs::rename(partial_path, path).await?;
+        Ok(())
+    }
+
+    async fn read(&self, remote_file: &str) -> GlobeliseResult<Vec<u8>> {
+        Ok(tokio::fs::read(self.local_path(remote_file)?).await?)
+    }
+
+    async fn list_files(&self, remote_dir: &str) -> GlobeliseResult<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(self.local_path(remote_dir)?).await?;
+        let mut remote_files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() || is_partial(&entry.path()) {
+                continue;
+            }
+            remote_files.push(
+                Path::new(remote_dir)
+                    .join(entry.file_name())
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        Ok(remote_files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_written_under_a_hidden_partial_name() {
+        let partial = partial_path(Path::new("/outgoing/payroll.xml.pgp")).unwrap();
+
+        assert_eq!(partial, Path::new("/outgoing/.payroll.xml.pgp.part"));
+        assert!(is_partial(&partial));
+        assert!(!is_partial(Path::new("/outgoing/payroll.xml.pgp")));
+    }
+
+    #[tokio::test]
+    async fn uploads_leave_only_the_complete_file() {
+        let root = std::env::temp_dir().join(format!("sftp-{}", uuid::Uuid::new_v4()));
+        let sftp = LocalSftp::new(&root);
+
+        sftp.upload("/outgoing/payroll.xml", b"<Document/>".to_vec())
+            .await
+            .unwrap();
+        // Left behind by an upload that was interrupted.
+        std::fs::write(root.join("outgoing/.other.xml.part"), b"<Doc").unwrap();
+
+        assert_eq!(
+            sftp.list_files("/outgoing").await.unwrap(),
+            ["/outgoing/payroll.xml"]
+        );
+        assert_eq!(
+            sftp.read("/outgoing/payroll.xml").await.unwrap(),
+            b"<Document/>"
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
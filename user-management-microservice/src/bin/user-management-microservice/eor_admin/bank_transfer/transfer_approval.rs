@@ -0,0 +1,333 @@
+//! Four-eyes approval of transfer batches.
+//!
+//! A batch goes from `draft` to `submitted` to `approved` to `sending` to `sent`, or back to
+//! `approved` if it could not be delivered. It can only be approved by an admin other than
+//! the one who submitted it, and only as it was when the approver looked at it: every change
+//! to a record bumps the version of the batch, and an approval names the version it was
+//! given for. Editing or deleting a record of a submitted or approved batch also withdraws
+//! the approval and makes the editor its new submitter.
+
+use axum::extract::{Extension, Json, Path};
+use common_utils::{
+    custom_serde::{OffsetDateWrapper, OptionOffsetDateWrapper},
+    error::{GlobeliseError, GlobeliseResult},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{FromRow, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+#[serde_as]
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "kebab-case")]
+pub struct TransferApprovalSummary {
+    pub ulid: Uuid,
+    pub title_identifier: String,
+    pub client_ulid: Uuid,
+    pub status: String,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+    /// Changes whenever a record is added, edited or deleted.
+    pub version: i32,
+    pub entries: i64,
+    pub total_amount: sqlx::types::Decimal,
+    pub submitted_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub submitted_at: Option<sqlx::types::time::OffsetDateTime>,
+    pub approved_by: Option<Uuid>,
+    #[serde_as(as = "TryFromInto<OptionOffsetDateWrapper>")]
+    pub approved_at: Option<sqlx::types::time::OffsetDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApproveTransferRequest {
+    pub file_ulid: Uuid,
+    /// Version of the file the approver was shown.
+    pub version: i32,
+    /// Number of records the approver was shown.
+    pub entries: i64,
+    /// Total amount the approver was shown.
+    pub total_amount: sqlx::types::Decimal,
+}
+
+/// Shows what an approver is about to approve.
+pub async fn get_transfer_approval_summary(
+    _: Token<AdminAccessToken>,
+    Path(file_ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<TransferApprovalSummary>> {
+    let database = database.lock().await;
+
+    let summary = database
+        .select_one_transfer_approval_summary(file_ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+
+    Ok(Json(summary))
+}
+
+/// Hands a draft batch over for approval.
+pub async fn submit_transfer_for_approval(
+    claims: Token<AdminAccessToken>,
+    Path(file_ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<TransferApprovalSummary>> {
+    let database = database.lock().await;
+
+    let summary = database
+        .select_one_transfer_approval_summary(file_ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+    if summary.status != "draft" {
+        return Err(GlobeliseError::bad_request(format!(
+            "Only draft files can be submitted, this file is {}",
+            summary.status
+        )));
+    }
+    if summary.entries == 0 {
+        return Err(GlobeliseError::bad_request("This file has no records"));
+    }
+
+    database
+        .submit_transfer_for_approval(file_ulid, claims.payload.ulid)
+        .await?;
+
+    let summary = database
+        .select_one_transfer_approval_summary(file_ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+
+    Ok(Json(summary))
+}
+
+/// Approves a submitted batch on behalf of a second admin.
+pub async fn approve_transfer(
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<ApproveTransferRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<TransferApprovalSummary>> {
+    let database = database.lock().await;
+
+    let summary = database
+        .select_one_transfer_approval_summary(request.file_ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+    if summary.status != "submitted" {
+        return Err(GlobeliseError::bad_request(format!(
+            "Only submitted files can be approved, this file is {}",
+            summary.status
+        )));
+    }
+    if summary.submitted_by == Some(claims.payload.ulid) {
+        return Err(GlobeliseError::Forbidden);
+    }
+
+    if !database
+        .approve_transfer(
+            request.file_ulid,
+            claims.payload.ulid,
+            request.version,
+            request.entries,
+            request.total_amount,
+        )
+        .await?
+    {
+        return Err(GlobeliseError::bad_request(
+            "The file changed since it was reviewed, please review it again",
+        ));
+    }
+
+    let summary = database
+        .select_one_transfer_approval_summary(request.file_ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the transfer file"))?;
+
+    Ok(Json(summary))
+}
+
+impl Database {
+    pub async fn select_one_transfer_approval_summary(
+        &self,
+        file_ulid: Uuid,
+    ) -> GlobeliseResult<Option<TransferApprovalSummary>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            f.ulid, f.title_identifier, f.client_ulid, f.status, f.created_at, f.version,
+            COUNT(r.ulid) AS entries,
+            COALESCE(SUM(r.amount), 0) AS total_amount,
+            f.submitted_by, f.submitted_at, f.approved_by, f.approved_at
+        FROM
+            uploaded_citibank_transfer_initiation_files f
+        LEFT JOIN
+            uploaded_citibank_transfer_initiation_files_records r
+        ON
+            r.file_ulid = f.ulid
+        WHERE
+            f.ulid = $1
+        GROUP BY
+            f.ulid",
+        )
+        .bind(file_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn submit_transfer_for_approval(
+        &self,
+        file_ulid: Uuid,
+        admin_ulid: Uuid,
+    ) -> GlobeliseResult<()> {
+        sqlx::query(
+            "
+        UPDATE
+            uploaded_citibank_transfer_initiation_files
+        SET
+            status = 'submitted',
+            submitted_by = $2,
+            submitted_at = CURRENT_TIMESTAMP,
+            approved_by = NULL,
+            approved_at = NULL,
+            version = version + 1
+        WHERE
+            ulid = $1 AND
+            status = 'draft'",
+        )
+        .bind(file_ulid)
+        .bind(admin_ulid)
+        .execute(&self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Approves the file if it is still submitted by someone else and still is the version
+    /// the approver reviewed.
+    ///
+    /// Returns whether the file was approved.
+    pub async fn approve_transfer(
+        &self,
+        file_ulid: Uuid,
+        admin_ulid: Uuid,
+        version: i32,
+        entries: i64,
+        total_amount: sqlx::types::Decimal,
+    ) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
+            "
+        UPDATE
+            uploaded_citibank_transfer_initiation_files f
+        SET
+            status = 'approved',
+            approved_by = $2,
+            approved_at = CURRENT_TIMESTAMP
+        WHERE
+            f.ulid = $1 AND
+            f.status = 'submitted' AND
+            f.submitted_by <> $2 AND
+            f.version = $3 AND
+            ($4::bigint, $5::numeric) = (
+                SELECT
+                    COUNT(*), COALESCE(SUM(r.amount), 0)
+                FROM
+                    uploaded_citibank_transfer_initiation_files_records r
+                WHERE
+                    r.file_ulid = f.ulid
+            )",
+        )
+        .bind(file_ulid)
+        .bind(admin_ulid)
+        .bind(version)
+        .bind(entries)
+        .bind(total_amount)
+        .execute(&self.0)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Finds the file a record belongs to, together with the file's status.
+    pub async fn select_transfer_record_file_status(
+        &self,
+        record_ulid: Uuid,
+    ) -> GlobeliseResult<Option<(Uuid, String)>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            f.ulid, f.status
+        FROM
+            uploaded_citibank_transfer_initiation_files_records r
+        JOIN
+            uploaded_citibank_transfer_initiation_files f
+        ON
+            f.ulid = r.file_ulid
+        WHERE
+            r.ulid = $1",
+        )
+        .bind(record_ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+/// Bumps the version of a file after one of its records changed and sends it back for
+/// approval if it was submitted or approved.
+pub async fn withdraw_transfer_approval(
+    transaction: &mut Transaction<'_, Postgres>,
+    file_ulid: Uuid,
+    admin_ulid: Uuid,
+) -> GlobeliseResult<()> {
+    sqlx::query(
+        "
+    UPDATE
+        uploaded_citibank_transfer_initiation_files
+    SET
+        version = version + 1
+    WHERE
+        ulid = $1",
+    )
+    .bind(file_ulid)
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query(
+        "
+    UPDATE
+        uploaded_citibank_transfer_initiation_files
+    SET
+        status = 'submitted',
+        submitted_by = $2,
+        submitted_at = CURRENT_TIMESTAMP,
+        approved_by = NULL,
+        approved_at = NULL
+    WHERE
+        ulid = $1 AND
+        status IN ('submitted', 'approved')",
+    )
+    .bind(file_ulid)
+    .bind(admin_ulid)
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Checks that the records of a file can still be changed.
+pub fn ensure_transfer_records_editable(status: &str) -> GlobeliseResult<()> {
+    match status {
+        "draft" | "submitted" | "approved" => Ok(()),
+        _ => Err(GlobeliseError::bad_request(format!(
+            "The records of a {} file cannot be changed",
+            status
+        ))),
+    }
+}
@@ -0,0 +1,220 @@
+//! FX rates, maintained by admins one at a time or imported from a daily rates file.
+
+use axum::extract::{ContentLengthLimit, Extension, Json, Path, Query};
+use common_utils::{
+    custom_serde::{Currency, DateWrapper, OptionDateWrapper, FORM_DATA_LENGTH_LIMIT},
+    database::{fx_rate::FxRate, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    fx::Conversion,
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as, TryFromInto};
+use sqlx::types::{time::Date, Decimal};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ListFxRatesQuery {
+    pub base_currency: Option<Currency>,
+    pub quote_currency: Option<Currency>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+pub async fn list_fx_rates(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<ListFxRatesQuery>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Vec<FxRate>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_fx_rates(
+            query.base_currency,
+            query.quote_currency,
+            query.page,
+            query.per_page,
+        )
+        .await?;
+
+    Ok(Json(result))
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostFxRateRequest {
+    pub base_currency: Currency,
+    pub quote_currency: Currency,
+    pub rate: Decimal,
+    #[serde_as(as = "TryFromInto<DateWrapper>")]
+    pub effective_date: Date,
+}
+
+pub async fn post_fx_rate(
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<PostFxRateRequest>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Uuid>> {
+    let database = database.lock().await;
+
+    let ulid = database
+        .upsert_one_fx_rate(
+            request.base_currency,
+            request.quote_currency,
+            request.rate,
+            request.effective_date,
+            "manual",
+            claims.payload.ulid,
+        )
+        .await?;
+
+    Ok(Json(ulid))
+}
+
+pub async fn delete_fx_rate(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+
+    database.delete_one_fx_rate(ulid).await?;
+
+    Ok(())
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportFxRatesRequest {
+    /// A CSV file with the columns effective date (YYYY-MM-DD), base currency, quote
+    /// currency and rate, after a header row.
+    #[serde_as(as = "Base64")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportFxRatesResponse {
+    pub imported: usize,
+}
+
+/// Imports a rates file. Nothing is imported unless every row is valid.
+pub async fn import_fx_rates(
+    claims: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(request)): ContentLengthLimit<
+        Json<ImportFxRatesRequest>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<ImportFxRatesResponse>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(request.data.as_slice());
+
+    let mut rates = Vec::new();
+    let mut errors = Vec::new();
+    for (index, row) in reader.records().enumerate() {
+        // The header is row 1.
+        let row_number = index + 2;
+        match row
+            .map_err(|e| e.to_string())
+            .and_then(|row| fx_rate_row(&row))
+        {
+            Ok(rate) => rates.push(rate),
+            Err(e) => errors.push(format!("Row {}: {}", row_number, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(GlobeliseError::bad_request(errors.join("\n")));
+    }
+    if rates.is_empty() {
+        return Err(GlobeliseError::bad_request(
+            "The file does not have any rates",
+        ));
+    }
+
+    let database = database.lock().await;
+    for (effective_date, base_currency, quote_currency, rate) in &rates {
+        database
+            .upsert_one_fx_rate(
+                *base_currency,
+                *quote_currency,
+                *rate,
+                *effective_date,
+                "import",
+                claims.payload.ulid,
+            )
+            .await?;
+    }
+
+    Ok(Json(ImportFxRatesResponse {
+        imported: rates.len(),
+    }))
+}
+
+fn fx_rate_row(row: &csv::StringRecord) -> Result<(Date, Currency, Currency, Decimal), String> {
+    if row.len() != 4 {
+        return Err(format!("expected 4 columns, found {}", row.len()));
+    }
+
+    let effective_date = Date::parse(&row[0], "%Y-%m-%d")
+        .map_err(|_| format!("'{}' is not a YYYY-MM-DD date", &row[0]))?;
+    let base_currency = row[1].to_uppercase().parse::<Currency>()?;
+    let quote_currency = row[2].to_uppercase().parse::<Currency>()?;
+    if base_currency.as_str() == quote_currency.as_str() {
+        return Err("the base and quote currencies must differ".to_string());
+    }
+    let rate = row[3]
+        .parse::<Decimal>()
+        .ok()
+        .filter(|rate| *rate > Decimal::from(0))
+        .ok_or_else(|| format!("'{}' is not a positive rate", &row[3]))?;
+
+    Ok((effective_date, base_currency, quote_currency, rate))
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertQuery {
+    pub amount: Decimal,
+    pub from: Currency,
+    pub to: Currency,
+    /// Today if not given.
+    #[serde_as(as = "TryFromInto<OptionDateWrapper>")]
+    #[serde(default)]
+    pub date: Option<Date>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertResponse {
+    pub amount: Decimal,
+    pub converted_amount: Decimal,
+    pub conversion: Conversion,
+}
+
+pub async fn convert(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<ConvertQuery>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<ConvertResponse>> {
+    let as_of = query.date.unwrap_or_else(today);
+    let rates = database.lock().await.select_fx_rates_as_of(as_of).await?;
+    let conversion = rates.conversion(query.from, query.to)?;
+
+    Ok(Json(ConvertResponse {
+        amount: query.amount,
+        converted_amount: conversion.convert(query.amount),
+        conversion,
+    }))
+}
+
+/// The default reporting date.
+pub fn today() -> Date {
+    sqlx::types::time::OffsetDateTime::now_utc().date()
+}
@@ -0,0 +1,185 @@
+//! Background jobs of this service, see [`common_utils::jobs`].
+
+use axum::{
+    extract::{Extension, Json, Path, Query},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
+};
+use common_utils::{
+    database::{
+        job::{Job, JobStatus},
+        CommonDatabase,
+    },
+    error::{GlobeliseError, GlobeliseResult},
+    jobs::JobRunner,
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use reqwest::Client;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{bulk_add, database::SharedDatabase};
+
+use super::{
+    bank_transfer::{citi_bank, citibank_status, payment_rail, payroll_batch},
+    sap::mulesoft_outbox,
+};
+
+/// Number of jobs run at the same time when `JOB_WORKERS` is not set.
+const DEFAULT_JOB_WORKERS: usize = 2;
+
+/// Starts the workers running the jobs of this service.
+///
+/// The number of workers is configured with `JOB_WORKERS`.
+pub fn spawn_job_workers(
+    database: SharedDatabase,
+    common_database: CommonDatabase,
+    reqwest_client: Client,
+) {
+    let workers = std::env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_JOB_WORKERS);
+
+    JobRunner::new(common_database.clone())
+        .register(bulk_add::BULK_ADD_EMPLOYEES_JOB, {
+            let database = database.clone();
+            let common_database = common_database.clone();
+            move |context| {
+                bulk_add::run_import_job(context, common_database.clone(), database.clone())
+            }
+        })
+        .register(bulk_add::entity::BULK_ADD_ENTITY_CONTRACTORS_JOB, {
+            let database = database.clone();
+            let common_database = common_database.clone();
+            move |context| {
+                bulk_add::entity::run_import_job(context, common_database.clone(), database.clone())
+            }
+        })
+        .register(bulk_add::pay_items::BULK_ASSIGN_PAY_ITEMS_JOB, {
+            let database = database.clone();
+            let common_database = common_database.clone();
+            move |context| {
+                bulk_add::pay_items::run_import_job(
+                    context,
+                    common_database.clone(),
+                    database.clone(),
+                )
+            }
+        })
+        .register(mulesoft_outbox::POST_SAP_PAYROLL_JOURNAL_JOB, {
+            let database = database.clone();
+            move |context| {
+                mulesoft_outbox::run_post_entry_job(
+                    context,
+                    database.clone(),
+                    reqwest_client.clone(),
+                )
+            }
+        })
+        .register(citi_bank::UPLOAD_CITIBANK_TRANSFER_INITIATION_FILE_JOB, {
+            let database = database.clone();
+            move |context| citi_bank::run_upload_job(context, database.clone())
+        })
+        .register(payment_rail::SEND_TRANSFER_FILE_JOB, {
+            let database = database.clone();
+            move |context| payment_rail::run_send_job(context, database.clone())
+        })
+        .register(payroll_batch::CREATE_PAYROLL_TRANSFER_BATCH_JOB, {
+            let database = database.clone();
+            move |context| payroll_batch::run_create_batch_job(context, database.clone())
+        })
+        .register(citibank_status::POLL_CITIBANK_STATUS_FILES_JOB, {
+            let common_database = common_database.clone();
+            move |context| {
+                citibank_status::run_poll_job(context, database.clone(), common_database.clone())
+            }
+        })
+        .spawn(workers);
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JobQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub kind: Option<String>,
+    pub status: Option<JobStatus>,
+}
+
+pub async fn get_many_jobs(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<JobQuery>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Vec<Job>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_jobs(
+            query.page,
+            query.per_page,
+            query.kind.as_deref(),
+            query.status,
+        )
+        .await?;
+
+    Ok(Json(result))
+}
+
+pub async fn get_one_job(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_one_job(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the job"))?;
+
+    Ok(Json(result))
+}
+
+/// Downloads what a job produced, with the content type it was stored with.
+pub async fn download_one_job_result(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<(HeaderMap, Vec<u8>)> {
+    let database = database.lock().await;
+
+    let (content_type, result) = database
+        .select_one_job_result(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("The job has no result"))?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+    Ok((headers, result))
+}
+
+/// Cancels a queued job, or asks a running one to stop.
+pub async fn cancel_one_job(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let database = database.lock().await;
+
+    if database.select_one_job(ulid).await?.is_none() {
+        return Err(GlobeliseError::not_found("Cannot find the job"));
+    }
+    if !database.update_job_cancel_requested(ulid).await? {
+        return Err(GlobeliseError::bad_request(
+            "Only queued and running jobs can be cancelled",
+        ));
+    }
+
+    let result = database
+        .select_one_job(ulid)
+        .await?
+        .ok_or_else(|| GlobeliseError::not_found("Cannot find the job"))?;
+
+    Ok(Json(result))
+}
@@ -9,22 +9,26 @@ use common_utils::{
         CommonDatabase,
     },
     error::{GlobeliseError, GlobeliseResult},
+    mail::EmailTemplate,
     token::Token,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
-use lettre::{Message, SmtpTransport, Transport};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, TryFromInto};
 use uuid::Uuid;
 
-use crate::env::{FRONTEND_URL, GLOBELISE_SENDER_EMAIL, GLOBELISE_SMTP_URL, SMTP_CREDENTIAL};
+use crate::env::FRONTEND_URL;
 
 pub mod bank_transfer;
 pub mod cost_center;
 pub mod entity_contractor_branch_pair;
+pub mod fx_rate;
 pub mod individual_contractor_branch_pair;
+pub mod job;
+pub mod net_pay;
 pub mod pay_items;
+pub mod report;
 pub mod sap;
 pub mod search_employee_contractors;
 pub mod teams;
@@ -36,6 +40,8 @@ pub struct AddUserRequest {
     pub email: EmailWrapper,
     pub client_ulid: Uuid,
     pub debug: Option<bool>,
+    /// Locale of the contractor, for the invitation. The default one when not given.
+    pub locale: Option<String>,
 }
 
 pub async fn add_individual_contractor(
@@ -86,47 +92,18 @@ pub async fn add_individual_contractor(
         .create_client_contractor_pair(body.client_ulid, contractor_ulid)
         .await?;
 
-    let receiver_email = body
-        .email
-        .0
-        // TODO: Get the name of the person associated to this email address
-        .to_display("")
-        .parse()?;
-    let email = Message::builder()
-        .from(GLOBELISE_SENDER_EMAIL.clone())
-        // TODO: Remove this because this is supposed to be a no-reply email?
-        .reply_to(GLOBELISE_SENDER_EMAIL.clone())
-        .to(receiver_email)
-        .subject("Invitation to Globelise")
-        .header(lettre::message::header::ContentType::TEXT_HTML)
-        // TODO: Once designer have a template for this. Use a templating library to populate data.
-        .body(format!(
-            r##"
-            <!DOCTYPE html>
-            <html>
-            <head>
-                <title>Globelise Invitation</title>
-            </head>
-            <body>
-                <p>
-               Click the <a href="{}/signup?as=contractor&type=individual">link</a> to sign up as a Globelise individual contractor.
-                </p>
-                <br/> your password is {}. Please change your password immediatly.
-                <p>If you did not expect to receive this email. Please ignore!</p>
-            </body>
-            </html>
-            "##,
-            (*FRONTEND_URL),
-            (default_password_raw),
-        ))?;
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(&GLOBELISE_SMTP_URL)?
-        .credentials(SMTP_CREDENTIAL.clone())
-        .build();
-
-    // Send the email
-    mailer.send(&email)?;
+    let link = format!("{}/signup?as=contractor&type=individual", (*FRONTEND_URL));
+    let rendered = EmailTemplate::ContractorAccountCreated.render(
+        body.locale.as_deref(),
+        &[
+            ("link", &link),
+            ("contractor_type", UserType::Individual.as_str()),
+            ("password", &default_password_raw),
+        ],
+    )?;
+    database
+        .insert_one_outbox_email(&body.email, &rendered)
+        .await?;
 
     Ok(())
 }
@@ -0,0 +1,273 @@
+//! Net pay of a contract for a pay period.
+//!
+//! The contract amount is the gross pay for a whole pay period. A contract that only covers
+//! part of the period is paid pro rata, by the share of the period it covers. The fixed pay
+//! items assigned to the contractor are then added or deducted according to their method.
+//! Employer's contributions and statement only items are not paid to the contractor, see the
+//! rules in `branch/pay_items.rs`. A contractor with several contracts in the period gets their
+//! pay items once, with the first of them.
+
+use common_utils::{
+    custom_serde::Currency,
+    error::{GlobeliseError, GlobeliseResult},
+    money::Money,
+};
+use sqlx::{types::Decimal, FromRow};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::{branch::pay_items::PayItemMethod, database::Database};
+
+/// A pay item of a branch assigned to a contractor.
+#[derive(Debug, Clone, FromRow)]
+pub struct ContractorPayItem {
+    pub contractor_ulid: Uuid,
+    pub name: String,
+    pub pay_item_type: Option<String>,
+    pub pay_item_method: Option<String>,
+    /// Only set when the amount is the same every pay period.
+    pub amount: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NetPay {
+    pub gross: Money,
+    pub additions: Money,
+    pub deductions: Money,
+    pub net: Money,
+}
+
+/// Hands out the pay items of every contractor once, so that a contractor with several
+/// contracts in a pay period is not paid or charged them twice.
+pub struct PayItemsByContractor<'a> {
+    pay_items: &'a [ContractorPayItem],
+    handed_out: HashSet<Uuid>,
+}
+
+impl<'a> PayItemsByContractor<'a> {
+    pub fn new(pay_items: &'a [ContractorPayItem]) -> Self {
+        Self {
+            pay_items,
+            handed_out: HashSet::new(),
+        }
+    }
+
+    /// The contractor's pay items the first time they are asked for, none afterwards.
+    pub fn take(&mut self, contractor_ulid: Uuid) -> Vec<&'a ContractorPayItem> {
+        if !self.handed_out.insert(contractor_ulid) {
+            return Vec::new();
+        }
+
+        self.pay_items
+            .iter()
+            .filter(|pay_item| pay_item.contractor_ulid == contractor_ulid)
+            .collect()
+    }
+}
+
+/// Pro-rates the gross pay of a contract to the part of the pay period it covers.
+pub fn prorated_gross_pay(
+    amount: Decimal,
+    currency: Currency,
+    contract_begin: sqlx::types::time::OffsetDateTime,
+    contract_end: sqlx::types::time::OffsetDateTime,
+    period_begin: sqlx::types::time::OffsetDateTime,
+    period_end: sqlx::types::time::OffsetDateTime,
+) -> Money {
+    let period = (period_end - period_begin).whole_seconds();
+    let covered = (contract_end.min(period_end) - contract_begin.max(period_begin)).whole_seconds();
+
+    let amount = if period <= 0 || covered >= period {
+        amount
+    } else if covered <= 0 {
+        Decimal::ZERO
+    } else {
+        amount * Decimal::from(covered) / Decimal::from(period)
+    };
+
+    Money::round(amount, currency)
+}
+
+/// Adds and deducts the contractor's fixed pay items to and from the gross pay.
+pub fn net_pay<'a>(
+    gross: Money,
+    pay_items: impl IntoIterator<Item = &'a ContractorPayItem>,
+) -> GlobeliseResult<NetPay> {
+    let currency = gross.currency();
+    let mut additions = Money::zero(currency);
+    let mut deductions = Money::zero(currency);
+
+    for pay_item in pay_items {
+        let method = pay_item
+            .pay_item_method
+            .as_deref()
+            .and_then(PayItemMethod::from_str)
+            .ok_or_else(|| {
+                GlobeliseError::bad_request(format!(
+                    "The pay item {} has no valid method",
+                    pay_item.name
+                ))
+            })?;
+        let total = match method {
+            PayItemMethod::Addition => &mut additions,
+            PayItemMethod::Deduction => &mut deductions,
+            PayItemMethod::EmployersContribution | PayItemMethod::StatementOnly => continue,
+        };
+        let amount = pay_item.amount.ok_or_else(|| {
+            GlobeliseError::bad_request(format!(
+                "The pay item {} has no fixed amount for this period",
+                pay_item.name
+            ))
+        })?;
+        *total = total.checked_add(&Money::new(amount, currency)?)?;
+    }
+
+    let net = gross.checked_add(&additions)?.checked_add(&-deductions)?;
+
+    Ok(NetPay {
+        gross,
+        additions,
+        deductions,
+        net,
+    })
+}
+
+impl Database {
+    /// Pay items of a branch assigned to its contractors.
+    pub async fn select_many_contractor_pay_items(
+        &self,
+        branch_ulid: Uuid,
+    ) -> GlobeliseResult<Vec<ContractorPayItem>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            cpi.contractor_ulid,
+            COALESCE(p.pay_item_custom_name, p.pay_item_type, '') AS name,
+            p.pay_item_type, p.pay_item_method, cpi.amount
+        FROM
+            contractor_pay_items cpi
+        JOIN
+            entity_client_branch_pay_items p
+        ON
+            p.ulid = cpi.pay_item_ulid
+        WHERE
+            p.branch_ulid = $1",
+        )
+        .bind(branch_ulid)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+
+    use super::*;
+
+    fn day(year: i32, month: u8, day: u8) -> OffsetDateTime {
+        PrimitiveDateTime::new(
+            Date::try_from_ymd(year, month, day).unwrap(),
+            Time::midnight(),
+        )
+        .assume_utc()
+    }
+
+    fn sgd() -> Currency {
+        "SGD".parse().unwrap()
+    }
+
+    fn pay_item(method: &str, amount: Option<&str>) -> ContractorPayItem {
+        ContractorPayItem {
+            contractor_ulid: Uuid::nil(),
+            name: method.to_string(),
+            pay_item_type: Some("others".to_string()),
+            pay_item_method: Some(method.to_string()),
+            amount: amount.map(|amount| amount.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn contracts_covering_the_period_are_paid_in_full() {
+        let gross = prorated_gross_pay(
+            "3000".parse().unwrap(),
+            sgd(),
+            day(2022, 1, 1),
+            day(2023, 1, 1),
+            day(2022, 9, 1),
+            day(2022, 10, 1),
+        );
+        assert_eq!(gross.amount().to_string(), "3000.00");
+    }
+
+    #[test]
+    fn contracts_starting_within_the_period_are_pro_rated() {
+        // 20 of the 30 days of September.
+        let gross = prorated_gross_pay(
+            "3000".parse().unwrap(),
+            sgd(),
+            day(2022, 9, 11),
+            day(2023, 1, 1),
+            day(2022, 9, 1),
+            day(2022, 10, 1),
+        );
+        assert_eq!(gross.amount().to_string(), "2000.00");
+
+        let gross = prorated_gross_pay(
+            "1000".parse().unwrap(),
+            sgd(),
+            day(2022, 9, 1),
+            day(2022, 9, 11),
+            day(2022, 9, 1),
+            day(2022, 10, 1),
+        );
+        assert_eq!(gross.amount().to_string(), "333.33");
+    }
+
+    #[test]
+    fn pay_items_are_added_and_deducted_by_method() {
+        let gross = Money::new("3000".parse().unwrap(), sgd()).unwrap();
+        let pay_items = [
+            pay_item("addition", Some("250.50")),
+            pay_item("deduction", Some("600")),
+            pay_item("employers_contribution", Some("510")),
+            pay_item("statement_only", None),
+        ];
+
+        let net_pay = net_pay(gross, &pay_items).unwrap();
+        assert_eq!(net_pay.additions.amount().to_string(), "250.50");
+        assert_eq!(net_pay.deductions.amount().to_string(), "600.00");
+        assert_eq!(net_pay.net.amount().to_string(), "2650.50");
+    }
+
+    #[test]
+    fn pay_items_are_handed_out_once_per_contractor() {
+        let contractor = Uuid::new_v4();
+        let mut other = pay_item("addition", Some("100"));
+        other.contractor_ulid = Uuid::new_v4();
+        let pay_items = [
+            ContractorPayItem {
+                contractor_ulid: contractor,
+                ..pay_item("deduction", Some("600"))
+            },
+            other.clone(),
+        ];
+        let mut by_contractor = PayItemsByContractor::new(&pay_items);
+
+        let first = by_contractor.take(contractor);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].name, "deduction");
+        assert!(by_contractor.take(contractor).is_empty());
+        assert_eq!(by_contractor.take(other.contractor_ulid).len(), 1);
+    }
+
+    #[test]
+    fn pay_items_without_a_fixed_amount_are_rejected() {
+        let gross = Money::new("3000".parse().unwrap(), sgd()).unwrap();
+
+        assert!(net_pay(gross, &[pay_item("deduction", None)]).is_err());
+        assert!(net_pay(gross, &[pay_item("unknown", Some("1"))]).is_err());
+    }
+}
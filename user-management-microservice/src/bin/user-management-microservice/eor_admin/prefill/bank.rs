@@ -1,5 +1,6 @@
 use axum::extract::{ContentLengthLimit, Extension, Json, Query};
 use common_utils::{
+    bank_details::BankDetails,
     custom_serde::{EmailWrapper, FORM_DATA_LENGTH_LIMIT},
     error::{GlobeliseError, GlobeliseResult},
     token::Token,
@@ -34,6 +35,14 @@ pub async fn individual_contractor_post_one(
     >,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
+    BankDetails {
+        bank_account_number: &body.bank_account_number,
+        bank_code: Some(&body.bank_code),
+        branch_code: Some(&body.branch_code),
+        ..Default::default()
+    }
+    .validate()?;
+
     let database = database.lock().await;
     if let Some(client_ulid) = body.client_ulid {
         database
@@ -111,6 +120,14 @@ pub async fn entity_client_post_one(
     >,
     Extension(database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
+    BankDetails {
+        bank_account_number: &body.bank_account_number,
+        bank_code: Some(&body.bank_code),
+        branch_code: Some(&body.branch_code),
+        ..Default::default()
+    }
+    .validate()?;
+
     let database = database.lock().await;
     database
         .insert_one_prefilled_entity_client_bank_details(
@@ -0,0 +1,289 @@
+//! Payroll and cost-center totals of a client, converted into one currency.
+//!
+//! Amounts are added up per currency first and converted with the FX rates in force on the
+//! reporting date, so that every report shows the rate and rate date behind each part.
+
+use std::collections::BTreeMap;
+
+use axum::extract::{Extension, Json, Query};
+use common_utils::{
+    custom_serde::{Currency, OptionDateWrapper},
+    database::CommonDatabase,
+    error::GlobeliseResult,
+    fx::ConvertedTotal,
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{
+    types::{time::Date, Decimal},
+    FromRow,
+};
+use uuid::Uuid;
+
+use crate::database::{Database, SharedDatabase};
+
+use super::fx_rate::today;
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertedReportQuery {
+    pub client_ulid: Uuid,
+    /// The currency to report in.
+    pub currency: Currency,
+    /// The date whose FX rates are used, today if not given.
+    #[serde_as(as = "TryFromInto<OptionDateWrapper>")]
+    #[serde(default)]
+    pub date: Option<Date>,
+}
+
+#[derive(Debug, FromRow)]
+struct CurrencyAmount {
+    group_ulid: Option<Uuid>,
+    group_name: Option<String>,
+    currency: Currency,
+    amount: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertedReport {
+    pub client_ulid: Uuid,
+    pub total: ConvertedTotal,
+    pub groups: Vec<ConvertedReportGroup>,
+}
+
+/// The total of a branch or cost center.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConvertedReportGroup {
+    pub ulid: Option<Uuid>,
+    pub name: Option<String>,
+    pub total: ConvertedTotal,
+}
+
+/// Everything paid out to a client's contractors, per branch.
+pub async fn payroll_report(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<ConvertedReportQuery>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<ConvertedReport>> {
+    let amounts = database
+        .lock()
+        .await
+        .select_payroll_amounts_by_branch(query.client_ulid)
+        .await?;
+
+    converted_report(&common_database, query, amounts).await
+}
+
+/// The monthly cost of the active contracts of a client's cost centers.
+pub async fn cost_center_report(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<ConvertedReportQuery>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<ConvertedReport>> {
+    let amounts = database
+        .lock()
+        .await
+        .select_cost_center_amounts(query.client_ulid)
+        .await?;
+
+    converted_report(&common_database, query, amounts).await
+}
+
+async fn converted_report(
+    common_database: &CommonDatabase,
+    query: ConvertedReportQuery,
+    amounts: Vec<CurrencyAmount>,
+) -> GlobeliseResult<Json<ConvertedReport>> {
+    let rates = common_database
+        .lock()
+        .await
+        .select_fx_rates_as_of(query.date.unwrap_or_else(today))
+        .await?;
+
+    let mut totals = BTreeMap::<&str, (Currency, Decimal)>::new();
+    let mut groups = BTreeMap::<Option<Uuid>, (Option<String>, Vec<(Currency, Decimal)>)>::new();
+    for amount in &amounts {
+        let total = totals
+            .entry(amount.currency.as_str())
+            .or_insert((amount.currency, Decimal::from(0)));
+        total.1 += amount.amount;
+        groups
+            .entry(amount.group_ulid)
+            .or_insert_with(|| (amount.group_name.clone(), vec![]))
+            .1
+            .push((amount.currency, amount.amount));
+    }
+
+    Ok(Json(ConvertedReport {
+        client_ulid: query.client_ulid,
+        total: rates.convert_totals(totals.into_values(), query.currency)?,
+        groups: groups
+            .into_iter()
+            .map(|(ulid, (name, amounts))| {
+                Ok(ConvertedReportGroup {
+                    ulid,
+                    name,
+                    total: rates.convert_totals(amounts, query.currency)?,
+                })
+            })
+            .collect::<GlobeliseResult<_>>()?,
+    }))
+}
+
+impl Database {
+    /// The amounts of a client's transfers that were sent to the bank and not rejected.
+    async fn select_payroll_amounts_by_branch(
+        &self,
+        client_ulid: Uuid,
+    ) -> GlobeliseResult<Vec<CurrencyAmount>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            f.branch_ulid AS group_ulid, NULL::text AS group_name,
+            r.currency_code AS currency, SUM(r.amount) AS amount
+        FROM
+            uploaded_citibank_transfer_initiation_files_records r
+        JOIN
+            uploaded_citibank_transfer_initiation_files f
+        ON
+            r.file_ulid = f.ulid
+        WHERE
+            f.client_ulid = $1 AND
+            f.status = 'sent' AND
+            r.transaction_status <> 'rcjt'
+        GROUP BY
+            f.branch_ulid, r.currency_code",
+        )
+        .bind(client_ulid)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn select_cost_center_amounts(
+        &self,
+        client_ulid: Uuid,
+    ) -> GlobeliseResult<Vec<CurrencyAmount>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            cc.ulid AS group_ulid, cc.cost_center_name AS group_name,
+            c.currency, SUM(c.contract_amount) AS amount
+        FROM
+            cost_center cc
+        JOIN
+            entity_client_branches b
+        ON
+            b.ulid = cc.branch_ulid
+        JOIN
+            cost_center_contractor_pairs ccc
+        ON
+            ccc.cost_center_ulid = cc.ulid
+        JOIN
+            contracts c
+        ON
+            c.contractor_ulid = ccc.contractor_ulid AND
+            c.branch_ulid = cc.branch_ulid
+        WHERE
+            b.client_ulid = $1 AND
+            c.contract_status = 'ACTIVE'
+        GROUP BY
+            cc.ulid, cc.cost_center_name, c.currency",
+        )
+        .bind(client_ulid)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn payroll_amounts_only_count_sent_transfers_that_were_not_rejected() {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+        let database = Database(pool);
+
+        let client_ulid = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (ulid, email, is_client) VALUES ($1, $2, 't')")
+            .bind(client_ulid)
+            .bind(format!("{}@client.example", client_ulid))
+            .execute(&database.0)
+            .await
+            .unwrap();
+        for (file_status, transaction_status, amount) in [
+            ("draft", "pending", 1),
+            ("approved", "pending", 10),
+            ("sent", "pending", 100),
+            ("sent", "ack", 1_000),
+            ("sent", "acpt", 10_000),
+            ("sent", "rcjt", 100_000),
+        ] {
+            let file_ulid = Uuid::new_v4();
+            sqlx::query(
+                "
+            INSERT INTO uploaded_citibank_transfer_initiation_files (
+                ulid, title_identifier, status, client_ulid
+            ) VALUES (
+                $1, $2, $3, $4
+            )",
+            )
+            .bind(file_ulid)
+            .bind(format!("Payroll {}", file_ulid))
+            .bind(file_status)
+            .bind(client_ulid)
+            .execute(&database.0)
+            .await
+            .unwrap();
+            sqlx::query(
+                "
+            INSERT INTO uploaded_citibank_transfer_initiation_files_records (
+                ulid, currency_code, country_code, employee_id, employee_name,
+                bank_name, bank_account_number, bank_code, bank_branch_code, swift_code,
+                amount, file_ulid, transaction_status
+            ) VALUES (
+                $1, 'SGD', 'SG', $2, 'Tan Wei Ming',
+                'DBS Bank', '0123456789', '7171', '081', 'DBSSSGSG',
+                $3, $4, $5
+            )",
+            )
+            .bind(Uuid::new_v4())
+            .bind(Uuid::new_v4())
+            .bind(Decimal::from(amount))
+            .bind(file_ulid)
+            .bind(transaction_status)
+            .execute(&database.0)
+            .await
+            .unwrap();
+        }
+
+        let amounts = database
+            .select_payroll_amounts_by_branch(client_ulid)
+            .await
+            .unwrap();
+
+        assert_eq!(amounts.len(), 1);
+        assert_eq!(amounts[0].currency.as_str(), "SGD");
+        assert_eq!(amounts[0].amount, Decimal::from(11_100));
+    }
+}
@@ -0,0 +1,497 @@
+//! The GL accounts every company code posts payroll journal lines to.
+//!
+//! A line is posted for a purpose of its own, like salaries or the outgoing bank payment,
+//! or for a pay item. Pay items are mapped by type, and optionally by method so that for
+//! example an employer's contribution to a statutory fund can go to another account than
+//! the employee's part.
+
+use axum::extract::{Extension, Json, Path, Query};
+use common_utils::{
+    custom_serde::OffsetDateWrapper,
+    error::{GlobeliseError, GlobeliseResult},
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    branch::pay_items::{PayItemMethod, PayItemType},
+    database::{Database, SharedDatabase},
+};
+
+use super::s4_hana::GlAccount;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalPurpose {
+    Salary,
+    BankOutgoing,
+    PayItem,
+}
+
+impl JournalPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalPurpose::Salary => "salary",
+            JournalPurpose::BankOutgoing => "bank_outgoing",
+            JournalPurpose::PayItem => "pay_item",
+        }
+    }
+
+    pub fn from_str(string: &str) -> Option<JournalPurpose> {
+        match string {
+            "salary" => Some(JournalPurpose::Salary),
+            "bank_outgoing" => Some(JournalPurpose::BankOutgoing),
+            "pay_item" => Some(JournalPurpose::PayItem),
+            _ => None,
+        }
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for JournalPurpose {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("text")
+    }
+}
+
+impl sqlx::Decode<'_, sqlx::Postgres> for JournalPurpose {
+    fn decode(value: sqlx::postgres::PgValueRef<'_>) -> Result<Self, sqlx::error::BoxDynError> {
+        let purpose_str: &'_ str = sqlx::decode::Decode::decode(value)?;
+        let purpose = JournalPurpose::from_str(purpose_str).ok_or(format!(
+            "Cannot convert {} into a JournalPurpose",
+            purpose_str
+        ))?;
+        Ok(purpose)
+    }
+}
+
+impl sqlx::encode::Encode<'_, sqlx::Postgres> for JournalPurpose {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::encode(val, buf)
+    }
+    fn size_hint(&self) -> std::primitive::usize {
+        let val = self.as_str();
+        sqlx::encode::Encode::<'_, sqlx::Postgres>::size_hint(&val)
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SapGlAccountMapping {
+    pub ulid: Uuid,
+    pub company_code: String,
+    pub purpose: JournalPurpose,
+    pub pay_item_type: Option<PayItemType>,
+    /// None maps every method of the pay item type.
+    pub pay_item_method: Option<PayItemMethod>,
+    pub gl_account: String,
+    pub description: Option<String>,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+/// The GL accounts of one company code.
+#[derive(Debug)]
+pub struct GlAccounts {
+    company_code: String,
+    mappings: Vec<SapGlAccountMapping>,
+}
+
+impl GlAccounts {
+    pub fn for_purpose(&self, purpose: JournalPurpose) -> GlobeliseResult<GlAccount> {
+        self.find(|mapping| mapping.purpose == purpose)
+            .ok_or_else(|| {
+                GlobeliseError::bad_request(format!(
+                    "Company code '{}' has no GL account for {}",
+                    self.company_code,
+                    purpose.as_str()
+                ))
+            })
+    }
+
+    /// The account of a pay item, mapped either for its method or for every method of its type.
+    pub fn for_pay_item(
+        &self,
+        pay_item_type: PayItemType,
+        pay_item_method: PayItemMethod,
+    ) -> GlobeliseResult<GlAccount> {
+        let of_type = |mapping: &SapGlAccountMapping| {
+            mapping.purpose == JournalPurpose::PayItem
+                && mapping.pay_item_type == Some(pay_item_type)
+        };
+        self.find(|mapping| of_type(mapping) && mapping.pay_item_method == Some(pay_item_method))
+            .or_else(|| self.find(|mapping| of_type(mapping) && mapping.pay_item_method.is_none()))
+            .ok_or_else(|| {
+                GlobeliseError::bad_request(format!(
+                    "Company code '{}' has no GL account for {} pay items paid as {}",
+                    self.company_code,
+                    pay_item_type.as_str(),
+                    pay_item_method.as_str()
+                ))
+            })
+    }
+
+    /// What lines are posted to an account for, or None if the account is not mapped.
+    pub fn purposes_of(&self, account: &GlAccount) -> Option<Vec<JournalPurpose>> {
+        let purposes = self
+            .mappings
+            .iter()
+            .filter(|mapping| mapping.gl_account == account.as_str())
+            .map(|mapping| mapping.purpose)
+            .collect::<Vec<_>>();
+        if purposes.is_empty() {
+            None
+        } else {
+            Some(purposes)
+        }
+    }
+
+    /// The description of an account, to be sent with lines that have no text of their own.
+    pub fn description_of(&self, account: &GlAccount) -> Option<&str> {
+        self.mappings
+            .iter()
+            .filter(|mapping| mapping.gl_account == account.as_str())
+            .find_map(|mapping| mapping.description.as_deref())
+    }
+
+    fn find(&self, predicate: impl Fn(&SapGlAccountMapping) -> bool) -> Option<GlAccount> {
+        self.mappings
+            .iter()
+            .find(|mapping| predicate(mapping))
+            .map(|mapping| GlAccount::new(mapping.gl_account.clone()))
+            .and_then(Result::ok)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SapGlAccountQuery {
+    pub company_code: Option<String>,
+}
+
+pub async fn get_many_gl_accounts(
+    _: Token<AdminAccessToken>,
+    Query(query): Query<SapGlAccountQuery>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<Vec<SapGlAccountMapping>>> {
+    let database = database.lock().await;
+
+    let result = database
+        .select_many_sap_gl_accounts(query.company_code.as_deref())
+        .await?;
+
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SapGlAccountRequest {
+    pub company_code: String,
+    pub purpose: JournalPurpose,
+    pub pay_item_type: Option<PayItemType>,
+    pub pay_item_method: Option<PayItemMethod>,
+    pub gl_account: GlAccount,
+    pub description: Option<String>,
+}
+
+impl SapGlAccountRequest {
+    fn validate(&self) -> GlobeliseResult<()> {
+        match (self.purpose, self.pay_item_type, self.pay_item_method) {
+            (JournalPurpose::PayItem, None, _) => Err(GlobeliseError::bad_request(
+                "A pay item GL account needs a pay item type",
+            )),
+            (JournalPurpose::PayItem, Some(_), _) | (_, None, None) => Ok(()),
+            (purpose, _, _) => Err(GlobeliseError::bad_request(format!(
+                "A GL account for {} cannot have a pay item type or method",
+                purpose.as_str()
+            ))),
+        }
+    }
+}
+
+pub async fn post_one_gl_account(
+    _: Token<AdminAccessToken>,
+    Json(request): Json<SapGlAccountRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<Uuid>> {
+    request.validate()?;
+
+    let database = database.lock().await;
+
+    if !database
+        .sap_mulesoft_payroll_journal_validate_company_code(&request.company_code)
+        .await?
+    {
+        return Err(GlobeliseError::bad_request(format!(
+            "The company code '{}' does not exist in the database",
+            request.company_code
+        )));
+    }
+
+    let ulid = database.insert_one_sap_gl_account(&request).await?;
+
+    Ok(Json(ulid))
+}
+
+pub async fn put_one_gl_account(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Json(request): Json<SapGlAccountRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    request.validate()?;
+
+    let database = database.lock().await;
+
+    if !database
+        .sap_mulesoft_payroll_journal_validate_company_code(&request.company_code)
+        .await?
+    {
+        return Err(GlobeliseError::bad_request(format!(
+            "The company code '{}' does not exist in the database",
+            request.company_code
+        )));
+    }
+
+    database.update_one_sap_gl_account(ulid, &request).await
+}
+
+pub async fn delete_one_gl_account(
+    _: Token<AdminAccessToken>,
+    Path(ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+
+    database.delete_one_sap_gl_account(ulid).await
+}
+
+impl Database {
+    pub async fn select_sap_gl_accounts(&self, company_code: &str) -> GlobeliseResult<GlAccounts> {
+        let mappings = self.select_many_sap_gl_accounts(Some(company_code)).await?;
+
+        Ok(GlAccounts {
+            company_code: company_code.to_string(),
+            mappings,
+        })
+    }
+
+    pub async fn select_many_sap_gl_accounts(
+        &self,
+        company_code: Option<&str>,
+    ) -> GlobeliseResult<Vec<SapGlAccountMapping>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            ulid, company_code, purpose, pay_item_type, pay_item_method, gl_account,
+            description, created_at
+        FROM
+            sap_mulesoft_payroll_journal_gl_accounts
+        WHERE
+            ($1 IS NULL OR company_code = $1)
+        ORDER BY
+            company_code, purpose, pay_item_type, pay_item_method",
+        )
+        .bind(company_code)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn insert_one_sap_gl_account(
+        &self,
+        request: &SapGlAccountRequest,
+    ) -> GlobeliseResult<Uuid> {
+        let ulid = Uuid::new_v4();
+
+        let inserted = sqlx::query(
+            "
+        INSERT INTO sap_mulesoft_payroll_journal_gl_accounts (
+            ulid, company_code, purpose, pay_item_type, pay_item_method, gl_account,
+            description
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6,
+            $7
+        ) ON CONFLICT DO NOTHING",
+        )
+        .bind(ulid)
+        .bind(&request.company_code)
+        .bind(request.purpose)
+        .bind(request.pay_item_type)
+        .bind(request.pay_item_method)
+        .bind(request.gl_account.as_str())
+        .bind(&request.description)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        if inserted == 0 {
+            return Err(GlobeliseError::bad_request(
+                "The company code already has a GL account for that purpose and pay item",
+            ));
+        }
+
+        Ok(ulid)
+    }
+
+    async fn update_one_sap_gl_account(
+        &self,
+        ulid: Uuid,
+        request: &SapGlAccountRequest,
+    ) -> GlobeliseResult<()> {
+        let taken = sqlx::query(
+            "
+        SELECT
+            ulid
+        FROM
+            sap_mulesoft_payroll_journal_gl_accounts
+        WHERE
+            ulid <> $1 AND
+            company_code = $2 AND
+            purpose = $3 AND
+            COALESCE(pay_item_type, '') = COALESCE($4, '') AND
+            COALESCE(pay_item_method, '') = COALESCE($5, '')",
+        )
+        .bind(ulid)
+        .bind(&request.company_code)
+        .bind(request.purpose)
+        .bind(request.pay_item_type)
+        .bind(request.pay_item_method)
+        .fetch_optional(&self.0)
+        .await?
+        .is_some();
+        if taken {
+            return Err(GlobeliseError::bad_request(
+                "The company code already has a GL account for that purpose and pay item",
+            ));
+        }
+
+        let updated = sqlx::query(
+            "
+        UPDATE
+            sap_mulesoft_payroll_journal_gl_accounts
+        SET
+            company_code = $2,
+            purpose = $3,
+            pay_item_type = $4,
+            pay_item_method = $5,
+            gl_account = $6,
+            description = $7
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .bind(&request.company_code)
+        .bind(request.purpose)
+        .bind(request.pay_item_type)
+        .bind(request.pay_item_method)
+        .bind(request.gl_account.as_str())
+        .bind(&request.description)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(GlobeliseError::not_found("Cannot find the GL account"));
+        }
+
+        Ok(())
+    }
+
+    async fn delete_one_sap_gl_account(&self, ulid: Uuid) -> GlobeliseResult<()> {
+        let deleted = sqlx::query(
+            "
+        DELETE FROM
+            sap_mulesoft_payroll_journal_gl_accounts
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            return Err(GlobeliseError::not_found("Cannot find the GL account"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(
+        purpose: JournalPurpose,
+        pay_item_type: Option<PayItemType>,
+        pay_item_method: Option<PayItemMethod>,
+        gl_account: &str,
+    ) -> SapGlAccountMapping {
+        SapGlAccountMapping {
+            ulid: Uuid::new_v4(),
+            company_code: "SG01".to_string(),
+            purpose,
+            pay_item_type,
+            pay_item_method,
+            gl_account: gl_account.to_string(),
+            description: None,
+            created_at: sqlx::types::time::OffsetDateTime::unix_epoch(),
+        }
+    }
+
+    fn gl_accounts() -> GlAccounts {
+        GlAccounts {
+            company_code: "SG01".to_string(),
+            mappings: vec![
+                mapping(JournalPurpose::Salary, None, None, "430101001"),
+                mapping(
+                    JournalPurpose::PayItem,
+                    Some(PayItemType::StatutoryFund),
+                    None,
+                    "220101001",
+                ),
+                mapping(
+                    JournalPurpose::PayItem,
+                    Some(PayItemType::StatutoryFund),
+                    Some(PayItemMethod::EmployersContribution),
+                    "430102001",
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn pay_items_prefer_the_account_of_their_method() {
+        let gl_accounts = gl_accounts();
+
+        let account = gl_accounts
+            .for_pay_item(
+                PayItemType::StatutoryFund,
+                PayItemMethod::EmployersContribution,
+            )
+            .unwrap();
+        assert_eq!(account.as_str(), "430102001");
+
+        let account = gl_accounts
+            .for_pay_item(PayItemType::StatutoryFund, PayItemMethod::Deduction)
+            .unwrap();
+        assert_eq!(account.as_str(), "220101001");
+    }
+
+    #[test]
+    fn pay_items_of_unmapped_types_have_no_account() {
+        let gl_accounts = gl_accounts();
+
+        assert!(gl_accounts
+            .for_pay_item(PayItemType::Tax, PayItemMethod::Deduction)
+            .is_err());
+        assert!(gl_accounts
+            .for_purpose(JournalPurpose::BankOutgoing)
+            .is_err());
+    }
+}
@@ -1 +1,4 @@
+pub mod gl_account;
+pub mod mulesoft_outbox;
+pub mod payroll_journal;
 pub mod s4_hana;
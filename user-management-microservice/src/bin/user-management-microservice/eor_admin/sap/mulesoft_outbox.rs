@@ -0,0 +1,1173 @@
+//! The outbox payroll journals are posted to S/4HANA from.
+//!
+//! A journal is stored with the exact body to send before anything is posted. Posting claims
+//! the journal, so that only one replica posts it at a time, and records every attempt with
+//! the Mulesoft response. Journals that could not be posted because Mulesoft or the network
+//! was unavailable are retried with an exponential backoff, up to [`MAX_ATTEMPTS`]. The
+//! ULID of the journal is sent as its idempotency key and its integration ID. A retry first
+//! looks the journal up by its integration ID, so a journal that was posted without us
+//! hearing back is not posted twice, and a conflict only counts as posted once the lookup
+//! answers with a journal that has that integration ID.
+//!
+//! The first attempt at a journal, and an attempt requested by hand, runs as a background
+//! job so that requests do not wait for Mulesoft. Failed journals can be posted again by
+//! hand, and posted journals can only be cancelled by posting a journal that reverses them.
+
+use axum::extract::{Extension, Json, Path};
+use common_utils::{
+    custom_serde::OffsetDateWrapper,
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    jobs::{self, JobContext, JobOutput},
+    money::Money,
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{FromRow, Row};
+use uuid::Uuid;
+
+use crate::{
+    database::{Database, SharedDatabase},
+    env::{MULESOFT_API_URL, MULESOFT_CLIENT_ID, MULESOFT_CLIENT_SECRET},
+};
+
+use super::{
+    payroll_journal::journal_workbook,
+    s4_hana::{
+        DebitCreditCode, GlAccount, InsertPayrollJournalRowData, MulesoftJournalEntry,
+        SapMulesoftPayrollJournalEntryIndex, SapMulesoftPayrollJournalRow,
+    },
+};
+
+/// Number of times a journal is attempted before it is marked as failed.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Seconds before the first retry. Every retry waits twice as long as the previous one.
+const FIRST_RETRY_DELAY_SECONDS: f64 = 60.0;
+
+/// The longest wait between two attempts.
+const MAX_RETRY_DELAY_SECONDS: f64 = 3600.0;
+
+/// Seconds between two runs of the outbox when `MULESOFT_OUTBOX_INTERVAL_SECONDS` is not set.
+const DEFAULT_OUTBOX_INTERVAL_SECONDS: u64 = 60;
+
+pub const POST_SAP_PAYROLL_JOURNAL_JOB: &str = "post-sap-payroll-journal";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PostSapPayrollJournalJob {
+    entry_ulid: Uuid,
+}
+
+/// Queues an attempt at posting a journal. A journal has at most one such job queued at a
+/// time.
+pub async fn enqueue_post_entry(
+    common_database: &CommonDatabase,
+    entry_ulid: Uuid,
+    created_by: Option<Uuid>,
+) -> GlobeliseResult<Job> {
+    jobs::enqueue(
+        common_database,
+        POST_SAP_PAYROLL_JOURNAL_JOB,
+        Some(&format!("{}:{}", POST_SAP_PAYROLL_JOURNAL_JOB, entry_ulid)),
+        &PostSapPayrollJournalJob { entry_ulid },
+        3,
+        created_by,
+    )
+    .await
+}
+
+/// Runs an attempt queued by [`enqueue_post_entry`]. Its result is the journal after the
+/// attempt.
+pub async fn run_post_entry_job(
+    context: JobContext,
+    database: SharedDatabase,
+    reqwest_client: Client,
+) -> GlobeliseResult<Option<JobOutput>> {
+    let job: PostSapPayrollJournalJob = context.payload()?;
+
+    let api = MulesoftApi::from_env(&reqwest_client);
+    let entry = post_entry(&database, &api, job.entry_ulid).await?;
+
+    Ok(Some(JobOutput::json(&entry)?))
+}
+
+/// Posts the journals that are due forever.
+///
+/// The interval is configured with `MULESOFT_OUTBOX_INTERVAL_SECONDS`.
+pub async fn post_pending_journals_periodically(database: SharedDatabase, reqwest_client: Client) {
+    let interval = std::env::var("MULESOFT_OUTBOX_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_OUTBOX_INTERVAL_SECONDS);
+
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        if let Err(e) = post_pending_journals(&database, &reqwest_client).await {
+            println!("Could not post pending SAP payroll journals: {:?}", e);
+        }
+    }
+}
+
+/// Attempts every journal that is due.
+pub async fn post_pending_journals(
+    database: &SharedDatabase,
+    reqwest_client: &Client,
+) -> GlobeliseResult<()> {
+    let due = database
+        .lock()
+        .await
+        .select_due_sap_mulesoft_payroll_journals()
+        .await?;
+
+    let api = MulesoftApi::from_env(reqwest_client);
+    for ulid in due {
+        if let Err(e) = post_entry(database, &api, ulid).await {
+            println!("Could not post SAP payroll journal {}: {:?}", ulid, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes one attempt at posting a journal, if it is due and no one else is posting it.
+///
+/// The database is not locked while waiting for Mulesoft.
+pub async fn post_entry(
+    database: &SharedDatabase,
+    api: &MulesoftApi<'_>,
+    ulid: Uuid,
+) -> GlobeliseResult<SapMulesoftPayrollJournalEntryIndex> {
+    let claimed = database
+        .lock()
+        .await
+        .claim_sap_mulesoft_payroll_journal(ulid)
+        .await?;
+
+    if let Some((payload, attempt_count)) = claimed {
+        let attempt = attempt_posting(api, ulid, payload, attempt_count).await;
+
+        database
+            .lock()
+            .await
+            .record_sap_mulesoft_payroll_journal_attempt(ulid, &attempt)
+            .await?;
+    }
+
+    database
+        .lock()
+        .await
+        .select_one_sap_mulesoft_payroll_journal_entry_index(ulid)
+        .await?
+        .ok_or_else(|| {
+            GlobeliseError::not_found("Cannot find payroll journal entry with that UUID")
+        })
+}
+
+/// The journal entry API of Mulesoft.
+pub struct MulesoftApi<'a> {
+    reqwest_client: &'a Client,
+    url: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+}
+
+impl<'a> MulesoftApi<'a> {
+    /// The API configured with `MULESOFT_API_URL`, `MULESOFT_CLIENT_ID` and
+    /// `MULESOFT_CLIENT_SECRET`.
+    pub fn from_env(reqwest_client: &'a Client) -> Self {
+        MulesoftApi {
+            reqwest_client,
+            url: MULESOFT_API_URL.as_str(),
+            client_id: MULESOFT_CLIENT_ID.as_str(),
+            client_secret: MULESOFT_CLIENT_SECRET.as_str(),
+        }
+    }
+
+    fn journal_entry_url(&self) -> String {
+        format!("{}/master-pub-v1/api/transaction/journalentry", self.url)
+    }
+
+    /// Posts a journal, returning the status code and body of the response.
+    async fn post_journal(
+        &self,
+        ulid: Uuid,
+        payload: String,
+    ) -> reqwest::Result<(StatusCode, Option<String>)> {
+        let response = self
+            .reqwest_client
+            .post(self.journal_entry_url())
+            .header("client_id", self.client_id)
+            .header("client_secret", self.client_secret)
+            .header("Idempotency-Key", ulid.to_string())
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload)
+            .send()
+            .await?;
+
+        let status_code = response.status();
+        Ok((status_code, response.text().await.ok()))
+    }
+
+    /// Whether S/4HANA has a journal with the ULID as its integration ID.
+    async fn journal_exists(&self, ulid: Uuid) -> Result<bool, String> {
+        let response = self
+            .reqwest_client
+            .get(self.journal_entry_url())
+            .query(&[("integrationId", ulid.to_string())])
+            .header("client_id", self.client_id)
+            .header("client_secret", self.client_secret)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match response.status() {
+            code if code.is_success() => {
+                let body = response.text().await.map_err(|e| e.to_string())?;
+                lookup_has_journal(&body, ulid)
+            }
+            StatusCode::NOT_FOUND => Ok(false),
+            code => Err(format!("Mulesoft responded with {} to the lookup", code)),
+        }
+    }
+}
+
+/// A journal found by the lookup, of which only the integration ID is needed.
+#[derive(Debug, Deserialize)]
+struct MulesoftJournalLookup {
+    integration: MulesoftJournalLookupIntegration,
+}
+
+#[derive(Debug, Deserialize)]
+struct MulesoftJournalLookupIntegration {
+    id: String,
+}
+
+/// Whether the journals the lookup answered with include the one with the ULID as its
+/// integration ID. A successful answer alone does not tell, a gateway may ignore the query.
+fn lookup_has_journal(body: &str, ulid: Uuid) -> Result<bool, String> {
+    let journals: Vec<MulesoftJournalLookup> = serde_json::from_str(body)
+        .map_err(|e| format!("Cannot read the journals Mulesoft found: {}", e))?;
+
+    Ok(journals
+        .iter()
+        .any(|journal| journal.integration.id == ulid.to_string()))
+}
+
+/// Makes one attempt at posting a claimed journal.
+async fn attempt_posting(
+    api: &MulesoftApi<'_>,
+    ulid: Uuid,
+    payload: String,
+    attempt_count: i32,
+) -> PostingAttempt {
+    // An earlier attempt may have posted the journal without us hearing back. If the lookup
+    // fails, the idempotency key still keeps Mulesoft from posting it twice.
+    if attempt_count > 1 && api.journal_exists(ulid).await == Ok(true) {
+        return PostingAttempt::found(None, None);
+    }
+
+    match api.post_journal(ulid, payload).await {
+        // Mulesoft answers a journal it has already seen with a conflict, but a conflict can
+        // have other causes too.
+        Ok((StatusCode::CONFLICT, response)) => match api.journal_exists(ulid).await {
+            Ok(true) => PostingAttempt::found(Some(StatusCode::CONFLICT), response),
+            Ok(false) => {
+                PostingAttempt::new(attempt_count, Some(StatusCode::CONFLICT), response, None)
+            }
+            Err(e) => PostingAttempt::new(
+                attempt_count,
+                None,
+                response,
+                Some(format!(
+                    "Mulesoft responded with a conflict and the journal could not be looked up: {}",
+                    e
+                )),
+            ),
+        },
+        Ok((status_code, response)) => {
+            PostingAttempt::new(attempt_count, Some(status_code), response, None)
+        }
+        Err(e) => PostingAttempt::new(attempt_count, None, None, Some(e.to_string())),
+    }
+}
+
+/// The outcome of one attempt at posting a journal.
+struct PostingAttempt {
+    status_code: Option<StatusCode>,
+    response: Option<String>,
+    error: Option<String>,
+    /// What the journal is after the attempt.
+    status: &'static str,
+    /// Seconds until the next attempt, if the journal is retried.
+    retry_in: Option<f64>,
+}
+
+impl PostingAttempt {
+    fn new(
+        attempt_count: i32,
+        status_code: Option<StatusCode>,
+        response: Option<String>,
+        error: Option<String>,
+    ) -> Self {
+        let posted = status_code.map(|code| code.is_success()).unwrap_or(false);
+        let retryable = status_code
+            .map(|code| {
+                code.is_server_error()
+                    || code == StatusCode::REQUEST_TIMEOUT
+                    || code == StatusCode::TOO_MANY_REQUESTS
+            })
+            .unwrap_or(true);
+
+        let (status, retry_in) = if posted {
+            ("posted", None)
+        } else if retryable && attempt_count < MAX_ATTEMPTS {
+            let delay = FIRST_RETRY_DELAY_SECONDS * 2f64.powi(attempt_count - 1);
+            ("pending", Some(delay.min(MAX_RETRY_DELAY_SECONDS)))
+        } else {
+            ("failed", None)
+        };
+
+        let error = match (error, status_code) {
+            (Some(error), _) => Some(error),
+            (None, Some(code)) if !posted => Some(format!(
+                "Mulesoft responded with {}: {}",
+                code,
+                response.as_deref().unwrap_or_default()
+            )),
+            (None, _) => None,
+        };
+
+        Self {
+            status_code,
+            response,
+            error,
+            status,
+            retry_in,
+        }
+    }
+
+    /// An attempt that found the journal already posted in S/4HANA by an earlier attempt.
+    fn found(status_code: Option<StatusCode>, response: Option<String>) -> Self {
+        Self {
+            status_code,
+            response: response.or_else(|| {
+                Some("Found in S/4HANA by its integration ID, posted by an earlier attempt".into())
+            }),
+            error: None,
+            status: "posted",
+            retry_in: None,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SapMulesoftPayrollJournalAttempt {
+    pub ulid: Uuid,
+    pub entry_ulid: Uuid,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub attempted_at: sqlx::types::time::OffsetDateTime,
+    pub status_code: Option<i32>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+pub async fn get_many_attempts(
+    // Only for validation
+    _: Token<AdminAccessToken>,
+    Path(entry_ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<Vec<SapMulesoftPayrollJournalAttempt>>> {
+    let database = database.lock().await;
+    let result = database
+        .select_many_sap_mulesoft_payroll_journal_attempts(entry_ulid)
+        .await?;
+    Ok(Json(result))
+}
+
+/// Queues a pending or failed journal to be posted now, with a fresh set of attempts.
+pub async fn repost_one_entry(
+    claims: Token<AdminAccessToken>,
+    Path(entry_ulid): Path<Uuid>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let result = repost_entry(
+        &database,
+        &common_database,
+        entry_ulid,
+        Some(claims.payload.ulid),
+    )
+    .await?;
+
+    Ok(Json(result))
+}
+
+async fn repost_entry(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    entry_ulid: Uuid,
+    created_by: Option<Uuid>,
+) -> GlobeliseResult<Job> {
+    if !database
+        .lock()
+        .await
+        .reset_sap_mulesoft_payroll_journal(entry_ulid)
+        .await?
+    {
+        return Err(GlobeliseError::bad_request(
+            "Only pending and failed payroll journals can be posted again",
+        ));
+    }
+
+    enqueue_post_entry(common_database, entry_ulid, created_by).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CancelPayrollJournalRequest {
+    /// The posting date of the reversal of a posted journal. Defaults to the posting date of
+    /// the journal.
+    pub posting_date: Option<String>,
+}
+
+/// Cancels a journal. A journal that was not posted yet is not posted at all, and a posted
+/// journal is reversed by a new journal, which is returned and queued to be posted.
+pub async fn cancel_one_entry(
+    claims: Token<AdminAccessToken>,
+    Path(entry_ulid): Path<Uuid>,
+    Json(request): Json<CancelPayrollJournalRequest>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<SapMulesoftPayrollJournalEntryIndex>> {
+    let result = cancel_entry(
+        &database,
+        &common_database,
+        entry_ulid,
+        request.posting_date,
+        Some(claims.payload.ulid),
+    )
+    .await?;
+
+    Ok(Json(result))
+}
+
+async fn cancel_entry(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    entry_ulid: Uuid,
+    posting_date: Option<String>,
+    created_by: Option<Uuid>,
+) -> GlobeliseResult<SapMulesoftPayrollJournalEntryIndex> {
+    let reversal_ulid = {
+        let database = database.lock().await;
+
+        let entry = database
+            .select_one_sap_mulesoft_payroll_journal_entry_index(entry_ulid)
+            .await?
+            .ok_or_else(|| {
+                GlobeliseError::not_found("Cannot find payroll journal entry with that UUID")
+            })?;
+
+        match entry.status.as_str() {
+            "pending" | "failed" => {
+                if !database
+                    .cancel_sap_mulesoft_payroll_journal(entry_ulid)
+                    .await?
+                {
+                    return Err(GlobeliseError::bad_request(
+                        "The payroll journal is being posted, please try again later",
+                    ));
+                }
+                None
+            }
+            "posting" => {
+                return Err(GlobeliseError::bad_request(
+                    "The payroll journal is being posted, please try again later",
+                ))
+            }
+            "cancelled" => {
+                return Err(GlobeliseError::bad_request(
+                    "The payroll journal is already cancelled",
+                ))
+            }
+            "posted" => {
+                if let Some(reversed_by) = entry.reversed_by_entry_ulid {
+                    return Err(GlobeliseError::bad_request(format!(
+                        "The payroll journal is already reversed by {}",
+                        reversed_by
+                    )));
+                }
+                Some(insert_reversal(&database, entry, posting_date).await?)
+            }
+            status => {
+                return Err(GlobeliseError::internal(format!(
+                    "Unknown payroll journal status '{}'",
+                    status
+                )))
+            }
+        }
+    };
+
+    if let Some(reversal_ulid) = reversal_ulid {
+        enqueue_post_entry(common_database, reversal_ulid, created_by).await?;
+    }
+
+    database
+        .lock()
+        .await
+        .select_one_sap_mulesoft_payroll_journal_entry_index(reversal_ulid.unwrap_or(entry_ulid))
+        .await?
+        .ok_or_else(|| {
+            GlobeliseError::not_found("Cannot find payroll journal entry with that UUID")
+        })
+}
+
+/// Stores the journal that reverses a posted one in the outbox.
+async fn insert_reversal(
+    database: &Database,
+    entry: SapMulesoftPayrollJournalEntryIndex,
+    posting_date: Option<String>,
+) -> GlobeliseResult<Uuid> {
+    let ulid = Uuid::new_v4();
+
+    let payload = database
+        .select_sap_mulesoft_payroll_journal_payload(entry.ulid)
+        .await?
+        .ok_or_else(|| {
+            GlobeliseError::bad_request(
+                "The payroll journal was posted before journals were stored with what was sent, please reverse it in S/4HANA",
+            )
+        })?;
+    let payload = serde_json::from_str::<MulesoftJournalEntry>(&payload)?
+        .reversal(ulid, posting_date.clone())?;
+
+    let rows = database
+        .select_many_sap_mulesoft_payroll_journal_rows(Some(entry.ulid))
+        .await?
+        .into_iter()
+        .map(|row| reversal_row(row, posting_date.clone()))
+        .collect::<GlobeliseResult<Vec<_>>>()?;
+    let file = journal_workbook(&rows)?;
+    let file_name = format!("reversal-{}", entry.file_name);
+
+    database
+        .insert_sap_mulesoft_payroll_journal(
+            ulid,
+            entry.client_ulid,
+            &entry.country_code.as_str().to_string(),
+            &rows,
+            &file,
+            &file_name,
+            &serde_json::to_string(&payload)?,
+            Some(entry.ulid),
+        )
+        .await?;
+
+    Ok(ulid)
+}
+
+fn reversal_row(
+    row: SapMulesoftPayrollJournalRow,
+    posting_date: Option<String>,
+) -> GlobeliseResult<InsertPayrollJournalRowData> {
+    let debit_credit_code = match row.debit_credit_code.as_str() {
+        "S" => DebitCreditCode::H,
+        _ => DebitCreditCode::S,
+    };
+    let currency = row.currency_code.parse().map_err(|_| {
+        GlobeliseError::internal(format!(
+            "Unknown currency '{}' in a posted journal",
+            row.currency_code
+        ))
+    })?;
+
+    Ok(InsertPayrollJournalRowData {
+        posting_date: posting_date.unwrap_or(row.posting_date),
+        doc_type: row.doc_type,
+        company_code: row.company_code,
+        currency_code: row.currency_code,
+        reference: row.reference,
+        debit_credit_code,
+        document_header_text: row.document_header_text,
+        gl_account: GlAccount::new(row.gl_account)?,
+        amount: -Money::round(row.amount, currency),
+        cost_center_code: row.cost_center,
+        document_item_text: None,
+    })
+}
+
+impl Database {
+    async fn select_due_sap_mulesoft_payroll_journals(&self) -> GlobeliseResult<Vec<Uuid>> {
+        let result = sqlx::query_scalar(
+            "
+        SELECT
+            ulid
+        FROM
+            sap_mulesoft_payroll_journals_entries
+        WHERE
+            payload IS NOT NULL AND (
+                (status = 'pending' AND next_attempt_at <= now()) OR
+                (status = 'posting' AND posting_started_at < now() - INTERVAL '10 minutes')
+            )
+        ORDER BY
+            created_at
+        LIMIT 100",
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Marks a due journal as being posted. Returns the body to post and the number of the
+    /// attempt, or None if the journal is not due or is being posted by someone else.
+    ///
+    /// Journals left as being posted for a while, by a replica that stopped in the middle of
+    /// an attempt, can be claimed again.
+    async fn claim_sap_mulesoft_payroll_journal(
+        &self,
+        ulid: Uuid,
+    ) -> GlobeliseResult<Option<(String, i32)>> {
+        let result = sqlx::query(
+            "
+        UPDATE
+            sap_mulesoft_payroll_journals_entries
+        SET
+            status = 'posting',
+            attempt_count = attempt_count + 1,
+            posting_started_at = now()
+        WHERE
+            ulid = $1 AND
+            payload IS NOT NULL AND (
+                (status = 'pending' AND next_attempt_at <= now()) OR
+                (status = 'posting' AND posting_started_at < now() - INTERVAL '10 minutes')
+            )
+        RETURNING
+            payload, attempt_count",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .map(|row| -> GlobeliseResult<_> {
+            Ok((row.try_get("payload")?, row.try_get("attempt_count")?))
+        })
+        .transpose()?;
+
+        Ok(result)
+    }
+
+    async fn record_sap_mulesoft_payroll_journal_attempt(
+        &self,
+        ulid: Uuid,
+        attempt: &PostingAttempt,
+    ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
+
+        sqlx::query(
+            "
+        INSERT INTO sap_mulesoft_payroll_journal_attempts (
+            ulid, entry_ulid, status_code, response, error
+        ) VALUES (
+            $1, $2, $3, $4, $5
+        )",
+        )
+        .bind(Uuid::new_v4())
+        .bind(ulid)
+        .bind(attempt.status_code.map(|code| code.as_u16() as i32))
+        .bind(&attempt.response)
+        .bind(&attempt.error)
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query(
+            "
+        UPDATE
+            sap_mulesoft_payroll_journals_entries
+        SET
+            status = $2,
+            last_error = $3,
+            next_attempt_at = now() + INTERVAL '1 second' * $4,
+            posted_at = CASE WHEN $2 = 'posted' THEN now() ELSE posted_at END,
+            posting_started_at = NULL
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .bind(attempt.status)
+        .bind(&attempt.error)
+        .bind(attempt.retry_in)
+        .execute(&mut transaction)
+        .await?;
+
+        if attempt.status == "posted" {
+            sqlx::query(
+                "
+            UPDATE
+                sap_mulesoft_payroll_journals_rows
+            SET
+                uploaded = 't'
+            WHERE
+                entry_ulid = $1",
+            )
+            .bind(ulid)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Makes a pending or failed journal due now, with a fresh set of attempts.
+    async fn reset_sap_mulesoft_payroll_journal(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let updated = sqlx::query(
+            "
+        UPDATE
+            sap_mulesoft_payroll_journals_entries
+        SET
+            status = 'pending',
+            attempt_count = 0,
+            next_attempt_at = now()
+        WHERE
+            ulid = $1 AND
+            payload IS NOT NULL AND
+            status IN ('pending', 'failed')",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    async fn cancel_sap_mulesoft_payroll_journal(&self, ulid: Uuid) -> GlobeliseResult<bool> {
+        let updated = sqlx::query(
+            "
+        UPDATE
+            sap_mulesoft_payroll_journals_entries
+        SET
+            status = 'cancelled',
+            next_attempt_at = NULL
+        WHERE
+            ulid = $1 AND
+            status IN ('pending', 'failed')",
+        )
+        .bind(ulid)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    async fn select_sap_mulesoft_payroll_journal_payload(
+        &self,
+        ulid: Uuid,
+    ) -> GlobeliseResult<Option<String>> {
+        let result = sqlx::query_scalar(
+            "
+        SELECT
+            payload
+        FROM
+            sap_mulesoft_payroll_journals_entries
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?
+        .flatten();
+
+        Ok(result)
+    }
+
+    async fn select_many_sap_mulesoft_payroll_journal_attempts(
+        &self,
+        entry_ulid: Uuid,
+    ) -> GlobeliseResult<Vec<SapMulesoftPayrollJournalAttempt>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            ulid, entry_ulid, attempted_at, status_code, response, error
+        FROM
+            sap_mulesoft_payroll_journal_attempts
+        WHERE
+            entry_ulid = $1
+        ORDER BY
+            attempted_at",
+        )
+        .bind(entry_ulid)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::Query, routing::post, Router};
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    /// How the mock answers a posted journal.
+    #[derive(Debug, Clone, Copy)]
+    enum Answer {
+        /// Posts the journal and responds with the status code.
+        Post(StatusCode),
+        /// Leaves the journal out and responds with the status code.
+        Reject(StatusCode),
+    }
+
+    /// Stands in for Mulesoft: journals are posted by integration ID, a journal posted
+    /// twice is answered with a conflict, and journals can be looked up.
+    #[derive(Default)]
+    struct MockMulesoft {
+        /// Answers to the next posts, which are posted with a 201 once these run out.
+        answers: Mutex<VecDeque<Answer>>,
+        journals: Mutex<Vec<String>>,
+        posts: Mutex<usize>,
+    }
+
+    impl MockMulesoft {
+        fn posts(&self) -> usize {
+            *self.posts.lock().unwrap()
+        }
+
+        fn has_journal(&self, ulid: Uuid) -> bool {
+            self.journals.lock().unwrap().contains(&ulid.to_string())
+        }
+    }
+
+    async fn mock_post(
+        Extension(mock): Extension<Arc<MockMulesoft>>,
+        Json(body): Json<serde_json::Value>,
+    ) -> StatusCode {
+        *mock.posts.lock().unwrap() += 1;
+        let id = body["integration"]["id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut journals = mock.journals.lock().unwrap();
+        if journals.contains(&id) {
+            return StatusCode::CONFLICT;
+        }
+        let answer = mock.answers.lock().unwrap().pop_front();
+        match answer.unwrap_or(Answer::Post(StatusCode::CREATED)) {
+            Answer::Post(code) => {
+                journals.push(id);
+                code
+            }
+            Answer::Reject(code) => code,
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct LookupQuery {
+        #[serde(rename = "integrationId")]
+        integration_id: String,
+    }
+
+    /// Answers with the journals that have the integration ID, which can be none.
+    async fn mock_lookup(
+        Extension(mock): Extension<Arc<MockMulesoft>>,
+        Query(query): Query<LookupQuery>,
+    ) -> Json<serde_json::Value> {
+        let journals = mock
+            .journals
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|id| **id == query.integration_id)
+            .map(|id| serde_json::json!({ "integration": { "id": id, "name": "payroll" } }))
+            .collect::<Vec<_>>();
+
+        Json(serde_json::Value::Array(journals))
+    }
+
+    /// Starts a mock Mulesoft, returning its URL.
+    fn spawn_mock(answers: impl IntoIterator<Item = Answer>) -> (String, Arc<MockMulesoft>) {
+        let mock = Arc::new(MockMulesoft {
+            answers: Mutex::new(answers.into_iter().collect()),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route(
+                "/master-pub-v1/api/transaction/journalentry",
+                post(mock_post).get(mock_lookup),
+            )
+            .layer(Extension(mock.clone()));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        (url, mock)
+    }
+
+    fn api<'a>(reqwest_client: &'a Client, url: &'a str) -> MulesoftApi<'a> {
+        MulesoftApi {
+            reqwest_client,
+            url,
+            client_id: "client-id",
+            client_secret: "client-secret",
+        }
+    }
+
+    fn payload(ulid: Uuid) -> String {
+        format!(
+            r#"{{"integration":{{"id":"{}","name":"payroll"}},"country_iso":"SG","companyCode":"SG01","postingDate":"20220930","details":[]}}"#,
+            ulid
+        )
+    }
+
+    #[test]
+    fn lookups_only_find_journals_with_the_integration_id() {
+        let ulid = Uuid::new_v4();
+        let other = format!(r#"{{"integration":{{"id":"{}"}}}}"#, Uuid::new_v4());
+        let found = format!(r#"{{"integration":{{"id":"{}"}}}}"#, ulid);
+
+        assert_eq!(lookup_has_journal("[]", ulid), Ok(false));
+        assert_eq!(lookup_has_journal(&format!("[{}]", other), ulid), Ok(false));
+        assert_eq!(
+            lookup_has_journal(&format!("[{},{}]", other, found), ulid),
+            Ok(true)
+        );
+        assert!(lookup_has_journal("<html>OK</html>", ulid).is_err());
+    }
+
+    #[tokio::test]
+    async fn unavailable_mulesoft_is_retried_with_a_growing_delay() {
+        let (url, _) = spawn_mock([
+            Answer::Reject(StatusCode::SERVICE_UNAVAILABLE),
+            Answer::Reject(StatusCode::TOO_MANY_REQUESTS),
+            Answer::Reject(StatusCode::SERVICE_UNAVAILABLE),
+        ]);
+        let client = Client::new();
+        let api = api(&client, &url);
+        let ulid = Uuid::new_v4();
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), 1).await;
+        assert_eq!(attempt.status, "pending");
+        assert_eq!(attempt.retry_in, Some(FIRST_RETRY_DELAY_SECONDS));
+        assert_eq!(attempt.status_code, Some(StatusCode::SERVICE_UNAVAILABLE));
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), 3).await;
+        assert_eq!(attempt.status, "pending");
+        assert_eq!(attempt.retry_in, Some(FIRST_RETRY_DELAY_SECONDS * 4.0));
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), MAX_ATTEMPTS).await;
+        assert_eq!(attempt.status, "failed");
+        assert!(attempt.error.unwrap().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn unreachable_mulesoft_is_retried() {
+        let client = Client::new();
+        // Nothing listens on the discard port.
+        let api = api(&client, "http://127.0.0.1:9");
+        let ulid = Uuid::new_v4();
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), 1).await;
+        assert_eq!(attempt.status, "pending");
+        assert_eq!(attempt.status_code, None);
+        assert!(attempt.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejected_journals_fail_without_retrying() {
+        let (url, _) = spawn_mock([Answer::Reject(StatusCode::BAD_REQUEST)]);
+        let client = Client::new();
+        let ulid = Uuid::new_v4();
+
+        let attempt = attempt_posting(&api(&client, &url), ulid, payload(ulid), 1).await;
+        assert_eq!(attempt.status, "failed");
+        assert_eq!(attempt.retry_in, None);
+        assert!(attempt.error.unwrap().contains("400"));
+    }
+
+    #[tokio::test]
+    async fn a_conflict_only_counts_as_posted_if_the_journal_is_found() {
+        let (url, mock) = spawn_mock([Answer::Reject(StatusCode::CONFLICT)]);
+        let client = Client::new();
+        let api = api(&client, &url);
+
+        // A conflict for a journal S/4HANA does not have.
+        let unknown = Uuid::new_v4();
+        let attempt = attempt_posting(&api, unknown, payload(unknown), 1).await;
+        assert_eq!(attempt.status, "failed");
+        assert_eq!(attempt.status_code, Some(StatusCode::CONFLICT));
+        assert!(attempt.error.unwrap().contains("409"));
+
+        // A conflict for a journal posted by someone else in the meantime.
+        let posted = Uuid::new_v4();
+        mock.journals.lock().unwrap().push(posted.to_string());
+        let attempt = attempt_posting(&api, posted, payload(posted), 1).await;
+        assert_eq!(attempt.status, "posted");
+        assert_eq!(attempt.status_code, Some(StatusCode::CONFLICT));
+        assert_eq!(attempt.error, None);
+    }
+
+    #[tokio::test]
+    async fn a_retry_does_not_post_a_journal_twice() {
+        // Mulesoft posts the journal, but the gateway times out before we hear back.
+        let (url, mock) = spawn_mock([Answer::Post(StatusCode::GATEWAY_TIMEOUT)]);
+        let client = Client::new();
+        let api = api(&client, &url);
+        let ulid = Uuid::new_v4();
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), 1).await;
+        assert_eq!(attempt.status, "pending");
+        assert!(mock.has_journal(ulid));
+
+        let attempt = attempt_posting(&api, ulid, payload(ulid), 2).await;
+        assert_eq!(attempt.status, "posted");
+        assert_eq!(attempt.error, None);
+        assert_eq!(mock.posts(), 1);
+    }
+
+    async fn connect() -> (SharedDatabase, CommonDatabase) {
+        let connection_str =
+            std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&connection_str)
+            .await
+            .unwrap();
+
+        (
+            Arc::new(tokio::sync::Mutex::new(Database(pool.clone()))),
+            Arc::new(tokio::sync::Mutex::new(common_utils::database::Database(
+                pool,
+            ))),
+        )
+    }
+
+    /// Stores a journal without rows in the outbox, for a new client.
+    async fn insert_journal(database: &SharedDatabase) -> Uuid {
+        let database = database.lock().await;
+        let client_ulid = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (ulid, email, is_client) VALUES ($1, $2, 't')")
+            .bind(client_ulid)
+            .bind(format!("{}@client.example", client_ulid))
+            .execute(&database.0)
+            .await
+            .unwrap();
+
+        let ulid = Uuid::new_v4();
+        database
+            .insert_sap_mulesoft_payroll_journal(
+                ulid,
+                client_ulid,
+                &"SG".to_string(),
+                &[],
+                &[],
+                &"payroll-journal.xlsx".to_string(),
+                &payload(ulid),
+                None,
+            )
+            .await
+            .unwrap();
+
+        ulid
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn failed_journals_can_be_posted_again() {
+        let (database, common_database) = connect().await;
+        let (url, mock) = spawn_mock([Answer::Reject(StatusCode::UNPROCESSABLE_ENTITY)]);
+        let client = Client::new();
+        let api = api(&client, &url);
+        let ulid = insert_journal(&database).await;
+
+        let entry = post_entry(&database, &api, ulid).await.unwrap();
+        assert_eq!(entry.status, "failed");
+        assert_eq!(entry.attempt_count, 1);
+
+        // Failed journals are not posted again by themselves.
+        let entry = post_entry(&database, &api, ulid).await.unwrap();
+        assert_eq!(entry.status, "failed");
+        assert_eq!(mock.posts(), 1);
+
+        repost_entry(&database, &common_database, ulid, None)
+            .await
+            .unwrap();
+        let entry = post_entry(&database, &api, ulid).await.unwrap();
+        assert_eq!(entry.status, "posted");
+        assert_eq!(entry.attempt_count, 1);
+        assert_eq!(entry.last_error, None);
+        assert!(mock.has_journal(ulid));
+
+        assert!(repost_entry(&database, &common_database, ulid, None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+    async fn cancelled_journals_are_not_posted_and_posted_ones_are_reversed() {
+        let (database, common_database) = connect().await;
+        let (url, mock) = spawn_mock([Answer::Reject(StatusCode::SERVICE_UNAVAILABLE)]);
+        let client = Client::new();
+        let api = api(&client, &url);
+
+        // A journal waiting for a retry is cancelled without being posted.
+        let pending = insert_journal(&database).await;
+        let entry = post_entry(&database, &api, pending).await.unwrap();
+        assert_eq!(entry.status, "pending");
+        let entry = cancel_entry(&database, &common_database, pending, None, None)
+            .await
+            .unwrap();
+        assert_eq!(entry.ulid, pending);
+        assert_eq!(entry.status, "cancelled");
+        let entry = post_entry(&database, &api, pending).await.unwrap();
+        assert_eq!(entry.status, "cancelled");
+        assert!(!mock.has_journal(pending));
+
+        // A posted journal is reversed by a new journal, once.
+        let posted = insert_journal(&database).await;
+        let entry = post_entry(&database, &api, posted).await.unwrap();
+        assert_eq!(entry.status, "posted");
+        let reversal = cancel_entry(
+            &database,
+            &common_database,
+            posted,
+            Some("20221031".to_string()),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_ne!(reversal.ulid, posted);
+        assert_eq!(reversal.status, "pending");
+        assert_eq!(reversal.reverses_entry_ulid, Some(posted));
+
+        let reversal = post_entry(&database, &api, reversal.ulid).await.unwrap();
+        assert_eq!(reversal.status, "posted");
+        assert!(mock.has_journal(reversal.ulid));
+        assert!(
+            cancel_entry(&database, &common_database, posted, None, None)
+                .await
+                .is_err()
+        );
+    }
+}
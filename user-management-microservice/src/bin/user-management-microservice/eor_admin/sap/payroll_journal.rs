@@ -0,0 +1,684 @@
+//! Payroll journals generated from the active contracts of a client.
+//!
+//! Every client cost center mapped to an SAP cost center of the company code gets a salary
+//! debit for the gross pay of the contracts of its members, and a line for every pay item
+//! type and method of those contracts: additions and employer's contributions are debited,
+//! deductions are credited. What is paid out, the net pay and the employer's contributions,
+//! is credited to the bank outgoing account. Every line goes to the GL account configured
+//! for it in the company code. The journal goes through the same validations and Mulesoft
+//! outbox as an uploaded one, with a generated spreadsheet in the format of the template
+//! stored as its file.
+//!
+//! Gross pay and pay items are those of the posting period, the same amounts a transfer
+//! batch pays, see `eor_admin/net_pay.rs`. A contractor in several mapped cost centers of
+//! the contract's branch is charged to only one of them, the first by name, so that no
+//! contract is posted twice, and the pay items of a contractor with several contracts are
+//! posted with the first of them only.
+
+use axum::extract::{Extension, Json, Path};
+use std::collections::BTreeMap;
+
+use common_utils::{
+    custom_serde::{Currency, OffsetDateWrapper},
+    database::{job::Job, CommonDatabase},
+    error::{GlobeliseError, GlobeliseResult},
+    money::Money,
+    token::Token,
+};
+use eor_admin_microservice_sdk::token::AdminAccessToken;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TryFromInto};
+use sqlx::{types::Decimal, FromRow};
+use uuid::Uuid;
+
+use crate::{
+    branch::pay_items::{PayItemMethod, PayItemType},
+    database::{Database, SharedDatabase},
+    eor_admin::net_pay::{net_pay, prorated_gross_pay, ContractorPayItem, PayItemsByContractor},
+};
+
+use super::{
+    gl_account::JournalPurpose,
+    s4_hana::{
+        post_journal, validate_journal, DebitCreditCode, InsertPayrollJournalRowData,
+        JOURNAL_HEADERS,
+    },
+};
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GeneratePayrollJournalRequest {
+    pub client_ulid: Uuid,
+    pub company_code: String,
+    /// The pay period the journal is for.
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub begin_period: sqlx::types::time::OffsetDateTime,
+    #[serde_as(as = "TryFromInto<OffsetDateWrapper>")]
+    pub end_period: sqlx::types::time::OffsetDateTime,
+    /// Sent to S/4HANA as is, in the same format as in uploaded journals.
+    pub posting_date: String,
+    pub doc_type: String,
+    pub reference: String,
+    pub document_header_text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GeneratedPayrollJournal {
+    /// What is paid out, credited to the bank outgoing account.
+    pub total: Money,
+    pub contract_count: i64,
+    pub rows: Vec<InsertPayrollJournalRowData>,
+}
+
+/// A contract of the period with the one SAP cost center it is charged to.
+#[derive(Debug, FromRow)]
+struct PayrollJournalContract {
+    contract_name: String,
+    contractor_ulid: Uuid,
+    branch_ulid: Uuid,
+    cost_center_code: String,
+    cost_center_name: String,
+    currency: Option<String>,
+    /// Gross pay for a whole pay period.
+    gross_amount: Decimal,
+    begin_at: sqlx::types::time::OffsetDateTime,
+    end_at: sqlx::types::time::OffsetDateTime,
+}
+
+#[derive(Debug)]
+struct PayrollJournalLine {
+    cost_center_code: String,
+    cost_center_name: String,
+    currency: Currency,
+    gross: Money,
+    /// Totals of the pay items posted, by type and method.
+    pay_items: Vec<(PayItemType, PayItemMethod, Money)>,
+    contract_count: i64,
+}
+
+/// Shows the journal that would be posted, after the same validations as posting it.
+pub async fn preview_payroll_journal(
+    _: Token<AdminAccessToken>,
+    Json(request): Json<GeneratePayrollJournalRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<Json<GeneratedPayrollJournal>> {
+    let database = database.lock().await;
+
+    let journal = generate_payroll_journal(&database, &request).await?;
+    validate_journal(&database, &journal.rows).await?;
+
+    Ok(Json(journal))
+}
+
+pub async fn post_payroll_journal(
+    claims: Token<AdminAccessToken>,
+    Json(request): Json<GeneratePayrollJournalRequest>,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let journal = generate_payroll_journal(&*database.lock().await, &request).await?;
+    let file = journal_workbook(&journal.rows)?;
+    let file_name = format!(
+        "payroll-journal-{}-{}.xlsx",
+        request.company_code, request.posting_date
+    );
+
+    let result = post_journal(
+        &database,
+        &common_database,
+        claims.payload.ulid,
+        request.client_ulid,
+        journal.rows,
+        &file,
+        &file_name,
+    )
+    .await?;
+
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SapCostCenterMappingRequest {
+    /// None removes the mapping.
+    pub sap_cost_center_code: Option<String>,
+}
+
+/// Maps a client cost center to the SAP cost center its payroll is posted to.
+pub async fn post_sap_cost_center_mapping(
+    _: Token<AdminAccessToken>,
+    Path(cost_center_ulid): Path<Uuid>,
+    Json(request): Json<SapCostCenterMappingRequest>,
+    Extension(database): Extension<SharedDatabase>,
+) -> GlobeliseResult<()> {
+    let database = database.lock().await;
+
+    if let Some(code) = &request.sap_cost_center_code {
+        if !database.sap_cost_center_exists(code).await? {
+            return Err(GlobeliseError::bad_request(format!(
+                "The SAP cost center '{}' does not exist",
+                code
+            )));
+        }
+    }
+
+    database
+        .update_cost_center_sap_cost_center_code(
+            cost_center_ulid,
+            request.sap_cost_center_code.as_deref(),
+        )
+        .await
+}
+
+async fn generate_payroll_journal(
+    database: &Database,
+    request: &GeneratePayrollJournalRequest,
+) -> GlobeliseResult<GeneratedPayrollJournal> {
+    if request.begin_period > request.end_period {
+        return Err(GlobeliseError::bad_request(
+            "The pay period ends before it begins",
+        ));
+    }
+
+    let contracts = database
+        .select_payroll_journal_contracts(
+            request.client_ulid,
+            &request.company_code,
+            request.begin_period,
+            request.end_period,
+        )
+        .await?;
+    let mut pay_items = Vec::new();
+    for branch_ulid in contracts
+        .iter()
+        .map(|contract| contract.branch_ulid)
+        .unique()
+    {
+        pay_items.extend(
+            database
+                .select_many_contractor_pay_items(branch_ulid)
+                .await?,
+        );
+    }
+    let lines = payroll_journal_lines(
+        &contracts,
+        &pay_items,
+        request.begin_period,
+        request.end_period,
+    )?;
+    if lines.is_empty() {
+        return Err(GlobeliseError::bad_request(format!(
+            "The client has no active contracts in cost centers mapped to company code '{}'",
+            request.company_code
+        )));
+    }
+
+    let currencies = lines
+        .iter()
+        .map(|line| line.currency.as_str())
+        .unique()
+        .collect::<Vec<_>>();
+    if currencies.len() != 1 {
+        return Err(GlobeliseError::bad_request(format!(
+            "A journal must use one currency, but the contracts are in {}",
+            currencies.join(", ")
+        )));
+    }
+    let currency = lines[0].currency;
+
+    let gl_accounts = database
+        .select_sap_gl_accounts(&request.company_code)
+        .await?;
+    let salary_gl_account = gl_accounts.for_purpose(JournalPurpose::Salary)?;
+    let bank_outgoing_gl_account = gl_accounts.for_purpose(JournalPurpose::BankOutgoing)?;
+
+    let row =
+        |debit_credit_code, gl_account, amount: Money, cost_center_code, document_item_text| {
+            InsertPayrollJournalRowData {
+                posting_date: request.posting_date.clone(),
+                doc_type: request.doc_type.clone(),
+                company_code: request.company_code.clone(),
+                currency_code: currency.as_str().to_string(),
+                reference: request.reference.clone(),
+                debit_credit_code,
+                document_header_text: request.document_header_text.clone(),
+                gl_account,
+                amount,
+                cost_center_code,
+                document_item_text,
+            }
+        };
+
+    let mut rows = Vec::new();
+    for line in &lines {
+        rows.push(row(
+            DebitCreditCode::S,
+            salary_gl_account.clone(),
+            line.gross,
+            Some(line.cost_center_code.clone()),
+            Some(line.cost_center_name.clone()),
+        ));
+        for (pay_item_type, pay_item_method, amount) in &line.pay_items {
+            if amount.is_zero() {
+                continue;
+            }
+            let gl_account = gl_accounts.for_pay_item(*pay_item_type, *pay_item_method)?;
+            let (debit_credit_code, amount) = match pay_item_method {
+                PayItemMethod::Deduction => (DebitCreditCode::H, -*amount),
+                _ => (DebitCreditCode::S, *amount),
+            };
+            rows.push(row(
+                debit_credit_code,
+                gl_account,
+                amount,
+                Some(line.cost_center_code.clone()),
+                Some(line.cost_center_name.clone()),
+            ));
+        }
+    }
+    let total = Money::sum(currency, rows.iter().map(|row| &row.amount))?;
+    rows.push(row(
+        DebitCreditCode::H,
+        bank_outgoing_gl_account,
+        -total,
+        None,
+        None,
+    ));
+
+    Ok(GeneratedPayrollJournal {
+        total,
+        contract_count: lines.iter().map(|line| line.contract_count).sum(),
+        rows,
+    })
+}
+
+/// Adds up the gross pay and the pay items of the contracts for the period per SAP cost
+/// center and currency. Statement only pay items are not posted.
+fn payroll_journal_lines(
+    contracts: &[PayrollJournalContract],
+    pay_items: &[ContractorPayItem],
+    begin_period: sqlx::types::time::OffsetDateTime,
+    end_period: sqlx::types::time::OffsetDateTime,
+) -> GlobeliseResult<Vec<PayrollJournalLine>> {
+    let mut lines = BTreeMap::<(String, &str), PayrollJournalLine>::new();
+    let mut pay_items = PayItemsByContractor::new(pay_items);
+    for contract in contracts {
+        let currency_code = contract.currency.as_deref().unwrap_or_default();
+        let currency: Currency = currency_code.parse().map_err(|_| {
+            GlobeliseError::bad_request(format!(
+                "The contract {} has an unknown currency '{}'",
+                contract.contract_name, currency_code
+            ))
+        })?;
+        let gross = prorated_gross_pay(
+            contract.gross_amount,
+            currency,
+            contract.begin_at,
+            contract.end_at,
+            begin_period,
+            end_period,
+        );
+        let contract_pay_items = pay_items.take(contract.contractor_ulid);
+        // Checks the pay items the same way a transfer batch does.
+        net_pay(gross, contract_pay_items.iter().copied()).map_err(|e| {
+            GlobeliseError::bad_request(format!("The contract {}: {}", contract.contract_name, e))
+        })?;
+
+        let line = lines
+            .entry((contract.cost_center_code.clone(), currency.as_str()))
+            .or_insert_with(|| PayrollJournalLine {
+                cost_center_code: contract.cost_center_code.clone(),
+                cost_center_name: contract.cost_center_name.clone(),
+                currency,
+                gross: Money::zero(currency),
+                pay_items: Vec::new(),
+                contract_count: 0,
+            });
+        line.gross = line.gross.checked_add(&gross)?;
+        line.contract_count += 1;
+
+        for pay_item in contract_pay_items {
+            let (pay_item_type, pay_item_method, amount) = match (
+                pay_item
+                    .pay_item_type
+                    .as_deref()
+                    .and_then(PayItemType::from_str),
+                pay_item
+                    .pay_item_method
+                    .as_deref()
+                    .and_then(PayItemMethod::from_str),
+                pay_item.amount,
+            ) {
+                (_, Some(PayItemMethod::StatementOnly), _) => continue,
+                (Some(pay_item_type), Some(pay_item_method), Some(amount)) => {
+                    (pay_item_type, pay_item_method, amount)
+                }
+                _ => {
+                    return Err(GlobeliseError::bad_request(format!(
+                        "The contract {}: the pay item {} cannot be posted without a type, \
+                         method and fixed amount",
+                        contract.contract_name, pay_item.name
+                    )))
+                }
+            };
+            let amount = Money::new(amount, currency)?;
+            match line
+                .pay_items
+                .iter_mut()
+                .find(|(t, m, _)| *t == pay_item_type && *m == pay_item_method)
+            {
+                Some((_, _, total)) => *total = total.checked_add(&amount)?,
+                None => line
+                    .pay_items
+                    .push((pay_item_type, pay_item_method, amount)),
+            }
+        }
+    }
+
+    Ok(lines.into_values().collect())
+}
+
+/// Writes the rows of a journal in the format of `journal_template.xlsx`, so that the stored
+/// file can be downloaded and uploaded again like any other journal.
+pub fn journal_workbook(rows: &[InsertPayrollJournalRowData]) -> GlobeliseResult<Vec<u8>> {
+    let mut book = umya_spreadsheet::new_file();
+    let sheet = book
+        .get_sheet_by_name_mut("Sheet1")
+        .map_err(|_| GlobeliseError::internal("Cannot create the journal spreadsheet"))?;
+
+    let cell = |column: usize, row: usize| format!("{}{}", (b'A' + column as u8) as char, row);
+    for (column, header) in JOURNAL_HEADERS.iter().enumerate() {
+        sheet.get_cell_mut(&cell(column, 1)).set_value(*header);
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let values = [
+            row.posting_date.clone(),
+            row.doc_type.clone(),
+            row.company_code.clone(),
+            row.currency_code.clone(),
+            row.reference.clone(),
+            row.debit_credit_code.as_code().to_string(),
+            row.document_header_text.clone(),
+            row.gl_account.as_str().to_string(),
+            row.amount.amount().to_string(),
+            row.cost_center_code.clone().unwrap_or_default(),
+            row.document_item_text.clone().unwrap_or_default(),
+        ];
+        for (column, value) in values.into_iter().enumerate() {
+            sheet
+                .get_cell_mut(&cell(column, index + 2))
+                .set_value(value);
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.xlsx", Uuid::new_v4().to_simple()));
+    umya_spreadsheet::writer::xlsx::write(&book, &path)
+        .map_err(|_| GlobeliseError::internal("Cannot write the journal spreadsheet"))?;
+    let file = std::fs::read(&path);
+    std::fs::remove_file(&path)?;
+
+    Ok(file?)
+}
+
+impl Database {
+    /// The active contracts of a client in the period that are charged to an SAP cost center
+    /// of a company code.
+    ///
+    /// A contractor in several cost centers of the company code mapped to SAP within the
+    /// contract's branch is charged to the first of them by name.
+    async fn select_payroll_journal_contracts(
+        &self,
+        client_ulid: Uuid,
+        company_code: &str,
+        begin_period: sqlx::types::time::OffsetDateTime,
+        end_period: sqlx::types::time::OffsetDateTime,
+    ) -> GlobeliseResult<Vec<PayrollJournalContract>> {
+        let result = sqlx::query_as(
+            "
+        WITH contract_cost_centers AS (
+            SELECT DISTINCT ON (c.ulid)
+                c.contract_name, c.contractor_ulid, c.branch_ulid,
+                sc.code AS cost_center_code, sc.long_name AS cost_center_name, c.currency,
+                c.contract_amount AS gross_amount, c.begin_at, c.end_at
+            FROM
+                contracts c
+            JOIN
+                cost_center_contractor_pairs ccc
+            ON
+                ccc.contractor_ulid = c.contractor_ulid
+            JOIN
+                cost_center cc
+            ON
+                cc.ulid = ccc.cost_center_ulid AND
+                cc.branch_ulid = c.branch_ulid
+            JOIN
+                sap_mulesoft_payroll_journal_cost_centers sc
+            ON
+                sc.code = cc.sap_cost_center_code
+            WHERE
+                c.client_ulid = $1 AND
+                c.contract_status = 'ACTIVE' AND
+                c.begin_at <= $4 AND
+                c.end_at >= $3 AND
+                sc.company_code = $2
+            ORDER BY
+                c.ulid, cc.cost_center_name, cc.ulid
+        )
+        SELECT
+            contract_name, contractor_ulid, branch_ulid, cost_center_code, cost_center_name,
+            currency, gross_amount, begin_at, end_at
+        FROM
+            contract_cost_centers
+        ORDER BY
+            cost_center_code, contractor_ulid, begin_at",
+        )
+        .bind(client_ulid)
+        .bind(company_code)
+        .bind(begin_period)
+        .bind(end_period)
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn sap_cost_center_exists(&self, code: &str) -> GlobeliseResult<bool> {
+        let result = sqlx::query(
+            "
+        SELECT
+            code
+        FROM
+            sap_mulesoft_payroll_journal_cost_centers
+        WHERE
+            code = $1",
+        )
+        .bind(code)
+        .fetch_optional(&self.0)
+        .await?
+        .is_some();
+
+        Ok(result)
+    }
+
+    async fn update_cost_center_sap_cost_center_code(
+        &self,
+        cost_center_ulid: Uuid,
+        sap_cost_center_code: Option<&str>,
+    ) -> GlobeliseResult<()> {
+        let updated = sqlx::query(
+            "
+        UPDATE
+            cost_center
+        SET
+            sap_cost_center_code = $2
+        WHERE
+            ulid = $1",
+        )
+        .bind(cost_center_ulid)
+        .bind(sap_cost_center_code)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(GlobeliseError::not_found("Cannot find the cost center"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+
+    use super::*;
+
+    fn day(year: i32, month: u8, day: u8) -> OffsetDateTime {
+        PrimitiveDateTime::new(
+            Date::try_from_ymd(year, month, day).unwrap(),
+            Time::midnight(),
+        )
+        .assume_utc()
+    }
+
+    fn contract(
+        contractor_ulid: Uuid,
+        cost_center_code: &str,
+        gross_amount: &str,
+        begin_at: OffsetDateTime,
+    ) -> PayrollJournalContract {
+        PayrollJournalContract {
+            contract_name: "Contract".to_string(),
+            contractor_ulid,
+            branch_ulid: Uuid::nil(),
+            cost_center_code: cost_center_code.to_string(),
+            cost_center_name: format!("Cost center {}", cost_center_code),
+            currency: Some("SGD".to_string()),
+            gross_amount: gross_amount.parse().unwrap(),
+            begin_at,
+            end_at: day(2023, 1, 1),
+        }
+    }
+
+    fn pay_item(
+        contractor_ulid: Uuid,
+        pay_item_type: &str,
+        method: &str,
+        amount: &str,
+    ) -> ContractorPayItem {
+        ContractorPayItem {
+            contractor_ulid,
+            name: pay_item_type.to_string(),
+            pay_item_type: Some(pay_item_type.to_string()),
+            pay_item_method: Some(method.to_string()),
+            amount: Some(amount.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn lines_add_up_the_gross_pay_and_pay_items_of_the_period_per_cost_center() {
+        let contractor = Uuid::new_v4();
+        let other_contractor = Uuid::new_v4();
+        let contracts = [
+            contract(contractor, "CC200", "3000", day(2022, 1, 1)),
+            // 20 of the 30 days of September.
+            contract(Uuid::new_v4(), "CC100", "3000", day(2022, 9, 11)),
+            contract(other_contractor, "CC200", "1000", day(2022, 1, 1)),
+        ];
+        let pay_items = [
+            pay_item(contractor, "statutory_fund", "deduction", "600"),
+            pay_item(
+                contractor,
+                "statutory_fund",
+                "employers_contribution",
+                "510",
+            ),
+            pay_item(other_contractor, "statutory_fund", "deduction", "200"),
+            pay_item(other_contractor, "allowance", "addition", "100"),
+            pay_item(other_contractor, "others", "statement_only", "0"),
+        ];
+
+        let lines =
+            payroll_journal_lines(&contracts, &pay_items, day(2022, 9, 1), day(2022, 10, 1))
+                .unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].cost_center_code, "CC100");
+        assert_eq!(lines[0].gross.amount().to_string(), "2000.00");
+        assert!(lines[0].pay_items.is_empty());
+        assert_eq!(lines[0].contract_count, 1);
+        assert_eq!(lines[1].cost_center_code, "CC200");
+        assert_eq!(lines[1].gross.amount().to_string(), "4000.00");
+        assert_eq!(lines[1].contract_count, 2);
+        let pay_items = lines[1]
+            .pay_items
+            .iter()
+            .map(|(pay_item_type, method, amount)| {
+                (
+                    pay_item_type.as_str(),
+                    method.as_str(),
+                    amount.amount().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pay_items,
+            [
+                ("statutory_fund", "deduction", "800.00".to_string()),
+                (
+                    "statutory_fund",
+                    "employers_contribution",
+                    "510.00".to_string()
+                ),
+                ("allowance", "addition", "100.00".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn pay_items_without_a_type_are_rejected() {
+        let contractor = Uuid::new_v4();
+        let mut pay_item = pay_item(contractor, "tax", "deduction", "100");
+        pay_item.pay_item_type = None;
+
+        assert!(payroll_journal_lines(
+            &[contract(contractor, "CC100", "3000", day(2022, 1, 1))],
+            &[pay_item],
+            day(2022, 9, 1),
+            day(2022, 10, 1)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn pay_items_are_posted_once_per_contractor() {
+        let contractor = Uuid::new_v4();
+        let contracts = [
+            contract(contractor, "CC100", "3000", day(2022, 1, 1)),
+            contract(contractor, "CC200", "1000", day(2022, 1, 1)),
+        ];
+        let pay_items = [pay_item(contractor, "tax", "deduction", "300")];
+
+        let lines =
+            payroll_journal_lines(&contracts, &pay_items, day(2022, 9, 1), day(2022, 10, 1))
+                .unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].pay_items.len(), 1);
+        assert_eq!(lines[0].pay_items[0].2.amount().to_string(), "300.00");
+        assert!(lines[1].pay_items.is_empty());
+    }
+
+    #[test]
+    fn contracts_with_an_unknown_currency_are_rejected() {
+        let mut contract = contract(Uuid::new_v4(), "CC100", "3000", day(2022, 1, 1));
+        contract.currency = None;
+
+        assert!(
+            payroll_journal_lines(&[contract], &[], day(2022, 9, 1), day(2022, 10, 1)).is_err()
+        );
+    }
+}
@@ -6,40 +6,63 @@ use axum::{
     http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
     response::IntoResponse,
 };
-use calamine::Reader;
+use calamine::{DataType, Reader};
 use common_utils::{
-    custom_serde::{Country, EmailWrapper, OffsetDateWrapper, FORM_DATA_LENGTH_LIMIT},
+    custom_serde::{Country, Currency, EmailWrapper, OffsetDateWrapper, FORM_DATA_LENGTH_LIMIT},
+    database::{job::Job, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
+    money::Money,
     token::Token,
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use itertools::Itertools;
-use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as, TryFromInto};
-use sqlx::{FromRow, Row};
+use sqlx::{types::Decimal, FromRow, Row};
 use strum::IntoStaticStr;
 use uuid::Uuid;
 
 use crate::{
     database::{Database, SharedDatabase},
-    env::{MULESOFT_API_URL, MULESOFT_CLIENT_ID, MULESOFT_CLIENT_SECRET},
+    eor_admin::bank_transfer::citi_bank::cell_text,
 };
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
-pub enum GlAccount {
-    #[serde(rename = "430101001")]
-    Salary,
-    #[serde(rename = "120202003")]
-    BankOutgoing,
-}
+use super::{
+    gl_account::{GlAccounts, JournalPurpose},
+    mulesoft_outbox::enqueue_post_entry,
+};
+
+/// An SAP GL account number. Which accounts a company code posts to is configured in
+/// [`super::gl_account`].
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct GlAccount(String);
 
 impl GlAccount {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            GlAccount::Salary => "430101001",
-            GlAccount::BankOutgoing => "120202003",
+    pub fn new(account: String) -> GlobeliseResult<Self> {
+        let account = account.trim().to_string();
+        if account.is_empty()
+            || account.len() > 10
+            || !account.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(GlobeliseError::bad_request(format!(
+                "'{}' is not a GL account. GL accounts have up to 10 letters and digits",
+                account
+            )));
         }
+        Ok(Self(account))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for GlAccount {
+    type Error = GlobeliseError;
+
+    fn try_from(account: String) -> Result<Self, Self::Error> {
+        GlAccount::new(account)
     }
 }
 
@@ -157,6 +180,17 @@ pub struct SapMulesoftPayrollJournalEntryIndex {
     pub client_ulid: Uuid,
     pub file_name: String,
     pub row_count: i64,
+    pub status: String,
+    pub attempt_count: i32,
+    #[serde_as(as = "Option<TryFromInto<OffsetDateWrapper>>")]
+    pub next_attempt_at: Option<sqlx::types::time::OffsetDateTime>,
+    #[serde_as(as = "Option<TryFromInto<OffsetDateWrapper>>")]
+    pub posted_at: Option<sqlx::types::time::OffsetDateTime>,
+    pub last_error: Option<String>,
+    /// The posted journal this one reverses.
+    pub reverses_entry_ulid: Option<Uuid>,
+    /// The journal that reverses this one.
+    pub reversed_by_entry_ulid: Option<Uuid>,
 }
 
 pub async fn get_many_entries(
@@ -196,341 +230,755 @@ pub async fn download_one_entry(
 }
 
 pub async fn post_one(
+    claims: Token<AdminAccessToken>,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<
+        Json<PostPayrollJournalS4Hana>,
+        FORM_DATA_LENGTH_LIMIT,
+    >,
+    Extension(database): Extension<SharedDatabase>,
+    Extension(common_database): Extension<CommonDatabase>,
+) -> GlobeliseResult<Json<Job>> {
+    let parsed = parse_journal_file(&body.file, &body.file_name)?;
+    if !parsed.problems.is_empty() {
+        return Err(problems_error(&parsed.problems));
+    }
+
+    let result = post_journal(
+        &database,
+        &common_database,
+        claims.payload.ulid,
+        body.client_ulid,
+        parsed.rows.into_iter().map(|(_, row)| row).collect(),
+        &body.file,
+        &body.file_name,
+    )
+    .await?;
+
+    Ok(Json(result))
+}
+
+#[serde_as]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ValidatePayrollJournalS4Hana {
+    #[serde_as(as = "Base64")]
+    pub file: Vec<u8>,
+    pub file_name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JournalValidationReport {
+    pub valid: bool,
+    pub row_count: usize,
+    pub problems: Vec<JournalProblem>,
+}
+
+/// Checks an uploaded journal without posting it, and reports every problem at once.
+pub async fn validate_one(
     // Only for validation
     _: Token<AdminAccessToken>,
     ContentLengthLimit(Json(body)): ContentLengthLimit<
-        Json<PostPayrollJournalS4Hana>,
+        Json<ValidatePayrollJournalS4Hana>,
         FORM_DATA_LENGTH_LIMIT,
     >,
-    Extension(reqwest_client): Extension<Client>,
     Extension(database): Extension<SharedDatabase>,
-) -> GlobeliseResult<String> {
-    let database = database.lock().await;
+) -> GlobeliseResult<Json<JournalValidationReport>> {
+    let parsed = parse_journal_file(&body.file, &body.file_name)?;
+
+    let mut problems = parsed.problems;
+    // Checking the journal as a whole only makes sense if some of its rows could be read.
+    if !parsed.rows.is_empty() || problems.is_empty() {
+        let rows = parsed
+            .rows
+            .iter()
+            .map(|(row_number, row)| (*row_number, row))
+            .collect::<Vec<_>>();
+        let database = database.lock().await;
+        let (journal_problems, _) =
+            check_journal(&database, parsed.sheet.as_deref(), &rows).await?;
+        problems.extend(journal_problems);
+    }
+    problems.sort_by_key(|problem| problem.row);
 
-    let mut workbook =
-        calamine::open_workbook_auto_from_rs(Cursor::new(&body.file)).map_err(|_| {
-            GlobeliseError::bad_request(
-                "Unsupported file format. Please provide XLSX, XLSB, XLS or ODS files",
-            )
-        })?;
-    if let Some((_, data)) = workbook.worksheets().get(0) {
-        let des = data.deserialize()?;
+    Ok(Json(JournalValidationReport {
+        valid: problems.is_empty(),
+        row_count: parsed.row_count,
+        problems,
+    }))
+}
+
+/// The columns of `journal_template.xlsx`.
+pub const JOURNAL_HEADERS: [&str; 11] = [
+    "Posting date*",
+    "Doc type*",
+    "Company  code *",
+    "Currency *",
+    "Reference *",
+    "Posting key *",
+    "Document Header Text*",
+    "GL Account *",
+    "Amount *",
+    "Cost center*",
+    "DocumentItemText",
+];
+
+const POSTING_DATE_COLUMN: usize = 0;
+const COMPANY_CODE_COLUMN: usize = 2;
+const CURRENCY_COLUMN: usize = 3;
+const POSTING_KEY_COLUMN: usize = 5;
+const GL_ACCOUNT_COLUMN: usize = 7;
+const AMOUNT_COLUMN: usize = 8;
+const COST_CENTER_COLUMN: usize = 9;
+
+/// Columns that can be left empty. The cost center is only empty on bank lines, which is
+/// checked against the GL account.
+const OPTIONAL_COLUMNS: [usize; 2] = [COST_CENTER_COLUMN, 10];
+
+/// A problem with a journal, with where it is in the file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JournalProblem {
+    /// None for CSV files and generated journals.
+    pub sheet: Option<String>,
+    /// The row in the file, counting the header as row 1. None for problems with the
+    /// journal as a whole.
+    pub row: Option<usize>,
+    /// The column letter, for problems with one cell.
+    pub column: Option<String>,
+    pub header: Option<String>,
+    pub reason: String,
+}
 
-        #[derive(Debug, Serialize)]
-        struct A {
-            id: String,
-            name: String,
+impl JournalProblem {
+    fn journal(sheet: Option<&str>, reason: String) -> Self {
+        Self {
+            sheet: sheet.map(str::to_string),
+            row: None,
+            column: None,
+            header: None,
+            reason,
         }
+    }
 
-        #[derive(Debug, Serialize)]
-        struct B {
-            #[serde(rename = "referenceDocumentItem")]
-            reference_document_item: String,
-            #[serde(rename = "glAccount")]
-            gl_account: GlAccount,
-            #[serde(rename = "currencyCode")]
-            currency_code: String,
-            #[serde(rename = "debitCreditCode")]
-            debit_credit_code: String,
-            #[serde(rename = "costCenter")]
-            cost_center: String,
-            #[serde(rename = "amount")]
-            amount: String,
-            #[serde(rename = "documentItemText")]
-            document_item_text: Option<String>,
+    fn cell(sheet: Option<&str>, row: usize, column: usize, reason: String) -> Self {
+        Self {
+            sheet: sheet.map(str::to_string),
+            row: Some(row),
+            column: Some(((b'A' + column as u8) as char).to_string()),
+            header: JOURNAL_HEADERS
+                .get(column)
+                .map(|header| header.trim_end_matches('*').split_whitespace().join(" ")),
+            reason,
         }
+    }
+}
 
-        #[derive(Debug, Serialize)]
-        struct C {
-            #[serde(rename = "integration")]
-            integration: A,
-            #[serde(rename = "country_iso")]
-            country_iso: String,
-            #[serde(rename = "companyCode")]
-            company_code: String,
-            #[serde(rename = "postingDate")]
-            posting_date: String,
-            #[serde(rename = "details")]
-            details: Vec<B>,
+impl std::fmt::Display for JournalProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(sheet) = &self.sheet {
+            write!(f, "{}: ", sheet)?;
+        }
+        if let Some(row) = self.row {
+            write!(f, "Row {}: ", row)?;
         }
+        if let (Some(column), Some(header)) = (&self.column, &self.header) {
+            write!(f, "{} ({}): ", header, column)?;
+        }
+        write!(f, "{}", self.reason)
+    }
+}
 
-        let raw_payroll_journals = des
-            .into_iter()
-            .collect::<Result<
-                Vec<(
-                    // Posting date
-                    String,
-                    // Doc type
-                    String,
-                    // Company code
-                    String,
-                    // Currency
-                    String,
-                    // Reference
-                    String,
-                    // Posting key. AKA the debit/credit code
-                    DebitCreditCode,
-                    // Document header text
-                    String,
-                    // GL Account
-                    GlAccount,
-                    // Amount
-                    String,
-                    // Cost center
-                    Option<String>,
-                    // Document Item Text
-                    Option<String>,
-                )>,
-                _,
-            >>()
-            .map_err(|_| {
+fn problems_error(problems: &[JournalProblem]) -> GlobeliseError {
+    GlobeliseError::bad_request(problems.iter().map(ToString::to_string).join("\n"))
+}
+
+/// The rows of an uploaded journal that could be read, with their row number, and the
+/// problems with the others.
+pub struct ParsedJournal {
+    pub sheet: Option<String>,
+    pub row_count: usize,
+    pub rows: Vec<(usize, InsertPayrollJournalRowData)>,
+    pub problems: Vec<JournalProblem>,
+}
+
+/// Reads a journal in the format of `journal_template.xlsx`, from a CSV file or from the
+/// first sheet of a workbook.
+pub fn parse_journal_file(file: &[u8], file_name: &str) -> GlobeliseResult<ParsedJournal> {
+    let (sheet, mut cells) = if file_name.to_lowercase().ends_with(".csv") {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(file);
+        let rows = reader
+            .records()
+            .map(|row| row.map(|row| row.iter().map(|cell| Ok(cell.to_string())).collect()))
+            .collect::<Result<Vec<Vec<_>>, _>>()
+            .map_err(|e| GlobeliseError::bad_request(format!("Invalid CSV file: {}", e)))?;
+        (None, rows)
+    } else {
+        let mut workbook =
+            calamine::open_workbook_auto_from_rs(Cursor::new(file)).map_err(|_| {
                 GlobeliseError::bad_request(
-                    "Invalid Excel file. Please follow the format in the template",
+                    "Unsupported file format. Please provide CSV, XLSX, XLSB, XLS or ODS files",
                 )
-            })?
+            })?;
+        let (name, range) = workbook
+            .worksheets()
             .into_iter()
-            .map(
-                |(
-                    posting_date,
-                    doc_type,
-                    company_code,
-                    currency_code,
-                    reference,
-                    debit_credit_code,
-                    document_header_text,
-                    gl_account,
-                    amount,
-                    cost_center,
-                    document_item_text,
-                )| {
-                    Ok(InsertPayrollJournalRowData {
-                        posting_date,
-                        doc_type,
-                        company_code,
-                        currency_code,
-                        reference,
-                        debit_credit_code,
-                        document_header_text,
-                        gl_account,
-                        amount: lexical::parse(amount)?,
-                        cost_center_code: cost_center,
-                        document_item_text,
-                    })
-                },
-            )
-            .collect::<GlobeliseResult<Vec<_>>>()?;
-
-        // VALIDATIONS
-        // NOTE: Consider parsing, not validate!
+            .next()
+            .ok_or_else(|| GlobeliseError::bad_request("No worksheets provided"))?;
+        // Skip the header
+        let rows = range
+            .rows()
+            .skip(1)
+            .map(|row| row.iter().map(journal_cell).collect())
+            .collect::<Vec<Vec<_>>>();
+        (Some(name), rows)
+    };
+
+    // Sheets often have formatted but empty rows after the journal.
+    while cells.last().map_or(false, |row| {
+        row.iter()
+            .all(|cell| matches!(cell, Ok(value) if value.is_empty()))
+    }) {
+        cells.pop();
+    }
 
-        if raw_payroll_journals.is_empty() {
-            return Err(GlobeliseError::bad_request("File cannot be empty"));
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+    for (index, row) in cells.iter().enumerate() {
+        // The header is row 1.
+        let row_number = index + 2;
+        match journal_row(row) {
+            Ok(row) => rows.push((row_number, row)),
+            Err(row_problems) => {
+                problems.extend(row_problems.into_iter().map(|(column, reason)| {
+                    JournalProblem::cell(sheet.as_deref(), row_number, column, reason)
+                }))
+            }
         }
+    }
 
-        // NOTE: Summation of floating points might not be accurate.
-        // We might want to introduce some delta to compensate.
-        if raw_payroll_journals.iter().map(|v| v.amount).sum::<f64>() != 0.0f64 {
-            return Err(GlobeliseError::bad_request(
-                "The total sum of amounts should be equal to 0",
-            ));
-        };
+    Ok(ParsedJournal {
+        sheet,
+        row_count: cells.len(),
+        rows,
+        problems,
+    })
+}
 
-        if raw_payroll_journals
-            .iter()
-            .map(|v| &v.company_code)
-            .unique()
-            .count()
-            != 1
-        {
-            return Err(GlobeliseError::bad_request(
-                "All country codes must be the same",
+/// Reads a cell of a journal. Dates and formula errors must be entered as text, to be sent
+/// to S/4HANA as they are.
+fn journal_cell(cell: &DataType) -> Result<String, String> {
+    match cell {
+        DataType::Empty
+        | DataType::String(_)
+        | DataType::Float(_)
+        | DataType::Int(_)
+        | DataType::Bool(_) => Ok(cell_text(cell)),
+        _ => Err("Cannot be read, please enter it as text".to_string()),
+    }
+}
+
+/// Reads one row of a journal, or returns the problem with every cell that cannot be read,
+/// by column.
+fn journal_row(
+    cells: &[Result<String, String>],
+) -> Result<InsertPayrollJournalRowData, Vec<(usize, String)>> {
+    let mut problems = Vec::new();
+    let values = (0..JOURNAL_HEADERS.len())
+        .map(|column| {
+            match cells
+                .get(column)
+                .cloned()
+                .unwrap_or_else(|| Ok(String::new()))
+            {
+                Ok(value) if value.is_empty() && !OPTIONAL_COLUMNS.contains(&column) => {
+                    problems.push((column, "Cannot be empty".to_string()));
+                    value
+                }
+                Ok(value) => value,
+                Err(reason) => {
+                    problems.push((column, reason));
+                    String::new()
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let value = |column: usize| values[column].clone();
+    let optional = |column: usize| Some(value(column)).filter(|value| !value.is_empty());
+
+    let debit_credit_code = match value(POSTING_KEY_COLUMN).as_str() {
+        "40" => Some(DebitCreditCode::S),
+        "50" => Some(DebitCreditCode::H),
+        "" => None,
+        key => {
+            problems.push((
+                POSTING_KEY_COLUMN,
+                format!(
+                    "'{}' is not a posting key, it must be 40 (debit) or 50 (credit)",
+                    key
+                ),
             ));
-        };
+            None
+        }
+    };
+    let gl_account = match value(GL_ACCOUNT_COLUMN).as_str() {
+        "" => None,
+        _ => GlAccount::new(value(GL_ACCOUNT_COLUMN))
+            .map_err(|e| problems.push((GL_ACCOUNT_COLUMN, e.to_string())))
+            .ok(),
+    };
+    let amount = match (
+        value(CURRENCY_COLUMN).as_str(),
+        value(AMOUNT_COLUMN).as_str(),
+    ) {
+        ("", _) | (_, "") => None,
+        (currency, amount) => match currency.parse() {
+            Ok(currency) => journal_amount(amount, currency)
+                .map_err(|e| problems.push((AMOUNT_COLUMN, e.to_string())))
+                .ok(),
+            Err(_) => {
+                problems.push((
+                    CURRENCY_COLUMN,
+                    format!("'{}' is not a known currency", currency),
+                ));
+                None
+            }
+        },
+    };
+
+    match (debit_credit_code, gl_account, amount) {
+        (Some(debit_credit_code), Some(gl_account), Some(amount)) if problems.is_empty() => {
+            Ok(InsertPayrollJournalRowData {
+                posting_date: value(POSTING_DATE_COLUMN),
+                doc_type: value(1),
+                company_code: value(COMPANY_CODE_COLUMN),
+                currency_code: value(CURRENCY_COLUMN),
+                reference: value(4),
+                debit_credit_code,
+                document_header_text: value(6),
+                gl_account,
+                amount,
+                cost_center_code: optional(COST_CENTER_COLUMN),
+                document_item_text: optional(10),
+            })
+        }
+        _ => Err(problems),
+    }
+}
 
-        let inferred_company_code = raw_payroll_journals
-            .get(0)
-            .expect("We already checked that there are at least 1 element in the vector")
-            .company_code
-            .clone();
+/// Validates a journal, stores it with the file it came from in the Mulesoft outbox and
+/// queues a first attempt at posting it to S/4HANA, which is the job returned. Journals that
+/// cannot be posted right away are retried in the background, see [`super::mulesoft_outbox`].
+pub async fn post_journal(
+    database: &SharedDatabase,
+    common_database: &CommonDatabase,
+    created_by: Uuid,
+    client_ulid: Uuid,
+    raw_payroll_journals: Vec<InsertPayrollJournalRowData>,
+    file: &[u8],
+    file_name: &String,
+) -> GlobeliseResult<Job> {
+    let ulid = Uuid::new_v4();
+
+    {
+        let database = database.lock().await;
+
+        let validated = validate_journal(&database, &raw_payroll_journals).await?;
+        let country_code = validated.country_code.clone();
+        let payload = MulesoftJournalEntry::new(ulid, validated, &raw_payroll_journals);
+
+        database
+            .insert_sap_mulesoft_payroll_journal(
+                ulid,
+                client_ulid,
+                &country_code,
+                &raw_payroll_journals,
+                file,
+                file_name,
+                &serde_json::to_string(&payload)?,
+                None,
+            )
+            .await?;
+    }
 
-        if !database
-            .sap_mulesoft_payroll_journal_validate_company_code(&inferred_company_code)
-            .await?
-        {
-            return Err(GlobeliseError::bad_request(format!(
-                "The company code '{}' does not exist in the database",
-                inferred_company_code
-            )));
-        }
+    enqueue_post_entry(common_database, ulid, Some(created_by)).await
+}
 
-        let inferred_country_code = database
-            .sap_mulesoft_payroll_journal_get_country_code_from_company_code(&inferred_company_code)
-            .await?
-            .ok_or_else(|| {
-                GlobeliseError::not_found(format!(
-                    "The company code '{}' does not correspond to any countries",
-                    inferred_company_code
-                ))
-            })?;
+/// What a journal is posted with once it is valid.
+pub struct ValidatedJournal {
+    pub company_code: String,
+    pub country_code: String,
+    pub gl_accounts: GlAccounts,
+}
 
-        if !database
-            .sap_mulesoft_payroll_journal_validate_country_code(&inferred_country_code)
-            .await?
-        {
-            return Err(GlobeliseError::bad_request(format!(
-                "The country code '{}' does not exist in the database",
-                inferred_country_code
-            )));
+/// Checks that a journal balances, uses one company code, posting date and currency, and
+/// only known company and cost center codes and the GL accounts mapped for the company.
+///
+/// Generated journals are numbered like the spreadsheet they are stored as.
+pub async fn validate_journal(
+    database: &Database,
+    raw_payroll_journals: &[InsertPayrollJournalRowData],
+) -> GlobeliseResult<ValidatedJournal> {
+    let rows = raw_payroll_journals
+        .iter()
+        .enumerate()
+        .map(|(index, row)| (index + 2, row))
+        .collect::<Vec<_>>();
+
+    match check_journal(database, None, &rows).await? {
+        (_, Some(validated)) => Ok(validated),
+        (problems, None) => Err(problems_error(&problems)),
+    }
+}
+
+/// Finds every problem with a journal, given with the row number of every row. The journal
+/// is only returned as valid if there are none.
+pub async fn check_journal(
+    database: &Database,
+    sheet: Option<&str>,
+    rows: &[(usize, &InsertPayrollJournalRowData)],
+) -> GlobeliseResult<(Vec<JournalProblem>, Option<ValidatedJournal>)> {
+    let (first_row_number, first_row) = match rows.first() {
+        Some(first) => *first,
+        None => {
+            return Ok((
+                vec![JournalProblem::journal(
+                    sheet,
+                    "The journal does not have any rows".to_string(),
+                )],
+                None,
+            ))
+        }
+    };
+
+    let mut problems = Vec::new();
+    let mut cell_problem = |row_number: usize, column: usize, reason: String| {
+        problems.push(JournalProblem::cell(sheet, row_number, column, reason))
+    };
+
+    // Every row must use the same values as the first one.
+    let same_as_first_row: [(usize, fn(&InsertPayrollJournalRowData) -> &String); 3] = [
+        (POSTING_DATE_COLUMN, |row| &row.posting_date),
+        (COMPANY_CODE_COLUMN, |row| &row.company_code),
+        (CURRENCY_COLUMN, |row| &row.currency_code),
+    ];
+    for (column, value) in same_as_first_row {
+        for (row_number, row) in rows {
+            if value(row) != value(first_row) {
+                cell_problem(
+                    *row_number,
+                    column,
+                    format!(
+                        "'{}' is not the same as on row {}, '{}'. All rows must use the same one",
+                        value(row),
+                        first_row_number,
+                        value(first_row)
+                    ),
+                );
+            }
         }
+    }
 
-        if raw_payroll_journals
-            .iter()
-            .map(|v| &v.posting_date)
-            .unique()
-            .count()
-            != 1
-        {
-            return Err(GlobeliseError::bad_request(
-                "All posting dates should be the same",
-            ));
-        };
+    for (row_number, row) in rows {
+        if row.amount.is_positive() && row.debit_credit_code == DebitCreditCode::H {
+            cell_problem(
+                *row_number,
+                AMOUNT_COLUMN,
+                "When posting key 50 AKA H AKA credit then the amount must be negative".to_string(),
+            );
+        }
+        if row.amount.is_negative() && row.debit_credit_code == DebitCreditCode::S {
+            cell_problem(
+                *row_number,
+                AMOUNT_COLUMN,
+                "When posting key 40 AKA S AKA debit then the amount must be positive".to_string(),
+            );
+        }
+    }
 
-        if raw_payroll_journals
-            .iter()
-            .map(|v| &v.currency_code)
-            .unique()
-            .count()
-            != 1
-        {
-            return Err(GlobeliseError::bad_request(
-                "All rows must use the same currency",
+    let currency = first_row.amount.currency();
+    if rows
+        .iter()
+        .all(|(_, row)| row.currency_code == first_row.currency_code)
+    {
+        let total = Money::sum(currency, rows.iter().map(|(_, row)| &row.amount))?;
+        if !total.is_zero() {
+            problems.push(JournalProblem::journal(
+                sheet,
+                format!(
+                    "The total sum of amounts should be equal to 0, got {}",
+                    total
+                ),
             ));
-        };
+        }
+    }
 
-        if raw_payroll_journals
-            .iter()
-            .any(|v| v.gl_account == GlAccount::BankOutgoing && v.cost_center_code.is_some())
-        {
-            return Err(GlobeliseError::bad_request(
-                "Cost center for the bank outgoing must be empty",
-            ));
+    let company_code = first_row.company_code.clone();
+    if !database
+        .sap_mulesoft_payroll_journal_validate_company_code(&company_code)
+        .await?
+    {
+        problems.push(JournalProblem::journal(
+            sheet,
+            format!(
+                "The company code '{}' does not exist in the database",
+                company_code
+            ),
+        ));
+        // The rest depends on the company code.
+        return Ok((problems, None));
+    }
+
+    let country_code = database
+        .sap_mulesoft_payroll_journal_get_country_code_from_company_code(&company_code)
+        .await?;
+    match &country_code {
+        None => problems.push(JournalProblem::journal(
+            sheet,
+            format!(
+                "The company code '{}' does not correspond to any countries",
+                company_code
+            ),
+        )),
+        Some(country_code) => {
+            if !database
+                .sap_mulesoft_payroll_journal_validate_country_code(country_code)
+                .await?
+            {
+                problems.push(JournalProblem::journal(
+                    sheet,
+                    format!(
+                        "The country code '{}' does not exist in the database",
+                        country_code
+                    ),
+                ));
+            }
         }
+    }
 
-        if raw_payroll_journals
-            .iter()
-            .any(|row| row.amount > 0.0f64 && row.debit_credit_code == DebitCreditCode::H)
-        {
-            return Err(GlobeliseError::bad_request(
-                "When posting key 50 AKA H AKA credit then the amount must be negative",
-            ));
+    let gl_accounts = database.select_sap_gl_accounts(&company_code).await?;
+    let cost_center_codes = rows
+        .iter()
+        .filter_map(|(_, row)| row.cost_center_code.as_ref())
+        .unique()
+        .collect::<Vec<_>>();
+    let unknown_cost_center_codes = database
+        .sap_mulesoft_payroll_journal_unknown_cost_center_codes(&company_code, &cost_center_codes)
+        .await?;
+
+    let mut cell_problem = |row_number: usize, column: usize, reason: String| {
+        problems.push(JournalProblem::cell(sheet, row_number, column, reason))
+    };
+    for (row_number, row) in rows {
+        match gl_accounts.purposes_of(&row.gl_account) {
+            None => cell_problem(
+                *row_number,
+                GL_ACCOUNT_COLUMN,
+                format!(
+                    "The GL account {} is not mapped for company code '{}'",
+                    row.gl_account.as_str(),
+                    company_code
+                ),
+            ),
+            Some(purposes) => {
+                if row.cost_center_code.is_some()
+                    && purposes.contains(&JournalPurpose::BankOutgoing)
+                {
+                    cell_problem(
+                        *row_number,
+                        COST_CENTER_COLUMN,
+                        "Cost center for the bank outgoing must be empty".to_string(),
+                    );
+                }
+            }
         }
 
-        if raw_payroll_journals
-            .iter()
-            .any(|row| row.amount < 0.0f64 && row.debit_credit_code == DebitCreditCode::S)
-        {
-            return Err(GlobeliseError::bad_request(
-                "When posting key 40 AKA S AKA debit then the amount must be positive",
-            ));
+        if let Some(cost_center_code) = &row.cost_center_code {
+            if unknown_cost_center_codes.contains(cost_center_code) {
+                cell_problem(
+                    *row_number,
+                    COST_CENTER_COLUMN,
+                    format!(
+                        "The cost center '{}' is not a cost center of company code '{}'",
+                        cost_center_code, company_code
+                    ),
+                );
+            }
         }
+    }
 
-        let cost_center_codes = raw_payroll_journals
-            .iter()
-            .filter_map(|v| v.cost_center_code.as_ref())
-            .unique()
-            .collect::<Vec<_>>();
+    match country_code {
+        Some(country_code) if problems.is_empty() => Ok((
+            problems,
+            Some(ValidatedJournal {
+                company_code,
+                country_code,
+                gl_accounts,
+            }),
+        )),
+        _ => Ok((problems, None)),
+    }
+}
 
-        if !database
-            .sap_mulesoft_payroll_journal_validate_cost_center_code(
-                &inferred_company_code,
-                &cost_center_codes,
-            )
-            .await?
-        {
-            return Err(GlobeliseError::bad_request(format!(
-                "Company code '{}' and one of the cost center codes in '{:?}' does not match",
-                inferred_company_code, cost_center_codes
-            )));
-        }
+#[derive(Debug, Serialize, Deserialize)]
+struct MulesoftIntegration {
+    id: String,
+    name: String,
+}
 
-        let ulid = database
-            .insert_sap_mulesoft_payroll_journal(
-                body.client_ulid,
-                &inferred_country_code,
-                &raw_payroll_journals,
-                &body.file,
-                &body.file_name,
-            )
-            .await?;
+#[derive(Debug, Serialize, Deserialize)]
+struct MulesoftJournalDetail {
+    #[serde(rename = "referenceDocumentItem")]
+    reference_document_item: String,
+    #[serde(rename = "glAccount")]
+    gl_account: GlAccount,
+    #[serde(rename = "currencyCode")]
+    currency_code: String,
+    #[serde(rename = "debitCreditCode")]
+    debit_credit_code: String,
+    #[serde(rename = "costCenter")]
+    cost_center: String,
+    #[serde(rename = "amount")]
+    amount: String,
+    #[serde(rename = "documentItemText")]
+    document_item_text: Option<String>,
+}
+
+/// The body posted to Mulesoft. It is stored with the journal, so that every attempt at
+/// posting a journal sends exactly the same thing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MulesoftJournalEntry {
+    #[serde(rename = "integration")]
+    integration: MulesoftIntegration,
+    #[serde(rename = "country_iso")]
+    country_iso: String,
+    #[serde(rename = "companyCode")]
+    company_code: String,
+    #[serde(rename = "postingDate")]
+    posting_date: String,
+    #[serde(rename = "details")]
+    details: Vec<MulesoftJournalDetail>,
+}
 
+impl MulesoftJournalEntry {
+    fn new(
+        ulid: Uuid,
+        validated: ValidatedJournal,
+        raw_payroll_journals: &[InsertPayrollJournalRowData],
+    ) -> Self {
         let posting_date = raw_payroll_journals
             .iter()
             .map(|v| v.posting_date.clone())
             .next()
             .expect("We already checked that there is only 1 unique posting date");
 
-        let payroll_journals = raw_payroll_journals
-            .into_iter()
+        let details = raw_payroll_journals
+            .iter()
+            .cloned()
             .enumerate()
-            .map(|(idx, row)| -> GlobeliseResult<B> {
-                Ok(B {
-                    reference_document_item: (idx + 1).to_string(),
-                    gl_account: row.gl_account,
-                    currency_code: row.currency_code,
-                    debit_credit_code: row.debit_credit_code.as_str().to_string(),
-                    cost_center: row.cost_center_code.unwrap_or_default(),
-                    amount: row.amount.to_string(),
-                    document_item_text: row.document_item_text,
-                })
+            .map(|(idx, row)| MulesoftJournalDetail {
+                reference_document_item: (idx + 1).to_string(),
+                document_item_text: row.document_item_text.or_else(|| {
+                    validated
+                        .gl_accounts
+                        .description_of(&row.gl_account)
+                        .map(str::to_string)
+                }),
+                gl_account: row.gl_account,
+                currency_code: row.currency_code,
+                debit_credit_code: row.debit_credit_code.as_str().to_string(),
+                cost_center: row.cost_center_code.unwrap_or_default(),
+                amount: row.amount.amount().to_string(),
             })
-            .collect::<GlobeliseResult<Vec<B>>>()?;
+            .collect();
 
-        let json_body = C {
-            integration: A {
+        Self {
+            integration: MulesoftIntegration {
                 id: ulid.to_string(),
                 name: "payroll".to_string(),
             },
-            country_iso: inferred_country_code,
-            company_code: inferred_company_code,
+            country_iso: validated.country_code,
+            company_code: validated.company_code,
             posting_date,
-            details: payroll_journals,
-        };
+            details,
+        }
+    }
 
-        let response = reqwest_client
-            .post(format!(
-                "{}/master-pub-v1/api/transaction/journalentry",
-                *MULESOFT_API_URL
-            ))
-            .header("client_id", &*MULESOFT_CLIENT_ID)
-            .header("client_secret", &*MULESOFT_CLIENT_SECRET)
-            .json(&json_body)
-            .send()
-            .await?;
+    /// The journal that reverses this one: every line is posted on the other side, with the
+    /// opposite amount.
+    pub fn reversal(self, ulid: Uuid, posting_date: Option<String>) -> GlobeliseResult<Self> {
+        let details = self
+            .details
+            .into_iter()
+            .map(|detail| {
+                let debit_credit_code = match detail.debit_credit_code.as_str() {
+                    "S" => DebitCreditCode::H,
+                    "H" => DebitCreditCode::S,
+                    code => {
+                        return Err(GlobeliseError::internal(format!(
+                            "Unknown debit/credit code '{}' in a posted journal",
+                            code
+                        )))
+                    }
+                };
+                let amount = detail.amount.parse::<Decimal>().map_err(|_| {
+                    GlobeliseError::internal(format!(
+                        "Invalid amount '{}' in a posted journal",
+                        detail.amount
+                    ))
+                })?;
+
+                Ok(MulesoftJournalDetail {
+                    debit_credit_code: debit_credit_code.as_str().to_string(),
+                    amount: (-amount).to_string(),
+                    ..detail
+                })
+            })
+            .collect::<GlobeliseResult<Vec<_>>>()?;
 
-        if response.status() == StatusCode::CREATED {
-            database
-                .update_sap_mulesoft_payroll_journal_as_uploaded(ulid)
-                .await?;
-        }
-        Ok(response.text().await?)
-    } else {
-        Err(GlobeliseError::bad_request("No worksheets provided"))
+        Ok(Self {
+            integration: MulesoftIntegration {
+                id: ulid.to_string(),
+                name: "payroll-reversal".to_string(),
+            },
+            posting_date: posting_date.unwrap_or(self.posting_date),
+            details,
+            ..self
+        })
     }
 }
 
-#[derive(Debug, Clone)]
+/// Reads the amount of a journal row in its currency.
+///
+/// Spreadsheets store numbers as floating point, so a cell can come back in scientific
+/// notation. The amount is then kept as an exact decimal from here on.
+fn journal_amount(amount: &str, currency: Currency) -> GlobeliseResult<Money> {
+    let amount = amount.trim();
+    let amount = amount
+        .parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map_err(|_| GlobeliseError::bad_request(format!("'{}' is not an amount", amount)))?;
+
+    Money::new(amount, currency)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct InsertPayrollJournalRowData {
-    posting_date: String,
-    doc_type: String,
-    company_code: String,
-    currency_code: String,
-    reference: String,
-    debit_credit_code: DebitCreditCode,
-    document_header_text: String,
-    gl_account: GlAccount,
-    amount: f64,
-    cost_center_code: Option<String>,
-    document_item_text: Option<String>,
+    pub posting_date: String,
+    pub doc_type: String,
+    pub company_code: String,
+    pub currency_code: String,
+    pub reference: String,
+    pub debit_credit_code: DebitCreditCode,
+    pub document_header_text: String,
+    pub gl_account: GlAccount,
+    pub amount: Money,
+    pub cost_center_code: Option<String>,
+    pub document_item_text: Option<String>,
 }
 
 impl Database {
@@ -564,7 +1012,9 @@ impl Database {
         let result = sqlx::query_as(
             "
         SELECT
-            ulid, country_code, created_at, client_ulid, file_name, row_count
+            ulid, country_code, created_at, client_ulid, file_name, row_count, status,
+            attempt_count, next_attempt_at, posted_at, last_error, reverses_entry_ulid,
+            reversed_by_entry_ulid
         FROM
             sap_mulesoft_payroll_journals_entry_index
         WHERE
@@ -579,6 +1029,28 @@ impl Database {
         Ok(result)
     }
 
+    pub async fn select_one_sap_mulesoft_payroll_journal_entry_index(
+        &self,
+        ulid: Uuid,
+    ) -> GlobeliseResult<Option<SapMulesoftPayrollJournalEntryIndex>> {
+        let result = sqlx::query_as(
+            "
+        SELECT
+            ulid, country_code, created_at, client_ulid, file_name, row_count, status,
+            attempt_count, next_attempt_at, posted_at, last_error, reverses_entry_ulid,
+            reversed_by_entry_ulid
+        FROM
+            sap_mulesoft_payroll_journals_entry_index
+        WHERE
+            ulid = $1",
+        )
+        .bind(ulid)
+        .fetch_optional(&self.0)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn download_one_sap_mulesoft_payroll_journal_entry(
         &self,
         ulid: Uuid,
@@ -601,22 +1073,29 @@ impl Database {
         Ok(result)
     }
 
+    /// Stores a journal in the outbox, to be posted with `payload`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert_sap_mulesoft_payroll_journal(
         &self,
+        ulid: Uuid,
         client_ulid: Uuid,
         country_code: &String,
         rows: &[InsertPayrollJournalRowData],
         file: &[u8],
         file_name: &String,
-    ) -> GlobeliseResult<Uuid> {
-        let ulid = Uuid::new_v4();
+        payload: &str,
+        reverses_entry_ulid: Option<Uuid>,
+    ) -> GlobeliseResult<()> {
+        let mut transaction = self.0.begin().await?;
 
         sqlx::query(
             "
         INSERT INTO sap_mulesoft_payroll_journals_entries (
-            ulid, country_code, client_ulid, uploaded_file, file_name
+            ulid, country_code, client_ulid, uploaded_file, file_name,
+            status, payload, reverses_entry_ulid
         ) VALUES (
-            $1, $2, $3, $4, $5
+            $1, $2, $3, $4, $5,
+            'pending', $6, $7
         )",
         )
         .bind(ulid)
@@ -624,7 +1103,9 @@ impl Database {
         .bind(client_ulid)
         .bind(file)
         .bind(file_name)
-        .execute(&self.0)
+        .bind(payload)
+        .bind(reverses_entry_ulid)
+        .execute(&mut transaction)
         .await?;
 
         let rows_query = "
@@ -686,34 +1167,16 @@ impl Database {
                     .map(|v| v.gl_account.as_str())
                     .collect::<Vec<&'static str>>(),
             )
-            .bind(rows.iter().cloned().map(|v| v.amount).collect::<Vec<_>>())
+            .bind(rows.iter().map(|v| v.amount.amount()).collect::<Vec<_>>())
             .bind(
                 rows.iter()
                     .map(|v| v.cost_center_code.clone())
                     .collect::<Vec<Option<String>>>(),
             )
-            .execute(&self.0)
+            .execute(&mut transaction)
             .await?;
 
-        Ok(ulid)
-    }
-
-    pub async fn update_sap_mulesoft_payroll_journal_as_uploaded(
-        &self,
-        ulid: Uuid,
-    ) -> GlobeliseResult<()> {
-        sqlx::query(
-            "
-            UPDATE
-                sap_mulesoft_payroll_journals_rows
-            SET
-                uploaded = 't'
-            WHERE
-                entry_ulid = $1",
-        )
-        .bind(ulid)
-        .execute(&self.0)
-        .await?;
+        transaction.commit().await?;
 
         Ok(())
     }
@@ -782,32 +1245,153 @@ impl Database {
         Ok(result)
     }
 
-    pub async fn sap_mulesoft_payroll_journal_validate_cost_center_code(
+    /// The cost center codes that are not cost centers of a company code.
+    pub async fn sap_mulesoft_payroll_journal_unknown_cost_center_codes(
         &self,
         company_code: &String,
         cost_center_codes: &[&String],
-    ) -> GlobeliseResult<bool> {
-        for cost_center_code in cost_center_codes {
-            let result = sqlx::query(
-                "
+    ) -> GlobeliseResult<Vec<String>> {
+        let result = sqlx::query_scalar(
+            "
             SELECT
-                code, company_code
+                code
             FROM
-                sap_mulesoft_payroll_journal_cost_centers
+                UNNEST($2::TEXT[]) AS u(code)
             WHERE
-                company_code = $1 AND
-                code = $2",
-            )
-            .bind(company_code)
-            .bind(cost_center_code)
-            .fetch_optional(&self.0)
-            .await?;
+                NOT EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        sap_mulesoft_payroll_journal_cost_centers c
+                    WHERE
+                        c.company_code = $1 AND
+                        c.code = u.code
+                )",
+        )
+        .bind(company_code)
+        .bind(
+            cost_center_codes
+                .iter()
+                .map(|code| code.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(&self.0)
+        .await?;
 
-            if result.is_none() {
-                return Ok(false);
-            }
-        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where and why every problem of a journal is reported.
+    fn problems(journal: &ParsedJournal) -> Vec<(usize, &str, &str)> {
+        journal
+            .problems
+            .iter()
+            .map(|problem| {
+                (
+                    problem.row.unwrap(),
+                    problem.column.as_deref().unwrap(),
+                    problem.reason.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn csv_journals_report_every_problem_by_row_and_column() {
+        let journal =
+            parse_journal_file(include_bytes!("testdata/journal.csv"), "journal.csv").unwrap();
+
+        assert_eq!(journal.sheet, None);
+        // The empty last row is left out.
+        assert_eq!(journal.row_count, 4);
+        assert_eq!(
+            journal
+                .rows
+                .iter()
+                .map(|(row_number, _)| *row_number)
+                .collect::<Vec<_>>(),
+            [2, 5]
+        );
+        assert_eq!(
+            problems(&journal),
+            [
+                (3, "D", "'XXX' is not a known currency"),
+                (4, "E", "Cannot be empty"),
+                (
+                    4,
+                    "I",
+                    "SGD amounts can have at most 2 decimal places, got 15.001"
+                ),
+            ]
+        );
+
+        let (_, debit) = &journal.rows[0];
+        assert_eq!(debit.debit_credit_code.as_code(), "40");
+        assert_eq!(debit.gl_account.as_str(), "430101001");
+        assert_eq!(debit.amount.amount().to_string(), "1500.00");
+        assert_eq!(debit.cost_center_code.as_deref(), Some("CC100"));
+        // A row can leave out the optional columns at its end.
+        let (_, credit) = &journal.rows[1];
+        assert_eq!(credit.debit_credit_code.as_code(), "50");
+        assert_eq!(credit.amount.amount().to_string(), "-1500.00");
+        assert_eq!(credit.cost_center_code, None);
+        assert_eq!(credit.document_item_text, None);
+    }
+
+    #[test]
+    fn xlsx_journals_report_every_problem_by_sheet_row_and_column() {
+        let journal =
+            parse_journal_file(include_bytes!("testdata/journal.xlsx"), "journal.xlsx").unwrap();
+
+        assert_eq!(journal.sheet.as_deref(), Some("Journal"));
+        assert_eq!(journal.row_count, 3);
+        assert_eq!(
+            journal
+                .rows
+                .iter()
+                .map(|(row_number, _)| *row_number)
+                .collect::<Vec<_>>(),
+            [2, 4]
+        );
+        assert_eq!(
+            problems(&journal),
+            [
+                (3, "C", "Cannot be empty"),
+                (3, "I", "Cannot be read, please enter it as text"),
+                (
+                    3,
+                    "F",
+                    "'41' is not a posting key, it must be 40 (debit) or 50 (credit)"
+                ),
+                (
+                    3,
+                    "H",
+                    "'430-101' is not a GL account. GL accounts have up to 10 letters and digits"
+                ),
+            ]
+        );
+        assert_eq!(
+            journal.problems[0].to_string(),
+            "Journal: Row 3: Company code (C): Cannot be empty"
+        );
+
+        // Numbers are read as the text they show.
+        let (_, debit) = &journal.rows[0];
+        assert_eq!(debit.posting_date, "20220930");
+        assert_eq!(debit.debit_credit_code.as_code(), "40");
+        assert_eq!(debit.amount.amount().to_string(), "1500.00");
+        let (_, credit) = &journal.rows[1];
+        assert_eq!(credit.amount.amount().to_string(), "-1500.50");
+        assert_eq!(credit.cost_center_code, None);
+    }
 
-        Ok(true)
+    #[test]
+    fn files_that_are_not_journals_are_rejected() {
+        assert!(parse_journal_file(b"not a workbook", "journal.xlsx").is_err());
     }
 }
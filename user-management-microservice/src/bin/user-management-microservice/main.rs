@@ -4,13 +4,15 @@ use axum::{
     error_handling::HandleErrorLayer,
     extract::Extension,
     http::{HeaderValue, Method, StatusCode},
-    routing::{get, post},
+    routing::{delete, get, post, put},
     BoxError, Json, Router,
 };
 use common_utils::{
     error::GlobeliseResult,
+    mail::{outbox::spawn_outbox_sender, transport::transport_from_env},
     pubsub::{PubSub, TopicSubscription},
     token::PublicKeys,
+    DaprAppId,
 };
 use database::Database;
 use reqwest::Client;
@@ -18,6 +20,7 @@ use tokio::sync::Mutex;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer, Origin};
 
+mod api_key;
 mod auth;
 mod benefits_market_place;
 mod branch;
@@ -44,7 +47,7 @@ use env::{DAPR_ADDRESS, DATABASE_URL, FRONTEND_URL, LISTENING_ADDRESS};
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> GlobeliseResult<()> {
     dotenv::dotenv().ok();
 
     let shared_state = State::new().await.expect("Could not connect to Dapr");
@@ -55,6 +58,24 @@ async fn main() {
         common_utils::database::Database::new(&*DATABASE_URL).await,
     ));
 
+    KEYS.reload(&common_database, DaprAppId::UserManagementMicroservice)
+        .await
+        .expect("Could not load signing keys");
+    tokio::spawn(KEYS.reload_periodically(
+        common_database.clone(),
+        DaprAppId::UserManagementMicroservice,
+    ));
+
+    if std::env::var("CITIBANK_SFTP_HOST").is_ok()
+        || std::env::var("CITIBANK_SFTP_LOCAL_DIR").is_ok()
+    {
+        tokio::spawn(
+            eor_admin::bank_transfer::citibank_status::poll_citibank_status_files_periodically(
+                common_database.clone(),
+            ),
+        );
+    }
+
     let public_keys = Arc::new(Mutex::new(PublicKeys::default()));
 
     let shared_reqwest_client = Client::builder()
@@ -62,6 +83,21 @@ async fn main() {
         .build()
         .unwrap();
 
+    tokio::spawn(
+        eor_admin::sap::mulesoft_outbox::post_pending_journals_periodically(
+            shared_database.clone(),
+            shared_reqwest_client.clone(),
+        ),
+    );
+
+    eor_admin::job::spawn_job_workers(
+        shared_database.clone(),
+        common_database.clone(),
+        shared_reqwest_client.clone(),
+    );
+
+    spawn_outbox_sender(common_database.clone(), transport_from_env()?)?;
+
     let shared_pubsub = Arc::new(Mutex::new(PubSub::new(
         shared_reqwest_client.clone(),
         DAPR_ADDRESS.clone(),
@@ -85,8 +121,11 @@ async fn main() {
             "/auth/password/reset/execute",
             post(auth::password::reset::execute),
         )
+        .route("/auth/email/verify", get(auth::email::verify))
+        .route("/auth/email/verify/resend", post(auth::email::user_resend))
         .route("/auth/access-token", post(auth::access_token))
         .route("/auth/public-key", get(auth::public_key))
+        .route("/auth/jwks", get(auth::jwks))
         .route(
             "/:user_role/users",
             get(user::user_get_many_users),
@@ -191,6 +230,14 @@ async fn main() {
             "/onboard/fully_onboarded/:role",
             get(onboard::fully_onboarded),
         )
+        .route(
+            "/client/api-keys",
+            get(api_key::user_get_many).post(api_key::user_post_one),
+        )
+        .route(
+            "/client/api-keys/:api_key_ulid",
+            delete(api_key::user_delete_one),
+        )
         .route(
             "/users/notifications",
             get(notification::user_get_many).
@@ -709,9 +756,17 @@ async fn main() {
             "/eor-admin/citibank/upload-citibank-transfer-initiation-template",
             post(eor_admin::bank_transfer::citi_bank::upload_citibank_transfer_initiation_template),
         )
+        .route(
+            "/eor-admin/citibank/create-transfer-batch-from-payroll",
+            post(eor_admin::bank_transfer::payroll_batch::create_payroll_transfer_batch),
+        )
+        .route(
+            "/eor-admin/citibank/poll-status-files",
+            post(eor_admin::bank_transfer::citibank_status::post_poll),
+        )
         .route(
             "/eor-admin/citibank/init-citibank-transfer",
-            post(eor_admin::bank_transfer::citi_bank::init_citibank_transfer),
+            post(eor_admin::bank_transfer::payment_rail::init_transfer),
         )
         .route(
             "/eor-admin/citibank/list-all-uploaded-citibank-transfer-initiation-files-for-client",
@@ -745,6 +800,97 @@ async fn main() {
         ).route(
             "/eor-admin/citibank/update-transaction-status",
             get(eor_admin::bank_transfer::citi_bank::update_transaction_status),
+        ).route(
+            "/eor-admin/citibank/transfer-approval/:ulid",
+            get(eor_admin::bank_transfer::transfer_approval::get_transfer_approval_summary),
+        ).route(
+            "/eor-admin/citibank/submit-transfer-for-approval/:ulid",
+            post(eor_admin::bank_transfer::transfer_approval::submit_transfer_for_approval),
+        ).route(
+            "/eor-admin/citibank/approve-transfer",
+            post(eor_admin::bank_transfer::transfer_approval::approve_transfer),
+        )
+        .route(
+            "/eor-admin/payment-rails",
+            get(eor_admin::bank_transfer::payment_rail::list_payment_rails),
+        )
+        .route(
+            "/eor-admin/payment-rails/branch/:branch_ulid",
+            get(eor_admin::bank_transfer::payment_rail::get_branch_payment_rail),
+        )
+        .route(
+            "/eor-admin/payment-rails/branch",
+            post(eor_admin::bank_transfer::payment_rail::post_branch_payment_rail),
+        )
+        .route(
+            "/eor-admin/payment-rails/init-transfer",
+            post(eor_admin::bank_transfer::payment_rail::init_transfer),
+        )
+        .route(
+            "/eor-admin/payment-rails/upload-status-report",
+            post(eor_admin::bank_transfer::payment_rail::upload_status_report),
+        )
+        .route(
+            "/eor-admin/bank-statements",
+            post(eor_admin::bank_transfer::bank_statement::import_bank_statement),
+        )
+        .route(
+            "/eor-admin/bank-statements/unmatched-lines",
+            get(eor_admin::bank_transfer::bank_statement::list_unmatched_bank_statement_lines),
+        )
+        .route(
+            "/eor-admin/bank-statements/lines/:line_ulid/match",
+            post(eor_admin::bank_transfer::bank_statement::match_bank_statement_line),
+        )
+        .route(
+            "/eor-admin/bank-statements/lines/:line_ulid/ignore",
+            post(eor_admin::bank_transfer::bank_statement::ignore_bank_statement_line),
+        )
+        .route(
+            "/eor-admin/fx-rates",
+            get(eor_admin::fx_rate::list_fx_rates).post(eor_admin::fx_rate::post_fx_rate),
+        )
+        .route(
+            "/eor-admin/fx-rates/:ulid",
+            delete(eor_admin::fx_rate::delete_fx_rate),
+        )
+        .route(
+            "/eor-admin/fx-rates/import",
+            post(eor_admin::fx_rate::import_fx_rates),
+        )
+        .route(
+            "/eor-admin/fx-rates/convert",
+            get(eor_admin::fx_rate::convert),
+        )
+        .route(
+            "/eor-admin/reports/payroll",
+            get(eor_admin::report::payroll_report),
+        )
+        .route(
+            "/eor-admin/reports/cost-centers",
+            get(eor_admin::report::cost_center_report),
+        )
+        .route("/eor-admin/auth/keys/rotate", post(auth::rotate_keys))
+        .route("/eor-admin/api-keys", get(api_key::admin_get_many))
+        .route(
+            "/eor-admin/api-keys/:api_key_ulid",
+            delete(api_key::admin_delete_one),
+        )
+        .route(
+            "/eor-admin/users/:user_ulid/impersonate",
+            post(auth::impersonation::admin_impersonate),
+        )
+        .route(
+            "/eor-admin/impersonation-logs",
+            get(auth::impersonation::admin_get_many_logs),
+        )
+        .route(
+            "/eor-admin/users/:user_ulid/email/verify",
+            post(auth::email::admin_verify),
+        )
+        .route(
+            "/eor-admin/users/:user_ulid/email/verify/resend",
+            post(auth::email::admin_resend),
         )
         .route(
             "/eor-admin/:user_role/users",
@@ -784,6 +930,44 @@ async fn main() {
             "/eor-admin/users/add-bulk-employees/download",
             get(bulk_add::download),
         )
+        .route(
+            "/eor-admin/users/add_bulk_entity_contractors",
+            post(bulk_add::entity::post_one),
+        )
+        .route(
+            "/eor-admin/users/add-bulk-entity-contractors/download",
+            get(bulk_add::entity::download),
+        )
+        .route(
+            "/eor-admin/users/assign_bulk_pay_items",
+            post(bulk_add::pay_items::post_one),
+        )
+        .route(
+            "/eor-admin/users/assign-bulk-pay-items/download",
+            get(bulk_add::pay_items::download),
+        )
+        .route(
+            "/eor-admin/users/add_bulk_employees/reports",
+            get(bulk_add::report::get_many_reports),
+        )
+        .route(
+            "/eor-admin/users/add_bulk_employees/reports/:ulid",
+            get(bulk_add::report::get_one_report),
+        )
+        .route(
+            "/eor-admin/users/add_bulk_employees/reports/:ulid/download",
+            get(bulk_add::report::download_one_report),
+        )
+        .route("/eor-admin/jobs", get(eor_admin::job::get_many_jobs))
+        .route("/eor-admin/jobs/:ulid", get(eor_admin::job::get_one_job))
+        .route(
+            "/eor-admin/jobs/:ulid/result",
+            get(eor_admin::job::download_one_job_result),
+        )
+        .route(
+            "/eor-admin/jobs/:ulid/cancel",
+            post(eor_admin::job::cancel_one_job),
+        )
         .route(
             "/eor-admin/users/onboard/prefill_individual_contractor_account_details",
                 post(prefill::account::admin_post_one_individual_contractor),
@@ -858,6 +1042,10 @@ async fn main() {
             "/eor-admin/sap/upload_payroll_to_s4hana",
             post(eor_admin::sap::s4_hana::post_one),
         )
+        .route(
+            "/eor-admin/sap/upload_payroll_to_s4hana/validate",
+            post(eor_admin::sap::s4_hana::validate_one),
+        )
         .route(
             "/eor-admin/sap/mulesoft/payroll_journal/entries",
             get(eor_admin::sap::s4_hana::get_many_entries),
@@ -866,6 +1054,18 @@ async fn main() {
             "/eor-admin/sap/mulesoft/payroll_journal/entry/:entry_ulid/download",
             get(eor_admin::sap::s4_hana::download_one_entry),
         )
+        .route(
+            "/eor-admin/sap/mulesoft/payroll_journal/entry/:entry_ulid/attempts",
+            get(eor_admin::sap::mulesoft_outbox::get_many_attempts),
+        )
+        .route(
+            "/eor-admin/sap/mulesoft/payroll_journal/entry/:entry_ulid/repost",
+            post(eor_admin::sap::mulesoft_outbox::repost_one_entry),
+        )
+        .route(
+            "/eor-admin/sap/mulesoft/payroll_journal/entry/:entry_ulid/cancel",
+            post(eor_admin::sap::mulesoft_outbox::cancel_one_entry),
+        )
         .route(
             "/eor-admin/sap/mulesoft/payroll_journal/rows",
             get(eor_admin::sap::s4_hana::get_many_rows),
@@ -874,6 +1074,28 @@ async fn main() {
             "/eor-admin/sap/journal_template.xlsx",
             get(eor_admin::sap::s4_hana::download),
         )
+        .route(
+            "/eor-admin/sap/mulesoft/payroll_journal/generate",
+            post(eor_admin::sap::payroll_journal::post_payroll_journal),
+        )
+        .route(
+            "/eor-admin/sap/mulesoft/payroll_journal/generate/preview",
+            post(eor_admin::sap::payroll_journal::preview_payroll_journal),
+        )
+        .route(
+            "/eor-admin/sap/cost_centers/:cost_center_ulid",
+            post(eor_admin::sap::payroll_journal::post_sap_cost_center_mapping),
+        )
+        .route(
+            "/eor-admin/sap/gl_accounts",
+            get(eor_admin::sap::gl_account::get_many_gl_accounts)
+                .post(eor_admin::sap::gl_account::post_one_gl_account),
+        )
+        .route(
+            "/eor-admin/sap/gl_accounts/:ulid",
+            put(eor_admin::sap::gl_account::put_one_gl_account)
+                .delete(eor_admin::sap::gl_account::delete_one_gl_account),
+        )
         .route(
             "/eor-admin/notifications",
             get(notification::admin_get_many_for_user).
@@ -930,7 +1152,6 @@ async fn main() {
                 .layer(Extension(shared_database))
                 .layer(Extension(common_database))
                 .layer(Extension(shared_state))
-                .layer(Extension(KEYS.decoding.clone()))
                 .layer(Extension(public_keys))
                 .layer(Extension(shared_pubsub))
                 .layer(Extension(shared_reqwest_client)),
@@ -945,6 +1166,8 @@ async fn main() {
     .serve(app.into_make_service())
     .await
     .unwrap();
+
+    Ok(())
 }
 
 async fn handle_healthz() -> String {
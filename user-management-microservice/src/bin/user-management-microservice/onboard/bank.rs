@@ -3,7 +3,7 @@ use common_utils::{
     custom_serde::{UserType, FORM_DATA_LENGTH_LIMIT},
     database::{onboard::bank::ContractorUserDetails, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
-    token::Token,
+    token::{NotImpersonated, Token},
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use user_management_microservice_sdk::token::UserAccessToken;
@@ -18,6 +18,14 @@ pub async fn user_post_one_bank_details(
     Extension(database): Extension<CommonDatabase>,
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
+
+    let country = database
+        .select_one_onboard_contractor_country(claims.payload.ulid, claims.payload.user_type)
+        .await?;
+    body.bank_details(country).validate()?;
 
     database
         .insert_one_onboard_user_bank_details(claims.payload.ulid, claims.payload.user_type, &body)
@@ -37,6 +45,11 @@ pub async fn admin_post_one_bank_details(
 ) -> GlobeliseResult<()> {
     let database = database.lock().await;
 
+    let country = database
+        .select_one_onboard_contractor_country(user_ulid, user_type)
+        .await?;
+    body.bank_details(country).validate()?;
+
     database
         .insert_one_onboard_user_bank_details(user_ulid, user_type, &body)
         .await?;
@@ -45,7 +58,7 @@ pub async fn admin_post_one_bank_details(
 }
 
 pub async fn user_get_one_bank_details(
-    claims: Token<UserAccessToken>,
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
     Extension(database): Extension<CommonDatabase>,
 ) -> GlobeliseResult<Json<ContractorUserDetails>> {
     let database = database.lock().await;
@@ -63,6 +63,9 @@ pub async fn user_post_one_client_account_details(
     }
 
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
 
     database
         .insert_one_onboard_entity_client_account_details(claims.payload.ulid, &body)
@@ -102,6 +105,9 @@ pub async fn user_post_one_contractor_account_details(
     }
 
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
 
     database
         .insert_one_onboard_entity_contractor_account_details(claims.payload.ulid, &body)
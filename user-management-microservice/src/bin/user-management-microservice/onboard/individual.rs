@@ -27,6 +27,9 @@ pub async fn user_post_one_client_account_details(
     let ulid = claims.payload.ulid;
 
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
 
     database
         .insert_one_onboard_individual_client_account_details(ulid, body)
@@ -105,6 +108,9 @@ pub async fn user_post_one_contractor_account_details(
     let ulid = claims.payload.ulid;
 
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
 
     database
         .insert_one_onboard_individual_contractor_account_details(ulid, &body)
@@ -3,7 +3,7 @@ use common_utils::{
     custom_serde::{UserType, FORM_DATA_LENGTH_LIMIT},
     database::{onboard::payment::OnboardClientPaymentDetails, CommonDatabase},
     error::{GlobeliseError, GlobeliseResult},
-    token::Token,
+    token::{NotImpersonated, Token},
 };
 use eor_admin_microservice_sdk::token::AdminAccessToken;
 use user_management_microservice_sdk::token::UserAccessToken;
@@ -12,7 +12,7 @@ use uuid::Uuid;
 use crate::database::SharedDatabase;
 
 pub async fn user_get_one_payment_details(
-    claims: Token<UserAccessToken>,
+    NotImpersonated(claims): NotImpersonated<UserAccessToken>,
     Extension(database): Extension<CommonDatabase>,
 ) -> GlobeliseResult<Json<OnboardClientPaymentDetails>> {
     let database = database.lock().await;
@@ -54,6 +54,12 @@ pub async fn user_post_one_payment_details(
     Extension(shared_database): Extension<SharedDatabase>,
 ) -> GlobeliseResult<()> {
     let common_database = common_database.lock().await;
+    if !common_database
+        .is_user_email_verified(claims.payload.ulid)
+        .await?
+    {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
     let shared_database = shared_database.lock().await;
 
     common_database
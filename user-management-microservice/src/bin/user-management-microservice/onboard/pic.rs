@@ -23,6 +23,9 @@ pub async fn user_post_one_onboard_entity_pic_details(
     }
 
     let database = database.lock().await;
+    if !database.is_user_email_verified(claims.payload.ulid).await? {
+        return Err(GlobeliseError::EmailNotVerified);
+    }
 
     database
         .insert_one_onboard_entity_pic_details(
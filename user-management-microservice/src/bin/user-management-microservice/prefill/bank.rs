@@ -43,23 +43,25 @@ pub async fn user_post_one_individual_contractor(
         .ok_or_else(|| GlobeliseError::not_found("Cannot find a user with this email"))?
         .ulid;
 
+    let details = ContractorUserDetails {
+        bank_name: body.bank_name,
+        bank_account_name: body.bank_account_name,
+        bank_account_number: body.bank_account_number,
+        bank_code: body.bank_code,
+        branch_code: body.branch_code,
+    };
+    let country = database
+        .select_one_onboard_contractor_country(ulid, UserType::Individual)
+        .await?;
+    details.bank_details(country).validate()?;
+
     if database
         .select_one_onboard_user_bank_detail(ulid, UserType::Individual)
         .await?
         .is_none()
     {
         database
-            .insert_one_onboard_user_bank_details(
-                ulid,
-                UserType::Individual,
-                &ContractorUserDetails {
-                    bank_name: body.bank_name,
-                    bank_account_name: body.bank_account_name,
-                    bank_account_number: body.bank_account_number,
-                    bank_code: body.bank_code,
-                    branch_code: body.branch_code,
-                },
-            )
+            .insert_one_onboard_user_bank_details(ulid, UserType::Individual, &details)
             .await?;
     }
 
@@ -83,18 +85,20 @@ pub async fn admin_post_one_individual_contractor(
         .ok_or_else(|| GlobeliseError::not_found("Cannot find a user with this email"))?
         .ulid;
 
+    let details = ContractorUserDetails {
+        bank_name: body.bank_name,
+        bank_account_name: body.bank_account_name,
+        bank_account_number: body.bank_account_number,
+        bank_code: body.bank_code,
+        branch_code: body.branch_code,
+    };
+    let country = database
+        .select_one_onboard_contractor_country(ulid, UserType::Individual)
+        .await?;
+    details.bank_details(country).validate()?;
+
     database
-        .insert_one_onboard_user_bank_details(
-            ulid,
-            UserType::Individual,
-            &ContractorUserDetails {
-                bank_name: body.bank_name,
-                bank_account_name: body.bank_account_name,
-                bank_account_number: body.bank_account_number,
-                bank_code: body.bank_code,
-                branch_code: body.branch_code,
-            },
-        )
+        .insert_one_onboard_user_bank_details(ulid, UserType::Individual, &details)
         .await?;
 
     Ok(())
@@ -116,23 +120,25 @@ pub async fn admin_post_one_entity_client(
         .ok_or_else(|| GlobeliseError::not_found("Cannot find a user with this email"))?
         .ulid;
 
+    let details = ContractorUserDetails {
+        bank_name: body.bank_name,
+        bank_account_name: body.bank_account_name,
+        bank_account_number: body.bank_account_number,
+        bank_code: body.bank_code,
+        branch_code: body.branch_code,
+    };
+    let country = database
+        .select_one_onboard_contractor_country(ulid, UserType::Entity)
+        .await?;
+    details.bank_details(country).validate()?;
+
     if database
         .select_one_onboard_user_bank_detail(ulid, UserType::Individual)
         .await?
         .is_none()
     {
         database
-            .insert_one_onboard_user_bank_details(
-                ulid,
-                UserType::Individual,
-                &ContractorUserDetails {
-                    bank_name: body.bank_name,
-                    bank_account_name: body.bank_account_name,
-                    bank_account_number: body.bank_account_number,
-                    bank_code: body.bank_code,
-                    branch_code: body.branch_code,
-                },
-            )
+            .insert_one_onboard_user_bank_details(ulid, UserType::Individual, &details)
             .await?;
     }
 
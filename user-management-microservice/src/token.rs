@@ -1,6 +1,7 @@
 use common_utils::{
     custom_serde::{EmailWrapper, UserRole, UserType},
-    token::TokenLike,
+    database::api_key::ApiKey,
+    token::{Impersonation, TokenLike},
     DaprAppId,
 };
 use serde::{Deserialize, Serialize};
@@ -17,6 +18,12 @@ pub struct UserAccessToken {
     pub email: EmailWrapper,
     pub user_type: UserType,
     pub user_roles: Vec<UserRole>,
+    /// Set when an EOR admin is acting as this user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonation: Option<Impersonation>,
+    /// Set when the request was made with an API key instead of by logging in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_ulid: Option<Uuid>,
 }
 
 impl TokenLike for UserAccessToken {
@@ -31,4 +38,20 @@ impl TokenLike for UserAccessToken {
     fn dapr_app_id() -> DaprAppId {
         DaprAppId::UserManagementMicroservice
     }
+
+    fn impersonation(&self) -> Option<&Impersonation> {
+        self.impersonation.as_ref()
+    }
+
+    fn from_api_key(api_key: &ApiKey) -> Option<Self> {
+        Some(UserAccessToken {
+            ulid: api_key.user_ulid,
+            email: api_key.email.clone(),
+            user_type: api_key.user_type,
+            // Only clients can create API keys.
+            user_roles: vec![UserRole::Client],
+            impersonation: None,
+            api_key_ulid: Some(api_key.ulid),
+        })
+    }
 }